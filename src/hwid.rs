@@ -0,0 +1,269 @@
+//! Hardware ID matching shared by `inspect --compare-system`, restore and the online checks:
+//! normalizes IDs, classifies how specific they are, and ranks matches against a device.
+
+use std::collections::HashMap;
+
+use crate::{device_instance_key, PnPEntity, PnPSignedDriver};
+
+/// How specific a hardware ID is, least specific first, so the variants compare in Windows' ranking order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HwidSpecificity {
+    /// Device class only: `USB\Class_03`, `PCI\CC_0300`, `HID_DEVICE_SYSTEM_MOUSE`
+    Class,
+    /// Generic or legacy compatible ID: `*PNP0303`, `USB\COMPOSITE`
+    Compatible,
+    /// Names the device: `PCI\VEN_8086&DEV_A370`, `USB\VID_046D&PID_C52B&REV_1200`, `ACPI\PNP0A08`
+    Device,
+    /// Device plus board/subsystem: `PCI\VEN_8086&DEV_A370&SUBSYS_00748086`
+    Subsystem,
+}
+
+impl HwidSpecificity {
+    /// Classify a hardware ID in `normalize_hardware_id` form
+    pub fn of(id: &str) -> Self {
+        if id.starts_with("HID_DEVICE") {
+            return HwidSpecificity::Class;
+        }
+        let (enumerator, rest) = id.split_once('\\').unwrap_or(("", id));
+        let parts: Vec<&str> = rest.split('&').collect();
+        let has = |prefix: &str| parts.iter().any(|part| part.starts_with(prefix));
+        if has("CC_") || has("CLASS_") {
+            return HwidSpecificity::Class;
+        }
+        if has("SUBSYS_") {
+            return HwidSpecificity::Subsystem;
+        }
+        let names_device = match enumerator {
+            "PCI" | "HDAUDIO" => has("VEN_") && has("DEV_"),
+            "USB" | "HID" => has("VID_") && has("PID_"),
+            "" => false,
+            _ => !rest.is_empty() && rest != "COMPOSITE",
+        };
+        if names_device { HwidSpecificity::Device } else { HwidSpecificity::Compatible }
+    }
+}
+
+/// Canonical form for comparing hardware IDs: uppercase with single `\` separators,
+/// the same rules as `device_instance_key`
+pub fn normalize_hardware_id(id: &str) -> String {
+    device_instance_key(id)
+}
+
+/// Ranked result of matching an INF (or Windows Update) hardware ID against a device
+#[derive(Debug, Clone)]
+pub struct HwidMatch {
+    /// Higher is a better match; only comparable between matches of the same INF ID set
+    pub score: u32,
+    pub specificity: HwidSpecificity,
+    /// The device's ID that matched, normalized
+    pub device_id: String,
+}
+
+/// The hardware and compatible IDs of one device, normalized once for repeated matching
+pub struct DeviceIds {
+    /// Most specific first, as Windows lists them
+    hardware: Vec<String>,
+    compatible: Vec<String>,
+}
+
+impl DeviceIds {
+    pub fn new<'a>(hardware: impl IntoIterator<Item = &'a str>, compatible: impl IntoIterator<Item = &'a str>) -> Self {
+        DeviceIds {
+            hardware: hardware.into_iter().map(normalize_hardware_id).collect(),
+            compatible: compatible.into_iter().map(normalize_hardware_id).collect(),
+        }
+    }
+
+    /// IDs of an installed driver's device: everything its `PnPEntity` reports, or only
+    /// the primary hardware ID when WMI returned no entity for it
+    pub fn of_driver(driver: &PnPSignedDriver, entities: &HashMap<String, PnPEntity>) -> Self {
+        match driver.device_id.as_deref().and_then(|id| entities.get(&device_instance_key(id))) {
+            Some(entity) => DeviceIds::new(
+                entity.hardware_ids.iter().flatten().map(String::as_str),
+                entity.compatible_ids.iter().flatten().map(String::as_str),
+            ),
+            None => DeviceIds::new(driver.hardware_id.as_deref(), None),
+        }
+    }
+
+    /// Best match of `inf_id` against this device, `None` if it doesn't apply.
+    ///
+    /// An exact match can be against any hardware or compatible ID. An INF ID that names
+    /// the device also matches a more detailed hardware ID of it (`PCI\VEN_1&DEV_2` matches
+    /// `PCI\VEN_1&DEV_2&SUBSYS_3&REV_04`), since WMI may only report the most detailed one.
+    /// The score ranks by the INF ID's specificity, then exact over derived matches, then
+    /// hardware over compatible IDs, then the ID's position in the device's list.
+    pub fn best_match(&self, inf_id: &str) -> Option<HwidMatch> {
+        let inf_id = normalize_hardware_id(inf_id);
+        let specificity = HwidSpecificity::of(&inf_id);
+        let score = |exact: bool, hardware: bool, position: usize| {
+            (specificity as u32) * 1000 + if exact { 500 } else { 0 } + if hardware { 200 } else { 0 } + 100 - position.min(99) as u32
+        };
+        let found = |score: u32, device_id: &String| Some(HwidMatch { score, specificity, device_id: device_id.clone() });
+
+        if let Some(position) = self.hardware.iter().position(|id| *id == inf_id) {
+            return found(score(true, true, position), &self.hardware[position]);
+        }
+        if let Some(position) = self.compatible.iter().position(|id| *id == inf_id) {
+            return found(score(true, false, position), &self.compatible[position]);
+        }
+        if specificity < HwidSpecificity::Device {
+            return None;
+        }
+        let (enumerator, parts) = inf_id.split_once('\\')?;
+        let position = self.hardware.iter().position(|id| {
+            id.split_once('\\').is_some_and(|(device_enumerator, device_parts)| {
+                let device_parts: Vec<&str> = device_parts.split('&').collect();
+                device_enumerator == enumerator && parts.split('&').all(|part| device_parts.contains(&part))
+            })
+        })?;
+        found(score(false, true, position), &self.hardware[position])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use HwidSpecificity::{Class, Compatible, Device, Subsystem};
+
+    #[test]
+    fn normalize_hardware_id_forms() {
+        let cases = [
+            ("pci\\ven_8086&dev_a370", "PCI\\VEN_8086&DEV_A370"),
+            ("PCI/VEN_8086&DEV_A370", "PCI\\VEN_8086&DEV_A370"),
+            ("PCI\\\\VEN_8086&DEV_A370", "PCI\\VEN_8086&DEV_A370"),
+            ("  USB\\VID_046D&PID_C52B  ", "USB\\VID_046D&PID_C52B"),
+            ("*pnp0a08", "*PNP0A08"),
+        ];
+        for (id, expected) in cases {
+            assert_eq!(normalize_hardware_id(id), expected, "{}", id);
+        }
+    }
+
+    #[test]
+    fn specificity_of_each_bus_form() {
+        let cases = [
+            ("PCI\\VEN_8086&DEV_A370&SUBSYS_00748086&REV_10", Subsystem),
+            ("PCI\\VEN_8086&DEV_A370&SUBSYS_00748086", Subsystem),
+            ("PCI\\VEN_8086&DEV_A370&REV_10", Device),
+            ("PCI\\VEN_8086&DEV_A370", Device),
+            ("PCI\\VEN_8086&CC_028000", Class),
+            ("PCI\\CC_0280", Class),
+            ("PCI\\VEN_8086", Compatible),
+            ("USB\\VID_046D&PID_C52B&REV_1200", Device),
+            ("USB\\VID_046D&PID_C52B&MI_00", Device),
+            ("USB\\Class_03&SubClass_01&Prot_02", Class),
+            ("USB\\COMPOSITE", Compatible),
+            ("HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256&SUBSYS_10280811&REV_1000", Subsystem),
+            ("HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256&REV_1000", Device),
+            ("HDAUDIO\\FUNC_01&VEN_10EC", Compatible),
+            ("ACPI\\PNP0A08", Device),
+            ("ACPI\\VEN_INT&DEV_33A1", Device),
+            ("*PNP0A08", Compatible),
+            ("HID\\VID_046D&PID_C52B&MI_00&COL01", Device),
+            ("HID\\VID_046D&UP:0001_U:0002", Compatible),
+            ("HID_DEVICE_SYSTEM_MOUSE", Class),
+            ("HID_DEVICE", Class),
+        ];
+        for (id, expected) in cases {
+            assert_eq!(HwidSpecificity::of(&normalize_hardware_id(id)), expected, "{}", id);
+        }
+    }
+
+    fn pci_device() -> DeviceIds {
+        DeviceIds::new(
+            ["PCI\\VEN_8086&DEV_A370&SUBSYS_00748086&REV_10", "PCI\\VEN_8086&DEV_A370&SUBSYS_00748086", "PCI\\VEN_8086&DEV_A370&CC_028000", "PCI\\VEN_8086&DEV_A370&CC_0280"],
+            ["PCI\\VEN_8086&DEV_A370&REV_10", "PCI\\VEN_8086&DEV_A370", "PCI\\VEN_8086&CC_028000", "PCI\\VEN_8086", "PCI\\CC_028000", "PCI\\CC_0280"],
+        )
+    }
+
+    fn usb_device() -> DeviceIds {
+        DeviceIds::new(
+            ["USB\\VID_046D&PID_C52B&REV_1200", "USB\\VID_046D&PID_C52B"],
+            ["USB\\Class_03&SubClass_01&Prot_02", "USB\\Class_03&SubClass_01", "USB\\Class_03"],
+        )
+    }
+
+    fn hdaudio_device() -> DeviceIds {
+        DeviceIds::new(
+            ["HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256&SUBSYS_10280811&REV_1000", "HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256&SUBSYS_10280811"],
+            ["HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256&REV_1000", "HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256", "HDAUDIO\\FUNC_01&VEN_10EC", "HDAUDIO\\FUNC_01"],
+        )
+    }
+
+    fn acpi_device() -> DeviceIds {
+        DeviceIds::new(["ACPI\\VEN_PNP&DEV_0A08", "ACPI\\PNP0A08", "*PNP0A08"], ["*PNP0A03"])
+    }
+
+    fn hid_device() -> DeviceIds {
+        DeviceIds::new(
+            ["HID\\VID_046D&PID_C52B&REV_1200&MI_00&COL01", "HID\\VID_046D&PID_C52B&MI_00&COL01", "HID\\VID_046D&UP:0001_U:0002"],
+            ["HID_DEVICE_SYSTEM_MOUSE", "HID_DEVICE_UP:0001_U:0002", "HID_DEVICE"],
+        )
+    }
+
+    #[test]
+    fn best_match_for_each_bus_form() {
+        let cases = [
+            ("pci", pci_device(), "pci\\ven_8086&dev_a370&subsys_00748086", Some((Subsystem, "PCI\\VEN_8086&DEV_A370&SUBSYS_00748086"))),
+            ("pci", pci_device(), "PCI\\VEN_8086&DEV_A370", Some((Device, "PCI\\VEN_8086&DEV_A370"))),
+            ("pci", pci_device(), "PCI\\CC_0280", Some((Class, "PCI\\CC_0280"))),
+            ("pci", pci_device(), "PCI\\VEN_8086&DEV_A370&SUBSYS_12345678", None),
+            ("pci", pci_device(), "PCI\\VEN_10DE&DEV_1F08", None),
+            ("usb", usb_device(), "USB\\VID_046D&PID_C52B&REV_1200", Some((Device, "USB\\VID_046D&PID_C52B&REV_1200"))),
+            ("usb", usb_device(), "USB\\Class_03", Some((Class, "USB\\CLASS_03"))),
+            ("usb", usb_device(), "USB\\VID_046D&PID_C52C", None),
+            ("usb", usb_device(), "USB\\COMPOSITE", None),
+            ("hdaudio", hdaudio_device(), "HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256&SUBSYS_10280811", Some((Subsystem, "HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256&SUBSYS_10280811"))),
+            ("hdaudio", hdaudio_device(), "HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256", Some((Device, "HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256"))),
+            ("hdaudio", hdaudio_device(), "HDAUDIO\\FUNC_01", Some((Compatible, "HDAUDIO\\FUNC_01"))),
+            ("hdaudio", hdaudio_device(), "HDAUDIO\\FUNC_01&VEN_10EC&DEV_0295", None),
+            ("acpi", acpi_device(), "ACPI\\PNP0A08", Some((Device, "ACPI\\PNP0A08"))),
+            ("acpi", acpi_device(), "*PNP0A08", Some((Compatible, "*PNP0A08"))),
+            ("acpi", acpi_device(), "*PNP0A03", Some((Compatible, "*PNP0A03"))),
+            ("acpi", acpi_device(), "ACPI\\PNP0C0A", None),
+            ("hid", hid_device(), "HID\\VID_046D&PID_C52B&MI_00", Some((Device, "HID\\VID_046D&PID_C52B&REV_1200&MI_00&COL01"))),
+            ("hid", hid_device(), "HID_DEVICE_SYSTEM_MOUSE", Some((Class, "HID_DEVICE_SYSTEM_MOUSE"))),
+            ("hid", hid_device(), "HID\\VID_046D&PID_C52C&MI_00", None),
+        ];
+        for (bus, device, inf_id, expected) in cases {
+            let found = device.best_match(inf_id);
+            assert_eq!(
+                found.as_ref().map(|found| (found.specificity, found.device_id.as_str())),
+                expected,
+                "{} device, INF ID {}",
+                bus,
+                inf_id
+            );
+        }
+    }
+
+    #[test]
+    fn device_level_ids_match_the_detailed_id_wmi_reports() {
+        // Without a PnPEntity only the primary (most detailed) hardware ID is known
+        let device = DeviceIds::new(["PCI\\VEN_8086&DEV_A370&SUBSYS_00748086&REV_10"], None);
+        let found = device.best_match("PCI\\VEN_8086&DEV_A370").expect("derived match");
+        assert_eq!(found.specificity, Device);
+        assert_eq!(found.device_id, "PCI\\VEN_8086&DEV_A370&SUBSYS_00748086&REV_10");
+        // Class and compatible IDs are never derived, or they would match every device on the bus
+        assert!(device.best_match("PCI\\CC_0280").is_none());
+        assert!(device.best_match("PCI\\VEN_8086").is_none());
+    }
+
+    #[test]
+    fn more_specific_ids_score_higher() {
+        let ranked = [
+            (pci_device(), ["PCI\\VEN_8086&DEV_A370&SUBSYS_00748086", "PCI\\VEN_8086&DEV_A370&REV_10", "PCI\\VEN_8086&DEV_A370", "PCI\\VEN_8086", "PCI\\CC_0280"].as_slice()),
+            (usb_device(), ["USB\\VID_046D&PID_C52B&REV_1200", "USB\\VID_046D&PID_C52B", "USB\\Class_03&SubClass_01&Prot_02", "USB\\Class_03"].as_slice()),
+            (hdaudio_device(), ["HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256&SUBSYS_10280811&REV_1000", "HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256&REV_1000", "HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256", "HDAUDIO\\FUNC_01"].as_slice()),
+            (acpi_device(), ["ACPI\\VEN_PNP&DEV_0A08", "ACPI\\PNP0A08", "*PNP0A08", "*PNP0A03"].as_slice()),
+            (hid_device(), ["HID\\VID_046D&PID_C52B&REV_1200&MI_00&COL01", "HID\\VID_046D&PID_C52B&MI_00", "HID_DEVICE_SYSTEM_MOUSE", "HID_DEVICE"].as_slice()),
+        ];
+        for (device, inf_ids) in ranked {
+            let scores: Vec<u32> = inf_ids.iter()
+                .map(|inf_id| device.best_match(inf_id).unwrap_or_else(|| panic!("{} should match", inf_id)).score)
+                .collect();
+            assert!(scores.windows(2).all(|pair| pair[0] > pair[1]), "{:?} scored {:?}", inf_ids, scores);
+        }
+    }
+}
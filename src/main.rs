@@ -5,9 +5,17 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tempfile::TempDir;
 use wmi::{COMLibrary, WMIConnection};
 
+mod hwid;
+use hwid::{normalize_hardware_id, DeviceIds, HwidSpecificity};
+
 // Struct for parsed INF driver information (mirrors PnPSignedDriver structure)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct InfDriverInfo {
@@ -22,18 +30,78 @@ struct InfDriverInfo {
     inf_name: Option<String>,
     catalog_file: Option<String>,
     manufacturer: Option<String>,
+    /// Whether `[ControlFlags] ExcludeFromSelect` keeps it out of the manual install list
+    #[serde(default)]
+    excluded: Exclusion,
+    /// From the models section decoration; `None` when it names no known platform
+    #[serde(default)]
+    architecture: Option<Architecture>,
+}
+
+/// `[ControlFlags] ExcludeFromSelect` status of an INF device entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Exclusion {
+    /// Offered for manual install
+    #[default]
+    Selectable,
+    /// Its hardware ID is listed; hidden unless `--show-excluded`
+    Listed,
+    /// `ExcludeFromSelect = *` covers every entry of the INF, so the entry stays listed
+    All,
+}
+
+impl Exclusion {
+    fn is_excluded(self) -> bool {
+        self != Exclusion::Selectable
+    }
 }
 
 // Struct for parsed INF file
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct ParsedInfFile {
     file_path: PathBuf,
     file_name: String,
     drivers: Vec<InfDriverInfo>,
     raw_version_info: InfVersionInfo,
+    /// Payload files from `[SourceDisksFiles]`, relative to the INF's folder
+    #[serde(default)]
+    source_files: Vec<String>,
+    /// INFs named by `Include=` in install sections, lowercase
+    #[serde(default)]
+    includes: Vec<String>,
+    /// Sections named by `Needs=` in install sections
+    #[serde(default)]
+    needs: Vec<String>,
+    /// Input (`--path` value) this INF was found under
+    #[serde(skip)]
+    source: String,
+    /// Folder size and missing files, filled in by scan
+    #[serde(skip)]
+    package: Option<PackageStatus>,
+    /// Authenticode timestamp of the package's catalog, filled in by inspect
+    #[serde(skip)]
+    signed_date: Option<chrono::NaiveDateTime>,
 }
 
-#[derive(Debug, Clone, Default)]
+/// One package that differs between a scan and its `--diff` folder.
+/// A `None` version means the package is missing on that side.
+struct PackageDiff {
+    file_name: String,
+    a_version: Option<String>,
+    b_version: Option<String>,
+}
+
+/// Whether a package folder holds everything its INF refers to
+#[derive(Debug, Clone)]
+struct PackageStatus {
+    /// Size of the INF's folder including subfolders, in bytes
+    folder_size: u64,
+    /// Catalog and source files that aren't present
+    missing_files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct InfVersionInfo {
     driver_version: Option<String>,
     driver_date: Option<String>,
@@ -43,8 +111,225 @@ struct InfVersionInfo {
     catalog_file: Option<String>,
 }
 
+/// Hardware ID prefixes (enumerators) recognized as real devices in INF model sections
+const HARDWARE_ID_PREFIXES: [&str; 7] = ["PCI\\", "USB\\", "HDAUDIO\\", "ACPI\\", "HID\\", "SWD\\", "ROOT\\"];
+
+/// Device bus, identified by the enumerator prefix of a hardware ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Bus {
+    Pci,
+    Usb,
+    Hdaudio,
+    Acpi,
+    Hid,
+}
+
+impl Bus {
+    /// Hardware ID prefix for this bus (one of `HARDWARE_ID_PREFIXES`)
+    fn prefix(self) -> &'static str {
+        match self {
+            Bus::Pci => "PCI\\",
+            Bus::Usb => "USB\\",
+            Bus::Hdaudio => "HDAUDIO\\",
+            Bus::Acpi => "ACPI\\",
+            Bus::Hid => "HID\\",
+        }
+    }
+
+    /// Check if a hardware ID belongs to this bus
+    fn matches(self, hardware_id: &str) -> bool {
+        hardware_id.to_uppercase().starts_with(self.prefix())
+    }
+}
+
+/// Processor architecture of a machine or of an INF models section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum Architecture {
+    X86,
+    Amd64,
+    Arm64,
+    Arm,
+    Ia64,
+}
+
+impl Architecture {
+    /// Architecture of a models section from its TargetOSVersion decoration (`NTamd64.10.0`).
+    /// Undecorated and plain `NT` sections only apply to x86. Unknown decorations give `None`.
+    fn from_decoration(decoration: Option<&str>) -> Option<Self> {
+        let Some(decoration) = decoration else {
+            return Some(Architecture::X86);
+        };
+        let platform = decoration.split('.').next().unwrap_or_default().to_lowercase();
+        match platform.strip_prefix("nt")? {
+            "" | "x86" => Some(Architecture::X86),
+            "amd64" => Some(Architecture::Amd64),
+            "arm64" => Some(Architecture::Arm64),
+            "arm" => Some(Architecture::Arm),
+            "ia64" => Some(Architecture::Ia64),
+            _ => None,
+        }
+    }
+
+    /// The machine's own architecture from `GetNativeSystemInfo`, which sees through WOW64
+    /// (an x86 build on x64 still gets `Amd64`)
+    fn native() -> Self {
+        use windows::Win32::System::SystemInformation::{
+            GetNativeSystemInfo, PROCESSOR_ARCHITECTURE_AMD64, PROCESSOR_ARCHITECTURE_ARM, PROCESSOR_ARCHITECTURE_ARM64,
+            PROCESSOR_ARCHITECTURE_IA64, PROCESSOR_ARCHITECTURE_INTEL, SYSTEM_INFO,
+        };
+        let mut info = SYSTEM_INFO::default();
+        // SAFETY: GetNativeSystemInfo only fills in the struct it is given
+        let architecture = unsafe {
+            GetNativeSystemInfo(&mut info);
+            info.Anonymous.Anonymous.wProcessorArchitecture
+        };
+        match architecture {
+            PROCESSOR_ARCHITECTURE_AMD64 => Architecture::Amd64,
+            PROCESSOR_ARCHITECTURE_ARM64 => Architecture::Arm64,
+            PROCESSOR_ARCHITECTURE_INTEL => Architecture::X86,
+            PROCESSOR_ARCHITECTURE_ARM => Architecture::Arm,
+            PROCESSOR_ARCHITECTURE_IA64 => Architecture::Ia64,
+            _ => match std::env::consts::ARCH {
+                "aarch64" => Architecture::Arm64,
+                "x86" => Architecture::X86,
+                _ => Architecture::Amd64,
+            },
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Architecture::X86 => "x86",
+            Architecture::Amd64 => "amd64",
+            Architecture::Arm64 => "arm64",
+            Architecture::Arm => "arm",
+            Architecture::Ia64 => "ia64",
+        }
+    }
+}
+
+/// Flavour of the standalone restore script written by `--gen-install-script`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ScriptKind {
+    Cmd,
+    Ps1,
+}
+
+/// Field the scan results are ordered by (`--sort`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum SortKey {
+    Name,
+    Class,
+    Provider,
+    Version,
+    Date,
+    Devices,
+}
+
+/// How `scan --group` buckets the results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum GroupBy {
+    Class,
+    Provider,
+}
+
+/// What backup does when a package folder name is already taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum CollisionStrategy {
+    /// Append " (2)", " (3)", ... to the folder name
+    Rename,
+    /// Keep the first package and skip the later one
+    Skip,
+    /// Delete the existing folder and export into it again
+    Overwrite,
+}
+
+/// How exported packages are arranged in the backup folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum PackageLayout {
+    /// `<Class>\<DeviceName>_<Version> Package`, the backup default
+    ByClass,
+    /// `<Class>_<Provider>_<Version>` folders side by side, the export --files default
+    Flat,
+}
+
+impl PackageLayout {
+    /// Folder name (not yet made unique) of the package that `driver`, the first device
+    /// using it, was exported for; `device_class` is the class after `--class-map`
+    fn folder_name(self, device_class: &str, driver: &PnPSignedDriver) -> String {
+        let version = driver.driver_version.as_deref().unwrap_or("Unknown_Version");
+        let name = match self {
+            PackageLayout::ByClass => format!("{}_{} Package", driver.device_name.as_deref().unwrap_or("Unknown_Device"), version)
+                .chars()
+                .map(|c| if c.is_alphanumeric() || c == ' ' || c == '.' || c == '-' || c == '_' || c == '(' || c == ')' { c } else { '_' })
+                .collect::<String>(),
+            PackageLayout::Flat => format!("{}_{}_{}", device_class, driver.driver_provider_name.as_deref().unwrap_or("Unknown"), version)
+                .chars()
+                .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '_' })
+                .collect::<String>(),
+        };
+        safe_folder_name(name)
+    }
+}
+
+/// What `backup_drivers` writes, taken from the `backup` or the `export --files` options
+#[derive(Debug, PartialEq)]
+struct PackageExportOptions {
+    /// Folder that gets the `drivers_<timestamp>` backup folder
+    output: PathBuf,
+    layout: PackageLayout,
+    verbose: bool,
+    /// Write the INF summary as `all_drivers.csv`; export writes its inventory there instead
+    summary_csv: bool,
+    /// Write a `README.txt` into each class folder
+    readmes: bool,
+}
+
+impl PackageExportOptions {
+    /// Options of a `backup` or `export --files` command, `None` for any other command
+    fn from_command(command: &Commands) -> Option<Self> {
+        match command {
+            Commands::Backup { output, layout, verbose, no_summary_csv, no_readme, .. } => Some(Self {
+                output: output.clone(),
+                layout: *layout,
+                verbose: *verbose > 0,
+                summary_csv: !no_summary_csv,
+                readmes: !no_readme,
+            }),
+            Commands::Export { output, layout, verbose, files: true, .. } => Some(Self {
+                // --files treats a .csv output as a file name and exports next to it
+                output: if output.extension().is_some_and(|extension| extension == "csv") {
+                    output.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf()
+                } else {
+                    output.clone()
+                },
+                layout: *layout,
+                verbose: *verbose > 0,
+                summary_csv: false,
+                readmes: false,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Device classes where vendors commonly repackage Windows' own drivers.
+/// `--exclude-generic` only ever drops drivers from these classes.
+const GENERIC_DEVICE_CLASSES: [&str; 4] = ["USB", "System", "HIDClass", "HDC"];
+
+fn is_generic_class(device_class: Option<&str>) -> bool {
+    device_class
+        .map(|class| GENERIC_DEVICE_CLASSES.iter().any(|generic| generic.eq_ignore_ascii_case(class)))
+        .unwrap_or(false)
+}
+
+/// Properties read from `Win32_PnPSignedDriver`; keep in sync with `PnPSignedDriver`
+const PNP_SIGNED_DRIVER_FIELDS: &str =
+    "ClassGuid, Description, DeviceClass, DeviceName, DriverDate, DriverProviderName, DriverVersion, InfName, HardwareID, DeviceID, Location";
+
 // Original driver struct
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename = "Win32_PnPSignedDriver")]
 struct PnPSignedDriver {
     #[serde(rename = "ClassGuid")]
@@ -76,1455 +361,9201 @@ struct PnPSignedDriver {
 
     #[serde(rename = "DeviceID")]
     device_id: Option<String>,
+
+    #[serde(rename = "Location")]
+    location: Option<String>,
 }
 
-struct DriverBackup {
-    wmi_con: WMIConnection,
-    args: Args,
+/// Backup grouping: device class -> OEM INF -> indexes into the driver list
+type DriverGroups = BTreeMap<String, BTreeMap<String, Vec<usize>>>;
+
+/// A backup package whose folder is ready, waiting for its `pnputil /export-driver`
+struct PlannedExport<'a> {
+    oem_inf: &'a String,
+    drivers: Vec<&'a PnPSignedDriver>,
+    folder_name: String,
+    dir: PathBuf,
+    original_inf: Option<String>,
 }
 
-impl DriverBackup {
-    fn new(args: Args) -> Result<Self> {
-        // Validate administrative privileges
-        Self::check_admin_privileges()?;
+/// Result of one `DriverBackup::export_package` call
+type ExportStatus = std::io::Result<(std::process::Output, ExportRecord)>;
 
-        // Validate output directory path for backup commands
-        if let Some(Commands::Backup { output, .. }) = &args.command {
-            Self::validate_output_directory(output)?;
-        }
+/// Properties read from `Win32_PnPEntity`; keep in sync with `PnPEntity`
+const PNP_ENTITY_FIELDS: &str = "PNPDeviceID, HardwareID, CompatibleID";
 
-        let com_con = COMLibrary::new().context("Failed to initialize COM library")?;
-        let wmi_con = WMIConnection::new(com_con.into()).context("Failed to create WMI connection")?;
+/// Extra `Win32_PnPEntity` properties read for `export --status`
+const PNP_ENTITY_STATUS_FIELDS: &str = "Present, Status, ConfigManagerErrorCode";
 
-        Ok(Self { wmi_con, args })
+/// Device node joined to `PnPSignedDriver` by `DeviceID` for the export inventory
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename = "Win32_PnPEntity")]
+struct PnPEntity {
+    #[serde(rename = "PNPDeviceID")]
+    pnp_device_id: Option<String>,
+
+    /// Most specific first; `PnPSignedDriver` only carries the first of these
+    #[serde(rename = "HardwareID")]
+    hardware_ids: Option<Vec<String>>,
+
+    #[serde(rename = "CompatibleID")]
+    compatible_ids: Option<Vec<String>>,
+
+    #[serde(rename = "Present")]
+    present: Option<bool>,
+
+    #[serde(rename = "Status")]
+    status: Option<String>,
+
+    #[serde(rename = "ConfigManagerErrorCode")]
+    problem_code: Option<u32>,
+}
+
+impl PnPEntity {
+    /// Hardware IDs followed by compatible IDs, in Windows' ranking order
+    fn device_ids(&self) -> impl Iterator<Item = &str> {
+        self.hardware_ids.iter().flatten()
+            .chain(self.compatible_ids.iter().flatten())
+            .map(String::as_str)
     }
 
-    /// Check if the program is running with administrative privileges
-    fn check_admin_privileges() -> Result<()> {
-        let test_path = Path::new("C:\\Windows\\Temp\\driver_backup_admin_test");
-        match fs::write(test_path, "test") {
-            Ok(_) => {
-                let _ = fs::remove_file(test_path);
-                Ok(())
-            }
-            Err(_) => anyhow::bail!(
-                "This program requires administrative privileges to access driver information. \
-                 Please run as Administrator."
-            ),
-        }
+    /// Bus enumerator the device was found on, e.g. `PCI` for `PCI\VEN_8086&...`
+    fn bus(&self) -> Option<&str> {
+        let id = self.pnp_device_id.as_deref()?;
+        id.split('\\').next().filter(|bus| !bus.is_empty() && bus.len() < id.len())
     }
+}
 
-    /// Validate that the output directory exists or can be created
-    fn validate_output_directory(output: &PathBuf) -> Result<()> {
-        if output.exists() && !output.is_dir() {
-            anyhow::bail!("Output path exists but is not a directory: {}", output.display());
-        }
+/// Properties read from `Win32_PrinterDriver`; keep in sync with `PrinterDriver`
+const PRINTER_DRIVER_FIELDS: &str = "Name, Version, SupportedPlatform, DriverPath, DataFile, ConfigFile, DependentFiles";
 
-        if !output.exists() {
-            fs::create_dir_all(output)
-                .with_context(|| format!("Failed to create output directory: {}", output.display()))?;
-        }
+/// Installed printer driver, which `Win32_PnPSignedDriver` often doesn't list
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename = "Win32_PrinterDriver")]
+struct PrinterDriver {
+    /// `<driver name>,<version>,<environment>`
+    #[serde(rename = "Name")]
+    name: Option<String>,
 
-        // Test write permissions
-        let test_file = output.join("write_test.tmp");
-        fs::write(&test_file, "test")
-            .with_context(|| format!("Cannot write to output directory: {}", output.display()))?;
-        fs::remove_file(&test_file).ok();
+    #[serde(rename = "Version")]
+    version: Option<u32>,
 
-        Ok(())
-    }
+    #[serde(rename = "SupportedPlatform")]
+    environment: Option<String>,
 
-    /// Get all signed drivers from WMI
-    async fn get_drivers(&self) -> Result<Vec<PnPSignedDriver>> {
-        let drivers: Vec<PnPSignedDriver> = self.wmi_con.query()
-            .context("Failed to query WMI for PnP signed drivers")?;
+    #[serde(rename = "DriverPath")]
+    driver_path: Option<String>,
 
-        Ok(drivers)
-    }
+    #[serde(rename = "DataFile")]
+    data_file: Option<String>,
 
-    /// Check if a driver is from Microsoft
-    fn is_microsoft_driver(&self, driver: &PnPSignedDriver) -> bool {
-        if let Some(ref provider) = driver.driver_provider_name {
-            provider.to_lowercase().contains("microsoft")
-        } else {
-            false
-        }
+    #[serde(rename = "ConfigFile")]
+    config_file: Option<String>,
+
+    #[serde(rename = "DependentFiles")]
+    dependent_files: Option<Vec<String>>,
+}
+
+impl PrinterDriver {
+    /// Driver name without the version and environment suffix
+    fn driver_name(&self) -> &str {
+        let name = self.name.as_deref().unwrap_or("Unknown");
+        name.split(',').next().unwrap_or(name)
     }
 
-    /// Filter out Microsoft drivers, keeping only third-party drivers
-    fn filter_non_microsoft_drivers(&self, drivers: Vec<PnPSignedDriver>) -> Vec<PnPSignedDriver> {
-        drivers.into_iter()
-            .filter(|driver| !self.is_microsoft_driver(driver))
+    /// Every file the spooler loads for this driver
+    fn files(&self) -> Vec<&str> {
+        [&self.driver_path, &self.data_file, &self.config_file]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .chain(self.dependent_files.iter().flatten().map(String::as_str))
             .collect()
     }
+}
 
-    /// Create the main backup directory structure
-    fn create_base_backup_directory(&self, output: &PathBuf) -> Result<PathBuf> {
-        let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
-        let backup_dir = output.join(format!("drivers_{}", timestamp));
+/// Properties read from `Win32_SystemDriver`; keep in sync with `SystemDriver`
+const SYSTEM_DRIVER_FIELDS: &str = "Name, State, StartMode, PathName";
 
-        fs::create_dir_all(&backup_dir)
-            .with_context(|| format!("Failed to create backup directory: {}", backup_dir.display()))?;
+/// Kernel service, for `--services`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename = "Win32_SystemDriver")]
+struct SystemDriver {
+    #[serde(rename = "Name")]
+    name: Option<String>,
 
-        Ok(backup_dir)
+    #[serde(rename = "State")]
+    state: Option<String>,
+
+    /// `Boot`, `System`, `Auto`, `Manual` or `Disabled`
+    #[serde(rename = "StartMode")]
+    start_mode: Option<String>,
+
+    #[serde(rename = "PathName")]
+    path_name: Option<String>,
+
+    /// OEM INF of the package that installed the binary, from `DriverBackup::service_owner`
+    #[serde(skip)]
+    oem_inf: Option<String>,
+}
+
+/// Driver update offered by Windows Update, for `export --compare-to-online`
+#[derive(Debug, Clone)]
+struct OnlineDriver {
+    title: Option<String>,
+    hardware_id: Option<String>,
+    /// `YYYYMMDD`; Windows Update reports a driver date but no version
+    driver_date: Option<String>,
+}
+
+impl OnlineDriver {
+    /// `title (YYYY-MM-DD)`
+    fn summary(&self) -> String {
+        format!("{} ({})", self.title.as_deref().unwrap_or("Unknown"), format_driver_date(self.driver_date.as_deref(), false))
     }
+}
 
-    /// Format driver date to a readable format
-    fn format_driver_date(&self, driver_date: &Option<String>) -> String {
-        match driver_date {
-            Some(date_str) => {
-                if date_str.len() >= 8 {
-                    if date_str[0..8].chars().all(|c| c.is_ascii_digit()) {
-                        let year = &date_str[0..4];
-                        let month = &date_str[4..6];
-                        let day = &date_str[6..8];
-                        if let (Ok(month_num), Ok(day_num)) = (month.parse::<u32>(), day.parse::<u32>()) {
-                            if month_num >= 1 && month_num <= 12 && day_num >= 1 && day_num <= 31 {
-                                return format!("{}-{}-{}", year, month, day);
-                            }
-                        }
-                    }
-                    date_str.clone()
-                } else {
-                    date_str.clone()
-                }
-            }
-            None => "Unknown".to_string()
-        }
+impl SystemDriver {
+    /// Boot and System start drivers load before Windows can recover from a broken one
+    fn is_boot_critical(&self) -> bool {
+        matches!(self.start_mode.as_deref().map(str::to_lowercase).as_deref(), Some("boot" | "system"))
     }
 
-    /// Extract OEM INF name from driver
-    fn extract_oem_inf_name(&self, inf_name: &str) -> Option<String> {
-        let inf_lower = inf_name.to_lowercase();
-        if inf_lower.starts_with("oem") && inf_lower.ends_with(".inf") {
-            // Validate characters
-            if inf_lower.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_') {
-                Some(inf_lower)
-            } else {
-                None
-            }
-        } else {
-            None
+    /// `name (start mode, state)`
+    fn summary(&self) -> String {
+        format!("{} ({}, {})",
+            self.name.as_deref().unwrap_or("Unknown"),
+            self.start_mode.as_deref().unwrap_or("Unknown"),
+            self.state.as_deref().unwrap_or("Unknown"))
+    }
+}
+
+/// Original INF name from a path inside the DriverStore, e.g.
+/// `\SystemRoot\System32\DriverStore\FileRepository\iastorvd.inf_amd64_0123abcd\iaStorVD.sys` -> `iastorvd.inf`
+fn driver_store_original_inf(path: &str) -> Option<String> {
+    let path = path.to_lowercase().replace('/', "\\");
+    let start = path.find("\\filerepository\\")? + "\\filerepository\\".len();
+    let package = path[start..].split('\\').next()?;
+    let end = package.find(".inf_")? + ".inf".len();
+    Some(package[..end].to_string())
+}
+
+/// Optional per-device data joined into the `export` inventory
+struct InventoryEnrichment {
+    /// `Win32_PnPEntity` rows keyed by `device_instance_key`
+    entities: HashMap<String, PnPEntity>,
+    /// Add the `--status` columns
+    status: bool,
+    /// `--file-versions`: service binary version keyed by lowercase OEM INF name
+    file_versions: Option<HashMap<String, String>>,
+    /// Host name for the leading `Computer` column of `--computer` inventories
+    computer: Option<String>,
+    /// `--printers`: listed in their own section after the inventory
+    printers: Option<Vec<PrinterDriver>>,
+    /// `--services`: kernel services, flagged per collection and listed after the inventory
+    services: Option<Vec<SystemDriver>>,
+    /// `--compare-to-online`: drivers Windows Update offers, matched to devices by hardware ID
+    online_drivers: Option<Vec<OnlineDriver>>,
+    /// Group collections on `normalize_driver_version` instead of the exact version
+    strip_version_suffix: bool,
+}
+
+/// `--username` for `export --computer`, logged on with `LOGON32_LOGON_NEW_CREDENTIALS`
+/// so the token only carries the credentials for outgoing connections and the
+/// password isn't kept once the logon is done
+struct RemoteCredential {
+    username: String,
+    token: windows::Win32::Foundation::HANDLE,
+}
+
+impl RemoteCredential {
+    /// Ask for the password once on the console, without echoing it, and log on with it.
+    ///
+    /// `DOMAIN\user` is split into its parts; `user@domain` is passed whole as a UPN.
+    fn prompt(username: String) -> Result<Self> {
+        use windows::core::HSTRING;
+        use windows::Win32::Foundation::HANDLE;
+        use windows::Win32::Security::{LogonUserW, LOGON32_LOGON_NEW_CREDENTIALS, LOGON32_PROVIDER_WINNT50};
+
+        let password = Self::read_password(&format!("Password for {}: ", username))?;
+        if password.is_empty() {
+            anyhow::bail!("No password was entered for {}", username);
+        }
+        let (domain, user) = match username.split_once('\\') {
+            Some((domain, user)) => (HSTRING::from(domain), HSTRING::from(user)),
+            None => (HSTRING::new(), HSTRING::from(username.as_str())),
+        };
+        let mut token = HANDLE::default();
+        // SAFETY: all strings outlive the call, and `token` is closed in `Drop`
+        unsafe {
+            LogonUserW(&user, &domain, &HSTRING::from(password), LOGON32_LOGON_NEW_CREDENTIALS, LOGON32_PROVIDER_WINNT50, &mut token)
         }
+        .with_context(|| format!("Failed to log on as {}", username))?;
+        Ok(Self { username, token })
     }
 
-    /// Backup drivers to the specified directory
-    async fn backup_drivers(&self, drivers: Vec<PnPSignedDriver>) -> Result<()> {
-        let output_path = match &self.args.command {
-            Some(Commands::Backup { output, .. }) => output.clone(),
-            _ => PathBuf::from("driver_backup")
+    /// Read a line from stdin with console echo turned off. Piped input is read as is.
+    fn read_password(prompt: &str) -> Result<String> {
+        use windows::Win32::System::Console::{
+            GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE, ENABLE_ECHO_INPUT, STD_INPUT_HANDLE,
         };
-        let base_backup_dir = self.create_base_backup_directory(&output_path)?;
-        let mut backed_up_count = 0;
-        let mut failed_count = 0;
-        let mut driver_info = Vec::new();
 
-        // Group drivers by Device Class, then by INF file name
-        let mut drivers_by_class_inf: HashMap<String, HashMap<String, Vec<PnPSignedDriver>>> = HashMap::new();
+        eprint!("{}", prompt);
+        let mut mode = CONSOLE_MODE::default();
+        // SAFETY: only the echo flag of this process's console input changes, and it's restored below
+        let console = unsafe {
+            GetStdHandle(STD_INPUT_HANDLE).ok().filter(|&input| {
+                GetConsoleMode(input, &mut mode).is_ok() && SetConsoleMode(input, mode & !ENABLE_ECHO_INPUT).is_ok()
+            })
+        };
+        let mut password = String::new();
+        let read = std::io::stdin().read_line(&mut password);
+        if let Some(input) = console {
+            // SAFETY: restores the mode read above on the same handle
+            let _ = unsafe { SetConsoleMode(input, mode) };
+            eprintln!();
+        }
+        read.context("Failed to read the password")?;
+        Ok(password.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+impl Drop for RemoteCredential {
+    fn drop(&mut self) {
+        // SAFETY: the token came from `LogonUserW` and is closed only here
+        let _ = unsafe { windows::Win32::Foundation::CloseHandle(self.token) };
+    }
+}
 
-        for driver in drivers {
-            if let Some(inf_name) = &driver.inf_name {
-                if let Some(oem_inf) = self.extract_oem_inf_name(inf_name) {
-                    let device_class = driver.device_class.as_deref().unwrap_or("Unknown_Class").to_string();
-                    
-                    drivers_by_class_inf
-                        .entry(device_class)
-                        .or_default()
-                        .entry(oem_inf)
-                        .or_default()
-                        .push(driver);
-                } else if matches!(self.args.command, Some(Commands::Backup { verbose, .. }) if verbose) {
-                    println!("Skipping non-OEM INF: {}", inf_name);
-                }
-            }
+/// Key used to match `PnPSignedDriver::device_id` against `PnPEntity::pnp_device_id`.
+///
+/// Instance IDs are compared case-insensitively, and `/` or doubled (escaped)
+/// backslashes are treated as a single `\` separator.
+fn device_instance_key(id: &str) -> String {
+    let mut key = String::with_capacity(id.len());
+    for c in id.trim().chars() {
+        let c = if c == '/' { '\\' } else { c };
+        if c == '\\' && key.ends_with('\\') {
+            continue;
         }
+        key.extend(c.to_uppercase());
+    }
+    key
+}
 
-        // Sort by device class for consistent order
-        let mut sorted_class_keys: Vec<_> = drivers_by_class_inf.keys().cloned().collect();
-        sorted_class_keys.sort();
+/// `Present`, `Status` and `Problem Code` cells for one signed driver's device
+fn device_status_cells(driver: &PnPSignedDriver, entities: &HashMap<String, PnPEntity>) -> [String; 3] {
+    let entity = driver.device_id.as_deref()
+        .and_then(|id| entities.get(&device_instance_key(id)));
+    match entity {
+        Some(entity) => [
+            match entity.present {
+                Some(true) => "yes".to_string(),
+                Some(false) => "no".to_string(),
+                None => "unknown".to_string(),
+            },
+            entity.status.clone().unwrap_or_else(|| "unknown".to_string()),
+            entity.problem_code.map(|code| code.to_string()).unwrap_or_default(),
+        ],
+        None => ["absent".to_string(), "absent".to_string(), "absent".to_string()],
+    }
+}
 
-        for device_class in sorted_class_keys {
-            if let Some(infs_in_class) = drivers_by_class_inf.get(&device_class) {
-                // Create device class folder
-                let class_folder_name = device_class
-                    .chars()
-                    .map(|c| if c.is_alphanumeric() || c == ' ' || c == '-' { c } else { '_' })
-                    .collect::<String>();
-                let class_backup_dir = base_backup_dir.join(&class_folder_name);
+/// Operating system the backup was taken on
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename(deserialize = "Win32_OperatingSystem"))]
+struct OsInfo {
+    #[serde(rename(deserialize = "Caption"))]
+    caption: Option<String>,
 
-                if matches!(self.args.command, Some(Commands::Backup { verbose, .. }) if verbose) {
-                    println!("Processing Device Class: {}", device_class);
-                    println!("  Class Folder: {}", class_folder_name);
-                    println!("  Number of driver packages in this class: {}", infs_in_class.len());
-                    println!();
-                }
+    #[serde(rename(deserialize = "Version"))]
+    version: Option<String>,
 
-                if let Some(Commands::Backup { dry_run, .. }) = &self.args.command {
-                    if !dry_run {
-                        fs::create_dir_all(&class_backup_dir)
-                            .with_context(|| format!("Failed to create class directory: {}", class_backup_dir.display()))?;
-                    }
-                }
+    #[serde(rename(deserialize = "BuildNumber"))]
+    build_number: Option<String>,
 
-                // Sort INF names within this class
-                let mut sorted_inf_keys: Vec<_> = infs_in_class.keys().cloned().collect();
-                sorted_inf_keys.sort();
-
-                for oem_inf in sorted_inf_keys {
-                    if let Some(drivers_for_package) = infs_in_class.get(&oem_inf) {
-                        // Get the primary device name and version for folder naming
-                        let primary_device_name = drivers_for_package
-                            .first()
-                            .and_then(|d| d.device_name.as_deref())
-                            .unwrap_or("Unknown_Device");
-                        
-                        let driver_version = drivers_for_package
-                            .first()
-                            .and_then(|d| d.driver_version.as_deref())
-                            .unwrap_or("Unknown_Version");
-                        
-                        // Create folder name: "DeviceName_Version Package"
-                        let folder_name = format!("{}_{} Package", primary_device_name, driver_version)
-                            .chars()
-                            .map(|c| if c.is_alphanumeric() || c == ' ' || c == '.' || c == '-' || c == '_' || c == '(' || c == ')' { c } else { '_' })
-                            .collect::<String>();
-
-                        let driver_backup_dir = class_backup_dir.join(&folder_name);
-
-                        if matches!(self.args.command, Some(Commands::Backup { verbose, .. }) if verbose) {
-                            println!("  Processing driver package: {} v{} ({})", primary_device_name, driver_version, oem_inf);
-                            println!("    Folder: {}", folder_name);
-                            println!("    Number of devices in this package: {}", drivers_for_package.len());
-                            println!();
-                            for (index, driver) in drivers_for_package.iter().enumerate() {
-                                println!("      {}. Device: {}", index + 1, driver.device_name.as_deref().unwrap_or("Unknown"));
-                                println!("         INF: {}", driver.inf_name.as_deref().unwrap_or("Unknown"));
-                                println!("         Hardware ID: {}", driver.hardware_id.as_deref().unwrap_or("Unknown"));
-                                println!("         Device ID: {}", driver.device_id.as_deref().unwrap_or("Unknown"));
-                                println!("         Description: {}", driver.description.as_deref().unwrap_or("Unknown"));
-                                println!("         Provider: {}", driver.driver_provider_name.as_deref().unwrap_or("Unknown"));
-                                println!("         Version: {}", driver.driver_version.as_deref().unwrap_or("Unknown"));
-                                println!("         Date: {}", self.format_driver_date(&driver.driver_date));
-                                println!();
-                            }
-                        }
+    #[serde(rename(deserialize = "OSArchitecture"))]
+    architecture: Option<String>,
+}
 
-                        if let Some(Commands::Backup { dry_run, .. }) = &self.args.command {
-                            if !dry_run {
-                                fs::create_dir_all(&driver_backup_dir)
-                                    .with_context(|| format!("Failed to create driver directory: {}", driver_backup_dir.display()))?;
-                                if !driver_backup_dir.exists() {
-                                    anyhow::bail!("Failed to create driver directory: {}", driver_backup_dir.display());
-                                }
-                                if matches!(self.args.command, Some(Commands::Backup { verbose, .. }) if verbose) {
-                                    println!("      Created folder: {}", driver_backup_dir.display());
-                                }
+/// Make and model of the machine the backup was taken on
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename(deserialize = "Win32_ComputerSystem"))]
+struct ComputerInfo {
+    #[serde(rename(deserialize = "Manufacturer"))]
+    manufacturer: Option<String>,
 
-                                // Export the driver package (only need to export once per INF)
-                                let backup_dir_str = driver_backup_dir.to_string_lossy();
-                                if backup_dir_str.contains("..") || backup_dir_str.contains("%") {
-                                    eprintln!("Skipping export due to unsafe path: {}", backup_dir_str);
-                                    failed_count += 1;
-                                    continue;
-                                }
+    #[serde(rename(deserialize = "Model"))]
+    model: Option<String>,
+}
 
-                                if matches!(self.args.command, Some(Commands::Backup { verbose, .. }) if verbose) {
-                                    println!("        Exporting {} to {}...", oem_inf, driver_backup_dir.display());
-                                }
+/// Firmware of the machine the backup was taken on
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename(deserialize = "Win32_BIOS"))]
+struct BiosInfo {
+    #[serde(rename(deserialize = "SMBIOSBIOSVersion"))]
+    version: Option<String>,
 
-                                let status = Command::new("pnputil")
-                                    .arg("/export-driver")
-                                    .arg(&oem_inf)
-                                    .arg(&driver_backup_dir)
-                                    .output();
-
-                                match status {
-                                    Ok(output) => {
-                                        if output.status.success() {
-                                            backed_up_count += 1;
-                                            driver_info.extend(drivers_for_package.clone());
-                                            if matches!(self.args.command, Some(Commands::Backup { verbose, .. }) if verbose) {
-                                                println!("        ✓ Successfully exported: {}", oem_inf);
-                                            }
-                                        } else {
-                                            let stdout = String::from_utf8_lossy(&output.stdout);
-                                            let stderr = String::from_utf8_lossy(&output.stderr);
-                                            
-                                            eprintln!("✗ Failed to export {}:", oem_inf);
-                                            if !stdout.is_empty() {
-                                                eprintln!("  stdout: {}", stdout.trim());
-                                            }
-                                            if !stderr.is_empty() {
-                                                eprintln!("  stderr: {}", stderr.trim());
-                                            }
-                                            let exit_code = output.status.code().unwrap_or(-1);
-                                            let stderr_lower = stderr.to_lowercase();
-                                            let stdout_lower = stdout.to_lowercase();
-
-                                            if stderr_lower.contains("access") || stderr_lower.contains("denied") {
-                                                eprintln!("  → This might be a permissions issue. Try running as Administrator.");
-                                            } else if stderr_lower.contains("not found") || stderr_lower.contains("cannot find") {
-                                                eprintln!("  → Driver package might be corrupted or already removed.");
-                                            } else if stdout_lower.contains("missing or invalid target directory") || exit_code == 87 {
-                                                eprintln!("  → Path too long or invalid. Using shorter path and retrying...");
-                                            } else if stdout_lower.contains("the data is invalid") || exit_code == 13 {
-                                                eprintln!("  → This driver may be protected or corrupted. Skipping.");
-                                            }
-
-                                            failed_count += 1;
-                                        }
-                                    }
-                                    Err(e) => {
-                                        eprintln!("✗ Failed to execute pnputil for {}:", oem_inf);
-                                        eprintln!("  Error: {}", e);
-                                        eprintln!("  → Make sure pnputil is in your PATH and you have administrative privileges.");
-                                        failed_count += 1;
-                                    }
-                                }
-                            } else {
-                                backed_up_count += 1;
-                                driver_info.extend(drivers_for_package.clone());
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    /// Left out with `backup --no-serial`
+    #[serde(rename(deserialize = "SerialNumber"), skip_serializing_if = "Option::is_none")]
+    serial_number: Option<String>,
+}
 
-        println!("\nDriver export completed!");
-        println!("Successfully exported: {} driver packages", backed_up_count);
-        if failed_count > 0 {
-            println!("Failed to export: {} drivers", failed_count);
-        }
+/// Where a backup came from, recorded in the manifest, `system_info.txt` and `all_drivers.csv`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct SystemContext {
+    os: Option<OsInfo>,
+    #[serde(default)]
+    computer: Option<ComputerInfo>,
+    #[serde(default)]
+    bios: Option<BiosInfo>,
+}
 
-        if let Some(Commands::Backup { dry_run, verbose, .. }) = &self.args.command {
-            if !dry_run {
-                println!("\nScanning exported drivers to create summary...");
-                
-                // Use InfParser to scan the backup folder and create summary CSV
-                let csv_path = base_backup_dir.join("all_drivers.csv");
-                InfParser::scan_and_export(&base_backup_dir, &csv_path, *verbose)?;
-                
-                println!("\nBackup location: {}", base_backup_dir.display());
+impl SystemContext {
+    /// `Label: value` lines for the text and CSV reports; unknown parts are left out
+    fn lines(&self) -> Vec<String> {
+        let unknown = "Unknown".to_string();
+        let mut lines = Vec::new();
+        if let Some(os) = &self.os {
+            lines.push(format!("Operating system: {}", os.caption.as_ref().unwrap_or(&unknown)));
+            lines.push(format!("OS version: {} (build {})",
+                os.version.as_ref().unwrap_or(&unknown),
+                os.build_number.as_ref().unwrap_or(&unknown)));
+            lines.push(format!("Architecture: {}", os.architecture.as_ref().unwrap_or(&unknown)));
+        }
+        if let Some(computer) = &self.computer {
+            lines.push(format!("Manufacturer: {}", computer.manufacturer.as_ref().unwrap_or(&unknown)));
+            lines.push(format!("Model: {}", computer.model.as_ref().unwrap_or(&unknown)));
+        }
+        if let Some(bios) = &self.bios {
+            lines.push(format!("BIOS version: {}", bios.version.as_ref().unwrap_or(&unknown)));
+            if let Some(serial) = &bios.serial_number {
+                lines.push(format!("Serial number: {}", serial));
             }
         }
-
-        Ok(())
+        lines
     }
+}
 
-    /// Run the backup process
-    async fn run(&self) -> Result<()> {
-        println!("Starting driver export process...");
-
-        let all_drivers = self.get_drivers().await?;
+/// Built-in `backup --report-template` layout: a Markdown summary of the backup
+const DEFAULT_REPORT_TEMPLATE: &str = "# Driver Backup Report
+
+- **Created:** {{timestamp}}
+- **Backup folder:** `{{backup_path}}`
+{{#each system}}
+- {{this}}
+{{/each}}
+
+## Summary
+
+| Packages | Devices | Failed |
+|----------|---------|--------|
+| {{counts.packages}} | {{counts.devices}} | {{counts.failed}} |
+
+## Packages
+
+| Class | Provider | Version | INF | Devices |
+|-------|----------|---------|-----|---------|
+{{#each packages}}
+| {{device_class}} | {{provider}} | {{driver_version}} | {{oem_inf}} | {{device_count}} |
+{{/each}}
+";
+
+/// Current manifest.json layout version
+const MANIFEST_SCHEMA_VERSION: u32 = 3;
+
+/// Machine-readable description of a backup, written as `manifest.json`
+#[derive(Serialize, Deserialize, Debug)]
+struct BackupManifest {
+    schema_version: u32,
+    tool_version: String,
+    created: String,
+    #[serde(flatten)]
+    system: SystemContext,
+    packages: Vec<ManifestPackage>,
+    /// Packages whose export failed, with the export record of the failed attempt
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    failures: Vec<ManifestPackage>,
+}
 
-        let non_ms_drivers = self.filter_non_microsoft_drivers(all_drivers);
+/// One exported driver package in the manifest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ManifestPackage {
+    device_class: String,
+    /// Package folder relative to the backup root
+    folder: String,
+    oem_inf: String,
+    /// INF name before the driver store renamed it to `oem_inf`; identifies the package across machines
+    #[serde(default)]
+    original_inf: Option<String>,
+    driver_version: Option<String>,
+    provider: Option<String>,
+    devices: Vec<String>,
+    /// How the package was exported, for post-mortems; absent in older manifests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    export: Option<ExportRecord>,
+}
 
-        if non_ms_drivers.is_empty() {
-            println!("No non-Microsoft drivers found to export.");
-            return Ok(());
-        }
+impl ManifestPackage {
+    /// Whether this is the same package build: same original INF and driver version
+    fn same_package(&self, original_inf: Option<&str>, driver_version: Option<&str>) -> bool {
+        matches!((self.original_inf.as_deref(), original_inf), (Some(a), Some(b)) if a.eq_ignore_ascii_case(b))
+            && match (self.driver_version.as_deref(), driver_version) {
+                (Some(a), Some(b)) => compare_driver_versions(a, b).is_eq(),
+                (a, b) => a == b,
+            }
+    }
+}
 
-        self.backup_drivers(non_ms_drivers).await?;
-        Ok(())
+/// Jaro-Winkler similarity from which `--match-device` accepts a run of words as a typo of the query
+const MATCH_DEVICE_THRESHOLD: f64 = 0.9;
+
+/// `--match-device`: whether `name` contains `query`, ignoring case, or has a run of as many
+/// words as the query that is at least `MATCH_DEVICE_THRESHOLD` similar to it
+/// (`gefroce rtx` matches `NVIDIA GeForce RTX 3080`)
+fn device_name_matches(name: &str, query: &str) -> bool {
+    let name = name.to_lowercase();
+    let query = query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    if query.is_empty() || name.contains(&query) {
+        return !query.is_empty();
     }
+    let words: Vec<&str> = name.split_whitespace().collect();
+    let window = query.split(' ').count().clamp(1, words.len().max(1));
+    words.windows(window).any(|run| strsim::jaro_winkler(&run.join(" "), &query) >= MATCH_DEVICE_THRESHOLD)
+}
 
-    /// Build lookup table for OEM INF to actual INF name mapping
-    fn build_inf_lookup() -> HashMap<String, String> {
-        let mut lookup = HashMap::new();
-        
-        println!("Building INF name lookup table...");
-        
-        let output = Command::new("pnputil")
-            .arg("/enum-drivers")
-            .output();
-        
-        if let Ok(result) = output {
-            let stdout = String::from_utf8_lossy(&result.stdout);
-            let mut current_oem: Option<String> = None;
-            let mut current_original: Option<String> = None;
-            
-            for line in stdout.lines() {
-                let line_lower = line.to_lowercase();
-                
-                if line_lower.contains("published name") {
-                    if let Some(val) = line.split(':').nth(1) {
-                        current_oem = Some(val.trim().to_lowercase());
-                    }
-                }
-                if line_lower.contains("original name") {
-                    if let Some(val) = line.split(':').nth(1) {
-                        current_original = Some(val.trim().to_string());
-                    }
-                }
-                
-                // Save mapping when we have both
-                if let (Some(ref oem), Some(ref original)) = (&current_oem, &current_original) {
-                    lookup.insert(oem.clone(), original.clone());
-                    current_oem = None;
-                    current_original = None;
-                }
-            }
+/// Bytes of stdout and stderr kept in an `ExportRecord`
+const EXPORT_OUTPUT_LIMIT: usize = 2048;
+
+/// One export command as it ran: shown by `browse` so a failed or odd export can be
+/// looked into without running pnputil again
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ExportRecord {
+    /// Full command line, e.g. `pnputil /export-driver oem12.inf "D:\backup\Net\..."`
+    command: String,
+    /// `None` when the process was ended without one
+    exit_code: Option<i32>,
+    duration_ms: u64,
+    /// The first `EXPORT_OUTPUT_LIMIT` bytes, with a marker when cut
+    stdout: String,
+    stderr: String,
+}
+
+impl ExportRecord {
+    /// Run `command`, printing its command line first with `verbose`, and record the run
+    fn run(command: &mut Command, verbose: bool) -> std::io::Result<(std::process::Output, Self)> {
+        let command_line = command_line(command);
+        if verbose {
+            println!("        > {}", command_line);
         }
-        
-        println!("Found {} INF mappings", lookup.len());
-        lookup
+        let started = Instant::now();
+        let output = command.output()?;
+        let record = Self::new(command_line, &output, started.elapsed());
+        Ok((output, record))
     }
 
-    /// Export WMI driver info to CSV, grouped by driver version (collection)
-    fn export_wmi_drivers_csv_static(drivers: &[PnPSignedDriver], output_path: &Path, verbose: bool) -> Result<()> {
-        let escape_csv = |s: &str| -> String {
-            if s.contains(',') || s.contains('"') || s.contains('\n') {
-                format!("\"{}\"", s.replace('"', "\"\""))
+    fn new(command: String, output: &std::process::Output, duration: Duration) -> Self {
+        ExportRecord {
+            command,
+            exit_code: output.status.code(),
+            duration_ms: duration.as_millis() as u64,
+            stdout: truncate_output(&output.stdout),
+            stderr: truncate_output(&output.stderr),
+        }
+    }
+}
+
+/// Command line of `command` as it would be typed, quoting arguments with spaces
+fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|part| {
+            let part = part.to_string_lossy();
+            if part.is_empty() || part.contains([' ', '\t']) {
+                format!("\"{}\"", part)
             } else {
-                s.to_string()
+                part.into_owned()
             }
-        };
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Trimmed process output cut to `EXPORT_OUTPUT_LIMIT` bytes (at a character boundary)
+fn truncate_output(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim();
+    if text.len() <= EXPORT_OUTPUT_LIMIT {
+        return text.to_string();
+    }
+    let mut end = EXPORT_OUTPUT_LIMIT;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n[... truncated, {} bytes in total]", &text[..end], text.len())
+}
+
+/// `diff_manifest.json` of a `backup --diff-base` backup: the base it was made against and the
+/// unchanged base packages it relies on instead of exporting them again
+#[derive(Serialize, Deserialize, Debug)]
+struct DiffManifest {
+    schema_version: u32,
+    created: String,
+    /// Base backup folder as given to `--diff-base`
+    base: PathBuf,
+    /// `created` of the base manifest, to recognise the base at restore time
+    base_created: String,
+    /// Packages `restore --base --diff` takes from the base; folders are relative to the base
+    base_packages: Vec<ManifestPackage>,
+}
 
-        // Build INF lookup table once
-        let inf_lookup = Self::build_inf_lookup();
+/// `SystemRestore.CreateRestorePoint` EventType opening a restore point before drivers change
+const BEGIN_SYSTEM_CHANGE: i32 = 100;
+/// EventType closing it once they have
+const END_SYSTEM_CHANGE: i32 = 101;
+
+/// Settings for `restore` and `restore --rollback`
+struct RestoreOptions {
+    /// Don't export the drivers being replaced
+    no_snapshot: bool,
+    dry_run: bool,
+    verbose: bool,
+    /// Create a System Restore point before changing drivers
+    restore_point: bool,
+    /// Stop instead of warning when no restore point could be created
+    require_restore_point: bool,
+    /// `--force-arch`: install packages for this architecture instead of the machine's
+    force_arch: Option<Architecture>,
+}
 
-        // Group drivers by driver version (collection)
-        let mut grouped: HashMap<String, Vec<&PnPSignedDriver>> = HashMap::new();
-        for driver in drivers {
-            let version = driver.driver_version.as_deref().unwrap_or("Unknown").to_string();
-            grouped.entry(version).or_default().push(driver);
+/// Written as `rollback.json` into a `pre_restore_snapshot_<timestamp>` folder by `restore`
+#[derive(Serialize, Deserialize, Debug)]
+struct RollbackManifest {
+    created: String,
+    /// The backup that was restored
+    backup: PathBuf,
+    devices: Vec<RollbackDevice>,
+    /// OEM INFs the restore added to the DriverStore; `--rollback` deletes these
+    installed_infs: Vec<String>,
+}
+
+/// A device whose driver the restore changes, and the driver it had before
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RollbackDevice {
+    device_id: String,
+    device_name: Option<String>,
+    /// The hardware ID the backup matched on
+    hardware_id: String,
+    /// INF in use before the restore; inbox INFs (not `oemNN.inf`) aren't snapshotted
+    previous_inf: Option<String>,
+    previous_version: Option<String>,
+    restored_version: Option<String>,
+    /// Snapshot subfolder holding the previous package
+    folder: Option<String>,
+}
+
+/// Inspect CSV columns in default order: (`--csv-columns` name, header)
+const INSPECT_CSV_COLUMNS: [(&str, &str); 14] = [
+    ("device_name", "Device Name"),
+    ("driver_version", "Driver Version"),
+    ("driver_date", "Driver Date"),
+    ("hardware_id", "Hardware ID"),
+    ("inf_name", "INF Name"),
+    ("description", "Description"),
+    ("provider", "Provider"),
+    ("device_class", "Device Class"),
+    ("class_guid", "Class GUID"),
+    ("catalog_file", "Catalog File"),
+    ("manufacturer", "Manufacturer"),
+    ("signed_date", "Signed Date"),
+    ("excluded", "Excluded"),
+    ("source", "Source"),
+];
+
+/// Scan CSV columns in default order: (`--csv-columns` name, header)
+const SCAN_CSV_COLUMNS: [(&str, &str); 11] = [
+    ("inf_file", "INF File"),
+    ("device_class", "Device Class"),
+    ("provider", "Provider"),
+    ("driver_version", "Driver Version"),
+    ("driver_date", "Driver Date"),
+    ("device_count", "Device Count"),
+    ("device_names", "Device Names"),
+    ("hardware_ids", "Hardware IDs"),
+    ("folder_size_mb", "Folder Size (MB)"),
+    ("complete", "Complete"),
+    ("source", "Source"),
+];
+
+/// Read a `--class-map` file of `from=to` lines (blank lines and `#`/`;` comments
+/// are ignored). Keys are matched case-insensitively.
+fn load_class_map(path: &Path) -> Result<HashMap<String, String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read class map: {}", path.display()))?;
+
+    let mut class_map = HashMap::new();
+    for (line_number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
         }
+        match line.split_once('=') {
+            Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+                class_map.insert(from.trim().to_lowercase(), to.trim().to_string());
+            }
+            _ => anyhow::bail!("{}:{}: expected `from=to`, got '{}'", path.display(), line_number + 1, line),
+        }
+    }
+    Ok(class_map)
+}
 
-        let mut csv_content = String::new();
-        csv_content.push_str("Collection,Device Class,Provider,Driver Version,Driver Date,Device Count,Actual INFs,Device Names,Hardware IDs\n");
+/// Apply a class map; unmapped classes pass through unchanged
+fn map_device_class(class_map: &HashMap<String, String>, class: &str) -> String {
+    class_map.get(&class.to_lowercase()).cloned().unwrap_or_else(|| class.to_string())
+}
 
-        // Sort by provider then version
-        let mut sorted_keys: Vec<_> = grouped.keys().cloned().collect();
-        sorted_keys.sort();
+/// Device names Windows reserves in every folder, also when followed by an extension (`NUL.txt`)
+const RESERVED_FILE_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Make an already sanitized folder name creatable on Windows. A reserved device name gets
+/// `_dev` after its base name (`CON` -> `CON_dev`), and a name ending in a dot or space,
+/// which Windows would silently strip, gets `_dev` appended (both for `CON.`).
+fn safe_folder_name(name: String) -> String {
+    let base = name.split('.').next().unwrap_or_default();
+    let name = if RESERVED_FILE_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(base.trim_end_matches(' '))) {
+        format!("{}_dev{}", base, &name[base.len()..])
+    } else {
+        name
+    };
+    if name.is_empty() || name.ends_with(['.', ' ']) {
+        return format!("{}_dev", name);
+    }
+    name
+}
 
-        for version in &sorted_keys {
-            if let Some(drivers_for_version) = grouped.get(version) {
-                let first = drivers_for_version.first().unwrap();
-                
-                let driver_date = first.driver_date.as_ref()
-                    .map(|d| {
-                        if d.len() >= 8 && d[0..8].chars().all(|c| c.is_ascii_digit()) {
-                            format!("{}-{}-{}", &d[0..4], &d[4..6], &d[6..8])
-                        } else {
-                            d.clone()
-                        }
-                    })
-                    .unwrap_or_else(|| "Unknown".to_string());
+/// Columns to write when `--csv-columns` isn't given: everything, except that the
+/// Source column is only useful when several inputs are merged
+fn default_csv_columns(available: &[(&str, &str)], multiple_inputs: bool) -> Option<Vec<usize>> {
+    if multiple_inputs {
+        return None;
+    }
+    Some((0..available.len()).filter(|&index| available[index].0 != "source").collect())
+}
 
-                // Collect unique actual INF names
-                let mut actual_infs: Vec<String> = drivers_for_version.iter()
-                    .filter_map(|d| {
-                        let oem = d.inf_name.as_deref()?.to_lowercase();
-                        Some(inf_lookup.get(&oem).cloned().unwrap_or(oem))
-                    })
-                    .collect();
-                actual_infs.sort();
-                actual_infs.dedup();
+/// Map `--csv-columns` names to column indexes, keeping the requested order
+fn resolve_csv_columns(requested: &[String], available: &[(&str, &str)]) -> Result<Vec<usize>> {
+    requested.iter()
+        .map(|name| {
+            let name = name.trim().to_lowercase();
+            available.iter()
+                .position(|(key, _)| *key == name)
+                .ok_or_else(|| {
+                    let known: Vec<&str> = available.iter().map(|(key, _)| *key).collect();
+                    anyhow::anyhow!("Unknown CSV column '{}'. Available columns: {}", name, known.join(", "))
+                })
+        })
+        .collect()
+}
 
-                // Collect device names and hardware IDs
-                let device_names: Vec<String> = drivers_for_version.iter()
-                    .filter_map(|d| d.device_name.clone())
-                    .collect();
-                let hardware_ids: Vec<String> = drivers_for_version.iter()
-                    .filter_map(|d| d.hardware_id.clone())
-                    .collect();
+/// SHA-256 of a file as lowercase hex, streamed so large binaries aren't loaded at once
+fn sha256_file(path: &Path) -> Result<String> {
+    use sha2::{Digest, Sha256};
 
-                // Create collection name from provider + version
-                let provider = first.driver_provider_name.as_deref().unwrap_or("Unknown");
-                let collection_name = format!("{} {} Package", provider, version);
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
-                csv_content.push_str(&format!(
-                    "{},{},{},{},{},{},{},{},{}\n",
-                    escape_csv(&collection_name),
-                    escape_csv(first.device_class.as_deref().unwrap_or("Unknown")),
-                    escape_csv(provider),
-                    escape_csv(version),
-                    escape_csv(&driver_date),
-                    drivers_for_version.len(),
-                    escape_csv(&actual_infs.join("; ")),
-                    escape_csv(&device_names.join("; ")),
-                    escape_csv(&hardware_ids.join("; ")),
-                ));
-            }
-        }
+/// Small CSV builder shared by all exporters so quoting rules stay consistent
+struct CsvWriter {
+    delimiter: char,
+    /// Indexes of the fields to output, in order; `None` writes every field
+    columns: Option<Vec<usize>>,
+    content: String,
+}
 
-        fs::write(output_path, &csv_content)
-            .with_context(|| format!("Failed to write CSV file: {}", output_path.display()))?;
+impl CsvWriter {
+    fn new(delimiter: char) -> Self {
+        Self { delimiter, columns: None, content: String::new() }
+    }
 
-        println!("CSV created: {}", output_path.display());
-        println!("Total collections: {}", grouped.len());
-        println!("Total devices: {}", drivers.len());
+    /// Only write the selected fields (by index) of every row, in the given order
+    fn with_columns(mut self, columns: Option<&[usize]>) -> Self {
+        self.columns = columns.map(|c| c.to_vec());
+        self
+    }
 
-        if verbose {
-            println!("\nDriver collections exported:");
-            for version in &sorted_keys {
-                if let Some(drivers_for_version) = grouped.get(version) {
-                    let first = drivers_for_version.first().unwrap();
-                    let provider = first.driver_provider_name.as_deref().unwrap_or("Unknown");
-                    println!("\n  {} {} - {} devices", provider, version, drivers_for_version.len());
-                    for driver in drivers_for_version {
-                        let oem = driver.inf_name.as_deref().unwrap_or("unknown").to_lowercase();
-                        let actual = inf_lookup.get(&oem).map(|s| s.as_str()).unwrap_or(&oem);
-                        println!("    - {} | {} | {}", 
-                            driver.device_name.as_deref().unwrap_or("Unknown"),
-                            driver.hardware_id.as_deref().unwrap_or("Unknown"),
-                            actual);
-                    }
-                }
-            }
+    /// Quote a field if it contains the delimiter, a quote, or a line break
+    fn escape(&self, s: &str) -> String {
+        if s.contains(self.delimiter) || s.contains('"') || s.contains('\n') || s.contains('\r') {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_string()
         }
+    }
 
-        Ok(())
+    /// Append one row; fields are escaped and joined with the delimiter
+    fn write_row<S: AsRef<str>>(&mut self, fields: &[S]) {
+        let selected: Vec<&str> = match &self.columns {
+            Some(columns) => columns.iter().filter_map(|&i| fields.get(i)).map(|f| f.as_ref()).collect(),
+            None => fields.iter().map(|f| f.as_ref()).collect(),
+        };
+        let row = selected.iter()
+            .map(|field| self.escape(field))
+            .collect::<Vec<_>>()
+            .join(&self.delimiter.to_string());
+        self.content.push_str(&row);
+        self.content.push('\n');
     }
-}
 
-// INF Parser for extracting driver information from INF files
-struct InfParser;
+    /// Append a `# ` comment line, e.g. to describe where the data came from
+    fn write_comment(&mut self, line: &str) {
+        self.content.push_str("# ");
+        self.content.push_str(line);
+        self.content.push('\n');
+    }
 
-impl InfParser {
-    /// Extract driver package from installer (.exe, .zip) or use folder directly
-    fn extract_or_use_path(path: &Path, verbose: bool) -> Result<(PathBuf, bool)> {
-        if path.is_dir() {
-            return Ok((path.to_path_buf(), false));
+    /// Write the accumulated CSV content to a file, or to stdout when the path is `-`
+    fn save(&self, output_path: &Path) -> Result<()> {
+        if is_stdout_path(output_path) {
+            use std::io::Write;
+            let mut stdout = std::io::stdout().lock();
+            return stdout.write_all(self.content.as_bytes())
+                .and_then(|()| stdout.flush())
+                .context("Failed to write CSV to stdout");
         }
+        write_atomic(output_path, self.content.as_bytes())
+            .with_context(|| format!("Failed to write CSV file: {}", output_path.display()))
+    }
+}
 
-        let extension = path.extension()
-            .and_then(|e| e.to_str())
-            .map(|e| e.to_lowercase())
-            .unwrap_or_default();
+/// Create the missing folders above an output file; the output checks in main don't create them
+fn create_parent_dir(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create output folder: {}", parent.display())),
+        _ => Ok(()),
+    }
+}
 
-        match extension.as_str() {
-            "exe" | "zip" | "7z" | "rar" => {
-                let temp_dir = std::env::temp_dir().join(format!("driver_inspect_{}", std::process::id()));
-                fs::create_dir_all(&temp_dir)?;
+/// Write a file through a temporary file in the same folder that is renamed over `path`
+/// once complete, so a failed or interrupted write never leaves a truncated file behind
+/// and an existing file is only replaced by a complete one.
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    create_parent_dir(path)?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut temp = tempfile::Builder::new()
+        .prefix(&format!(".{}.", file_name))
+        .suffix(".tmp")
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create a temporary file in {}", dir.display()))?;
+    temp.write_all(contents)
+        .and_then(|()| temp.as_file().sync_all())
+        .context("Failed to write the temporary file")?;
+    temp.persist(path)
+        .map_err(|e| e.error)
+        .with_context(|| format!("Failed to move the temporary file into place as {}", path.display()))?;
+    Ok(())
+}
 
-                if verbose {
-                    println!("Extracting {} to {}...", path.display(), temp_dir.display());
+/// Expand `{hostname}`, `{year}`, `{month}` and `{day}` in a backup `--output` path, so one
+/// scheduled command line sorts backups per machine and date. Other `{...}` names are an error.
+fn expand_output_template(output: &Path, now: chrono::DateTime<chrono::FixedOffset>) -> Result<PathBuf> {
+    let template = output.to_string_lossy();
+    if !template.contains('{') {
+        return Ok(output.to_path_buf());
+    }
+    let mut expanded = String::new();
+    let mut rest = template.as_ref();
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            anyhow::bail!("Unclosed '{{' in output path: {}", template);
+        };
+        let value = match rest[start + 1..start + end].to_lowercase().as_str() {
+            "hostname" => {
+                let hostname = std::env::var("COMPUTERNAME").unwrap_or_default();
+                if hostname.trim().is_empty() {
+                    anyhow::bail!("{{hostname}} in the output path, but COMPUTERNAME is not set");
                 }
+                hostname.trim().to_string()
+            }
+            "year" => now.format("%Y").to_string(),
+            "month" => now.format("%m").to_string(),
+            "day" => now.format("%d").to_string(),
+            other => anyhow::bail!("Unknown placeholder {{{}}} in output path; use {{hostname}}, {{year}}, {{month}} or {{day}}", other),
+        };
+        expanded.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(PathBuf::from(expanded))
+}
 
-                // Try 7z first, then fall back to other methods
-                let extract_result = Self::extract_with_7z(path, &temp_dir)
-                    .or_else(|_| Self::extract_with_powershell(path, &temp_dir));
+/// `--output -` sends the CSV to stdout
+fn is_stdout_path(path: &Path) -> bool {
+    path == Path::new("-")
+}
 
-                match extract_result {
-                    Ok(_) => {
-                        if verbose {
-                            println!("Successfully extracted to {}", temp_dir.display());
-                        }
-                        Ok((temp_dir, true))
-                    }
-                    Err(e) => {
-                        let _ = fs::remove_dir_all(&temp_dir);
-                        Err(e)
-                    }
-                }
-            }
-            "inf" => {
-                // Single INF file - use parent directory
-                Ok((path.parent().unwrap_or(Path::new(".")).to_path_buf(), false))
-            }
-            _ => anyhow::bail!("Unsupported file type: {}", extension)
-        }
+/// Write one JSON object per line (JSON Lines) to a file, or to stdout when the path is `-`.
+/// Rows are serialized as they're written, so large inventories are never held as one document.
+fn write_ndjson<T: Serialize>(rows: impl IntoIterator<Item = T>, path: &Path) -> Result<usize> {
+    use std::io::Write;
+    let writer: Box<dyn Write> = if is_stdout_path(path) {
+        Box::new(std::io::stdout().lock())
+    } else {
+        create_parent_dir(path)?;
+        Box::new(fs::File::create(path)
+            .with_context(|| format!("Failed to create JSON Lines file: {}", path.display()))?)
+    };
+    let mut writer = std::io::BufWriter::new(writer);
+    let mut count = 0;
+    for row in rows {
+        serde_json::to_writer(&mut writer, &row)?;
+        writer.write_all(b"\n")?;
+        count += 1;
     }
+    writer.flush()
+        .with_context(|| format!("Failed to write JSON Lines: {}", path.display()))?;
+    Ok(count)
+}
 
-    fn extract_with_7z(archive: &Path, dest: &Path) -> Result<()> {
-        // Try common 7z locations
-        let seven_zip_paths = [
-            "7z",
-            "C:\\Program Files\\7-Zip\\7z.exe",
-            "C:\\Program Files (x86)\\7-Zip\\7z.exe",
-        ];
+/// One `scan --ndjson` line: a device entry with the INF it came from.
+/// INFs without device entries get a line of their own with only the INF fields.
+#[derive(Serialize)]
+struct ScanJsonLine<'a> {
+    inf_path: &'a Path,
+    class: Option<&'a str>,
+    /// All catalog and source files present; `None` when not checked
+    complete: Option<bool>,
+    #[serde(flatten)]
+    device: Option<&'a InfDriverInfo>,
+}
 
-        for seven_zip in &seven_zip_paths {
-            let output = Command::new(seven_zip)
-                .arg("x")
-                .arg("-y")
-                .arg(format!("-o{}", dest.display()))
-                .arg(archive)
-                .output();
+/// Escape text for an XML element or attribute value
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
 
-            if let Ok(result) = output {
-                if result.status.success() {
-                    return Ok(());
-                }
+/// Minimal well-formedness check for the XML this tool generates: a single root element,
+/// every tag closed in order and only known entity references. Not a general XML parser;
+/// attribute values must not contain `>`.
+fn check_xml_well_formed(xml: &str) -> Result<()> {
+    let mut open: Vec<&str> = Vec::new();
+    let mut roots = 0;
+    let mut rest = xml;
+    while let Some(start) = rest.find(['<', '&']) {
+        let markup = &rest[start..];
+        if let Some(entity) = markup.strip_prefix('&') {
+            let end = entity.find(';').context("Unterminated entity reference")?;
+            let name = &entity[..end];
+            let numeric = name.strip_prefix("#x").map(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                .or_else(|| name.strip_prefix('#').map(|dec| !dec.is_empty() && dec.chars().all(|c| c.is_ascii_digit())))
+                .unwrap_or(false);
+            if !numeric && !["lt", "gt", "amp", "quot", "apos"].contains(&name) {
+                anyhow::bail!("Unknown entity reference &{};", name);
             }
+            rest = &entity[end + 1..];
+            continue;
         }
 
-        anyhow::bail!("7-Zip not found or extraction failed")
+        let end = markup.find('>').context("Unterminated tag")?;
+        let tag = &markup[1..end];
+        rest = &markup[end + 1..];
+        if tag.starts_with('?') || tag.starts_with("!--") {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            match open.pop() {
+                Some(opened) if opened == name.trim() => {}
+                Some(opened) => anyhow::bail!("</{}> closes <{}>", name.trim(), opened),
+                None => anyhow::bail!("</{}> has no opening tag", name.trim()),
+            }
+            continue;
+        }
+        let name = tag.split_whitespace().next().unwrap_or_default().trim_end_matches('/');
+        if name.is_empty() {
+            anyhow::bail!("Empty tag name");
+        }
+        if open.is_empty() {
+            roots += 1;
+        }
+        if !tag.ends_with('/') {
+            open.push(name);
+        }
+    }
+    if let Some(name) = open.last() {
+        anyhow::bail!("<{}> is never closed", name);
     }
+    if roots != 1 {
+        anyhow::bail!("Expected one root element, found {}", roots);
+    }
+    Ok(())
+}
 
-    fn extract_with_powershell(archive: &Path, dest: &Path) -> Result<()> {
-        let extension = archive.extension()
-            .and_then(|e| e.to_str())
-            .map(|e| e.to_lowercase())
-            .unwrap_or_default();
+/// Set by the Ctrl-C handler; long-running loops check it between steps
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
 
-        if extension == "zip" {
-            let output = Command::new("powershell")
-                .arg("-Command")
-                .arg(format!(
-                    "Expand-Archive -Path '{}' -DestinationPath '{}' -Force",
-                    archive.display(),
-                    dest.display()
-                ))
-                .output()?;
+/// Whether `command` runs a loop that checks `interrupted()`. The Ctrl-C handler is only
+/// installed for these; every other command still quits on the first Ctrl-C.
+fn checks_interrupts(command: &Commands) -> bool {
+    match command {
+        Commands::Backup { summary_csv_only, .. } => summary_csv_only.is_none(),
+        Commands::Restore { .. } | Commands::Inspect { .. } => true,
+        Commands::Scan { watch, .. } => *watch,
+        Commands::Export { files, computer, file_versions, .. } => *files || !computer.is_empty() || *file_versions,
+        _ => false,
+    }
+}
 
-            if output.status.success() {
-                return Ok(());
-            }
-        }
+/// Set when the CSV goes to stdout; banners and progress are suppressed so only the CSV is printed
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+fn quiet() -> bool {
+    QUIET.load(Ordering::SeqCst)
+}
+
+/// 7-Zip executables found for this run, see `InfParser::seven_zip_paths`
+static SEVEN_ZIP: OnceLock<Vec<PathBuf>> = OnceLock::new();
+
+/// `--locale` of scan and inspect: the `[Strings.<LANGID>]` table preferred for INF strings
+static INF_LOCALE: OnceLock<String> = OnceLock::new();
+
+/// Normalized language ID of `--locale`, `0409` (English) unless set
+fn inf_locale() -> &'static str {
+    INF_LOCALE.get().map(String::as_str).unwrap_or("0409")
+}
+
+/// How path columns of CSV reports are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum PathStyle {
+    /// Relative to the scanned folder, the backup or the extraction folder, for reports that move between machines
+    Relative,
+    /// Full paths
+    Absolute,
+}
+
+/// `--paths`, shared by every CSV exporter; unset, each report keeps its own default
+static PATH_STYLE: OnceLock<PathStyle> = OnceLock::new();
 
-        anyhow::bail!("PowerShell extraction failed or unsupported format")
+/// `path` for a CSV column, in the `--paths` style or else `default`. Relative paths are taken
+/// from `root` (the scanned input, the backup or the extraction folder); a path outside `root` stays absolute.
+fn csv_path(path: &Path, root: &Path, default: PathStyle) -> String {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    if PATH_STYLE.get().copied().unwrap_or(default) == PathStyle::Absolute {
+        return absolute.display().to_string();
     }
+    let root_absolute = std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+    match absolute.strip_prefix(&root_absolute) {
+        Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+        Ok(relative) => relative.display().to_string(),
+        Err(_) => absolute.display().to_string(),
+    }
+}
 
-    /// Find all INF files in a directory recursively
-    fn find_inf_files(dir: &Path) -> Result<Vec<PathBuf>> {
-        let mut inf_files = Vec::new();
-        Self::find_inf_files_recursive(dir, &mut inf_files)?;
-        inf_files.sort();
-        Ok(inf_files)
+/// Normalize a language ID (`409`, `0x0409`, `0C0A`) to four lowercase hex digits
+fn normalize_locale(value: &str) -> Option<String> {
+    let hex = value.trim().trim_start_matches("0x").trim_start_matches("0X");
+    if hex.is_empty() || hex.len() > 4 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
     }
+    Some(format!("{:0>4}", hex.to_lowercase()))
+}
 
-    /// Find INF files in a single folder (non-recursive)
-    fn find_inf_files_in_folder(dir: &Path) -> Result<Vec<PathBuf>> {
-        let mut inf_files = Vec::new();
-        
-        if !dir.is_dir() {
-            return Ok(inf_files);
-        }
+fn parse_locale(value: &str) -> std::result::Result<String, String> {
+    normalize_locale(value).ok_or_else(|| "expected a hexadecimal language ID such as 0409 or 0407".to_string())
+}
 
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if ext.to_string_lossy().to_lowercase() == "inf" {
-                        inf_files.push(path);
-                    }
-                }
-            }
-        }
+/// Human-readable file size (B, KB, MB, GB)
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
 
-        inf_files.sort();
-        Ok(inf_files)
+/// Parse a driver date as WMI (CIM_DATETIME, `20230517000000.000000+000`) or an
+/// INF `DriverVer` (`MM/DD/YYYY`) reports it. Impossible dates yield `None`.
+fn parse_driver_date(raw: &str) -> Option<chrono::NaiveDate> {
+    let raw = raw.trim();
+
+    if raw.contains('/') {
+        return chrono::NaiveDate::parse_from_str(raw, "%m/%d/%Y")
+            .or_else(|_| chrono::NaiveDate::parse_from_str(raw, "%Y/%m/%d"))
+            .ok();
     }
 
-    fn find_inf_files_recursive(dir: &Path, inf_files: &mut Vec<PathBuf>) -> Result<()> {
-        if !dir.is_dir() {
-            return Ok(());
+    // CIM_DATETIME: yyyymmddHHMMSS.mmmmmmsUUU, where the suffix is a UTC offset in minutes
+    let (timestamp, rest) = raw.split_at_checked(14).unwrap_or((raw, ""));
+    if !rest.is_empty() {
+        let (fraction, offset) = rest.split_at_checked(7)?;
+        let offset_valid = offset.len() == 4
+            && (offset.starts_with('+') || offset.starts_with('-'))
+            && offset[1..].chars().all(|c| c.is_ascii_digit());
+        if !fraction.starts_with('.') || !offset_valid {
+            return None;
         }
+    }
+    match timestamp.len() {
+        14 => chrono::NaiveDateTime::parse_from_str(timestamp, "%Y%m%d%H%M%S").ok().map(|dt| dt.date()),
+        8 => chrono::NaiveDate::parse_from_str(timestamp, "%Y%m%d").ok(),
+        _ => None,
+    }
+}
 
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
+/// `YYYYMMDD` of an OLE Automation date (days since 1899-12-30), as Windows Update reports them
+fn ole_date_to_yyyymmdd(date: f64) -> Option<String> {
+    let epoch = chrono::NaiveDate::from_ymd_opt(1899, 12, 30)?;
+    let days = chrono::TimeDelta::try_days(date.floor() as i64)?;
+    Some(epoch.checked_add_signed(days)?.format("%Y%m%d").to_string())
+}
 
-            if path.is_dir() {
-                Self::find_inf_files_recursive(&path, inf_files)?;
-            } else if let Some(ext) = path.extension() {
-                if ext.to_string_lossy().to_lowercase() == "inf" {
-                    inf_files.push(path);
-                }
+/// Render a driver date as `YYYY-MM-DD`, or "Unknown" when it's missing or invalid
+fn format_driver_date(raw: Option<&str>, verbose: bool) -> String {
+    let Some(raw) = raw else {
+        return "Unknown".to_string();
+    };
+    match parse_driver_date(raw) {
+        Some(date) => date.format("%Y-%m-%d").to_string(),
+        None => {
+            if verbose {
+                println!("Note: ignoring invalid driver date '{}'", raw);
             }
+            "Unknown".to_string()
         }
-
-        Ok(())
     }
+}
 
-    /// Parse a single INF file
-    fn parse_inf_file(inf_path: &Path) -> Result<ParsedInfFile> {
-        // Try different encodings (INF files can be UTF-8, UTF-16, or ANSI)
-        let content = Self::read_inf_content(inf_path)?;
-        
-        let file_name = inf_path.file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown.inf")
-            .to_string();
+/// Minimum driver age for `export --stale`, e.g. `90d`, `6m` or `3y`
+#[derive(Clone, Copy, Debug)]
+enum DriverAge {
+    Days(u32),
+    Months(u32),
+}
 
-        let mut version_info = InfVersionInfo::default();
-        let mut manufacturers: HashMap<String, String> = HashMap::new();
-        let mut device_sections: HashMap<String, Vec<(String, String)>> = HashMap::new();
-        let mut string_table: HashMap<String, String> = HashMap::new();
-        let mut current_section = String::new();
+impl DriverAge {
+    /// Drivers dated before this day are older than the age
+    fn cutoff(self, today: chrono::NaiveDate) -> Option<chrono::NaiveDate> {
+        match self {
+            DriverAge::Days(days) => today.checked_sub_days(chrono::Days::new(days.into())),
+            DriverAge::Months(months) => today.checked_sub_months(chrono::Months::new(months)),
+        }
+    }
+}
 
-        for line in content.lines() {
-            let line = line.trim();
-            
-            // Skip empty lines and comments
-            if line.is_empty() || line.starts_with(';') {
-                continue;
-            }
+/// Parse a `--stale` value: a number followed by `d` (days), `m` (months) or `y` (years)
+fn parse_driver_age(value: &str) -> std::result::Result<DriverAge, String> {
+    let value = value.trim();
+    let (split, unit) = value.char_indices().last().unwrap_or((0, ' '));
+    let count = value[..split].parse::<u32>()
+        .map_err(|_| format!("expected a number followed by d, m or y (e.g. 3y), got '{}'", value))?;
+    match unit.to_ascii_lowercase() {
+        'd' => Ok(DriverAge::Days(count)),
+        'm' => Ok(DriverAge::Months(count)),
+        'y' => count.checked_mul(12).map(DriverAge::Months).ok_or_else(|| "too many years".to_string()),
+        _ => Err(format!("unknown unit in '{}'; use d, m or y", value)),
+    }
+}
 
-            // Section header
-            if line.starts_with('[') && line.ends_with(']') {
-                current_section = line[1..line.len()-1].to_lowercase();
-                continue;
-            }
+/// A driver version such as `30.0.101.1340`, `6.1` or `1.0.0.0-beta`, ordered segment by
+/// segment as numbers so that `10.0.2.10` sorts after `10.0.2.9`.
+///
+/// Missing segments count as zero (`6.1` equals `6.1.0.0`). The last segment may carry a
+/// non-numeric suffix, which sorts before the plain version (`1.0-beta` < `1.0`). Anything
+/// else, like an empty or `%token%` version, can't be parsed: it is ordered by its text,
+/// case-insensitively, and `is_comparable` is false so callers can say so.
+#[derive(Debug, Clone)]
+struct DriverVersion {
+    text: String,
+    /// `None` when the text isn't a dotted number
+    segments: Option<Vec<u64>>,
+    /// Lowercase text after the last segment's digits, e.g. `-beta`
+    suffix: String,
+}
 
-            // Parse based on current section
-            match current_section.as_str() {
-                "version" => Self::parse_version_line(line, &mut version_info),
-                "manufacturer" => Self::parse_manufacturer_line(line, &mut manufacturers),
-                "strings" => Self::parse_strings_line(line, &mut string_table),
-                section if manufacturers.values().any(|v| {
-                    let sec_lower = section.to_lowercase();
-                    v.to_lowercase().starts_with(&sec_lower) || sec_lower.starts_with(&v.to_lowercase())
-                }) => {
-                    Self::parse_device_line(line, &current_section, &mut device_sections);
+impl DriverVersion {
+    fn parse(text: &str) -> Self {
+        let text = text.trim();
+        let mut segments = Vec::new();
+        let mut suffix = String::new();
+        let parts: Vec<&str> = text.split('.').collect();
+        for (index, part) in parts.iter().enumerate() {
+            let digits = part.find(|c: char| !c.is_ascii_digit()).unwrap_or(part.len());
+            let number = part[..digits].parse::<u64>().ok();
+            match number {
+                Some(number) if digits == part.len() => segments.push(number),
+                Some(number) if index == parts.len() - 1 => {
+                    segments.push(number);
+                    suffix = part[digits..].to_lowercase();
                 }
                 _ => {
-                    // Check if this is a device section
-                    for mfg_section in manufacturers.values() {
-                        let base_section = mfg_section.split(',').next().unwrap_or(mfg_section);
-                        if current_section.to_lowercase().starts_with(&base_section.to_lowercase()) {
-                            Self::parse_device_line(line, &current_section, &mut device_sections);
-                            break;
+                    segments.clear();
+                    break;
+                }
+            }
+        }
+        DriverVersion {
+            text: text.to_string(),
+            segments: (!segments.is_empty()).then_some(segments),
+            suffix,
+        }
+    }
+
+    /// Whether the version parsed as numbers; otherwise only a text comparison is possible
+    fn is_comparable(&self) -> bool {
+        self.segments.is_some()
+    }
+}
+
+impl Ord for DriverVersion {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let (Some(left), Some(right)) = (&self.segments, &other.segments) else {
+            return self.text.to_lowercase().cmp(&other.text.to_lowercase());
+        };
+        let length = left.len().max(right.len());
+        let segment = |segments: &Vec<u64>, index: usize| segments.get(index).copied().unwrap_or(0);
+        (0..length)
+            .map(|index| segment(left, index).cmp(&segment(right, index)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| match (self.suffix.is_empty(), other.suffix.is_empty()) {
+                (true, true) => std::cmp::Ordering::Equal,
+                // A release sorts after its pre-release builds
+                (true, false) => std::cmp::Ordering::Greater,
+                (false, true) => std::cmp::Ordering::Less,
+                (false, false) => self.suffix.cmp(&other.suffix),
+            })
+    }
+}
+
+impl PartialOrd for DriverVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for DriverVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for DriverVersion {}
+
+/// Compare two driver version strings as `DriverVersion`s
+fn compare_driver_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    DriverVersion::parse(a).cmp(&DriverVersion::parse(b))
+}
+
+/// Hardware ID without its `SUBSYS_`/`REV_` parts, so revisions of the same chip
+/// compare equal: `PCI\VEN_8086&DEV_A370&SUBSYS_00748086&REV_10` -> `PCI\VEN_8086&DEV_A370`
+fn base_hardware_id(hardware_id: &str) -> String {
+    hardware_id
+        .to_uppercase()
+        .split('&')
+        .filter(|part| !part.starts_with("SUBSYS_") && !part.starts_with("REV_"))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Read the file version from a binary's `VS_FIXEDFILEINFO` resource with `GetFileVersionInfoW`,
+/// the same value Explorer shows as "File version". Returns `None` when there is no version resource.
+fn read_file_version(path: &Path) -> Option<String> {
+    use windows::core::{w, HSTRING};
+    use windows::Win32::Storage::FileSystem::{GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW, VS_FIXEDFILEINFO};
+
+    let path = HSTRING::from(path.as_os_str());
+    // SAFETY: `data` is sized as GetFileVersionInfoSizeW asks, and `info` points into it
+    // only while it's alive; VerQueryValueW reports the length it points to
+    unsafe {
+        let size = GetFileVersionInfoSizeW(&path, None);
+        if size == 0 {
+            return None;
+        }
+        let mut data = vec![0u8; size as usize];
+        GetFileVersionInfoW(&path, 0, size, data.as_mut_ptr().cast()).ok()?;
+        let mut info = std::ptr::null_mut();
+        let mut len = 0;
+        if !VerQueryValueW(data.as_ptr().cast(), w!("\\"), &mut info, &mut len).as_bool()
+            || (len as usize) < std::mem::size_of::<VS_FIXEDFILEINFO>()
+        {
+            return None;
+        }
+        let info = &*info.cast::<VS_FIXEDFILEINFO>();
+        let (ms, ls) = (info.dwFileVersionMS, info.dwFileVersionLS);
+        Some(format!("{}.{}.{}.{}", ms >> 16, ms & 0xFFFF, ls >> 16, ls & 0xFFFF))
+    }
+}
+
+/// `--strip-version-suffix` grouping key: at most four dotted components, ending at the
+/// first non-digit, so `31.0.101.2111_whql` groups with `31.0.101.2111`.
+/// Versions that don't start with a number are returned unchanged.
+fn normalize_driver_version(version: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in version.trim().split('.').take(4) {
+        let end = part.find(|c: char| !c.is_ascii_digit()).unwrap_or(part.len());
+        if end > 0 {
+            parts.push(&part[..end]);
+        }
+        if end < part.len() || end == 0 {
+            break;
+        }
+    }
+    if parts.is_empty() {
+        version.to_string()
+    } else {
+        parts.join(".")
+    }
+}
+
+/// Parse a `--delimiter` value: a single character, or `tab`/`\t` for tab-separated output
+fn parse_delimiter(value: &str) -> std::result::Result<char, String> {
+    match value {
+        "tab" | "\\t" => Ok('\t'),
+        _ => {
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some('"'), None) | (Some('\n'), None) | (Some('\r'), None) => {
+                    Err("quotes and line breaks can't be used as a delimiter".to_string())
+                }
+                (Some(c), None) => Ok(c),
+                _ => Err(format!("expected a single character or 'tab', got '{}'", value)),
+            }
+        }
+    }
+}
+
+/// Quote one argument for a Windows command line (the rules `CommandLineToArgvW` undoes)
+fn quote_windows_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// Parse a `--wql-where` value; the expression itself is passed to WMI untouched
+fn parse_wql_where(value: &str) -> std::result::Result<String, String> {
+    let expr = value.trim();
+    if expr.is_empty() {
+        return Err("the WHERE expression can't be empty".to_string());
+    }
+    if expr.len() >= 5 && expr[..5].eq_ignore_ascii_case("where") && expr[5..].starts_with(char::is_whitespace) {
+        return Err("leave out the WHERE keyword; it's added automatically".to_string());
+    }
+    Ok(expr.to_string())
+}
+
+/// OEM INF -> original INF name, see `DriverBackup::inf_lookup`
+static INF_LOOKUP: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+struct DriverBackup {
+    wmi_con: WMIConnection,
+    args: Args,
+}
+
+impl DriverBackup {
+    fn new(mut args: Args) -> Result<Self> {
+        // Validate administrative privileges
+        Self::check_admin_privileges()?;
+
+        // Validate output directory path for backup commands; placeholders resolve first,
+        // on the same clock as the drivers_<timestamp> folder name
+        if let Some(Commands::Backup { output, local_time, .. }) = &mut args.command {
+            let now = if *local_time { chrono::Local::now().fixed_offset() } else { Utc::now().fixed_offset() };
+            *output = expand_output_template(output, now)?;
+            Self::validate_output_directory(output)?;
+        }
+
+        let com_con = COMLibrary::new().context("Failed to initialize COM library")?;
+        let wmi_con = WMIConnection::new(com_con.into()).context("Failed to create WMI connection")?;
+
+        Ok(Self { wmi_con, args })
+    }
+
+    /// Check if the program is running with administrative privileges
+    fn check_admin_privileges() -> Result<()> {
+        let test_path = Path::new("C:\\Windows\\Temp\\driver_backup_admin_test");
+        match fs::write(test_path, "test") {
+            Ok(_) => {
+                let _ = fs::remove_file(test_path);
+                Ok(())
+            }
+            Err(_) => anyhow::bail!(
+                "This program requires administrative privileges to access driver information. \
+                 Please run as Administrator."
+            ),
+        }
+    }
+
+    /// Start this executable again with the same arguments through a UAC prompt.
+    ///
+    /// The elevated copy opens in its own console window, so the caller should exit
+    /// once this returns. `ShellExecuteExW` with the `runas` verb does the prompting.
+    fn relaunch_elevated() -> Result<()> {
+        use windows::core::{w, HSTRING, PCWSTR};
+        use windows::Win32::Foundation::ERROR_CANCELLED;
+        use windows::Win32::UI::Shell::{ShellExecuteExW, SEE_MASK_FLAG_NO_UI, SEE_MASK_NOASYNC, SHELLEXECUTEINFOW};
+        use windows::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+        let exe = std::env::current_exe().context("Failed to locate the running executable")?;
+        let cwd = std::env::current_dir().context("Failed to read the current folder")?;
+
+        let mut arguments: Vec<String> = std::env::args().skip(1)
+            .filter(|arg| arg != "--elevate")
+            .collect();
+        arguments.push("--elevated".to_string());
+        let command_line = arguments.iter()
+            .map(|arg| quote_windows_arg(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        println!("Not running as Administrator, asking Windows to relaunch elevated...");
+        let (file, parameters, directory) = (HSTRING::from(exe.as_os_str()), HSTRING::from(command_line), HSTRING::from(cwd.as_os_str()));
+        let mut info = SHELLEXECUTEINFOW {
+            cbSize: std::mem::size_of::<SHELLEXECUTEINFOW>() as u32,
+            // Report errors to us instead of in a dialog, and finish before this process exits
+            fMask: SEE_MASK_FLAG_NO_UI | SEE_MASK_NOASYNC,
+            lpVerb: w!("runas"),
+            lpFile: PCWSTR(file.as_ptr()),
+            lpParameters: PCWSTR(parameters.as_ptr()),
+            lpDirectory: PCWSTR(directory.as_ptr()),
+            nShow: SW_SHOWNORMAL.0,
+            ..Default::default()
+        };
+        // SAFETY: `info` and the strings it points to outlive the call
+        match unsafe { ShellExecuteExW(&mut info) } {
+            Ok(()) => {}
+            Err(e) if e.code() == ERROR_CANCELLED.to_hresult() => anyhow::bail!(
+                "The UAC prompt was cancelled, so nothing was backed up. \
+                 Run again and choose Yes, or start from an Administrator prompt."
+            ),
+            Err(e) => return Err(e).context("Failed to relaunch as Administrator"),
+        }
+        println!("Continuing in the elevated window.");
+        Ok(())
+    }
+
+    /// Check everything backup and inspect depend on, printing a ✓/✗ line with a tip for each
+    fn doctor() -> Result<()> {
+        let mut failed_count = 0;
+        let mut report = |name: &str, result: Result<String>, tip: &str| match result {
+            Ok(detail) => println!("✓ {}: {}", name, detail),
+            Err(e) => {
+                println!("✗ {}: {:#}", name, e);
+                println!("  → {}", tip);
+                failed_count += 1;
+            }
+        };
+
+        report(
+            "Administrator",
+            Self::check_admin_privileges().map(|()| "running elevated".to_string()),
+            "Right-click the terminal and choose \"Run as administrator\"; backup needs it to export drivers.",
+        );
+
+        report(
+            "pnputil",
+            Command::new("pnputil").arg("/?").output()
+                .map(|_| "found".to_string())
+                .context("not found in PATH"),
+            "pnputil ships with Windows 10 and later in C:\\Windows\\System32; make sure that folder is in PATH.",
+        );
+
+        report(
+            "7-Zip",
+            InfParser::seven_zip_paths().first()
+                .map(|path| format!("found ({})", path.display()))
+                .context("not found"),
+            "Install 7-Zip from https://www.7-zip.org to inspect .exe, .7z and .rar packages.",
+        );
+
+        report(
+            "PowerShell",
+            Command::new("powershell")
+                .args(["-NoProfile", "-Command", "$PSVersionTable.PSVersion.ToString()"])
+                .output()
+                .context("not found")
+                .and_then(|output| {
+                    if !output.status.success() {
+                        anyhow::bail!("exited with {}", output.status);
+                    }
+                    Ok(format!("version {}", String::from_utf8_lossy(&output.stdout).trim()))
+                }),
+            "PowerShell is used by the generated .ps1 install scripts; re-enable it in Windows Features.",
+        );
+
+        report(
+            "WMI",
+            COMLibrary::new()
+                .context("Failed to initialize COM library")
+                .and_then(|com_con| WMIConnection::new(com_con.into()).context("Failed to create WMI connection"))
+                .and_then(|wmi_con| wmi_con.query::<OsInfo>().context("Failed to query Win32_OperatingSystem"))
+                .map(|os| {
+                    os.first()
+                        .and_then(|os| os.caption.clone())
+                        .unwrap_or_else(|| "connected".to_string())
+                }),
+            "Restart the \"Windows Management Instrumentation\" service, or check the repository with `winmgmt /verifyrepository`.",
+        );
+
+        report(
+            "Temp folder",
+            tempfile::Builder::new()
+                .prefix(InfParser::TEMP_DIR_PREFIX)
+                .tempdir()
+                .context("Failed to create a temporary directory")
+                .and_then(|temp| {
+                    fs::write(temp.path().join("write_test.tmp"), "test").context("Failed to write a test file")?;
+                    Ok(format!("writable ({})", std::env::temp_dir().display()))
+                }),
+            "Free up disk space or point the TEMP environment variable at a writable folder.",
+        );
+
+        println!();
+        if failed_count > 0 {
+            anyhow::bail!("{} checks failed", failed_count);
+        }
+        println!("All checks passed.");
+        Ok(())
+    }
+
+    /// Validate that the output directory exists or can be created
+    fn validate_output_directory(output: &PathBuf) -> Result<()> {
+        if output.exists() && !output.is_dir() {
+            anyhow::bail!("Output path exists but is not a directory: {}", output.display());
+        }
+
+        if !output.exists() {
+            fs::create_dir_all(output)
+                .with_context(|| format!("Failed to create output directory: {}", output.display()))?;
+        }
+
+        // Test write permissions
+        let test_file = output.join("write_test.tmp");
+        fs::write(&test_file, "test")
+            .with_context(|| format!("Cannot write to output directory: {}", output.display()))?;
+        fs::remove_file(&test_file).ok();
+
+        Ok(())
+    }
+
+    /// Check a file the run will write: not an existing folder or read-only file, and its
+    /// folder passes `validate_output_folder`. `-` (stdout) is always fine.
+    fn validate_output_file(output: &Path, dry_run: bool) -> Result<()> {
+        if is_stdout_path(output) {
+            return Ok(());
+        }
+        if output.is_dir() {
+            anyhow::bail!("Output path is a folder, but a file is expected: {}", output.display());
+        }
+        if fs::metadata(output).is_ok_and(|metadata| metadata.permissions().readonly()) {
+            anyhow::bail!("Output file is read-only: {}", output.display());
+        }
+        let parent = output.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        Self::validate_output_folder(parent, dry_run)
+    }
+
+    /// Check a folder the run will write into without creating it (the writer creates missing
+    /// folders): the nearest existing folder at or above it must be a directory that takes
+    /// new files. The write test is skipped on a dry run, which leaves the disk untouched.
+    fn validate_output_folder(folder: &Path, dry_run: bool) -> Result<()> {
+        let existing = folder.ancestors()
+            .map(|ancestor| if ancestor.as_os_str().is_empty() { Path::new(".") } else { ancestor })
+            .find(|ancestor| ancestor.exists())
+            .unwrap_or(Path::new("."));
+        if !existing.is_dir() {
+            anyhow::bail!("Output folder exists but is not a directory: {}", existing.display());
+        }
+        if !dry_run {
+            tempfile::Builder::new()
+                .prefix(".driver-backup-")
+                .tempfile_in(existing)
+                .with_context(|| format!("Cannot write to output folder: {}", existing.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Check every file and folder `command` will write, and the report template it will
+    /// render, before any work starts, so a bad `-o` fails in the first second rather than
+    /// after a long scan or export
+    fn validate_output_paths(command: &Commands) -> Result<()> {
+        match command {
+            Commands::Inspect { output: Some(output), list_files, .. } => {
+                Self::validate_output_file(output, false)?;
+                if *list_files && !is_stdout_path(output) {
+                    Self::validate_output_file(&InfParser::payload_csv_path(output), false)?;
+                }
+                Ok(())
+            }
+            Commands::Scan { output, write_index, duplicates_csv, diff_csv, ndjson, .. } => {
+                [output, write_index, duplicates_csv, diff_csv, ndjson].into_iter()
+                    .flatten()
+                    .try_for_each(|path| Self::validate_output_file(path, false))
+            }
+            Commands::Export { output, files, computer, stale_csv, ndjson, .. } => {
+                // --files treats anything but a .csv path as the folder to export into
+                let is_csv = output.extension().is_some_and(|extension| extension == "csv");
+                if *files && computer.is_empty() && !is_csv {
+                    Self::validate_output_directory(output)?;
+                } else {
+                    Self::validate_output_file(output, false)?;
+                }
+                [stale_csv, ndjson].into_iter()
+                    .flatten()
+                    .try_for_each(|path| Self::validate_output_file(path, false))
+            }
+            Commands::Backup { deploy_list, unattend_snippet, for_dism, mirror, report_template, dry_run, .. } => {
+                [deploy_list, unattend_snippet].into_iter()
+                    .flatten()
+                    .try_for_each(|path| Self::validate_output_file(path, *dry_run))?;
+                [for_dism, mirror].into_iter()
+                    .flatten()
+                    .try_for_each(|folder| Self::validate_output_folder(folder, *dry_run))?;
+                if let Some(Some(template)) = report_template {
+                    let source = fs::read_to_string(template)
+                        .with_context(|| format!("Failed to read report template: {}", template.display()))?;
+                    handlebars::Template::compile(&source)
+                        .with_context(|| format!("Invalid report template: {}", template.display()))?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Get all signed drivers from WMI
+    async fn get_drivers(&self) -> Result<Vec<PnPSignedDriver>> {
+        let (timeout, wql_where, verbose) = match &self.args.command {
+            Some(Commands::Backup { wmi_timeout, wql_where, verbose, .. }) => (*wmi_timeout, wql_where.as_deref(), *verbose > 0),
+            _ => (None, None, false),
+        };
+
+        Self::query_signed_drivers(timeout.map(Duration::from_secs), wql_where, verbose)
+    }
+
+    /// Query the signed drivers, selecting only the properties `PnPSignedDriver` uses.
+    ///
+    /// The query runs on a worker thread with its own WMI connection so a hung WMI
+    /// service can be abandoned after `timeout` instead of blocking forever.
+    fn query_signed_drivers(timeout: Option<Duration>, wql_where: Option<&str>, verbose: bool) -> Result<Vec<PnPSignedDriver>> {
+        let started = Instant::now();
+        let drivers = Self::run_wmi_query::<PnPSignedDriver>(Self::signed_drivers_query(wql_where), "PnP signed drivers", timeout)?;
+        log::debug!("WMI returned {} drivers in {:.2?}", drivers.len(), started.elapsed());
+        if verbose {
+            println!("WMI query returned {} drivers in {:.2}s", drivers.len(), started.elapsed().as_secs_f64());
+        }
+        Ok(drivers)
+    }
+
+    /// WQL for `Win32_PnPSignedDriver` with the `PnPSignedDriver` properties and an optional `--wql-where`
+    fn signed_drivers_query(wql_where: Option<&str>) -> String {
+        let mut query = format!("SELECT {} FROM Win32_PnPSignedDriver", PNP_SIGNED_DRIVER_FIELDS);
+        if let Some(expr) = wql_where {
+            query.push_str(" WHERE ");
+            query.push_str(expr);
+        }
+        query
+    }
+
+    /// Query `Win32_PnPEntity` and key the devices by instance ID.
+    ///
+    /// The presence and status properties are only selected when `with_status` is set.
+    fn query_pnp_entities(timeout: Option<Duration>, with_status: bool, verbose: bool) -> Result<HashMap<String, PnPEntity>> {
+        let mut query = format!("SELECT {}", PNP_ENTITY_FIELDS);
+        if with_status {
+            query.push_str(", ");
+            query.push_str(PNP_ENTITY_STATUS_FIELDS);
+        }
+        query.push_str(" FROM Win32_PnPEntity");
+        let entities = Self::run_wmi_query::<PnPEntity>(query, "PnP entities", timeout)?;
+        if verbose {
+            println!("WMI query returned {} PnP entities", entities.len());
+        }
+        Ok(entities.into_iter()
+            .filter_map(|entity| Some((device_instance_key(entity.pnp_device_id.as_deref()?), entity)))
+            .collect())
+    }
+
+    /// Run a WQL query on a worker thread with its own WMI connection so a hung WMI
+    /// service can be abandoned after `timeout` instead of blocking forever.
+    fn run_wmi_query<T>(query: String, what: &'static str, timeout: Option<Duration>) -> Result<Vec<T>>
+    where
+        T: serde::de::DeserializeOwned + Send + 'static,
+    {
+        Self::run_wmi_query_with(query, what, timeout, || {
+            let com_con = COMLibrary::new().context("Failed to initialize COM library")?;
+            WMIConnection::new(com_con.into()).context("Failed to create WMI connection")
+        })
+    }
+
+    /// `run_wmi_query` with the connection made by `connect` on the worker thread.
+    /// The thread ends with the query, so thread state such as impersonation goes with it.
+    fn run_wmi_query_with<T, F>(query: String, what: &'static str, timeout: Option<Duration>, connect: F) -> Result<Vec<T>>
+    where
+        T: serde::de::DeserializeOwned + Send + 'static,
+        F: FnOnce() -> Result<WMIConnection> + Send + 'static,
+    {
+        log::debug!("WMI query: {}", query);
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let result = connect()
+                .and_then(|wmi_con| {
+                    wmi_con.raw_query::<T>(&query)
+                        .with_context(|| format!("Failed to query WMI for {} ({})", what, query))
+                });
+            let _ = sender.send(result);
+        });
+
+        let result = match timeout {
+            Some(limit) => match receiver.recv_timeout(limit) {
+                Ok(result) => result,
+                Err(mpsc::RecvTimeoutError::Timeout) => anyhow::bail!(
+                    "WMI did not respond within {} seconds. The WMI repository may be damaged; \
+                     check it with `winmgmt /verifyrepository` from an elevated prompt.",
+                    limit.as_secs()
+                ),
+                Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("WMI query thread exited unexpectedly"),
+            },
+            None => receiver.recv().context("WMI query thread exited unexpectedly")?,
+        };
+        result
+    }
+
+    /// Get version and architecture of the running OS; failures only cost the metadata
+    fn get_os_info(&self) -> Option<OsInfo> {
+        match self.wmi_con.query::<OsInfo>() {
+            Ok(mut os) => os.pop(),
+            Err(e) => {
+                eprintln!("Warning: Failed to query operating system info: {}", e);
+                None
+            }
+        }
+    }
+
+    /// OS, make/model and BIOS of this machine for the backup records
+    fn get_system_context(&self, include_serial: bool) -> SystemContext {
+        let computer = match self.wmi_con.query::<ComputerInfo>() {
+            Ok(mut computer) => computer.pop(),
+            Err(e) => {
+                eprintln!("Warning: Failed to query computer model: {}", e);
+                None
+            }
+        };
+        let bios = match self.wmi_con.query::<BiosInfo>() {
+            Ok(mut bios) => bios.pop().map(|bios| BiosInfo {
+                serial_number: bios.serial_number.filter(|_| include_serial),
+                ..bios
+            }),
+            Err(e) => {
+                eprintln!("Warning: Failed to query BIOS info: {}", e);
+                None
+            }
+        };
+        SystemContext { os: self.get_os_info(), computer, bios }
+    }
+
+    /// Check if a driver is from Microsoft
+    fn is_microsoft_driver(&self, driver: &PnPSignedDriver) -> bool {
+        if let Some(ref provider) = driver.driver_provider_name {
+            provider.to_lowercase().contains("microsoft")
+        } else {
+            false
+        }
+    }
+
+    /// Filter out Microsoft drivers, keeping only third-party drivers
+    fn filter_non_microsoft_drivers(&self, drivers: Vec<PnPSignedDriver>) -> Vec<PnPSignedDriver> {
+        drivers.into_iter()
+            .filter(|driver| !self.is_microsoft_driver(driver))
+            .collect()
+    }
+
+    /// Versions of the inbox (`%WINDIR%\INF`, non-OEM) drivers in the generic classes,
+    /// keyed by `normalize_hardware_id`
+    fn inbox_generic_versions() -> HashMap<String, Vec<DriverVersion>> {
+        let mut versions: HashMap<String, Vec<DriverVersion>> = HashMap::new();
+        let Some(inf_dir) = std::env::var_os("WINDIR").map(|dir| PathBuf::from(dir).join("INF")) else {
+            return versions;
+        };
+        let Ok(inf_files) = InfParser::find_inf_files_in_folder(&inf_dir) else {
+            return versions;
+        };
+
+        // The inbox folder holds thousands of INFs, so reuse the scan cache
+        let cache_path = ScanCache::default_path();
+        let mut cache = cache_path.as_deref().map(ScanCache::load).unwrap_or_default();
+
+        for inf_path in inf_files {
+            let file_name = inf_path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            if Self::is_oem_inf_name(file_name) {
+                continue;
+            }
+            let Ok(parsed) = cache.parse(&inf_path, DEFAULT_MAX_INF_SIZE) else {
+                continue;
+            };
+            for driver in parsed.drivers {
+                let is_generic = is_generic_class(driver.device_class.as_deref());
+                if let (true, Some(hwid), Some(version)) = (is_generic, driver.hardware_id, driver.driver_version) {
+                    versions.entry(normalize_hardware_id(&hwid)).or_default().push(DriverVersion::parse(&version));
+                }
+            }
+        }
+
+        if let Some(path) = cache_path {
+            let _ = cache.save(&path);
+        }
+        versions
+    }
+
+    /// Drop third-party drivers that are just the inbox driver under another provider name:
+    /// the class must be one of `GENERIC_DEVICE_CLASSES` and an inbox INF must list the
+    /// same hardware ID with an equal `DriverVersion`. Anything else is kept.
+    fn filter_generic_drivers(&self, drivers: Vec<PnPSignedDriver>, verbose: bool) -> Vec<PnPSignedDriver> {
+        let inbox_versions = Self::inbox_generic_versions();
+        let mut dropped = 0;
+
+        let kept: Vec<PnPSignedDriver> = drivers.into_iter()
+            .filter(|driver| {
+                let is_generic = is_generic_class(driver.device_class.as_deref());
+                let matches_inbox = match (&driver.hardware_id, &driver.driver_version) {
+                    (Some(hwid), Some(version)) => inbox_versions
+                        .get(&normalize_hardware_id(hwid))
+                        .is_some_and(|versions| versions.contains(&DriverVersion::parse(version))),
+                    _ => false,
+                };
+                if is_generic && matches_inbox {
+                    if verbose {
+                        println!("Excluding generic driver: {} ({}, {} v{})",
+                            driver.device_name.as_deref().unwrap_or("Unknown"),
+                            driver.hardware_id.as_deref().unwrap_or("Unknown"),
+                            driver.driver_provider_name.as_deref().unwrap_or("Unknown"),
+                            driver.driver_version.as_deref().unwrap_or("Unknown"));
+                    }
+                    dropped += 1;
+                    return false;
+                }
+                true
+            })
+            .collect();
+
+        println!("Excluded {} generic drivers matching inbox versions", dropped);
+        kept
+    }
+
+    /// `--latest-only`: within each class, group packages by provider and the base hardware
+    /// ID of their first device, and drop every package but the highest `DriverVersion`.
+    /// Packages without a hardware ID are always kept.
+    fn keep_latest_packages(drivers_by_class_inf: &mut DriverGroups, drivers: &[PnPSignedDriver], verbose: bool) {
+        let mut skipped = 0;
+        for infs_in_class in drivers_by_class_inf.values_mut() {
+            let mut newest: HashMap<(String, String), (&str, &str)> = HashMap::new();
+            for (oem_inf, indexes) in infs_in_class.iter() {
+                let Some(first) = indexes.first().map(|&i| &drivers[i]) else { continue };
+                let Some(hwid) = indexes.iter().find_map(|&i| drivers[i].hardware_id.as_deref()) else { continue };
+                let key = (
+                    first.driver_provider_name.as_deref().unwrap_or("").to_lowercase(),
+                    base_hardware_id(hwid),
+                );
+                let version = first.driver_version.as_deref().unwrap_or("");
+                newest.entry(key)
+                    .and_modify(|best| if compare_driver_versions(version, best.1).is_gt() { *best = (oem_inf, version) })
+                    .or_insert((oem_inf, version));
+            }
+            let keep: std::collections::HashSet<String> = newest.values().map(|(inf, _)| inf.to_string()).collect();
+
+            infs_in_class.retain(|oem_inf, indexes| {
+                let grouped = indexes.iter().any(|&i| drivers[i].hardware_id.is_some());
+                if !grouped || keep.contains(oem_inf) {
+                    return true;
+                }
+                let first = &drivers[indexes[0]];
+                println!("Skipping older version: {} v{} ({})",
+                    first.device_name.as_deref().unwrap_or("Unknown"),
+                    first.driver_version.as_deref().unwrap_or("Unknown"),
+                    oem_inf);
+                if verbose {
+                    println!("  Provider: {}, Hardware ID: {}",
+                        first.driver_provider_name.as_deref().unwrap_or("Unknown"),
+                        first.hardware_id.as_deref().unwrap_or("Unknown"));
+                }
+                skipped += 1;
+                false
+            });
+        }
+        drivers_by_class_inf.retain(|_, infs_in_class| !infs_in_class.is_empty());
+
+        println!("Skipped {} older driver package versions (--latest-only)", skipped);
+    }
+
+    /// Current time for the backup folder name and records: UTC, or local time with `--local-time`
+    fn backup_time(&self) -> chrono::DateTime<chrono::FixedOffset> {
+        if matches!(self.args.command, Some(Commands::Backup { local_time: true, .. })) {
+            chrono::Local::now().fixed_offset()
+        } else {
+            Utc::now().fixed_offset()
+        }
+    }
+
+    /// Create the main backup directory structure
+    fn create_base_backup_directory(&self, output: &PathBuf) -> Result<PathBuf> {
+        let timestamp = self.backup_time().format("%Y%m%d_%H%M%S");
+        let backup_dir = output.join(format!("drivers_{}", timestamp));
+
+        fs::create_dir_all(&backup_dir)
+            .with_context(|| format!("Failed to create backup directory: {}", backup_dir.display()))?;
+
+        Ok(backup_dir)
+    }
+
+    /// Format driver date to a readable format
+    fn format_driver_date(&self, driver_date: &Option<String>) -> String {
+        let verbose = matches!(self.args.command, Some(Commands::Backup { verbose: 1.., .. }));
+        format_driver_date(driver_date.as_deref(), verbose)
+    }
+
+    /// Extract OEM INF name from driver
+    fn extract_oem_inf_name(&self, inf_name: &str) -> Option<String> {
+        let inf_lower = inf_name.to_lowercase();
+        if inf_lower.starts_with("oem") && inf_lower.ends_with(".inf") {
+            // Validate characters
+            if inf_lower.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_') {
+                Some(inf_lower)
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Build a fake pnputil result that looks like a protected/corrupted package,
+    /// so the failure classification and reporting paths can be exercised in tests
+    #[cfg(feature = "testhooks")]
+    fn synthetic_export_failure() -> std::process::Output {
+        use std::os::windows::process::ExitStatusExt;
+
+        std::process::Output {
+            status: std::process::ExitStatus::from_raw(13),
+            stdout: b"Failed to export driver package: The data is invalid. (simulated)".to_vec(),
+            stderr: Vec::new(),
+        }
+    }
+
+    /// Backup drivers to the specified directory
+    async fn backup_drivers(&self, drivers: Vec<PnPSignedDriver>, system: SystemContext, options: &PackageExportOptions) -> Result<RunStats> {
+        let output_path = &options.output;
+        let layout = options.layout;
+        let verbose = options.verbose;
+        let class_map = match &self.args.command {
+            Some(Commands::Backup { class_map: Some(path), .. }) => load_class_map(path)?,
+            _ => HashMap::new(),
+        };
+        let on_collision = match &self.args.command {
+            Some(Commands::Backup { on_collision, .. }) => *on_collision,
+            _ => CollisionStrategy::Rename,
+        };
+        let dry_run = matches!(self.args.command, Some(Commands::Backup { dry_run: true, .. }));
+        let jobs = match &self.args.command {
+            Some(Commands::Backup { jobs, .. }) => *jobs as usize,
+            _ => 1,
+        };
+        // --diff-base: packages the base already holds in the same version aren't exported again
+        let diff_base = match &self.args.command {
+            Some(Commands::Backup { diff_base: Some(base_dir), .. }) => Some((base_dir.clone(), Self::load_backup_manifest(base_dir)?)),
+            _ => None,
+        };
+        let mut base_packages: Vec<ManifestPackage> = Vec::new();
+        let verify = matches!(self.args.command, Some(Commands::Backup { verify_after_export: true, .. }));
+        let mut verified_count = 0;
+        // (OEM INF, differences) of exports that don't match what WMI reported
+        let mut verify_mismatches: Vec<(String, Vec<String>)> = Vec::new();
+        let base_backup_dir = self.create_base_backup_directory(output_path)?;
+        let mut backed_up_count = 0;
+        let mut failed_count = 0;
+        let mut collision_count = 0;
+        let mut partial_failure = false;
+        // Folders handed out so far; dry runs create nothing, so exists() alone isn't enough
+        let mut used_dirs: std::collections::HashSet<PathBuf> = std::collections::HashSet::new();
+        #[cfg(feature = "testhooks")]
+        let mut export_attempts: usize = 0;
+        let mut exported_dirs: Vec<PathBuf> = Vec::new();
+        let mut manifest_packages: Vec<ManifestPackage> = Vec::new();
+        let mut manifest_failures: Vec<ManifestPackage> = Vec::new();
+        // --export-registry: class GUIDs (lowercase) and device instance IDs of the exported packages
+        let mut registry_classes: BTreeSet<String> = BTreeSet::new();
+        let mut registry_devices: BTreeSet<String> = BTreeSet::new();
+
+        // Group drivers by Device Class, then by INF file name. The groups hold indexes into
+        // `drivers` rather than copies of the drivers.
+        let mut drivers_by_class_inf: DriverGroups = BTreeMap::new();
+
+        for (index, driver) in drivers.iter().enumerate() {
+            if let Some(inf_name) = &driver.inf_name {
+                if let Some(oem_inf) = self.extract_oem_inf_name(inf_name) {
+                    let device_class = map_device_class(&class_map, driver.device_class.as_deref().unwrap_or("Unknown_Class"));
+                    
+                    drivers_by_class_inf
+                        .entry(device_class)
+                        .or_default()
+                        .entry(oem_inf)
+                        .or_default()
+                        .push(index);
+                } else if verbose {
+                    println!("Skipping non-OEM INF: {}", inf_name);
+                }
+            }
+        }
+
+        if matches!(self.args.command, Some(Commands::Backup { latest_only: true, .. })) {
+            Self::keep_latest_packages(&mut drivers_by_class_inf, &drivers, verbose);
+        }
+
+        // BTreeMap keeps classes and INFs in a stable order between runs
+        'classes: for (device_class, infs_in_class) in &drivers_by_class_inf {
+            // Create device class folder
+            let class_folder_name = safe_folder_name(device_class
+                .chars()
+                .map(|c| if c.is_alphanumeric() || c == ' ' || c == '-' { c } else { '_' })
+                .collect::<String>());
+            // Flat packages sit directly in the backup folder
+            let class_backup_dir = match layout {
+                PackageLayout::ByClass => base_backup_dir.join(&class_folder_name),
+                PackageLayout::Flat => base_backup_dir.clone(),
+            };
+            let manifest_folder = |folder_name: &str| match layout {
+                PackageLayout::ByClass => format!("{}\\{}", class_folder_name, folder_name),
+                PackageLayout::Flat => folder_name.to_string(),
+            };
+
+            if verbose {
+                println!("Processing Device Class: {}", device_class);
+                println!("  Class Folder: {}", class_folder_name);
+                println!("  Number of driver packages in this class: {}", infs_in_class.len());
+                println!();
+            }
+
+            if !dry_run {
+                fs::create_dir_all(&class_backup_dir)
+                    .with_context(|| format!("Failed to create class directory: {}", class_backup_dir.display()))?;
+            }
+
+            // Folder names, collisions and folders are settled in order first; the exports then
+            // run up to `--jobs` at a time and their results are handled in the same order
+            let mut planned: Vec<PlannedExport> = Vec::new();
+            for (oem_inf, indexes) in infs_in_class {
+                if interrupted() {
+                    break 'classes;
+                }
+                let drivers_for_package: Vec<&PnPSignedDriver> = indexes.iter().map(|&i| &drivers[i]).collect();
+
+                // Get the primary device name and version for folder naming
+                let primary_device_name = drivers_for_package
+                    .first()
+                    .and_then(|d| d.device_name.as_deref())
+                    .unwrap_or("Unknown_Device");
+                
+                let driver_version = drivers_for_package
+                    .first()
+                    .and_then(|d| d.driver_version.as_deref())
+                    .unwrap_or("Unknown_Version");
+
+                let original_inf = Self::inf_lookup().get(oem_inf).cloned();
+                if let Some((_, base_manifest)) = &diff_base {
+                    let version = drivers_for_package.first().and_then(|d| d.driver_version.as_deref());
+                    if let Some(unchanged) = base_manifest.packages.iter().find(|base| base.same_package(original_inf.as_deref(), version)) {
+                        if verbose {
+                            println!("  Unchanged since base: {} v{} ({})", primary_device_name, driver_version, unchanged.folder);
+                        }
+                        if !base_packages.iter().any(|p| p.folder == unchanged.folder) {
+                            base_packages.push(unchanged.clone());
+                        }
+                        continue;
+                    }
+                }
+
+                let mut folder_name = layout.folder_name(device_class, drivers_for_package[0]);
+
+                let mut driver_backup_dir = class_backup_dir.join(&folder_name);
+
+                // Two packages can share a device name and version; resolve per --on-collision.
+                // Flat packages of the same class, provider and version share their folder
+                if layout == PackageLayout::ByClass && (used_dirs.contains(&driver_backup_dir) || driver_backup_dir.exists()) {
+                    collision_count += 1;
+                    match on_collision {
+                        CollisionStrategy::Rename => {
+                            let base_name = folder_name.clone();
+                            let mut suffix = 2;
+                            while used_dirs.contains(&driver_backup_dir) || driver_backup_dir.exists() {
+                                folder_name = format!("{} ({})", base_name, suffix);
+                                driver_backup_dir = class_backup_dir.join(&folder_name);
+                                suffix += 1;
+                            }
+                            println!("  Folder name collision for {}: using {}", oem_inf, folder_name);
+                        }
+                        CollisionStrategy::Skip => {
+                            println!("  Folder name collision for {}: skipping, {} already exported there", oem_inf, folder_name);
+                            continue;
+                        }
+                        CollisionStrategy::Overwrite => {
+                            println!("  Folder name collision for {}: overwriting {}", oem_inf, folder_name);
+                            if !dry_run && driver_backup_dir.exists() {
+                                fs::remove_dir_all(&driver_backup_dir)
+                                    .with_context(|| format!("Failed to remove {}", driver_backup_dir.display()))?;
+                            }
+                            // Folders are per class, so a replaced package of this run is one planned above
+                            planned.retain(|package| package.dir != driver_backup_dir);
+                        }
+                    }
+                }
+                used_dirs.insert(driver_backup_dir.clone());
+
+                if verbose {
+                    let original_inf = Self::inf_lookup().get(oem_inf).map(|s| s.as_str()).unwrap_or("unknown");
+                    println!("  Processing driver package: {} v{} ({} -> {})", primary_device_name, driver_version, oem_inf, original_inf);
+                    println!("    Folder: {}", folder_name);
+                    println!("    Number of devices in this package: {}", drivers_for_package.len());
+                    println!();
+                    for (index, driver) in drivers_for_package.iter().enumerate() {
+                        println!("      {}. Device: {}", index + 1, driver.device_name.as_deref().unwrap_or("Unknown"));
+                        println!("         INF: {}", driver.inf_name.as_deref().unwrap_or("Unknown"));
+                        println!("         Hardware ID: {}", driver.hardware_id.as_deref().unwrap_or("Unknown"));
+                        println!("         Device ID: {}", driver.device_id.as_deref().unwrap_or("Unknown"));
+                        println!("         Description: {}", driver.description.as_deref().unwrap_or("Unknown"));
+                        println!("         Provider: {}", driver.driver_provider_name.as_deref().unwrap_or("Unknown"));
+                        println!("         Version: {}", driver.driver_version.as_deref().unwrap_or("Unknown"));
+                        println!("         Date: {}", format_driver_date(driver.driver_date.as_deref(), verbose));
+                        println!();
+                    }
+                }
+
+                if dry_run {
+                    backed_up_count += 1;
+                    continue;
+                }
+                fs::create_dir_all(&driver_backup_dir)
+                    .with_context(|| format!("Failed to create driver directory: {}", driver_backup_dir.display()))?;
+                if !driver_backup_dir.exists() {
+                    anyhow::bail!("Failed to create driver directory: {}", driver_backup_dir.display());
+                }
+                if verbose {
+                    println!("      Created folder: {}", driver_backup_dir.display());
+                }
+
+                // Export the driver package (only need to export once per INF)
+                let backup_dir_str = driver_backup_dir.to_string_lossy();
+                if backup_dir_str.contains("..") || backup_dir_str.contains("%") {
+                    eprintln!("Skipping export due to unsafe path: {}", backup_dir_str);
+                    failed_count += 1;
+                    continue;
+                }
+
+                planned.push(PlannedExport {
+                    oem_inf,
+                    drivers: drivers_for_package,
+                    folder_name,
+                    dir: driver_backup_dir,
+                    original_inf,
+                });
+            }
+
+            let statuses = Self::export_packages(&planned, jobs, verbose);
+            for (package, status) in planned.iter().zip(statuses) {
+                // Not started because of Ctrl-C
+                let Some(status) = status else { continue };
+                let (oem_inf, drivers_for_package, folder_name, driver_backup_dir, original_inf) =
+                    (package.oem_inf, &package.drivers, &package.folder_name, &package.dir, &package.original_inf);
+
+                #[cfg(feature = "testhooks")]
+                let status = {
+                    export_attempts += 1;
+                    match &self.args.command {
+                        Some(Commands::Backup { simulate_failure: Some(n), .. })
+                            if *n > 0 && export_attempts % n == 0 =>
+                        {
+                            let output = Self::synthetic_export_failure();
+                            let record = ExportRecord::new("(simulated failure)".to_string(), &output, Duration::ZERO);
+                            Ok((output, record))
+                        }
+                        _ => status,
+                    }
+                };
+
+                let manifest_package = |export_record| ManifestPackage {
+                    device_class: device_class.clone(),
+                    folder: manifest_folder(folder_name),
+                    oem_inf: oem_inf.clone(),
+                    original_inf: original_inf.clone(),
+                    driver_version: drivers_for_package.first().and_then(|d| d.driver_version.clone()),
+                    provider: drivers_for_package.first().and_then(|d| d.driver_provider_name.clone()),
+                    devices: drivers_for_package.iter().filter_map(|d| d.device_name.clone()).collect(),
+                    export: Some(export_record),
+                };
+                match status {
+                    Ok((output, export_record)) => {
+                        if output.status.success() {
+                            backed_up_count += 1;
+                            if !exported_dirs.contains(driver_backup_dir) {
+                                exported_dirs.push(driver_backup_dir.clone());
+                            }
+                            for driver in drivers_for_package {
+                                registry_classes.extend(driver.class_guid.as_ref().map(|guid| guid.to_lowercase()));
+                                registry_devices.extend(driver.device_id.clone());
+                            }
+                            manifest_packages.push(manifest_package(export_record));
+                            if verbose {
+                                println!("        ✓ Successfully exported: {}", oem_inf);
+                            }
+                            if verify {
+                                verified_count += 1;
+                                let differences = Self::verify_exported_package(driver_backup_dir, original_inf.as_deref(), drivers_for_package[0]);
+                                if !differences.is_empty() {
+                                    eprintln!("⚠ Exported {} doesn't match WMI: {}", oem_inf, differences.join("; "));
+                                    verify_mismatches.push((oem_inf.clone(), differences));
+                                }
+                            }
+                            if matches!(self.args.command, Some(Commands::Backup { zip_per_package: true, .. })) {
+                                // The folder is already backed up, so a failed zip is only a warning
+                                let zip_path = class_backup_dir.join(format!("{}.zip", folder_name));
+                                let csv = self.package_csv(folder_name, oem_inf, drivers_for_package);
+                                if let Err(e) = Self::zip_package(driver_backup_dir, &zip_path, &format!("{}.csv", folder_name), &csv) {
+                                    eprintln!("Warning: Failed to create {}: {:#}", zip_path.display(), e);
+                                }
+                            }
+                        } else {
+                            let stdout = String::from_utf8_lossy(&output.stdout);
+                            let stderr = String::from_utf8_lossy(&output.stderr);
+                            
+                            eprintln!("✗ Failed to export {}:", oem_inf);
+                            if !stdout.is_empty() {
+                                eprintln!("  stdout: {}", stdout.trim());
+                            }
+                            if !stderr.is_empty() {
+                                eprintln!("  stderr: {}", stderr.trim());
+                            }
+                            let exit_code = output.status.code().unwrap_or(-1);
+                            let stderr_lower = stderr.to_lowercase();
+                            let stdout_lower = stdout.to_lowercase();
+
+                            if stderr_lower.contains("access") || stderr_lower.contains("denied") {
+                                eprintln!("  → This might be a permissions issue. Try running as Administrator.");
+                            } else if stderr_lower.contains("not found") || stderr_lower.contains("cannot find") {
+                                eprintln!("  → Driver package might be corrupted or already removed.");
+                            } else if stdout_lower.contains("missing or invalid target directory") || exit_code == 87 {
+                                eprintln!("  → Path too long or invalid. Using shorter path and retrying...");
+                            } else if stdout_lower.contains("the data is invalid") || exit_code == 13 {
+                                eprintln!("  → This driver may be protected or corrupted. Skipping.");
+                            }
+
+                            manifest_failures.push(manifest_package(export_record));
+                            failed_count += 1;
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("✗ Failed to execute pnputil for {}:", oem_inf);
+                        eprintln!("  Error: {}", e);
+                        eprintln!("  → Make sure pnputil is in your PATH and you have administrative privileges.");
+                        failed_count += 1;
+                    }
+                }
+            }
+            if interrupted() {
+                break 'classes;
+            }
+        }
+
+        println!("\nDriver export completed!");
+        println!("Successfully exported: {} driver packages", backed_up_count);
+        if failed_count > 0 {
+            println!("Failed to export: {} drivers", failed_count);
+        }
+        if diff_base.is_some() {
+            println!("Unchanged since the base backup (not exported): {} driver packages", base_packages.len());
+        }
+        if verify && !dry_run {
+            println!("Verified after export: {} packages, {} mismatches", verified_count, verify_mismatches.len());
+            for (oem_inf, differences) in &verify_mismatches {
+                println!("  {}: {}", oem_inf, differences.join("; "));
+            }
+            if !verify_mismatches.is_empty() {
+                partial_failure = true;
+            }
+        }
+        if collision_count > 0 {
+            let action = match on_collision {
+                CollisionStrategy::Rename => "renamed",
+                CollisionStrategy::Skip => "skipped",
+                CollisionStrategy::Overwrite => "overwritten",
+            };
+            println!("Folder name collisions: {} ({})", collision_count, action);
+        }
+
+        if interrupted() {
+            if matches!(self.args.command, Some(Commands::Backup { clean_on_abort: true, .. })) && !dry_run {
+                fs::remove_dir_all(&base_backup_dir)
+                    .with_context(|| format!("Failed to remove partial backup: {}", base_backup_dir.display()))?;
+                println!("Removed partial backup: {}", base_backup_dir.display());
+            } else {
+                println!("Partial backup left at: {}", base_backup_dir.display());
+            }
+            anyhow::bail!("Backup interrupted by Ctrl-C");
+        }
+
+        if !dry_run {
+            if matches!(self.args.command, Some(Commands::Backup { dedupe_against_previous: true, .. })) {
+                println!("\nDeduplicating against the previous backup...");
+                match Self::dedupe_against_previous(&base_backup_dir, verbose)? {
+                    Some((previous, linked_count, bytes_saved)) => {
+                        println!("Linked {} files to {}, saved {:.1} MB",
+                            linked_count, previous.display(), bytes_saved as f64 / (1024.0 * 1024.0));
+                    }
+                    None => println!("No earlier backup in {}; keeping full copies.", output_path.display()),
+                }
+            }
+
+            if matches!(self.args.command, Some(Commands::Backup { dedup_files: true, .. })) {
+                println!("\nDeduplicating identical files...");
+                let (linked_count, bytes_saved) = Self::dedup_files(&base_backup_dir, verbose)?;
+                println!("Replaced {} duplicate files with hardlinks, saved {:.1} MB",
+                    linked_count, bytes_saved as f64 / (1024.0 * 1024.0));
+            }
+
+            if matches!(self.args.command, Some(Commands::Backup { export_registry: true, .. })) {
+                println!("\nExporting driver registry keys...");
+                Self::export_registry_keys(&base_backup_dir, &registry_classes, &registry_devices, verbose)?;
+            }
+
+            if matches!(self.args.command, Some(Commands::Backup { no_summary_csv: true, .. })) {
+                println!("\nSkipping the summary CSV (--no-summary-csv)");
+            }
+            if options.summary_csv || options.readmes {
+                println!("\nScanning exported drivers to create summary...");
+
+                // Use InfParser to scan the backup folder; the CSV and the READMEs share its result
+                let parsed_files = InfParser::scan_backup(&base_backup_dir, verbose)?;
+                if options.summary_csv {
+                    let csv_path = base_backup_dir.join("all_drivers.csv");
+                    InfParser::export_backup_summary(&parsed_files, &base_backup_dir, &csv_path, &system.lines())?;
+                }
+                if options.readmes && layout == PackageLayout::ByClass {
+                    let generated = self.backup_time().format("%Y-%m-%d %H:%M:%S %:z").to_string();
+                    let count = InfParser::write_class_readmes(&parsed_files, &base_backup_dir, &generated)?;
+                    println!("Class README.txt files written: {}", count);
+                }
+            }
+
+            let package_folders: Vec<String> = manifest_packages.iter().map(|p| p.folder.clone()).collect();
+            let manifest = BackupManifest {
+                schema_version: MANIFEST_SCHEMA_VERSION,
+                tool_version: env!("CARGO_PKG_VERSION").to_string(),
+                created: self.backup_time().to_rfc3339(),
+                system,
+                packages: manifest_packages,
+                failures: manifest_failures,
+            };
+            Self::write_backup_info(&base_backup_dir, &manifest)?;
+
+            if let Some((base_dir, base_manifest)) = &diff_base {
+                let diff_manifest = DiffManifest {
+                    schema_version: MANIFEST_SCHEMA_VERSION,
+                    created: manifest.created.clone(),
+                    base: std::path::absolute(base_dir).unwrap_or_else(|_| base_dir.clone()),
+                    base_created: base_manifest.created.clone(),
+                    base_packages,
+                };
+                let diff_path = base_backup_dir.join("diff_manifest.json");
+                fs::write(&diff_path, serde_json::to_string_pretty(&diff_manifest)?)
+                    .with_context(|| format!("Failed to write diff manifest: {}", diff_path.display()))?;
+                println!("Diff manifest created: {} ({} base packages still required)", diff_path.display(), diff_manifest.base_packages.len());
+            }
+
+            if let Some(Commands::Backup { report_template: Some(template), .. }) = &self.args.command {
+                let report_path = Self::write_report(&base_backup_dir, &manifest, failed_count, template.as_deref())?;
+                println!("Report created: {}", report_path.display());
+            }
+
+            if let Some(Commands::Backup { gen_install_script: Some(kind), .. }) = &self.args.command {
+                let script_path = Self::write_install_script(&base_backup_dir, &package_folders, *kind)?;
+                println!("Install script created: {}", script_path.display());
+            }
+
+            if let Some(Commands::Backup { deploy_list: Some(list_path), .. }) = &self.args.command {
+                Self::write_deploy_list(&exported_dirs, list_path)?;
+                println!("Deployment list created: {}", list_path.display());
+            }
+
+            if let Some(Commands::Backup { unattend_snippet: Some(snippet_path), path_prefix, .. }) = &self.args.command {
+                Self::write_unattend_snippet(&base_backup_dir, &package_folders, path_prefix.as_deref(), snippet_path)?;
+                println!("Unattend snippet created: {} ({} driver paths)", snippet_path.display(), package_folders.len());
+            }
+
+            if let Some(Commands::Backup { for_dism: Some(dism_dir), .. }) = &self.args.command {
+                println!("\nPreparing packages for DISM...");
+                let (copied, excluded) = Self::write_dism_folder(&base_backup_dir, &manifest.packages, dism_dir, verbose)?;
+                println!("DISM-ready packages: {} ({} extension/software-only skipped)", copied, excluded);
+                println!("DISM command line: {}", dism_dir.join("dism_command.txt").display());
+            }
+
+            if let Some(Commands::Backup { printers: true, wmi_timeout, .. }) = &self.args.command {
+                println!("\nBacking up printer drivers...");
+                let printers = Self::query_printer_drivers(wmi_timeout.map(Duration::from_secs))?;
+                let script_path = Self::backup_printer_drivers(&base_backup_dir, &printers, verbose)?;
+                println!("Printer drivers saved: {}", printers.len());
+                println!("Printer driver install script: {}", script_path.display());
+            }
+            
+            println!("\nBackup location: {}", base_backup_dir.display());
+
+            // Last, so the mirror holds everything written above
+            if let Some(Commands::Backup { mirror: Some(mirror_root), .. }) = &self.args.command {
+                println!("\nMirroring backup to {}...", mirror_root.display());
+                if let Err(e) = Self::mirror_backup(&base_backup_dir, mirror_root, verbose) {
+                    eprintln!("✗ Mirror failed: {:#}", e);
+                    eprintln!("  The backup itself is complete at {}", base_backup_dir.display());
+                    partial_failure = true;
+                }
+            }
+        }
+
+        Ok(RunStats {
+            location: (!dry_run).then_some(base_backup_dir),
+            succeeded: backed_up_count,
+            failed: failed_count,
+            partial_failure,
+        })
+    }
+
+    /// `--export-registry`: `reg export` the `Control\Class\{guid}` key of each class and the
+    /// `Enum\<device ID>` key of each device into `registry\`, for settings pnputil doesn't keep.
+    /// Every key is listed in `registry\exported_keys.txt`, including those that aren't present
+    /// or failed to export; those only give a warning.
+    fn export_registry_keys(backup_dir: &Path, class_guids: &BTreeSet<String>, device_ids: &BTreeSet<String>, verbose: bool) -> Result<()> {
+        const CONTROL_SET: &str = "HKLM\\SYSTEM\\CurrentControlSet";
+        let registry_dir = backup_dir.join("registry");
+        fs::create_dir_all(&registry_dir)
+            .with_context(|| format!("Failed to create {}", registry_dir.display()))?;
+
+        let keys = class_guids.iter()
+            .map(|guid| (format!("{}\\Control\\Class\\{}", CONTROL_SET, guid), format!("Class_{}.reg", guid)))
+            .chain(device_ids.iter().map(|device_id| {
+                let file_name: String = device_id.chars()
+                    .map(|c| match c {
+                        '\\' => '#',
+                        '<' | '>' | ':' | '"' | '/' | '|' | '?' | '*' => '_',
+                        c => c,
+                    })
+                    .collect();
+                (format!("{}\\Enum\\{}", CONTROL_SET, device_id), format!("Enum_{}.reg", file_name))
+            }));
+
+        let (mut exported, mut missing, mut failed) = (0, 0, 0);
+        let mut list = String::new();
+        for (key, file_name) in keys {
+            let present = Command::new("reg")
+                .args(["query", &key, "/ve"])
+                .output()
+                .is_ok_and(|output| output.status.success());
+            let outcome = if !present {
+                missing += 1;
+                "not present".to_string()
+            } else {
+                let output = Command::new("reg")
+                    .arg("export")
+                    .arg(&key)
+                    .arg(registry_dir.join(&file_name))
+                    .arg("/y")
+                    .output();
+                match output {
+                    Ok(output) if output.status.success() => {
+                        exported += 1;
+                        file_name
+                    }
+                    result => {
+                        failed += 1;
+                        let reason = match result {
+                            Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                            Err(e) => e.to_string(),
+                        };
+                        eprintln!("Warning: Failed to export {}: {}", key, reason);
+                        format!("failed: {}", reason)
+                    }
+                }
+            };
+            if verbose {
+                println!("  {} -> {}", key, outcome);
+            }
+            list.push_str(&format!("{}\t{}\r\n", key, outcome));
+        }
+
+        let list_path = registry_dir.join("exported_keys.txt");
+        fs::write(&list_path, list)
+            .with_context(|| format!("Failed to write {}", list_path.display()))?;
+        println!("Registry keys exported: {} ({} not present, {} failed), listed in {}",
+            exported, missing, failed, list_path.display());
+        Ok(())
+    }
+
+    /// `--mirror`: copy the finished backup folder into `mirror_root` (a local folder or UNC
+    /// share). Each file is hashed before copying and the copy is checked by size and SHA-256;
+    /// a failed copy or mismatch is retried with backoff, for flaky network shares.
+    fn mirror_backup(backup_dir: &Path, mirror_root: &Path, verbose: bool) -> Result<()> {
+        const MIRROR_ATTEMPTS: u32 = 3;
+        let started = Instant::now();
+        let mirror_dir = mirror_root.join(backup_dir.file_name().unwrap_or_default());
+
+        let mut files = Vec::new();
+        let mut warnings = Vec::new();
+        InfParser::find_files_recursive(backup_dir, &WalkOptions::default(), &|_| true, &mut files, &mut warnings)?;
+        if let Some((dir, error)) = warnings.first() {
+            anyhow::bail!("Failed to read {}: {}", dir.display(), error);
+        }
+        files.sort();
+
+        let mut bytes_copied: u64 = 0;
+        let mut failed: Vec<PathBuf> = Vec::new();
+        for file in &files {
+            if interrupted() {
+                anyhow::bail!("Interrupted by Ctrl-C");
+            }
+            let relative = file.strip_prefix(backup_dir).unwrap_or(file);
+            let dest = mirror_dir.join(relative);
+            let expected = sha256_file(file)?;
+            let size = fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+
+            let mut attempt = 1;
+            let result = loop {
+                let copied = dest.parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|()| fs::copy(file, &dest))
+                    .map_err(anyhow::Error::from)
+                    .and_then(|copied_size| {
+                        if copied_size != size {
+                            anyhow::bail!("size mismatch ({} of {} bytes)", copied_size, size);
+                        }
+                        if sha256_file(&dest)? != expected {
+                            anyhow::bail!("SHA-256 mismatch");
+                        }
+                        Ok(())
+                    });
+                match copied {
+                    Err(e) if attempt < MIRROR_ATTEMPTS => {
+                        eprintln!("  Retrying {} ({:#}), attempt {}/{}", relative.display(), e, attempt + 1, MIRROR_ATTEMPTS);
+                        std::thread::sleep(Duration::from_secs(2u64.pow(attempt)));
+                        attempt += 1;
+                    }
+                    result => break result,
+                }
+            };
+            match result {
+                Ok(()) => {
+                    bytes_copied += size;
+                    if verbose {
+                        println!("  ✓ {}", relative.display());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("  ✗ {}: {:#}", relative.display(), e);
+                    failed.push(relative.to_path_buf());
+                }
+            }
+        }
+
+        let elapsed = started.elapsed();
+        println!("Mirrored {} of {} files, {} in {:.1?} ({:.1} MB/s)",
+            files.len() - failed.len(), files.len(), format_size(bytes_copied), elapsed,
+            bytes_copied as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64().max(0.001));
+        println!("Mirror location: {}", mirror_dir.display());
+        if !failed.is_empty() {
+            anyhow::bail!("{} files could not be copied and verified", failed.len());
+        }
+        Ok(())
+    }
+
+    /// Export the packages of `planned`, up to `jobs` at a time, and return their results in
+    /// the same order. Packages sharing a folder (flat layout) go to the same worker, one after
+    /// the other. `None` marks an export that wasn't started because of Ctrl-C.
+    fn export_packages(planned: &[PlannedExport], jobs: usize, verbose: bool) -> Vec<Option<ExportStatus>> {
+        let mut units: Vec<Vec<usize>> = Vec::new();
+        let mut unit_of_dir: HashMap<&Path, usize> = HashMap::new();
+        for (index, package) in planned.iter().enumerate() {
+            let unit = *unit_of_dir.entry(&package.dir).or_insert_with(|| {
+                units.push(Vec::new());
+                units.len() - 1
+            });
+            units[unit].push(index);
+        }
+
+        let next_unit = std::sync::atomic::AtomicUsize::new(0);
+        let results: Vec<std::sync::Mutex<Option<ExportStatus>>> = planned.iter().map(|_| Default::default()).collect();
+        std::thread::scope(|scope| {
+            for _ in 0..jobs.min(units.len()) {
+                scope.spawn(|| {
+                    while let Some(unit) = units.get(next_unit.fetch_add(1, Ordering::Relaxed)) {
+                        for &index in unit {
+                            if interrupted() {
+                                return;
+                            }
+                            let package = &planned[index];
+                            if verbose {
+                                println!("        Exporting {} to {}...", package.oem_inf, package.dir.display());
+                            }
+                            let status = Self::export_package(package.oem_inf, &package.dir, Self::inf_lookup(), verbose);
+                            *results[index].lock().unwrap_or_else(|e| e.into_inner()) = Some(status);
+                        }
+                    }
+                });
+            }
+        });
+        results.into_iter()
+            .map(|result| result.into_inner().unwrap_or_else(|e| e.into_inner()))
+            .collect()
+    }
+
+    /// Export one OEM package into `dest` with `pnputil /export-driver`. Without pnputil
+    /// (e.g. WinPE), the staged package is copied out of the DriverStore instead.
+    /// With `verbose`, the command line is printed before it runs.
+    fn export_package(oem_inf: &str, dest: &Path, inf_lookup: &HashMap<String, String>, verbose: bool) -> std::io::Result<(std::process::Output, ExportRecord)> {
+        let export_started = Instant::now();
+        let status = ExportRecord::run(Command::new("pnputil").arg("/export-driver").arg(oem_inf).arg(dest), verbose);
+        log::debug!("pnputil /export-driver {} took {:.2?}", oem_inf, export_started.elapsed());
+
+        match status {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::copy_from_driver_store(oem_inf, dest, inf_lookup, verbose),
+            status => status,
+        }
+    }
+
+    /// `--verify-after-export`: parse the exported INF and compare its version, provider and
+    /// class with the WMI record the package was exported for. Returns the differences.
+    fn verify_exported_package(package_dir: &Path, original_inf: Option<&str>, driver: &PnPSignedDriver) -> Vec<String> {
+        let inf_files = match InfParser::find_inf_files_in_folder(package_dir) {
+            Ok(inf_files) => inf_files,
+            Err(e) => return vec![format!("can't list the exported files ({:#})", e)],
+        };
+        // The export keeps the original INF name; fall back to the only INF of the folder
+        let inf_file = inf_files.iter()
+            .find(|inf| original_inf.is_some_and(|name| inf.file_name().is_some_and(|f| f.to_string_lossy().eq_ignore_ascii_case(name))))
+            .or(inf_files.first().filter(|_| inf_files.len() == 1));
+        let Some(inf_file) = inf_file else {
+            return vec![format!("exported INF {} not found", original_inf.unwrap_or("(unknown name)"))];
+        };
+        let parsed = match InfParser::parse_inf_file(inf_file, DEFAULT_MAX_INF_SIZE) {
+            Ok(parsed) => parsed,
+            Err(e) => return vec![format!("can't parse {} ({:#})", inf_file.display(), e)],
+        };
+
+        let mut differences = Vec::new();
+        let version = parsed.raw_version_info.driver_version.as_deref();
+        if let (Some(expected), Some(exported)) = (driver.driver_version.as_deref(), version) {
+            if !compare_driver_versions(expected, exported).is_eq() {
+                differences.push(format!("version {} in WMI, {} exported", expected, exported));
+            }
+        }
+        // The [Version] provider is often a %token%; device entries carry the resolved name
+        let provider = parsed.drivers.first().and_then(|d| d.driver_provider_name.as_deref())
+            .or(parsed.raw_version_info.provider.as_deref());
+        if let (Some(expected), Some(exported)) = (driver.driver_provider_name.as_deref(), provider) {
+            if !expected.trim().eq_ignore_ascii_case(exported.trim()) {
+                differences.push(format!("provider '{}' in WMI, '{}' exported", expected, exported));
+            }
+        }
+        if let (Some(expected), Some(exported)) = (driver.device_class.as_deref(), parsed.raw_version_info.class.as_deref()) {
+            if !expected.eq_ignore_ascii_case(exported) {
+                differences.push(format!("class {} in WMI, {} exported", expected, exported));
+            }
+        }
+        differences
+    }
+
+    /// Fallback for a missing pnputil: copy the package folder from the DriverStore
+    /// with `xcopy`. Unlike `/export-driver`, this copies the folder as staged,
+    /// including any extra files Windows keeps next to the INF.
+    fn copy_from_driver_store(oem_inf: &str, dest: &Path, inf_lookup: &HashMap<String, String>, verbose: bool) -> std::io::Result<(std::process::Output, ExportRecord)> {
+        let original_inf = inf_lookup.get(oem_inf).cloned().unwrap_or_else(|| oem_inf.to_string());
+        let Some(package) = Self::driver_store_package(&Self::driver_store_repository(), &original_inf) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("pnputil is not available and no DriverStore package was found for {}", original_inf),
+            ));
+        };
+        if verbose {
+            println!("        pnputil not found, copying {} from the DriverStore instead", package.display());
+        }
+        ExportRecord::run(Command::new("xcopy").arg(&package).arg(dest).args(["/E", "/I", "/Y", "/Q"]), verbose)
+    }
+
+    /// One-row CSV describing a package, stored inside its `--zip-per-package` archive
+    fn package_csv(&self, folder_name: &str, oem_inf: &str, drivers: &[&PnPSignedDriver]) -> String {
+        let first = drivers.first();
+        let field = |get: fn(&PnPSignedDriver) -> Option<&String>| first.and_then(|d| get(d)).map(String::as_str).unwrap_or("Unknown");
+        let joined = |get: fn(&PnPSignedDriver) -> Option<&String>| drivers.iter()
+            .filter_map(|d| get(d).cloned())
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        let mut csv = CsvWriter::new(',');
+        csv.write_row(&["Package", "OEM INF", "Original INF", "Device Class", "Provider", "Driver Version", "Driver Date", "Device Count", "Device Names", "Hardware IDs"]);
+        csv.write_row(&[
+            folder_name,
+            oem_inf,
+            Self::inf_lookup().get(oem_inf).map(|s| s.as_str()).unwrap_or("unknown"),
+            field(|d| d.device_class.as_ref()),
+            field(|d| d.driver_provider_name.as_ref()),
+            field(|d| d.driver_version.as_ref()),
+            &self.format_driver_date(&first.and_then(|d| d.driver_date.clone())),
+            &drivers.len().to_string(),
+            &joined(|d| d.device_name.as_ref()),
+            &joined(|d| d.hardware_id.as_ref()),
+        ]);
+        csv.content
+    }
+
+    /// Compress an exported package folder into `zip_path`, adding `csv` as `csv_name`
+    fn zip_package(package_dir: &Path, zip_path: &Path, csv_name: &str, csv: &str) -> Result<()> {
+        use std::io::Write;
+
+        let mut files = Vec::new();
+        InfParser::find_files_recursive(package_dir, &WalkOptions::default(), &|_| true, &mut files, &mut Vec::new())?;
+        files.sort();
+
+        let file = fs::File::create(zip_path)
+            .with_context(|| format!("Failed to create file: {}", zip_path.display()))?;
+        let mut zip = zip::ZipWriter::new(file);
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+
+        for path in &files {
+            // ZIP entry names always use forward slashes
+            let name = path.strip_prefix(package_dir).unwrap_or(path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            zip.start_file(name.as_str(), options)
+                .with_context(|| format!("Failed to add {} to {}", name, zip_path.display()))?;
+            let mut source = fs::File::open(path)
+                .with_context(|| format!("Failed to open file: {}", path.display()))?;
+            std::io::copy(&mut source, &mut zip)
+                .with_context(|| format!("Failed to add {} to {}", name, zip_path.display()))?;
+        }
+
+        zip.start_file(csv_name, options)
+            .with_context(|| format!("Failed to add {} to {}", csv_name, zip_path.display()))?;
+        zip.write_all(csv.as_bytes())?;
+        zip.finish()
+            .with_context(|| format!("Failed to finish {}", zip_path.display()))?;
+        Ok(())
+    }
+
+    /// Replace byte-identical files across the backup with hardlinks to one copy.
+    /// Returns (files linked, bytes saved).
+    fn dedup_files(backup_dir: &Path, verbose: bool) -> Result<(usize, u64)> {
+        let mut all_files = Vec::new();
+        let mut warnings = Vec::new();
+        InfParser::find_files_recursive(backup_dir, &WalkOptions::default(), &|_| true, &mut all_files, &mut warnings)?;
+        all_files.sort();
+
+        // Only files sharing a size can be identical, so hash those alone
+        let mut by_size: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+        for file in all_files {
+            if let Ok(metadata) = fs::metadata(&file) {
+                if metadata.len() > 0 {
+                    by_size.entry(metadata.len()).or_default().push(file);
+                }
+            }
+        }
+
+        let mut linked_count = 0;
+        let mut bytes_saved = 0;
+
+        for (size, files) in by_size.into_iter().filter(|(_, files)| files.len() > 1) {
+            let mut by_hash: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+            for file in files {
+                if let Ok(hash) = sha256_file(&file) {
+                    by_hash.entry(hash).or_default().push(file);
+                }
+            }
+
+            for (original, duplicates) in by_hash.values().filter_map(|files| files.split_first()) {
+                for duplicate in duplicates {
+                    // Link under a temporary name first so the copy is never lost
+                    let temp_link = duplicate.with_extension("dedup_tmp");
+                    if let Err(e) = fs::hard_link(original, &temp_link) {
+                        println!("Hardlinks not supported on this drive ({}); keeping duplicate copies.", e);
+                        return Ok((linked_count, bytes_saved));
+                    }
+                    if fs::rename(&temp_link, duplicate).is_err() {
+                        let _ = fs::remove_file(&temp_link);
+                        continue;
+                    }
+
+                    if verbose {
+                        println!("  Linked {} -> {}", duplicate.display(), original.display());
+                    }
+                    linked_count += 1;
+                    bytes_saved += size;
+                }
+            }
+        }
+
+        Ok((linked_count, bytes_saved))
+    }
+
+    /// `--dedupe-against-previous`: replace files identical to one in the newest earlier backup
+    /// next to `backup_dir` with hardlinks to that copy. Only files of the same size are hashed.
+    /// Keeps full copies when the backups are on different volumes or the drive can't hardlink.
+    /// Returns the earlier backup and (files linked, bytes saved), `None` if there is none.
+    fn dedupe_against_previous(backup_dir: &Path, verbose: bool) -> Result<Option<(PathBuf, usize, u64)>> {
+        let Some(root) = backup_dir.parent() else { return Ok(None) };
+        let current_time = Self::backup_timestamp(backup_dir);
+        let entries = fs::read_dir(root)
+            .with_context(|| format!("Failed to read backup folder: {}", root.display()))?;
+        let previous = entries
+            .flatten()
+            .filter(|entry| entry.file_type().map(|t| t.is_dir()).unwrap_or(false))
+            .filter_map(|entry| Some((Self::backup_timestamp(&entry.path())?, entry.path())))
+            .filter(|(timestamp, path)| path != backup_dir && current_time.is_none_or(|current| *timestamp < current))
+            .max_by_key(|(timestamp, _)| *timestamp)
+            .map(|(_, path)| path);
+        let Some(previous) = previous else { return Ok(None) };
+
+        // Hardlinks can't span volumes; a junction in the output folder could put them on different ones
+        let volume = |path: &Path| {
+            fs::canonicalize(path).ok()
+                .and_then(|path| path.components().next().map(|c| c.as_os_str().to_ascii_lowercase()))
+        };
+        if volume(backup_dir).is_none() || volume(backup_dir) != volume(&previous) {
+            if verbose {
+                println!("  {} is on another volume; keeping full copies.", previous.display());
+            }
+            return Ok(Some((previous, 0, 0)));
+        }
+
+        let mut warnings = Vec::new();
+        let mut previous_files = Vec::new();
+        InfParser::find_files_recursive(&previous, &WalkOptions::default(), &|_| true, &mut previous_files, &mut warnings)?;
+        let mut previous_by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        for file in previous_files {
+            if let Ok(metadata) = fs::metadata(&file) {
+                if metadata.len() > 0 {
+                    previous_by_size.entry(metadata.len()).or_default().push(file);
+                }
+            }
+        }
+
+        let mut new_files = Vec::new();
+        InfParser::find_files_recursive(backup_dir, &WalkOptions::default(), &|_| true, &mut new_files, &mut warnings)?;
+        new_files.sort();
+
+        let mut previous_hashes: HashMap<PathBuf, Option<String>> = HashMap::new();
+        let mut linked_count = 0;
+        let mut bytes_saved = 0;
+
+        for file in new_files {
+            let Ok(size) = fs::metadata(&file).map(|m| m.len()) else { continue };
+            let Some(candidates) = previous_by_size.get(&size) else { continue };
+            let Ok(hash) = sha256_file(&file) else { continue };
+            let original = candidates.iter().find(|candidate| {
+                previous_hashes.entry(candidate.to_path_buf())
+                    .or_insert_with(|| sha256_file(candidate).ok())
+                    .as_deref() == Some(hash.as_str())
+            });
+            let Some(original) = original else { continue };
+
+            // Link under a temporary name first so the copy is never lost
+            let temp_link = file.with_extension("dedup_tmp");
+            if let Err(e) = fs::hard_link(original, &temp_link) {
+                // FAT/exFAT and most network shares: keep the full copies
+                if verbose {
+                    println!("  Hardlinks not available ({}); keeping full copies.", e);
+                }
+                break;
+            }
+            if fs::rename(&temp_link, &file).is_err() {
+                let _ = fs::remove_file(&temp_link);
+                continue;
+            }
+
+            if verbose {
+                println!("  Linked {} -> {}", file.display(), original.display());
+            }
+            linked_count += 1;
+            bytes_saved += size;
+        }
+
+        Ok(Some((previous, linked_count, bytes_saved)))
+    }
+
+    /// When a backup folder named `drivers_YYYYMMDD_HHMMSS` was made. The `created` time in its
+    /// `manifest.json` carries the offset; without one the name is read as UTC, which is off
+    /// by the local offset for `backup --local-time` folders.
+    fn backup_timestamp(backup_dir: &Path) -> Option<chrono::DateTime<Utc>> {
+        let name = backup_dir.file_name()?.to_string_lossy();
+        let from_name = chrono::NaiveDateTime::parse_from_str(name.strip_prefix("drivers_")?, "%Y%m%d_%H%M%S").ok()?;
+        let created = Self::manifest_created(backup_dir)
+            .and_then(|created| chrono::DateTime::parse_from_rfc3339(&created).ok());
+        Some(created.map(|created| created.with_timezone(&Utc)).unwrap_or_else(|| from_name.and_utc()))
+    }
+
+    /// `created` of a backup's `manifest.json`, read without the schema check of `load_backup_manifest`
+    fn manifest_created(backup_dir: &Path) -> Option<String> {
+        let json = fs::read_to_string(backup_dir.join("manifest.json")).ok()?;
+        let manifest: serde_json::Value = serde_json::from_str(&json).ok()?;
+        Some(manifest.get("created")?.as_str()?.to_string())
+    }
+
+    /// Source machine of a backup's `manifest.json`, read from the raw JSON: `SystemContext`
+    /// deserializes the WMI property names, not the names it's written with
+    fn manifest_system(backup_dir: &Path) -> Option<SystemContext> {
+        let json = fs::read_to_string(backup_dir.join("manifest.json")).ok()?;
+        let manifest: serde_json::Value = serde_json::from_str(&json).ok()?;
+        let section = |name: &str| manifest.get(name).filter(|value| value.is_object());
+        let text = |section: &serde_json::Value, key: &str| section.get(key).and_then(|value| value.as_str()).map(str::to_string);
+        Some(SystemContext {
+            os: section("os").map(|os| OsInfo {
+                caption: text(os, "caption"),
+                version: text(os, "version"),
+                build_number: text(os, "build_number"),
+                architecture: text(os, "architecture"),
+            }),
+            computer: section("computer").map(|computer| ComputerInfo {
+                manufacturer: text(computer, "manufacturer"),
+                model: text(computer, "model"),
+            }),
+            bios: section("bios").map(|bios| BiosInfo {
+                version: text(bios, "version"),
+                serial_number: text(bios, "serial_number"),
+            }),
+        })
+    }
+
+    /// Print the machine a backup was taken on and warn when its architecture or model
+    /// differs from this machine's. Only informs: the restore goes ahead either way.
+    fn check_source_machine(backup_dir: &Path) {
+        let Some(source) = Self::manifest_system(backup_dir) else {
+            println!("Source machine: unknown (no manifest.json)");
+            return;
+        };
+        println!("Source machine:");
+        for line in source.lines() {
+            println!("  {}", line);
+        }
+
+        let os = Self::run_wmi_query::<OsInfo>("SELECT Caption, Version, BuildNumber, OSArchitecture FROM Win32_OperatingSystem".to_string(), "operating system", None)
+            .map(|mut os| os.pop());
+        let computer = Self::run_wmi_query::<ComputerInfo>("SELECT Manufacturer, Model FROM Win32_ComputerSystem".to_string(), "computer model", None)
+            .map(|mut computer| computer.pop());
+        let (Ok(os), Ok(computer)) = (os, computer) else {
+            eprintln!("Warning: Can't compare the backup's source machine with this one");
+            return;
+        };
+        // Compared on letters and digits only, so `64-bit` and `64 bit` agree
+        let key = |text: &str| text.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+        let differs = |source: Option<&String>, target: Option<&String>| {
+            matches!((source, target), (Some(source), Some(target)) if key(source) != key(target))
+        };
+        let source_architecture = source.os.as_ref().and_then(|os| os.architecture.as_ref());
+        let target_architecture = os.as_ref().and_then(|os| os.architecture.as_ref());
+        if differs(source_architecture, target_architecture) {
+            eprintln!("⚠ Architecture differs: the backup is from a {} system, this one is {}",
+                source_architecture.map_or("", String::as_str), target_architecture.map_or("", String::as_str));
+        }
+        let source_model = source.computer.as_ref().and_then(|computer| computer.model.as_ref());
+        let target_model = computer.as_ref().and_then(|computer| computer.model.as_ref());
+        if differs(source_model, target_model) {
+            eprintln!("⚠ Model differs: the backup is from a {}, this machine is a {}",
+                source_model.map_or("", String::as_str), target_model.map_or("", String::as_str));
+        }
+    }
+
+    /// Delete backup folders under `root` that fall outside the retention policy.
+    /// With both limits, a backup is only deleted when it is outside the newest `keep_last`
+    /// and older than `older_than_days`, so the newest backups always survive.
+    /// The base of a `--diff-base` backup that is kept is kept too, unless `force`.
+    fn prune_backups(root: &Path, keep_last: Option<usize>, older_than_days: Option<u64>, force: bool, dry_run: bool) -> Result<()> {
+        if keep_last.is_none() && older_than_days.is_none() {
+            anyhow::bail!("Specify --keep-last and/or --older-than");
+        }
+
+        let entries = fs::read_dir(root)
+            .with_context(|| format!("Failed to read backup folder: {}", root.display()))?;
+        let mut backups: Vec<(chrono::DateTime<Utc>, PathBuf)> = Vec::new();
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to read backup folder: {}", root.display()))?;
+            if !entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                continue;
+            }
+            if let Some(timestamp) = Self::backup_timestamp(&entry.path()) {
+                backups.push((timestamp, entry.path()));
+            }
+        }
+
+        // Newest first, so the first `keep_last` entries are the ones to keep
+        backups.sort_by_key(|backup| std::cmp::Reverse(backup.0));
+
+        let now = Utc::now();
+        // Capped so absurd values can't overflow the date arithmetic
+        let cutoff = older_than_days.map(|days| now - chrono::Duration::days(days.min(1_000_000) as i64));
+
+        let expired: Vec<bool> = backups.iter().enumerate()
+            .map(|(index, (timestamp, _))| {
+                let beyond_count = keep_last.map(|keep| index >= keep).unwrap_or(true);
+                let too_old = cutoff.map(|cutoff| *timestamp < cutoff).unwrap_or(true);
+                beyond_count && too_old
+            })
+            .collect();
+        // Diff manifests of the backups that stay; their bases can't go without breaking `restore --diff`
+        let kept_diffs: Vec<(&PathBuf, DiffManifest)> = backups.iter().zip(&expired)
+            .filter(|(_, expired)| !**expired)
+            .filter_map(|((_, path), _)| {
+                let json = fs::read_to_string(path.join("diff_manifest.json")).ok()?;
+                Some((path, serde_json::from_str(&json).ok()?))
+            })
+            .collect();
+
+        let mut removed_count = 0;
+        let mut failed_count = 0;
+        // Original INF (lowercase) -> boot-critical services, queried once something is deleted
+        let mut boot_critical: Option<HashMap<String, Vec<String>>> = None;
+        for ((timestamp, path), expired) in backups.iter().zip(&expired) {
+            if !expired {
+                continue;
+            }
+            let created = Self::manifest_created(path);
+            let same_folder = |a: &Path, b: &Path| match (fs::canonicalize(a), fs::canonicalize(b)) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => false,
+            };
+            let needed_by = kept_diffs.iter()
+                .find(|(_, diff)| created.as_ref() == Some(&diff.base_created) || same_folder(&diff.base, path))
+                .map(|(diff_dir, _)| diff_dir);
+            if let (Some(diff_dir), false) = (needed_by, force) {
+                println!("Keeping: {} (base of {}; --force deletes it anyway)", path.display(), diff_dir.display());
+                continue;
+            }
+
+            if dry_run {
+                println!("Would delete: {} ({})", path.display(), timestamp.format("%Y-%m-%d %H:%M:%S UTC"));
+            } else {
+                println!("Deleting: {} ({})", path.display(), timestamp.format("%Y-%m-%d %H:%M:%S UTC"));
+            }
+            if let Some(diff_dir) = needed_by {
+                println!("  ⚠ Base of {}, which can't be restored without it", diff_dir.display());
+            }
+            let boot_critical = boot_critical.get_or_insert_with(|| {
+                Self::query_boot_critical_services().into_iter()
+                    .filter_map(|(oem, services)| Some((Self::inf_lookup().get(&oem)?.to_lowercase(), services)))
+                    .collect()
+            });
+            // Backups without a readable manifest can't be checked
+            for package in Self::load_backup_manifest(path).map(|manifest| manifest.packages).unwrap_or_default() {
+                if let Some(services) = package.original_inf.as_ref().and_then(|inf| boot_critical.get(&inf.to_lowercase())) {
+                    println!("  ⚠ Boot-critical on this machine: {} ({})", package.folder, services.join(", "));
+                }
+            }
+            if dry_run {
+                removed_count += 1;
+                continue;
+            }
+
+            match fs::remove_dir_all(path) {
+                Ok(()) => removed_count += 1,
+                Err(e) => {
+                    eprintln!("✗ Failed to delete {}: {}", path.display(), e);
+                    failed_count += 1;
+                }
+            }
+        }
+
+        println!();
+        println!("Backups found: {}", backups.len());
+        if dry_run {
+            println!("Would delete: {}", removed_count);
+        } else {
+            println!("Deleted: {}", removed_count);
+        }
+        println!("Kept: {}", backups.len() - removed_count - failed_count);
+
+        if failed_count > 0 {
+            anyhow::bail!("{} backups could not be deleted", failed_count);
+        }
+        Ok(())
+    }
+
+    /// Install every driver package of a backup with `pnputil /add-driver /install`.
+    /// Unless `no_snapshot`, the OEM packages of devices whose driver version the backup
+    /// changes are exported first into `pre_restore_snapshot_<timestamp>` next to the backup,
+    /// with a `rollback.json` for `restore --rollback`.
+    /// `base_packages` are extra package folders from the base of a `--diff` chain.
+    fn restore_backup(backup_dir: &Path, base_packages: &[PathBuf], options: &RestoreOptions) -> Result<RunStats> {
+        let verbose = options.verbose;
+        if !backup_dir.is_dir() {
+            anyhow::bail!("Backup folder not found: {}", backup_dir.display());
+        }
+        Self::check_source_machine(backup_dir);
+
+        // Every folder holding INFs is installed as one package
+        let mut packages: BTreeMap<PathBuf, Vec<ParsedInfFile>> = BTreeMap::new();
+        for root in std::iter::once(backup_dir).chain(base_packages.iter().map(PathBuf::as_path)) {
+            let (inf_files, _) = InfParser::find_inf_files(root, &WalkOptions::default())?;
+            for inf_file in &inf_files {
+                match InfParser::parse_inf_file(inf_file, DEFAULT_MAX_INF_SIZE) {
+                    Ok(parsed) => packages.entry(inf_file.parent().unwrap_or(root).to_path_buf()).or_default().push(parsed),
+                    Err(e) => eprintln!("Warning: Skipping {}: {:#}", inf_file.display(), e),
+                }
+            }
+        }
+        if packages.is_empty() {
+            anyhow::bail!("No driver packages found in {}", backup_dir.display());
+        }
+        if base_packages.is_empty() {
+            println!("Driver packages in backup: {}", packages.len());
+        } else {
+            println!("Driver packages in backup: {} (including {} unchanged from the base)", packages.len(), base_packages.len());
+        }
+
+        // A package applies if any of its entries is for this architecture (or of unknown architecture)
+        let machine = options.force_arch.unwrap_or_else(Architecture::native);
+        let mut wrong_architecture = 0;
+        packages.retain(|package_dir, parsed_files| {
+            let mut entries = parsed_files.iter().flat_map(|parsed| &parsed.drivers).peekable();
+            let applies = entries.peek().is_none()
+                || entries.any(|driver| driver.architecture.is_none_or(|architecture| architecture == machine));
+            if !applies {
+                wrong_architecture += 1;
+                if verbose {
+                    println!("  Skipping {}: no {} entries", package_dir.display(), machine.name());
+                }
+            }
+            applies
+        });
+        if wrong_architecture > 0 {
+            println!("{} packages skipped: wrong architecture (this machine is {}; override with --force-arch)", wrong_architecture, machine.name());
+        }
+        if packages.is_empty() {
+            println!("Nothing to install");
+            return Ok(RunStats { location: Some(backup_dir.to_path_buf()), ..RunStats::default() });
+        }
+
+        let changes = Self::devices_changed_by(packages.values().flatten(), verbose)?;
+        println!("Devices whose driver changes: {}", changes.len());
+        let boot_critical = if changes.is_empty() { BTreeMap::new() } else { Self::query_boot_critical_services() };
+        for change in &changes {
+            println!("  {}: {} v{} -> v{}",
+                change.device_name.as_deref().unwrap_or(&change.device_id),
+                change.previous_inf.as_deref().unwrap_or("no driver"),
+                change.previous_version.as_deref().unwrap_or("?"),
+                change.restored_version.as_deref().unwrap_or("?"));
+            if let Some(services) = change.previous_inf.as_ref().and_then(|inf| boot_critical.get(inf)) {
+                println!("    ⚠ Boot-critical: the current driver provides {}", services.join(", "));
+            }
+        }
+
+        if options.dry_run {
+            for package_dir in packages.keys() {
+                println!("Would install: {}", package_dir.display());
+            }
+            return Ok(RunStats { location: Some(backup_dir.to_path_buf()), ..RunStats::default() });
+        }
+        Self::check_admin_privileges()?;
+        let restore_point = Self::restore_point_before_changes(options)?;
+
+        let snapshot = if options.no_snapshot || changes.is_empty() {
+            None
+        } else {
+            Some(Self::snapshot_drivers(backup_dir, changes, verbose)?)
+        };
+
+        // Packages already in the store come back with their existing name; only new ones are ours to roll back
+        let known_infs = Self::inf_lookup();
+        let mut installed_infs: Vec<String> = Vec::new();
+        let mut installed_count = 0;
+        let mut failed_count = 0;
+        for package_dir in packages.keys() {
+            if interrupted() {
+                break;
+            }
+            match Self::add_driver_package(package_dir) {
+                Ok(published) => {
+                    println!("Installed: {}", package_dir.display());
+                    installed_infs.extend(published.into_iter().filter(|oem| !known_infs.contains_key(oem)));
+                    installed_count += 1;
+                }
+                Err(e) => {
+                    eprintln!("✗ Failed to install {}: {:#}", package_dir.display(), e);
+                    failed_count += 1;
+                }
+            }
+        }
+        Self::end_restore_point(restore_point);
+
+        if let Some((snapshot_dir, mut manifest)) = snapshot {
+            installed_infs.sort();
+            installed_infs.dedup();
+            manifest.installed_infs = installed_infs;
+            Self::write_rollback_manifest(&snapshot_dir, &manifest)?;
+            println!("Rollback snapshot: {}", snapshot_dir.display());
+            println!("  Undo with: driver-backup restore --rollback \"{}\"", snapshot_dir.display());
+        }
+
+        println!();
+        println!("Installed: {} driver packages", installed_count);
+        if failed_count > 0 {
+            anyhow::bail!("{} driver packages could not be installed", failed_count);
+        }
+        Ok(RunStats {
+            location: Some(backup_dir.to_path_buf()),
+            succeeded: installed_count,
+            failed: failed_count,
+            ..RunStats::default()
+        })
+    }
+
+    /// Installed devices that a package in `parsed_files` matches (hardware IDs first, then
+    /// compatible IDs) with a different driver version than the one they run now
+    fn devices_changed_by<'a>(parsed_files: impl Iterator<Item = &'a ParsedInfFile>, verbose: bool) -> Result<Vec<RollbackDevice>> {
+        let mut backup_versions: HashMap<String, &str> = HashMap::new();
+        for driver in parsed_files.flat_map(|parsed| &parsed.drivers) {
+            if let (Some(hwid), Some(version)) = (&driver.hardware_id, &driver.driver_version) {
+                let entry = backup_versions.entry(normalize_hardware_id(hwid)).or_insert(version);
+                if compare_driver_versions(version, entry).is_gt() {
+                    *entry = version;
+                }
+            }
+        }
+
+        let installed = Self::query_signed_drivers(None, None, verbose)?;
+        let entities = Self::query_pnp_entities(None, false, verbose).unwrap_or_else(|e| {
+            eprintln!("Warning: {:#}; matching on the primary hardware ID only", e);
+            HashMap::new()
+        });
+
+        let mut changes = Vec::new();
+        for driver in &installed {
+            let Some(device_id) = &driver.device_id else { continue };
+            let ids = DeviceIds::of_driver(driver, &entities);
+            let Some((found, restored_version)) = backup_versions.iter()
+                .filter_map(|(hwid, version)| ids.best_match(hwid).map(|found| (found, *version)))
+                .filter(|(found, _)| found.specificity >= HwidSpecificity::Compatible)
+                .max_by_key(|(found, _)| found.score)
+            else {
+                continue;
+            };
+            let hardware_id = found.device_id;
+            let current = driver.driver_version.as_deref().unwrap_or_default();
+            if compare_driver_versions(current, restored_version).is_eq() {
+                continue;
+            }
+            changes.push(RollbackDevice {
+                device_id: device_id.clone(),
+                device_name: driver.device_name.clone(),
+                hardware_id,
+                previous_inf: driver.inf_name.as_ref().map(|inf| inf.to_lowercase()),
+                previous_version: driver.driver_version.clone(),
+                restored_version: Some(restored_version.to_string()),
+                folder: None,
+            });
+        }
+        Ok(changes)
+    }
+
+    /// Export the OEM packages `changes` currently use into a new `pre_restore_snapshot_<timestamp>`
+    /// folder next to the backup and write its `rollback.json`. Fails if any export fails,
+    /// so nothing is installed without a way back.
+    fn snapshot_drivers(backup_dir: &Path, mut changes: Vec<RollbackDevice>, verbose: bool) -> Result<(PathBuf, RollbackManifest)> {
+        let backup_dir = std::path::absolute(backup_dir).unwrap_or_else(|_| backup_dir.to_path_buf());
+        let snapshot_dir = backup_dir.parent().unwrap_or(Path::new("."))
+            .join(format!("pre_restore_snapshot_{}", Utc::now().format("%Y%m%d_%H%M%S")));
+        fs::create_dir_all(&snapshot_dir)
+            .with_context(|| format!("Failed to create snapshot folder: {}", snapshot_dir.display()))?;
+        println!("Saving current drivers to {}...", snapshot_dir.display());
+
+        let inf_lookup = Self::inf_lookup();
+        let mut exported: HashMap<String, String> = HashMap::new();
+        for change in &mut changes {
+            let Some(oem_inf) = change.previous_inf.clone().filter(|inf| Self::is_oem_inf_name(inf)) else {
+                continue;
+            };
+            if !exported.contains_key(&oem_inf) {
+                let folder = oem_inf.trim_end_matches(".inf").to_string();
+                let dest = snapshot_dir.join(&folder);
+                fs::create_dir_all(&dest)
+                    .with_context(|| format!("Failed to create snapshot folder: {}", dest.display()))?;
+                let (output, _) = Self::export_package(&oem_inf, &dest, inf_lookup, verbose)
+                    .with_context(|| format!("Failed to snapshot {}", oem_inf))?;
+                if !output.status.success() {
+                    anyhow::bail!(
+                        "Failed to snapshot {} ({}); nothing was installed. Use --no-snapshot to restore without a snapshot",
+                        oem_inf,
+                        String::from_utf8_lossy(&output.stdout).trim()
+                    );
+                }
+                if verbose {
+                    println!("  Saved {} ({})", oem_inf, inf_lookup.get(&oem_inf).map(String::as_str).unwrap_or("unknown"));
+                }
+                exported.insert(oem_inf.clone(), folder);
+            }
+            change.folder = exported.get(&oem_inf).cloned();
+        }
+        println!("Saved {} driver packages", exported.len());
+
+        let manifest = RollbackManifest {
+            created: Utc::now().to_rfc3339(),
+            backup: backup_dir,
+            devices: changes,
+            installed_infs: Vec::new(),
+        };
+        // Written now as well, so an interrupted restore still leaves the device record
+        Self::write_rollback_manifest(&snapshot_dir, &manifest)?;
+        Ok((snapshot_dir, manifest))
+    }
+
+    fn write_rollback_manifest(snapshot_dir: &Path, manifest: &RollbackManifest) -> Result<()> {
+        let path = snapshot_dir.join("rollback.json");
+        fs::write(&path, serde_json::to_string_pretty(manifest)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Undo a restore: reinstall the snapshot's packages, then delete the packages the restore
+    /// added so the devices fall back to the best remaining driver, the snapshot's
+    fn rollback_restore(snapshot_dir: &Path, options: &RestoreOptions) -> Result<RunStats> {
+        let manifest_path = snapshot_dir.join("rollback.json");
+        let json = fs::read_to_string(&manifest_path)
+            .with_context(|| format!("Failed to read {} (is this a pre_restore_snapshot folder?)", manifest_path.display()))?;
+        let manifest: RollbackManifest = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid rollback manifest: {}", manifest_path.display()))?;
+
+        let mut folders: Vec<&str> = manifest.devices.iter().filter_map(|d| d.folder.as_deref()).collect();
+        folders.sort();
+        folders.dedup();
+        println!("Rolling back restore of {} ({})", manifest.backup.display(), manifest.created);
+        println!("Devices affected: {}", manifest.devices.len());
+
+        if options.dry_run {
+            for folder in &folders {
+                println!("Would reinstall: {}", snapshot_dir.join(folder).display());
+            }
+            for oem_inf in &manifest.installed_infs {
+                println!("Would delete: {}", oem_inf);
+            }
+            return Ok(RunStats { location: Some(snapshot_dir.to_path_buf()), ..RunStats::default() });
+        }
+        if folders.is_empty() && manifest.installed_infs.is_empty() {
+            println!("Nothing to roll back");
+            return Ok(RunStats { location: Some(snapshot_dir.to_path_buf()), ..RunStats::default() });
+        }
+        Self::check_admin_privileges()?;
+        let restore_point = Self::restore_point_before_changes(options)?;
+
+        let (mut succeeded_count, mut failed_count) = (0, 0);
+        for folder in &folders {
+            let package_dir = snapshot_dir.join(folder);
+            match Self::add_driver_package(&package_dir) {
+                Ok(_) => {
+                    println!("Reinstalled: {}", package_dir.display());
+                    succeeded_count += 1;
+                }
+                Err(e) => {
+                    eprintln!("✗ Failed to reinstall {}: {:#}", package_dir.display(), e);
+                    failed_count += 1;
+                }
+            }
+        }
+        for oem_inf in &manifest.installed_infs {
+            let output = Command::new("pnputil")
+                .args(["/delete-driver", oem_inf, "/uninstall"])
+                .output()
+                .context("Failed to run pnputil")?;
+            if output.status.success() {
+                println!("Deleted: {}", oem_inf);
+                succeeded_count += 1;
+            } else {
+                eprintln!("✗ Failed to delete {}: {}", oem_inf, String::from_utf8_lossy(&output.stdout).trim());
+                failed_count += 1;
+            }
+        }
+        Self::end_restore_point(restore_point);
+
+        if failed_count > 0 {
+            anyhow::bail!("Rollback incomplete: {} steps failed", failed_count);
+        }
+        println!("Rollback complete");
+        Ok(RunStats {
+            location: Some(snapshot_dir.to_path_buf()),
+            succeeded: succeeded_count,
+            failed: failed_count,
+            ..RunStats::default()
+        })
+    }
+
+    /// Open the `--restore-point` right before drivers change and return its description for
+    /// `end_restore_point`. A failure (System Restore is often turned off) is only a warning
+    /// unless `--require-restore-point` is set.
+    fn restore_point_before_changes(options: &RestoreOptions) -> Result<Option<String>> {
+        if !options.restore_point {
+            return Ok(None);
+        }
+        let description = format!("driver-backup restore {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"));
+        match Self::create_restore_point(&description, BEGIN_SYSTEM_CHANGE) {
+            Ok(()) => {
+                println!("Restore point created: {}", description);
+                Ok(Some(description))
+            }
+            Err(e) if options.require_restore_point => {
+                Err(e.context("No restore point could be created and --require-restore-point is set; nothing was changed"))
+            }
+            Err(e) => {
+                eprintln!("Warning: No restore point created: {:#}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Close the restore point `restore_point_before_changes` opened, once the drivers changed
+    fn end_restore_point(description: Option<String>) {
+        if let Some(description) = description {
+            if let Err(e) = Self::create_restore_point(&description, END_SYSTEM_CHANGE) {
+                eprintln!("Warning: Failed to complete the restore point: {:#}", e);
+            }
+        }
+    }
+
+    /// Call `SystemRestore.CreateRestorePoint` in root\default as a device driver install, with
+    /// `event_type` `BEGIN_SYSTEM_CHANGE` before the install or `END_SYSTEM_CHANGE` after it
+    fn create_restore_point(description: &str, event_type: i32) -> Result<()> {
+        use windows::core::{w, BSTR, VARIANT};
+        use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
+        use windows::Win32::System::Wmi::{IWbemClassObject, IWbemContext, IWbemLocator, WbemLocator, WBEM_FLAG_RETURN_WBEM_COMPLETE};
+        // RestorePointType
+        const DEVICE_DRIVER_INSTALL: i32 = 10;
+        // ReturnValue when System Restore is turned off
+        const ERROR_SERVICE_DISABLED: i32 = 1058;
+
+        let _com_con = COMLibrary::new().context("Failed to initialize COM library")?;
+        // SAFETY: plain COM calls on interfaces that live until the end of the block;
+        // uint32 method parameters are passed as VT_I4, as WMI expects
+        let return_value = unsafe {
+            let locator: IWbemLocator = CoCreateInstance(&WbemLocator, None, CLSCTX_INPROC_SERVER)
+                .context("Failed to create the WMI locator")?;
+            let services = locator
+                .ConnectServer(&BSTR::from(r"root\default"), &BSTR::new(), &BSTR::new(), &BSTR::new(), 0, &BSTR::new(), None::<&IWbemContext>)
+                .context(r"Failed to connect to root\default")?;
+            let class_name = BSTR::from("SystemRestore");
+            let mut class: Option<IWbemClassObject> = None;
+            services.GetObject(&class_name, WBEM_FLAG_RETURN_WBEM_COMPLETE, None::<&IWbemContext>, Some(&mut class), None)
+                .context("System Restore is not available on this system")?;
+            let class = class.context("System Restore is not available on this system")?;
+            let mut signature: Option<IWbemClassObject> = None;
+            class.GetMethod(w!("CreateRestorePoint"), 0, &mut signature, std::ptr::null_mut())?;
+            let in_params = signature.context("SystemRestore has no CreateRestorePoint method")?.SpawnInstance(0)?;
+            in_params.Put(w!("Description"), 0, &VARIANT::from(description), 0)?;
+            in_params.Put(w!("RestorePointType"), 0, &VARIANT::from(DEVICE_DRIVER_INSTALL), 0)?;
+            in_params.Put(w!("EventType"), 0, &VARIANT::from(event_type), 0)?;
+
+            let mut out_params: Option<IWbemClassObject> = None;
+            services.ExecMethod(
+                &class_name, &BSTR::from("CreateRestorePoint"), WBEM_FLAG_RETURN_WBEM_COMPLETE,
+                None::<&IWbemContext>, &in_params, Some(&mut out_params), None,
+            ).context("CreateRestorePoint failed")?;
+            let mut value = VARIANT::default();
+            out_params.context("CreateRestorePoint returned nothing")?
+                .Get(w!("ReturnValue"), 0, &mut value, None, None)?;
+            i32::try_from(&value)?
+        };
+        match return_value {
+            0 => Ok(()),
+            ERROR_SERVICE_DISABLED => anyhow::bail!("System Restore is turned off for the system drive"),
+            code => anyhow::bail!("CreateRestorePoint returned {}", code),
+        }
+    }
+
+    /// `pnputil /add-driver <dir>\*.inf /install`; returns the OEM INF names pnputil published
+    fn add_driver_package(package_dir: &Path) -> Result<Vec<String>> {
+        let output = Command::new("pnputil")
+            .arg("/add-driver")
+            .arg(package_dir.join("*.inf"))
+            .arg("/install")
+            .output()
+            .context("Failed to run pnputil")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        // 259: added, but no device needed it; 3010: installed, reboot required
+        match output.status.code() {
+            Some(0 | 259 | 3010) => Ok(Self::published_oem_infs(&stdout)),
+            code => anyhow::bail!(
+                "pnputil exited with {}: {}",
+                code.map(|c| c.to_string()).unwrap_or_else(|| "no exit code".to_string()),
+                stdout.lines().map(str::trim).rfind(|line| !line.is_empty()).unwrap_or_default()
+            ),
+        }
+    }
+
+    /// `oemNN.inf` names in `pnputil /add-driver` output, matched by shape since the labels are localized
+    fn published_oem_infs(output: &str) -> Vec<String> {
+        output.split_whitespace()
+            .map(|word| word.trim_matches(|c: char| !c.is_ascii_alphanumeric() && c != '.').trim_end_matches('.').to_lowercase())
+            .filter(|word| Self::is_oem_inf_name(word))
+            .collect()
+    }
+
+    /// Read a backup's `manifest.json`, failing clearly if it is missing or from another schema version
+    fn load_backup_manifest(backup_dir: &Path) -> Result<BackupManifest> {
+        let manifest_path = backup_dir.join("manifest.json");
+        if !manifest_path.is_file() {
+            anyhow::bail!("No manifest.json in {} (is this a backup folder made by driver-backup?)", backup_dir.display());
+        }
+        let json = fs::read_to_string(&manifest_path)
+            .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+        // Checked before the layout, so an older manifest reports its version rather than a missing field
+        let value: serde_json::Value = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid manifest: {}", manifest_path.display()))?;
+        let version = value.get("schema_version").and_then(|v| v.as_u64()).unwrap_or(0);
+        if version != MANIFEST_SCHEMA_VERSION as u64 {
+            anyhow::bail!(
+                "{} has manifest schema version {}, but version {} is required; make a new full backup to use as the base",
+                manifest_path.display(), version, MANIFEST_SCHEMA_VERSION
+            );
+        }
+        serde_json::from_value(value)
+            .with_context(|| format!("Invalid manifest: {}", manifest_path.display()))
+    }
+
+    /// `backup --summary-csv-only`: rebuild `all_drivers.csv` (and the class READMEs unless
+    /// `no_readme`) of an existing backup without exporting anything.
+    /// The machine lines at the top of the CSV come from its manifest, if readable.
+    fn regenerate_summary_csv(backup_dir: &Path, no_readme: bool, verbose: bool) -> Result<()> {
+        if !backup_dir.is_dir() {
+            anyhow::bail!("Backup folder not found: {}", backup_dir.display());
+        }
+        let preamble = match Self::load_backup_manifest(backup_dir) {
+            Ok(manifest) => manifest.system.lines(),
+            Err(e) => {
+                if verbose {
+                    println!("Writing the CSV without machine details: {:#}", e);
+                }
+                Vec::new()
+            }
+        };
+        println!("Scanning {} to create summary...", backup_dir.display());
+        let parsed_files = InfParser::scan_backup(backup_dir, verbose)?;
+        InfParser::export_backup_summary(&parsed_files, backup_dir, &backup_dir.join("all_drivers.csv"), &preamble)?;
+        if !no_readme {
+            let generated = chrono::Local::now().format("%Y-%m-%d %H:%M:%S %:z").to_string();
+            let count = InfParser::write_class_readmes(&parsed_files, backup_dir, &generated)?;
+            println!("Class README.txt files written: {}", count);
+        }
+        Ok(())
+    }
+
+    /// `restore --base --diff`: check that `diff_dir` was made against `base_dir` and return the
+    /// base package folders it still needs. Packages in the diff itself take precedence.
+    fn resolve_diff_chain(base_dir: &Path, diff_dir: &Path) -> Result<Vec<PathBuf>> {
+        let base_manifest = Self::load_backup_manifest(base_dir)?;
+        let diff_manifest = Self::load_backup_manifest(diff_dir)?;
+        let diff_path = diff_dir.join("diff_manifest.json");
+        let json = fs::read_to_string(&diff_path)
+            .with_context(|| format!("Failed to read {} (was this backup made with --diff-base?)", diff_path.display()))?;
+        let diff: DiffManifest = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid diff manifest: {}", diff_path.display()))?;
+        if diff.schema_version != MANIFEST_SCHEMA_VERSION {
+            anyhow::bail!("{} has schema version {}, but version {} is required", diff_path.display(), diff.schema_version, MANIFEST_SCHEMA_VERSION);
+        }
+        if diff.base_created != base_manifest.created {
+            anyhow::bail!(
+                "{} was made against the backup created {} ({}), but {} was created {}",
+                diff_dir.display(), diff.base_created, diff.base.display(), base_dir.display(), base_manifest.created
+            );
+        }
+
+        let mut folders = Vec::new();
+        for package in &diff.base_packages {
+            if diff_manifest.packages.iter().any(|p| p.same_package(package.original_inf.as_deref(), package.driver_version.as_deref())) {
+                continue;
+            }
+            let folder = base_dir.join(package.folder.replace('\\', std::path::MAIN_SEPARATOR_STR));
+            if !folder.is_dir() {
+                anyhow::bail!("Base package {} ({}) is missing from {}", package.folder, package.oem_inf, base_dir.display());
+            }
+            folders.push(folder);
+        }
+        Ok(folders)
+    }
+
+    /// Write `manifest.json`, the human-readable `BACKUP_INFO.txt` and the source machine's
+    /// `system_info.txt` into the backup root
+    fn write_backup_info(backup_dir: &Path, manifest: &BackupManifest) -> Result<()> {
+        let manifest_path = backup_dir.join("manifest.json");
+        let json = serde_json::to_string_pretty(manifest)?;
+        fs::write(&manifest_path, json)
+            .with_context(|| format!("Failed to write manifest: {}", manifest_path.display()))?;
+
+        let mut info = String::new();
+        info.push_str("Driver Backup Information\r\n");
+        info.push_str("=========================\r\n");
+        info.push_str(&format!("Created: {}\r\n", manifest.created));
+        info.push_str(&format!("Tool version: {}\r\n", manifest.tool_version));
+        info.push_str(&format!("Driver packages: {}\r\n", manifest.packages.len()));
+
+        let info_path = backup_dir.join("BACKUP_INFO.txt");
+        fs::write(&info_path, info)
+            .with_context(|| format!("Failed to write backup info: {}", info_path.display()))?;
+
+        let mut system_info = String::new();
+        system_info.push_str("Source Machine\r\n");
+        system_info.push_str("==============\r\n");
+        for line in manifest.system.lines() {
+            system_info.push_str(&line);
+            system_info.push_str("\r\n");
+        }
+        let system_info_path = backup_dir.join("system_info.txt");
+        fs::write(&system_info_path, system_info)
+            .with_context(|| format!("Failed to write system info: {}", system_info_path.display()))?;
+
+        Ok(())
+    }
+
+    /// Write the exported package folders for imaging tools.
+    ///
+    /// A `.xml` target gets an unattend `DriverPaths` block, anything else a plain
+    /// list of absolute folders (one per line) usable with `DISM /Add-Driver /Driver:<folder>`.
+    fn write_deploy_list(package_dirs: &[PathBuf], list_path: &Path) -> Result<()> {
+        let absolute_dirs: Vec<String> = package_dirs.iter()
+            .map(|dir| std::path::absolute(dir).unwrap_or_else(|_| dir.clone()).display().to_string())
+            .collect();
+
+        let is_xml = list_path.extension()
+            .map(|e| e.to_string_lossy().eq_ignore_ascii_case("xml"))
+            .unwrap_or(false);
+
+        let content = if is_xml {
+            let xml = Self::driver_paths_xml(&absolute_dirs, "");
+            check_xml_well_formed(&xml)
+                .with_context(|| format!("Generated deployment list is not well-formed XML: {}", list_path.display()))?;
+            xml
+        } else {
+            let mut list = absolute_dirs.join("\r\n");
+            list.push_str("\r\n");
+            list
+        };
+
+        create_parent_dir(list_path)?;
+        fs::write(list_path, content)
+            .with_context(|| format!("Failed to write deployment list: {}", list_path.display()))?;
+
+        Ok(())
+    }
+
+    /// Copy the exported packages into `dism_dir` for `dism /Add-Driver /Recurse`: one flat
+    /// folder per package with a plain ASCII name, no CSVs or scripts, and `dism_command.txt`.
+    /// Packages whose INFs are all extension or software component INFs are left out, and
+    /// only folders that end up with both an INF and a CAT file are counted.
+    /// Returns the number of packages copied and excluded.
+    fn write_dism_folder(backup_dir: &Path, packages: &[ManifestPackage], dism_dir: &Path, verbose: bool) -> Result<(usize, usize)> {
+        const NON_DEVICE_CLASSES: [&str; 2] = ["extension", "softwarecomponent"];
+        fs::create_dir_all(dism_dir)
+            .with_context(|| format!("Failed to create DISM folder: {}", dism_dir.display()))?;
+
+        let (mut copied, mut excluded) = (0, 0);
+        for package in packages {
+            let package_dir = backup_dir.join(&package.folder);
+            let (inf_files, _) = InfParser::find_inf_files(&package_dir, &WalkOptions::default())?;
+            let classes: Vec<String> = inf_files.iter()
+                .filter_map(|inf| InfParser::parse_inf_file(inf, DEFAULT_MAX_INF_SIZE).ok())
+                .map(|parsed| parsed.raw_version_info.class.unwrap_or_default().to_lowercase())
+                .collect();
+            if !classes.is_empty() && classes.iter().all(|class| NON_DEVICE_CLASSES.contains(&class.as_str())) {
+                if verbose {
+                    println!("  Skipping {}: extension or software component INF only", package.folder);
+                }
+                excluded += 1;
+                continue;
+            }
+
+            // DISM and WinPE choke on long or non-ASCII paths; keep names short and plain
+            let name: String = package.folder
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+                .take(60)
+                .collect();
+            let dest = dism_dir.join(format!("{:03}_{}", copied + 1, name.trim_matches('_')));
+            let output = Command::new("xcopy")
+                .arg(&package_dir)
+                .arg(&dest)
+                .args(["/E", "/I", "/Y", "/Q"])
+                .output()
+                .context("Failed to run xcopy")?;
+            if !output.status.success() {
+                eprintln!("Warning: Failed to copy {} for DISM: {}", package.folder, String::from_utf8_lossy(&output.stdout).trim());
+                continue;
+            }
+
+            let has_extension = |wanted: &str| fs::read_dir(&dest).into_iter().flatten().flatten()
+                .any(|entry| entry.path().extension().is_some_and(|e| e.eq_ignore_ascii_case(wanted)));
+            if !has_extension("inf") || !has_extension("cat") {
+                eprintln!("Warning: {} has no INF and CAT pair, removed from the DISM folder", package.folder);
+                fs::remove_dir_all(&dest)
+                    .with_context(|| format!("Failed to remove {}", dest.display()))?;
+                continue;
+            }
+            if verbose {
+                println!("  {} -> {}", package.folder, dest.display());
+            }
+            copied += 1;
+        }
+
+        let absolute = std::path::absolute(dism_dir).unwrap_or_else(|_| dism_dir.to_path_buf());
+        let command = format!(
+            "rem Mount the image first, e.g. dism /Mount-Image /ImageFile:install.wim /Index:1 /MountDir:C:\\mount\r\n\
+             dism /Image:C:\\mount /Add-Driver /Driver:\"{}\" /Recurse\r\n",
+            absolute.display());
+        let command_path = dism_dir.join("dism_command.txt");
+        fs::write(&command_path, command)
+            .with_context(|| format!("Failed to write {}", command_path.display()))?;
+
+        Ok((copied, excluded))
+    }
+
+    /// `<DriverPaths>` block of an unattend file, one `<PathAndCredentials>` per folder
+    /// with keys counting up from 1. Every line is prefixed with `indent`.
+    fn driver_paths_xml(dirs: &[String], indent: &str) -> String {
+        let mut xml = format!("{}<DriverPaths>\n", indent);
+        for (index, dir) in dirs.iter().enumerate() {
+            xml.push_str(&format!("{}    <PathAndCredentials wcm:action=\"add\" wcm:keyValue=\"{}\">\n", indent, index + 1));
+            xml.push_str(&format!("{}        <Path>{}</Path>\n", indent, escape_xml(dir)));
+            xml.push_str(&format!("{}    </PathAndCredentials>\n", indent));
+        }
+        xml.push_str(&format!("{}</DriverPaths>\n", indent));
+        xml
+    }
+
+    /// Write `--unattend-snippet`: a `Microsoft-Windows-PnpCustomizationsWinPE` component for
+    /// the windowsPE pass of autounattend.xml, listing every package folder as a driver path.
+    /// With `--path-prefix`, folders are given relative to that prefix (where the backup
+    /// folder's contents will be on the target) instead of as absolute local paths.
+    fn write_unattend_snippet(backup_dir: &Path, package_folders: &[String], path_prefix: Option<&str>, snippet_path: &Path) -> Result<()> {
+        let root = match path_prefix {
+            Some(prefix) => prefix.trim_end_matches('\\').to_string(),
+            None => std::path::absolute(backup_dir).unwrap_or_else(|_| backup_dir.to_path_buf()).display().to_string(),
+        };
+        let dirs: Vec<String> = package_folders.iter()
+            .map(|folder| format!("{}\\{}", root, folder))
+            .collect();
+        if let Some(dir) = dirs.iter().find(|dir| dir.chars().any(|c| c.is_control())) {
+            anyhow::bail!("Driver path can't be written to XML (control character): {}", dir);
+        }
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str(&format!(
+            "<component name=\"Microsoft-Windows-PnpCustomizationsWinPE\" processorArchitecture=\"{}\" \
+             publicKeyToken=\"31bf3856ad364e35\" language=\"neutral\" versionScope=\"nonSxS\" \
+             xmlns=\"urn:schemas-microsoft-com:unattend\" xmlns:wcm=\"http://schemas.microsoft.com/WMIConfig/2002/State\">\n",
+            Architecture::native().name()));
+        xml.push_str(&Self::driver_paths_xml(&dirs, "    "));
+        xml.push_str("</component>\n");
+
+        check_xml_well_formed(&xml)
+            .with_context(|| format!("Generated unattend snippet is not well-formed XML: {}", snippet_path.display()))?;
+        create_parent_dir(snippet_path)?;
+        fs::write(snippet_path, xml)
+            .with_context(|| format!("Failed to write unattend snippet: {}", snippet_path.display()))?;
+        Ok(())
+    }
+
+    /// Render `--report-template` (or the built-in Markdown layout) for the finished backup
+    /// and write it to the backup root; the file is named after the template minus `.hbs`.
+    fn write_report(backup_dir: &Path, manifest: &BackupManifest, failed_count: usize, template: Option<&Path>) -> Result<PathBuf> {
+        let (source, file_name) = match template {
+            Some(path) => {
+                let source = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read report template: {}", path.display()))?;
+                let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                let name = match name.strip_suffix(".hbs") {
+                    Some(stripped) if !stripped.is_empty() => stripped.to_string(),
+                    _ => name,
+                };
+                (source, name)
+            }
+            None => (DEFAULT_REPORT_TEMPLATE.to_string(), "backup_report.md".to_string()),
+        };
+
+        let packages: Vec<serde_json::Value> = manifest.packages.iter()
+            .map(|package| {
+                let mut value = serde_json::json!(package);
+                value["device_count"] = package.devices.len().into();
+                value
+            })
+            .collect();
+        let context = serde_json::json!({
+            "backup_path": backup_dir.display().to_string(),
+            "timestamp": manifest.created,
+            "tool_version": manifest.tool_version,
+            "system": manifest.system.lines(),
+            "os": manifest.system.os,
+            "computer": manifest.system.computer,
+            "bios": manifest.system.bios,
+            "counts": {
+                "packages": manifest.packages.len(),
+                "devices": manifest.packages.iter().map(|p| p.devices.len()).sum::<usize>(),
+                "failed": failed_count,
+            },
+            "packages": packages,
+        });
+
+        // Reports are text or Markdown, so values are inserted as-is rather than HTML-escaped
+        let mut handlebars = handlebars::Handlebars::new();
+        handlebars.register_escape_fn(handlebars::no_escape);
+        let report = handlebars.render_template(&source, &context)
+            .with_context(|| format!("Failed to render report template {}", template.map(|p| p.display().to_string()).unwrap_or_default()))?;
+
+        let report_path = backup_dir.join(file_name);
+        fs::write(&report_path, report)
+            .with_context(|| format!("Failed to write report: {}", report_path.display()))?;
+        Ok(report_path)
+    }
+
+    /// Write a script that installs every exported package without this tool.
+    /// It uses `pnputil /add-driver` where available and DISM on older systems.
+    fn write_install_script(backup_dir: &Path, package_folders: &[String], kind: ScriptKind) -> Result<PathBuf> {
+        let (file_name, content) = match kind {
+            ScriptKind::Cmd => ("install_drivers.cmd", Self::render_install_cmd(package_folders)),
+            ScriptKind::Ps1 => ("install_drivers.ps1", Self::render_install_ps1(package_folders)),
+        };
+
+        let script_path = backup_dir.join(file_name);
+        fs::write(&script_path, content)
+            .with_context(|| format!("Failed to write install script: {}", script_path.display()))?;
+        Ok(script_path)
+    }
+
+    /// Batch version of the install script; package folders are relative to the script
+    fn render_install_cmd(package_folders: &[String]) -> String {
+        let mut lines = vec![
+            "@echo off".to_string(),
+            "rem Installs the driver packages in this backup. Run as Administrator.".to_string(),
+            "setlocal".to_string(),
+            "cd /d \"%~dp0\"".to_string(),
+            "net session >nul 2>&1 || goto not_admin".to_string(),
+            "set FAILED=0".to_string(),
+            "set USE_DISM=".to_string(),
+            "pnputil /? 2>nul | find /i \"/add-driver\" >nul || set USE_DISM=1".to_string(),
+            String::new(),
+        ];
+        for folder in package_folders {
+            lines.push(format!("call :install \"{}\"", folder));
+        }
+        lines.extend([
+            String::new(),
+            "echo Done. %FAILED% package(s) failed.".to_string(),
+            "exit /b %FAILED%".to_string(),
+            String::new(),
+            ":install".to_string(),
+            "set \"PKG=%~1\"".to_string(),
+            "if defined USE_DISM goto install_dism".to_string(),
+            "pnputil /add-driver \"%PKG%\\*.inf\" /subdirs /install >nul".to_string(),
+            "goto install_result".to_string(),
+            ":install_dism".to_string(),
+            "dism /Online /Add-Driver /Driver:\"%PKG%\" /Recurse /NoRestart >nul".to_string(),
+            ":install_result".to_string(),
+            "set RC=%errorlevel%".to_string(),
+            "if \"%RC%\"==\"0\" goto install_ok".to_string(),
+            "if \"%RC%\"==\"3010\" goto install_ok".to_string(),
+            "echo FAILED [%RC%]: %PKG%".to_string(),
+            "set /a FAILED+=1".to_string(),
+            "exit /b 0".to_string(),
+            ":install_ok".to_string(),
+            "echo Installed: %PKG%".to_string(),
+            "exit /b 0".to_string(),
+            String::new(),
+            ":not_admin".to_string(),
+            "echo Please run this script as Administrator.".to_string(),
+            "exit /b 1".to_string(),
+        ]);
+
+        let mut script = lines.join("\r\n");
+        script.push_str("\r\n");
+        script
+    }
+
+    /// PowerShell version of the install script; package folders are relative to the script
+    fn render_install_ps1(package_folders: &[String]) -> String {
+        let mut script = String::from(
+            "#Requires -RunAsAdministrator\r\n\
+             # Installs the driver packages in this backup.\r\n\
+             \r\n\
+             $packages = @(\r\n",
+        );
+        for folder in package_folders {
+            script.push_str(&format!("    '{}'\r\n", folder.replace('\'', "''")));
+        }
+        script.push_str(
+            ")\r\n\
+             \r\n\
+             $useDism = -not ((pnputil.exe /? 2>$null) -match '/add-driver')\r\n\
+             $failed = 0\r\n\
+             foreach ($package in $packages) {\r\n\
+             \x20   $path = Join-Path $PSScriptRoot $package\r\n\
+             \x20   if ($useDism) {\r\n\
+             \x20       dism.exe /Online /Add-Driver \"/Driver:$path\" /Recurse /NoRestart | Out-Null\r\n\
+             \x20   } else {\r\n\
+             \x20       pnputil.exe /add-driver \"$path\\*.inf\" /subdirs /install | Out-Null\r\n\
+             \x20   }\r\n\
+             \x20   if ($LASTEXITCODE -eq 0 -or $LASTEXITCODE -eq 3010) {\r\n\
+             \x20       Write-Host \"Installed: $package\"\r\n\
+             \x20   } else {\r\n\
+             \x20       Write-Host \"FAILED [$LASTEXITCODE]: $package\"\r\n\
+             \x20       $failed++\r\n\
+             \x20   }\r\n\
+             }\r\n\
+             \r\n\
+             Write-Host \"Done. $failed package(s) failed.\"\r\n\
+             exit $failed\r\n",
+        );
+        script
+    }
+
+    /// Run the backup process
+    async fn run(&self) -> Result<RunStats> {
+        println!("Starting driver export process...");
+
+        let all_drivers = self.get_drivers().await?;
+
+        let filter_started = Instant::now();
+        let total_count = all_drivers.len();
+        let mut non_ms_drivers = self.filter_non_microsoft_drivers(all_drivers);
+
+        if let Some(Commands::Backup { bus: Some(bus), .. }) = &self.args.command {
+            non_ms_drivers.retain(|driver| {
+                driver.hardware_id.as_deref().map(|id| bus.matches(id)).unwrap_or(false)
+            });
+        }
+
+        if let Some(Commands::Backup { match_device: Some(query), .. }) = &self.args.command {
+            non_ms_drivers = Self::filter_by_device_name(non_ms_drivers, query);
+        }
+
+        if let Some(Commands::Backup { exclude_generic: true, verbose, .. }) = &self.args.command {
+            non_ms_drivers = self.filter_generic_drivers(non_ms_drivers, *verbose > 0);
+        }
+
+        log::debug!("Filtering kept {} of {} drivers in {:.2?}", non_ms_drivers.len(), total_count, filter_started.elapsed());
+
+        if non_ms_drivers.is_empty() {
+            println!("No non-Microsoft drivers found to export.");
+            return Ok(RunStats::default());
+        }
+
+        let include_serial = !matches!(self.args.command, Some(Commands::Backup { no_serial: true, .. }));
+        let system = self.get_system_context(include_serial);
+
+        let options = self.args.command.as_ref()
+            .and_then(PackageExportOptions::from_command)
+            .context("run() needs the backup command")?;
+        let backup_started = Instant::now();
+        let driver_count = non_ms_drivers.len();
+        let stats = self.backup_drivers(non_ms_drivers, system, &options).await?;
+        log::debug!("Backup of {} drivers finished in {:.2?}", driver_count, backup_started.elapsed());
+        Ok(stats)
+    }
+
+    /// Get the OEM INF to original INF lookup, building it on first use; every command
+    /// shares the one lookup for the whole run
+    fn inf_lookup() -> &'static HashMap<String, String> {
+        INF_LOOKUP.get_or_init(Self::build_inf_lookup)
+    }
+
+    /// `--refresh-lookup`: build the lookup now rather than on first use. Must run before
+    /// `inf_lookup`, since the lookup is never replaced once set.
+    fn refresh_inf_lookup() {
+        if INF_LOOKUP.set(Self::build_inf_lookup()).is_err() {
+            unreachable!("--refresh-lookup runs before the INF lookup is first used");
+        }
+    }
+
+    /// Build lookup table for OEM INF to actual INF name mapping.
+    ///
+    /// The driver database in the registry is preferred since it doesn't depend on
+    /// the display language; pnputil output is only parsed when the registry is unreadable.
+    fn build_inf_lookup() -> HashMap<String, String> {
+        if !quiet() {
+            println!("Building INF name lookup table...");
+        }
+
+        let mut lookup = Self::inf_lookup_from_registry();
+        if lookup.is_empty() {
+            lookup = Self::inf_lookup_from_pnputil();
+        }
+
+        if !quiet() {
+            println!("Found {} INF mappings", lookup.len());
+        }
+        lookup
+    }
+
+    /// Read OEM INF to original INF mappings from `HKLM\SYSTEM\DriverDatabase\DriverInfFiles`
+    fn inf_lookup_from_registry() -> HashMap<String, String> {
+        let output = Command::new("reg")
+            .arg("query")
+            .arg("HKLM\\SYSTEM\\DriverDatabase\\DriverInfFiles")
+            .arg("/s")
+            .output();
+
+        match output {
+            Ok(result) if result.status.success() => {
+                Self::parse_driver_inf_files(&String::from_utf8_lossy(&result.stdout))
+            }
+            _ => HashMap::new(),
+        }
+    }
+
+    /// Parse `reg query ...\DriverInfFiles /s` output.
+    ///
+    /// Each `oemNN.inf` subkey has a REG_MULTI_SZ default value listing driver store
+    /// package IDs such as `nvlddmkm.inf_amd64_0123456789abcdef`; the original INF
+    /// name is the part before the architecture suffix. Only key paths and value
+    /// types are matched, never the localized "(Default)" value name.
+    fn parse_driver_inf_files(output: &str) -> HashMap<String, String> {
+        let mut lookup = HashMap::new();
+        let mut current_oem: Option<String> = None;
+
+        for line in output.lines() {
+            let line = line.trim();
+
+            if line.to_uppercase().starts_with("HKEY_") {
+                current_oem = line.rsplit('\\')
+                    .next()
+                    .map(|key| key.to_lowercase())
+                    .filter(|key| Self::is_oem_inf_name(key));
+                continue;
+            }
+
+            let Some(oem) = &current_oem else {
+                continue;
+            };
+            let Some(type_pos) = line.find("REG_MULTI_SZ") else {
+                continue;
+            };
+
+            let data = line[type_pos + "REG_MULTI_SZ".len()..].trim();
+            let package_id = data.split("\\0").next().unwrap_or("").to_lowercase();
+            if let Some(pos) = package_id.find(".inf_") {
+                lookup.insert(oem.clone(), package_id[..pos + ".inf".len()].to_string());
+            }
+        }
+
+        lookup
+    }
+
+    /// Read OEM INF to original INF mappings from `pnputil /enum-drivers`
+    fn inf_lookup_from_pnputil() -> HashMap<String, String> {
+        let output = Command::new("pnputil")
+            .arg("/enum-drivers")
+            .output();
+
+        match output {
+            Ok(result) => Self::parse_enum_drivers(&String::from_utf8_lossy(&result.stdout)),
+            Err(_) => HashMap::new(),
+        }
+    }
+
+    /// Parse `pnputil /enum-drivers` output without relying on its localized labels.
+    ///
+    /// Every record starts with the published name (`oemNN.inf`) followed by the
+    /// original name, so labels like "Published Name", "Veröffentlichter Name" or
+    /// "公開名" are skipped and records are recognized by the shape of their values.
+    fn parse_enum_drivers(output: &str) -> HashMap<String, String> {
+        let mut lookup = HashMap::new();
+        let mut current_oem: Option<String> = None;
+
+        for line in output.lines() {
+            // Labels end with an ASCII or full-width colon (French adds a space before it)
+            let Some(value) = line.split([':', '：']).nth(1) else {
+                continue;
+            };
+            let value = value.trim();
+            let value_lower = value.to_lowercase();
+
+            if Self::is_oem_inf_name(&value_lower) {
+                current_oem = Some(value_lower);
+            } else if value_lower.ends_with(".inf") {
+                // Save mapping when we have both
+                if let Some(oem) = current_oem.take() {
+                    lookup.insert(oem, value.to_string());
+                }
+            }
+        }
+
+        lookup
+    }
+
+    /// Check if a file name looks like a published driver store INF (`oem<number>.inf`)
+    fn is_oem_inf_name(name: &str) -> bool {
+        name.strip_prefix("oem")
+            .and_then(|rest| rest.strip_suffix(".inf"))
+            .map(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(false)
+    }
+
+    /// Read the service binary version of each OEM INF's DriverStore package for
+    /// `export --file-versions`, keyed by lowercase OEM INF name.
+    ///
+    /// Packages are looked up on several threads since each one means parsing an INF
+    /// and reading a binary. INFs whose package or binary can't be found are left out.
+    fn collect_file_versions(drivers: &[PnPSignedDriver], inf_lookup: &HashMap<String, String>, verbose: bool) -> HashMap<String, String> {
+        let mut oem_infs: Vec<String> = drivers.iter()
+            .filter_map(|d| d.inf_name.as_deref().map(str::to_lowercase))
+            .collect();
+        oem_infs.sort();
+        oem_infs.dedup();
+        if !quiet() {
+            println!("Reading driver binary versions for {} INFs...", oem_infs.len());
+        }
+
+        let repository = Self::driver_store_repository();
+        let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        let chunk_size = oem_infs.len().div_ceil(threads).max(1);
+
+        let versions: HashMap<String, String> = std::thread::scope(|scope| {
+            let workers: Vec<_> = oem_infs.chunks(chunk_size)
+                .map(|chunk| {
+                    let repository = &repository;
+                    scope.spawn(move || {
+                        chunk.iter()
+                            .take_while(|_| !interrupted())
+                            .filter_map(|oem| {
+                                let original = inf_lookup.get(oem).unwrap_or(oem);
+                                let version = Self::driver_store_binary_version(repository, original);
+                                if verbose && version.is_none() {
+                                    println!("  No binary version found for {} ({})", oem, original);
+                                }
+                                Some((oem.clone(), version?))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers.into_iter()
+                .flat_map(|worker| worker.join().unwrap_or_default())
+                .collect()
+        });
+
+        if !quiet() {
+            println!("Found binary versions for {} of {} INFs", versions.len(), oem_infs.len());
+        }
+        versions
+    }
+
+    /// `%SystemRoot%\System32\DriverStore\FileRepository`
+    fn driver_store_repository() -> PathBuf {
+        std::env::var_os("SystemRoot")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("C:\\Windows"))
+            .join("System32\\DriverStore\\FileRepository")
+    }
+
+    /// Newest DriverStore package folder holding `original_inf`
+    fn driver_store_package(repository: &Path, original_inf: &str) -> Option<PathBuf> {
+        // Package folders are named `<inf>_<arch>_<hash>`; several versions can be staged
+        let pattern = repository.join(format!("{}_*", glob::Pattern::escape(original_inf)));
+        glob::glob(&pattern.to_string_lossy()).ok()?
+            .flatten()
+            .filter(|dir| dir.join(original_inf).is_file())
+            .max_by_key(|dir| fs::metadata(dir).and_then(|m| m.modified()).ok())
+    }
+
+    /// File version of the service binary in the newest DriverStore package for `original_inf`
+    fn driver_store_binary_version(repository: &Path, original_inf: &str) -> Option<String> {
+        let package = Self::driver_store_package(repository, original_inf)?;
+        let content = InfParser::read_inf_content(&package.join(original_inf), DEFAULT_MAX_INF_SIZE).ok()?;
+        let binary = Self::service_binary_name(&content)?;
+        let is_binary = |path: &Path| path.file_name()
+            .map(|name| name.to_string_lossy().eq_ignore_ascii_case(&binary))
+            .unwrap_or(false);
+        let mut found = Vec::new();
+        InfParser::find_files_recursive(&package, &WalkOptions::default(), &is_binary, &mut found, &mut Vec::new()).ok()?;
+        found.iter().find_map(|path| read_file_version(path))
+    }
+
+    /// File name of the INF's primary service binary: the `ServiceBinary` of the
+    /// service installed with `SPSVCINST_ASSOCSERVICE` (0x2), or else the first one.
+    fn service_binary_name(content: &str) -> Option<String> {
+        let mut services: Vec<(bool, String)> = Vec::new();
+        let mut binaries: HashMap<String, String> = HashMap::new();
+        let mut section = String::new();
+
+        for line in content.lines() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_lowercase();
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let key = key.trim();
+            if key.eq_ignore_ascii_case("AddService") {
+                // AddService = name, flags, service-install-section
+                let fields: Vec<&str> = value.split(',').map(str::trim).collect();
+                let flags = fields.get(1)
+                    .and_then(|f| match f.strip_prefix("0x").or_else(|| f.strip_prefix("0X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => f.parse().ok(),
+                    })
+                    .unwrap_or(0);
+                if let Some(install) = fields.get(2).filter(|s| !s.is_empty()) {
+                    services.push((flags & 0x2 != 0, install.to_lowercase()));
+                }
+            } else if key.eq_ignore_ascii_case("ServiceBinary") {
+                let file = value.trim().trim_matches('"').rsplit('\\').next().unwrap_or_default();
+                if !file.is_empty() {
+                    binaries.entry(section.clone()).or_insert_with(|| file.to_string());
+                }
+            }
+        }
+
+        let primary = services.iter().find(|(associated, _)| *associated).or(services.first())?;
+        binaries.get(&primary.1).cloned()
+    }
+
+    /// `export --stale`: list devices whose driver is older than `age`, oldest first,
+    /// followed by the ones whose driver date is missing or unreadable
+    fn stale_report(drivers: &[PnPSignedDriver], age: DriverAge, csv_path: Option<&Path>, delimiter: char) -> Result<()> {
+        let today = Utc::now().date_naive();
+        let cutoff = age.cutoff(today).context("--stale reaches back before the earliest supported date")?;
+
+        let mut stale: Vec<(&PnPSignedDriver, chrono::NaiveDate)> = Vec::new();
+        let mut undated: Vec<&PnPSignedDriver> = Vec::new();
+        for driver in drivers {
+            match driver.driver_date.as_deref().and_then(parse_driver_date) {
+                Some(date) if date < cutoff => stale.push((driver, date)),
+                Some(_) => {}
+                None => undated.push(driver),
+            }
+        }
+        stale.sort_by_key(|(_, date)| *date);
+        let years = |date: chrono::NaiveDate| format!("{:.1}", (today - date).num_days() as f64 / 365.25);
+
+        println!("\n----------------------------------------");
+        println!("Drivers older than {} ({} devices):", cutoff.format("%Y-%m-%d"), stale.len());
+        println!("----------------------------------------");
+        for (driver, date) in &stale {
+            println!(
+                "  {} | {} | v{} | {} | {} years",
+                driver.driver_provider_name.as_deref().unwrap_or("Unknown"),
+                driver.device_name.as_deref().unwrap_or("Unknown"),
+                driver.driver_version.as_deref().unwrap_or("?"),
+                date.format("%Y-%m-%d"),
+                years(*date),
+            );
+        }
+        if !undated.is_empty() {
+            println!("\nUnknown driver date ({} devices):", undated.len());
+            for driver in &undated {
+                println!(
+                    "  {} | {} | v{}",
+                    driver.driver_provider_name.as_deref().unwrap_or("Unknown"),
+                    driver.device_name.as_deref().unwrap_or("Unknown"),
+                    driver.driver_version.as_deref().unwrap_or("?"),
+                );
+            }
+        }
+
+        if let Some(csv_path) = csv_path {
+            let mut csv = CsvWriter::new(delimiter);
+            csv.write_row(&["Provider", "Device", "Driver Version", "Driver Date", "Age (years)"]);
+            let rows = stale.iter()
+                .map(|(driver, date)| (*driver, date.format("%Y-%m-%d").to_string(), years(*date)))
+                .chain(undated.iter().map(|driver| (*driver, String::new(), "unknown".to_string())));
+            for (driver, date, age) in rows {
+                csv.write_row(&[
+                    driver.driver_provider_name.as_deref().unwrap_or_default(),
+                    driver.device_name.as_deref().unwrap_or_default(),
+                    driver.driver_version.as_deref().unwrap_or_default(),
+                    &date,
+                    &age,
+                ]);
+            }
+            csv.save(csv_path)?;
+            println!("\nExported stale drivers to: {}", csv_path.display());
+        }
+        Ok(())
+    }
+
+    /// Query another machine's signed drivers for `export --computer` through
+    /// `\\HOST\root\cimv2`, with the same properties and `--wql-where` as a local query.
+    ///
+    /// COM is set up with dynamic cloaking so the connection goes out with the thread's
+    /// token, which is the `--username` logon when one was given.
+    fn query_remote_signed_drivers(computer: &str, credential: Option<&RemoteCredential>, timeout: Option<Duration>, wql_where: Option<&str>) -> Result<Vec<PnPSignedDriver>> {
+        use windows::Win32::Foundation::{HANDLE, RPC_E_TOO_LATE};
+        use windows::Win32::Security::ImpersonateLoggedOnUser;
+        use windows::Win32::System::Com::{
+            CoInitializeSecurity, EOAC_DYNAMIC_CLOAKING, RPC_C_AUTHN_LEVEL_DEFAULT, RPC_C_IMP_LEVEL_IMPERSONATE,
+        };
+
+        let namespace = format!(r"\\{}\root\cimv2", computer);
+        let user = credential.map(|c| c.username.clone());
+        // HANDLE isn't Send; the token stays open while `credential` is borrowed
+        let token = credential.map(|c| c.token.0 as isize);
+        Self::run_wmi_query_with(Self::signed_drivers_query(wql_where), "PnP signed drivers", timeout, move || {
+            let com_con = COMLibrary::without_security().context("Failed to initialize COM library")?;
+            // SAFETY: plain COM/Win32 calls; the token is a valid logon token (see above)
+            unsafe {
+                // Process-wide and only accepted once, so later hosts reuse the first call's settings
+                if let Err(e) = CoInitializeSecurity(
+                    None, -1, None, None, RPC_C_AUTHN_LEVEL_DEFAULT, RPC_C_IMP_LEVEL_IMPERSONATE, None, EOAC_DYNAMIC_CLOAKING, None,
+                ) {
+                    if e.code() != RPC_E_TOO_LATE {
+                        return Err(e).context("Failed to set up COM security");
+                    }
+                }
+                if let Some(token) = token {
+                    ImpersonateLoggedOnUser(HANDLE(token as *mut _)).context("Failed to use the --username logon")?;
+                }
+            }
+            WMIConnection::with_namespace_path(&namespace, com_con.into()).with_context(|| match &user {
+                Some(user) => format!("Failed to connect to {} as {}", namespace, user),
+                None => format!("Failed to connect to {}", namespace),
+            })
+        })
+    }
+
+    /// Search Windows Update for drivers not yet installed, for `--compare-to-online`.
+    /// Read-only: nothing is downloaded or installed. Needs network access and can take minutes.
+    fn query_online_drivers() -> Result<Vec<OnlineDriver>> {
+        use windows::core::{Interface, BSTR, HRESULT};
+        use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
+        use windows::Win32::System::UpdateAgent::{IUpdateSession, IWindowsDriverUpdate, UpdateSession};
+        const SERVICE_DISABLED: HRESULT = HRESULT(0x80070422_u32 as i32);
+        const WU_NO_CONNECTION: HRESULT = HRESULT(0x8024402C_u32 as i32);
+        const CANNOT_CONNECT: HRESULT = HRESULT(0x80072EE7_u32 as i32);
+
+        let _com_con = COMLibrary::new().context("Failed to initialize COM library")?;
+        // SAFETY: plain COM calls on interfaces that live until the end of the closure
+        let search = || unsafe {
+            let session: IUpdateSession = CoCreateInstance(&UpdateSession, None, CLSCTX_INPROC_SERVER)?;
+            let updates = session.CreateUpdateSearcher()?
+                .Search(&BSTR::from("IsInstalled=0 and Type='Driver'"))?
+                .Updates()?;
+            let mut online = Vec::new();
+            for index in 0..updates.Count()? {
+                let update = updates.get_Item(index)?.cast::<IWindowsDriverUpdate>()?;
+                online.push(OnlineDriver {
+                    title: update.Title().ok().map(|title| title.to_string()),
+                    hardware_id: update.DriverHardwareID().ok().map(|hwid| hwid.to_string()),
+                    driver_date: update.DriverVerDate().ok().and_then(ole_date_to_yyyymmdd),
+                });
+            }
+            windows::core::Result::Ok(online)
+        };
+        search().map_err(|e| match e.code() {
+            SERVICE_DISABLED => anyhow::anyhow!("the Windows Update service is disabled"),
+            WU_NO_CONNECTION | CANNOT_CONNECT => anyhow::anyhow!("Windows Update can't be reached (no network connection?)"),
+            _ => anyhow::Error::new(e).context("Windows Update search failed"),
+        })
+    }
+
+    /// Offer from Windows Update for one of the device's IDs with a newer driver date than installed
+    fn newer_online_driver<'a>(driver: &PnPSignedDriver, entities: &HashMap<String, PnPEntity>, online: &'a [OnlineDriver]) -> Option<&'a OnlineDriver> {
+        let installed = parse_driver_date(driver.driver_date.as_deref()?)?;
+        let ids = DeviceIds::of_driver(driver, entities);
+        online.iter()
+            .filter(|offer| offer.hardware_id.as_deref()
+                .and_then(|hwid| ids.best_match(hwid))
+                .is_some_and(|found| found.specificity >= HwidSpecificity::Compatible))
+            .filter(|offer| offer.driver_date.as_deref().and_then(parse_driver_date).is_some_and(|date| date > installed))
+            .max_by_key(|offer| offer.driver_date.as_deref().and_then(parse_driver_date))
+    }
+
+    /// Query the installed printer drivers for `--printers`
+    fn query_printer_drivers(timeout: Option<Duration>) -> Result<Vec<PrinterDriver>> {
+        let query = format!("SELECT {} FROM Win32_PrinterDriver", PRINTER_DRIVER_FIELDS);
+        Self::run_wmi_query::<PrinterDriver>(query, "printer drivers", timeout)
+    }
+
+    /// Query the kernel services for `--services` and attribute each one to the OEM package
+    /// that installed its binary. Unless `all`, services no OEM package owns are dropped.
+    fn query_system_drivers(timeout: Option<Duration>, inf_lookup: &HashMap<String, String>, all: bool) -> Result<Vec<SystemDriver>> {
+        let query = format!("SELECT {} FROM Win32_SystemDriver", SYSTEM_DRIVER_FIELDS);
+        let mut services = Self::run_wmi_query::<SystemDriver>(query, "kernel services", timeout)?;
+
+        let binaries = Self::oem_service_binaries(inf_lookup);
+        for service in &mut services {
+            service.oem_inf = service.path_name.as_deref()
+                .and_then(|path| Self::service_owner(path, inf_lookup, &binaries));
+        }
+        if !all {
+            services.retain(|service| service.oem_inf.is_some());
+        }
+        services.sort_by_key(|service| service.name.as_deref().unwrap_or_default().to_lowercase());
+        Ok(services)
+    }
+
+    /// Boot-critical services of OEM packages as `SystemDriver::summary` lines, by OEM INF.
+    /// This is the warning flag of export --services, prune and restore.
+    fn boot_critical_services(services: &[SystemDriver]) -> BTreeMap<String, Vec<String>> {
+        let mut flagged: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for service in services.iter().filter(|service| service.is_boot_critical()) {
+            if let Some(oem) = &service.oem_inf {
+                flagged.entry(oem.clone()).or_default().push(service.summary());
+            }
+        }
+        flagged
+    }
+
+    /// `boot_critical_services` of this machine for prune and restore. The flag is only a
+    /// warning, so a failed query is reported and flags nothing.
+    fn query_boot_critical_services() -> BTreeMap<String, Vec<String>> {
+        match Self::query_system_drivers(None, Self::inf_lookup(), false) {
+            Ok(services) => Self::boot_critical_services(&services),
+            Err(e) => {
+                eprintln!("Warning: Can't flag boot-critical drivers: {:#}", e);
+                BTreeMap::new()
+            }
+        }
+    }
+
+    /// Primary service binary file name (lowercase) of every OEM INF in `%SystemRoot%\INF`, mapped to that INF
+    fn oem_service_binaries(inf_lookup: &HashMap<String, String>) -> HashMap<String, String> {
+        let inf_dir = std::env::var_os("SystemRoot")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("C:\\Windows"))
+            .join("INF");
+        let mut oem_infs: Vec<&String> = inf_lookup.keys().collect();
+        oem_infs.sort();
+
+        let mut binaries = HashMap::new();
+        for oem in oem_infs {
+            let Ok(content) = InfParser::read_inf_content(&inf_dir.join(oem), DEFAULT_MAX_INF_SIZE) else { continue };
+            if let Some(binary) = Self::service_binary_name(&content) {
+                binaries.entry(binary.to_lowercase()).or_insert_with(|| oem.clone());
+            }
+        }
+        binaries
+    }
+
+    /// OEM INF owning a kernel service binary. A DriverStore path names the original INF,
+    /// which `inf_lookup` maps back to its OEM INF; any other path (usually `System32\drivers`)
+    /// is matched on file name against `binaries` from `oem_service_binaries`.
+    fn service_owner(path: &str, inf_lookup: &HashMap<String, String>, binaries: &HashMap<String, String>) -> Option<String> {
+        if let Some(original) = driver_store_original_inf(path) {
+            // Several staged versions can share an original name; take the same one on every run
+            let owner = inf_lookup.iter()
+                .filter(|(_, inf)| **inf == original)
+                .map(|(oem, _)| oem)
+                .min();
+            if owner.is_some() {
+                return owner.cloned();
+            }
+        }
+        let file_name = path.rsplit(['\\', '/']).next()?.trim_matches('"').to_lowercase();
+        binaries.get(&file_name).cloned()
+    }
+
+    /// Copy every printer driver's spooler files into `PrinterDrivers\<driver>` and write
+    /// `install_printer_drivers.ps1` listing the files and the command that re-adds each driver.
+    /// Missing files are reported and skipped. Returns the script path.
+    fn backup_printer_drivers(backup_dir: &Path, printers: &[PrinterDriver], verbose: bool) -> Result<PathBuf> {
+        let printer_dir = backup_dir.join("PrinterDrivers");
+        fs::create_dir_all(&printer_dir)
+            .with_context(|| format!("Failed to create directory: {}", printer_dir.display()))?;
+
+        let mut script = String::new();
+        script.push_str("# Re-add the printer drivers saved by driver-backup. Run as Administrator.\r\n");
+        script.push_str("# Drivers installed from a package need that package restored first (pnputil /add-driver).\r\n");
+
+        for printer in printers {
+            let folder_name = printer.driver_name()
+                .chars()
+                .map(|c| if c.is_alphanumeric() || c == ' ' || c == '.' || c == '-' || c == '_' { c } else { '_' })
+                .collect::<String>();
+            let driver_dir = printer_dir.join(&folder_name);
+            fs::create_dir_all(&driver_dir)
+                .with_context(|| format!("Failed to create directory: {}", driver_dir.display()))?;
+
+            script.push_str(&format!("\r\n# {} ({})\r\n", printer.driver_name(), printer.environment.as_deref().unwrap_or("unknown environment")));
+            for file in printer.files() {
+                let source = Path::new(file);
+                let Some(file_name) = source.file_name() else { continue };
+                match fs::copy(source, driver_dir.join(file_name)) {
+                    Ok(_) => {
+                        script.push_str(&format!("#   {}\\{}\r\n", folder_name, file_name.to_string_lossy()));
+                        if verbose {
+                            println!("  Copied {}", source.display());
+                        }
+                    }
+                    Err(e) => eprintln!("Warning: Failed to copy printer driver file {}: {}", source.display(), e),
+                }
+            }
+            let mut command = format!("Add-PrinterDriver -Name '{}'", printer.driver_name().replace('\'', "''"));
+            if let Some(environment) = &printer.environment {
+                command.push_str(&format!(" -PrinterEnvironment '{}'", environment.replace('\'', "''")));
+            }
+            script.push_str(&command);
+            script.push_str("\r\n");
+        }
+
+        let script_path = printer_dir.join("install_printer_drivers.ps1");
+        fs::write(&script_path, script)
+            .with_context(|| format!("Failed to write {}", script_path.display()))?;
+        Ok(script_path)
+    }
+
+    /// `--match-device`: keep drivers whose device name or description matches `query`
+    /// (see `device_name_matches`) and list the matched names so a fuzzy hit can be checked
+    fn filter_by_device_name(drivers: Vec<PnPSignedDriver>, query: &str) -> Vec<PnPSignedDriver> {
+        let matched: Vec<PnPSignedDriver> = drivers.into_iter()
+            .filter(|d| [&d.device_name, &d.description].into_iter().flatten().any(|name| device_name_matches(name, query)))
+            .collect();
+        if !quiet() {
+            let mut names: Vec<&str> = matched.iter()
+                .filter_map(|d| d.device_name.as_deref().or(d.description.as_deref()))
+                .collect();
+            names.sort_unstable_by_key(|name| name.to_lowercase());
+            names.dedup();
+            println!("Devices matching \"{}\": {}", query, names.len());
+            for name in names {
+                println!("  {}", name);
+            }
+        }
+        matched
+    }
+
+    /// Drop drivers whose provider is Microsoft (the default for `export` without `--all`)
+    fn third_party_drivers(drivers: Vec<PnPSignedDriver>) -> Vec<PnPSignedDriver> {
+        drivers.into_iter()
+            .filter(|d| {
+                d.driver_provider_name.as_ref()
+                    .map(|p| !p.to_lowercase().contains("microsoft"))
+                    .unwrap_or(true)
+            })
+            .collect()
+    }
+
+    /// Group drivers by driver version (collection), the rows of the export inventory
+    fn group_by_version(drivers: &[PnPSignedDriver], strip_version_suffix: bool) -> BTreeMap<String, Vec<&PnPSignedDriver>> {
+        let mut grouped: BTreeMap<String, Vec<&PnPSignedDriver>> = BTreeMap::new();
+        for driver in drivers {
+            let version = driver.driver_version.as_deref().unwrap_or("Unknown");
+            let key = if strip_version_suffix { normalize_driver_version(version) } else { version.to_string() };
+            grouped.entry(key).or_default().push(driver);
+        }
+        grouped
+    }
+
+    /// Header row of the export inventory for the columns `enrichment` adds
+    fn inventory_header(enrichment: &InventoryEnrichment) -> Vec<&'static str> {
+        let mut header = Vec::new();
+        if enrichment.computer.is_some() {
+            header.push("Computer");
+        }
+        header.extend(["Collection", "Device Class", "Provider", "Driver Version", "Driver Date", "Device Count", "Actual INFs", "Device Names", "Hardware IDs", "Location", "Bus", "All Hardware IDs"]);
+        if enrichment.status {
+            header.extend(["Present", "Status", "Problem Code"]);
+        }
+        if enrichment.file_versions.is_some() {
+            header.extend(["File Version", "Version Mismatch"]);
+        }
+        if enrichment.services.is_some() {
+            header.extend(["Kernel Services", "Boot Critical"]);
+        }
+        if enrichment.online_drivers.is_some() {
+            header.push("Newer on Windows Update");
+        }
+        header
+    }
+
+    /// Export WMI driver info to CSV, grouped by driver version (collection)
+    fn export_wmi_drivers_csv_static(drivers: &[PnPSignedDriver], enrichment: &InventoryEnrichment, inf_lookup: &HashMap<String, String>, output_path: &Path, delimiter: char, verbose: bool) -> Result<()> {
+        let grouped = Self::group_by_version(drivers, enrichment.strip_version_suffix);
+        let mut csv = CsvWriter::new(delimiter);
+        csv.write_row(&Self::inventory_header(enrichment));
+        Self::write_inventory_rows(&mut csv, &grouped, enrichment, inf_lookup, verbose);
+        if let Some(printers) = &enrichment.printers {
+            csv.write_row::<&str>(&[]);
+            csv.write_row(&["Printer Driver", "Version", "Environment", "Driver Path", "Data File", "Config File"]);
+            for printer in printers {
+                csv.write_row(&[
+                    printer.driver_name(),
+                    &printer.version.map(|v| v.to_string()).unwrap_or_default(),
+                    printer.environment.as_deref().unwrap_or_default(),
+                    printer.driver_path.as_deref().unwrap_or_default(),
+                    printer.data_file.as_deref().unwrap_or_default(),
+                    printer.config_file.as_deref().unwrap_or_default(),
+                ]);
+            }
+        }
+        if let Some(services) = &enrichment.services {
+            csv.write_row::<&str>(&[]);
+            csv.write_row(&["Kernel Service", "State", "Start Mode", "Path", "OEM INF", "Original INF", "Boot Critical"]);
+            for service in services {
+                let original = service.oem_inf.as_ref().and_then(|oem| inf_lookup.get(oem));
+                csv.write_row(&[
+                    service.name.as_deref().unwrap_or_default(),
+                    service.state.as_deref().unwrap_or_default(),
+                    service.start_mode.as_deref().unwrap_or_default(),
+                    service.path_name.as_deref().unwrap_or_default(),
+                    service.oem_inf.as_deref().unwrap_or_default(),
+                    original.map(String::as_str).unwrap_or_default(),
+                    if service.is_boot_critical() { "Yes" } else { "No" },
+                ]);
+            }
+        }
+        csv.save(output_path)?;
+        if quiet() {
+            return Ok(());
+        }
+
+        println!("CSV created: {}", output_path.display());
+        println!("Total collections: {}", grouped.len());
+        println!("Total devices: {}", drivers.len());
+        if let Some(printers) = &enrichment.printers {
+            println!("Printer drivers: {}", printers.len());
+        }
+        if let Some(services) = &enrichment.services {
+            println!("Kernel services: {}", services.len());
+        }
+
+        if verbose {
+            println!("\nDriver collections exported:");
+            for (version, drivers_for_version) in &grouped {
+                let first = drivers_for_version.first().unwrap();
+                let provider = first.driver_provider_name.as_deref().unwrap_or("Unknown");
+                println!("\n  {} {} - {} devices", provider, version, drivers_for_version.len());
+                for driver in drivers_for_version {
+                    let oem = driver.inf_name.as_deref().unwrap_or("unknown").to_lowercase();
+                    let actual = inf_lookup.get(&oem).map(|s| s.as_str()).unwrap_or(&oem);
+                    println!("    - {} | {} | {}", 
+                        driver.device_name.as_deref().unwrap_or("Unknown"),
+                        driver.hardware_id.as_deref().unwrap_or("Unknown"),
+                        actual);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Append one inventory row per driver collection
+    fn write_inventory_rows(csv: &mut CsvWriter, grouped: &BTreeMap<String, Vec<&PnPSignedDriver>>, enrichment: &InventoryEnrichment, inf_lookup: &HashMap<String, String>, verbose: bool) {
+        let entities = &enrichment.entities;
+        // Rows in version order; the map itself orders the version strings as text
+        let mut grouped: Vec<_> = grouped.iter().collect();
+        grouped.sort_by(|(a, _), (b, _)| compare_driver_versions(a, b).then_with(|| a.cmp(b)));
+        for (version, drivers_for_version) in grouped {
+            let first = drivers_for_version.first().unwrap();
+            
+            let driver_date = format_driver_date(first.driver_date.as_deref(), verbose);
+
+            // Collect unique actual INF names
+            let mut actual_infs: Vec<String> = drivers_for_version.iter()
+                .filter_map(|d| {
+                    let oem = d.inf_name.as_deref()?.to_lowercase();
+                    Some(inf_lookup.get(&oem).cloned().unwrap_or(oem))
+                })
+                .collect();
+            actual_infs.sort();
+            actual_infs.dedup();
+
+            // Collect device names and hardware IDs
+            let device_names: Vec<String> = drivers_for_version.iter()
+                .filter_map(|d| d.device_name.clone())
+                .collect();
+            let hardware_ids: Vec<String> = drivers_for_version.iter()
+                .filter_map(|d| d.hardware_id.clone())
+                .collect();
+
+            // Location comes from the signed driver; the bus from its matching PnP entity
+            let locations: Vec<String> = drivers_for_version.iter()
+                .filter_map(|d| d.location.clone())
+                .collect();
+            let mut buses: Vec<String> = drivers_for_version.iter()
+                .filter_map(|d| entities.get(&device_instance_key(d.device_id.as_deref()?)))
+                .filter_map(|entity| entity.bus().map(str::to_string))
+                .collect();
+            buses.sort();
+            buses.dedup();
+
+            // Every ID the devices report, ranked as Windows ranks them
+            let mut all_ids: Vec<&str> = Vec::new();
+            for entity in drivers_for_version.iter().filter_map(|d| entities.get(&device_instance_key(d.device_id.as_deref()?))) {
+                for id in entity.device_ids() {
+                    if !all_ids.iter().any(|seen| seen.eq_ignore_ascii_case(id)) {
+                        all_ids.push(id);
+                    }
+                }
+            }
+
+            // Create collection name from provider + version
+            let provider = first.driver_provider_name.as_deref().unwrap_or("Unknown");
+            let collection_name = format!("{} {} Package", provider, version);
+
+            // The CSV keeps the versions as reported, even when grouped on a normalized key
+            let mut original_versions: Vec<&str> = drivers_for_version.iter()
+                .filter_map(|d| d.driver_version.as_deref())
+                .collect();
+            original_versions.sort_by(|a, b| compare_driver_versions(a, b).then_with(|| a.cmp(b)));
+            original_versions.dedup();
+            let shown_version = if original_versions.is_empty() { version.clone() } else { original_versions.join("; ") };
+
+            let mut row: Vec<String> = enrichment.computer.iter().cloned().collect();
+            row.extend([
+                collection_name,
+                first.device_class.clone().unwrap_or_else(|| "Unknown".to_string()),
+                provider.to_string(),
+                shown_version,
+                driver_date,
+                drivers_for_version.len().to_string(),
+                actual_infs.join("; "),
+                device_names.join("; "),
+                hardware_ids.join("; "),
+                locations.join("; "),
+                buses.join("; "),
+                all_ids.join("; "),
+            ]);
+            if enrichment.status {
+                // One value per device, in the same order for all three columns
+                let cells: Vec<[String; 3]> = drivers_for_version.iter()
+                    .map(|d| device_status_cells(d, entities))
+                    .collect();
+                for column in 0..3 {
+                    row.push(cells.iter().map(|c| c[column].as_str()).collect::<Vec<_>>().join("; "));
+                }
+            }
+            if let Some(file_versions) = &enrichment.file_versions {
+                let mut versions: Vec<&str> = drivers_for_version.iter()
+                    .filter_map(|d| file_versions.get(&d.inf_name.as_deref()?.to_lowercase()))
+                    .map(String::as_str)
+                    .collect();
+                versions.sort_by(|a, b| compare_driver_versions(a, b));
+                versions.dedup();
+                // Blank when no binary was found, rather than guessing
+                let mismatch = if versions.is_empty() || version == "Unknown" {
+                    ""
+                } else if versions.iter().any(|v| compare_driver_versions(v, version) != std::cmp::Ordering::Equal) {
+                    "Yes"
+                } else {
+                    "No"
+                };
+                row.push(versions.join("; "));
+                row.push(mismatch.to_string());
+            }
+            if let Some(services) = &enrichment.services {
+                let owned: Vec<&SystemDriver> = services.iter()
+                    .filter(|service| drivers_for_version.iter().any(|d| {
+                        d.inf_name.as_deref().map(str::to_lowercase) == service.oem_inf
+                    }))
+                    .collect();
+                let boot_critical = owned.iter().any(|service| service.is_boot_critical());
+                row.push(owned.iter().map(|service| service.summary()).collect::<Vec<_>>().join("; "));
+                row.push(if boot_critical { "Yes" } else { "No" }.to_string());
+            }
+            if let Some(online) = &enrichment.online_drivers {
+                let mut offers: Vec<String> = drivers_for_version.iter()
+                    .filter_map(|d| Self::newer_online_driver(d, entities, online))
+                    .map(OnlineDriver::summary)
+                    .collect();
+                offers.sort();
+                offers.dedup();
+                row.push(offers.join("; "));
+            }
+            let row: Vec<&str> = row.iter().map(String::as_str).collect();
+            csv.write_row(&row);
+        }
+    }
+}
+
+/// Paths that couldn't be processed, with the reason
+type PathErrors = Vec<(PathBuf, String)>;
+
+/// Controls how directory trees are walked when looking for files
+#[derive(Default)]
+struct WalkOptions {
+    /// Descend into symlinks and junctions (loops are detected and skipped)
+    follow_links: bool,
+    /// Maximum folder depth below the starting folder, `None` for unlimited
+    max_depth: Option<usize>,
+    /// Folders whose name or full path matches one of these are skipped entirely
+    exclude_dirs: Vec<glob::Pattern>,
+}
+
+impl WalkOptions {
+    fn is_excluded(&self, dir: &Path) -> bool {
+        let match_options = glob::MatchOptions { case_sensitive: false, ..Default::default() };
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        self.exclude_dirs.iter().any(|pattern| {
+            pattern.matches_with(&name, match_options) || pattern.matches_path_with(dir, match_options)
+        })
+    }
+}
+
+/// Result filters shared by scan and inspect (`--class`, `--provider`, `--hwid`).
+/// All given filters must match, compared case-insensitively; the classes of `--class`
+/// and the `--only-*` presets are one list of which any may match.
+#[derive(Debug, Default, Clone, clap::Args)]
+struct InfFilter {
+    /// Only show INFs of this device class (repeat for several)
+    #[arg(long, value_name = "NAME")]
+    class: Vec<String>,
+
+    /// Only show graphics drivers (class Display)
+    #[arg(long)]
+    only_gpu: bool,
+
+    /// Only show network drivers (class Net)
+    #[arg(long)]
+    only_network: bool,
+
+    /// Only show audio drivers (classes Media and AudioEndpoint)
+    #[arg(long)]
+    only_audio: bool,
+
+    /// Only show storage controller drivers (classes SCSIAdapter and HDC)
+    #[arg(long)]
+    only_storage: bool,
+
+    /// Only show INFs whose provider contains this text
+    #[arg(long, value_name = "TEXT")]
+    provider: Option<String>,
+
+    /// Only show INFs with a device entry whose hardware ID contains this text
+    #[arg(long, value_name = "TEXT")]
+    hwid: Option<String>,
+
+    /// Keep device entries whose hardware ID is listed under [ControlFlags] ExcludeFromSelect
+    /// (hidden by default; `ExcludeFromSelect = *` entries are always kept and marked)
+    #[arg(long)]
+    show_excluded: bool,
+}
+
+impl InfFilter {
+    /// Classes from `--class` plus those of the `--only-*` presets given
+    fn classes(&self) -> Vec<&str> {
+        let presets: [(bool, &[&str]); 4] = [
+            (self.only_gpu, &["Display"]),
+            (self.only_network, &["Net"]),
+            (self.only_audio, &["Media", "AudioEndpoint"]),
+            (self.only_storage, &["SCSIAdapter", "HDC"]),
+        ];
+        let mut classes: Vec<&str> = self.class.iter().map(String::as_str).collect();
+        classes.extend(presets.into_iter().filter(|(enabled, _)| *enabled).flat_map(|(_, names)| names.iter().copied()));
+        classes
+    }
+
+    fn matches(&self, parsed: &ParsedInfFile) -> bool {
+        let classes = self.classes();
+        if !classes.is_empty() {
+            let inf_class = parsed.raw_version_info.class.as_deref().unwrap_or_default();
+            if !classes.iter().any(|class| inf_class.eq_ignore_ascii_case(class)) {
+                return false;
+            }
+        }
+        if let Some(provider) = &self.provider {
+            // The [Version] provider is often a %token%; device entries carry the resolved name
+            let provider = provider.to_lowercase();
+            let raw = parsed.raw_version_info.provider.as_deref();
+            let resolved = parsed.drivers.first().and_then(|d| d.driver_provider_name.as_deref());
+            if ![raw, resolved].into_iter().flatten().any(|p| p.to_lowercase().contains(&provider)) {
+                return false;
+            }
+        }
+        if let Some(hwid) = &self.hwid {
+            let hwid = normalize_hardware_id(hwid);
+            let any_device = parsed.drivers.iter()
+                .filter_map(|d| d.hardware_id.as_deref())
+                .any(|id| normalize_hardware_id(id).contains(&hwid));
+            if !any_device {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Drop INFs that don't match, returning how many were removed, then the device entries
+    /// listed by hardware ID under `ExcludeFromSelect` (unless `--show-excluded`)
+    fn apply(&self, parsed_files: &mut Vec<ParsedInfFile>) -> usize {
+        let before = parsed_files.len();
+        parsed_files.retain(|parsed| self.matches(parsed));
+        if !self.show_excluded {
+            for parsed in parsed_files.iter_mut() {
+                parsed.drivers.retain(|d| d.excluded != Exclusion::Listed);
+            }
+        }
+        before - parsed_files.len()
+    }
+}
+
+/// Settings for the scan command
+struct ScanOptions {
+    output: Option<PathBuf>,
+    delimiter: char,
+    /// Selected `SCAN_CSV_COLUMNS` indexes, `None` for all
+    csv_columns: Option<Vec<usize>>,
+    verbose: bool,
+    /// `None` lists the INFs without grouping
+    group_by: Option<GroupBy>,
+    /// Groups with fewer INFs than this are folded into "Other"
+    group_threshold: usize,
+    /// Order applied to the list, the groups' contents and the CSV (`None` keeps the default)
+    sort: Option<SortKey>,
+    descending: bool,
+    recursive: bool,
+    walk: WalkOptions,
+    bus: Option<Bus>,
+    /// Parse cache file, `None` when caching is disabled
+    cache_path: Option<PathBuf>,
+    /// INFs larger than this many bytes are rejected as parse errors
+    max_inf_size: u64,
+    /// `--class-map` renames applied when grouping, keyed by lowercase class
+    class_map: HashMap<String, String>,
+    filter: InfFilter,
+    /// Report copies of the same package (`--duplicates`)
+    duplicates: bool,
+    /// Write the duplicate clusters to this CSV
+    duplicates_csv: Option<PathBuf>,
+    /// Second folder to compare the scan against
+    diff: Option<PathBuf>,
+    /// Write the `--diff` results to this CSV
+    diff_csv: Option<PathBuf>,
+    /// Read results from this index instead of scanning `paths`
+    index: Option<PathBuf>,
+    /// Save all parsed results (before filtering) to this index
+    write_index: Option<PathBuf>,
+    /// Include INF SHA-256 hashes in the written index
+    index_hashes: bool,
+    /// Also write one JSON object per device entry to this file (`-` for stdout)
+    ndjson: Option<PathBuf>,
+}
+
+/// `scan --watch` waits until the folders have been quiet this long before rescanning
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Size limit for INF files when the user can't choose one (backup summary, inbox scan)
+const DEFAULT_MAX_INF_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Bump whenever `parse_inf_file` output changes so stale cache entries are discarded
+const SCAN_CACHE_VERSION: u32 = 12;
+
+/// Parse results from previous scans, keyed by absolute INF path
+#[derive(Serialize, Deserialize, Default)]
+struct ScanCache {
+    version: u32,
+    /// `--locale` the entries were parsed with; device names depend on it
+    #[serde(default)]
+    locale: String,
+    entries: BTreeMap<String, ScanCacheEntry>,
+    #[serde(skip)]
+    hits: usize,
+    #[serde(skip)]
+    misses: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScanCacheEntry {
+    size: u64,
+    modified_nanos: u64,
+    parsed: ParsedInfFile,
+}
+
+impl ScanCache {
+    /// Default cache location: `%LOCALAPPDATA%\driver-backup\scan-cache.json`
+    fn default_path() -> Option<PathBuf> {
+        std::env::var_os("LOCALAPPDATA")
+            .map(|dir| PathBuf::from(dir).join("driver-backup").join("scan-cache.json"))
+    }
+
+    /// Load a cache file; a missing, unreadable, or outdated cache starts empty
+    fn load(path: &Path) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<ScanCache>(&bytes).ok())
+            .filter(|cache| cache.version == SCAN_CACHE_VERSION && cache.locale == inf_locale())
+            .unwrap_or_else(|| ScanCache { version: SCAN_CACHE_VERSION, locale: inf_locale().to_string(), ..Default::default() })
+    }
+
+    /// Save the cache, dropping entries for files that no longer exist
+    fn save(&mut self, path: &Path) -> Result<()> {
+        self.entries.retain(|inf_path, _| Path::new(inf_path).is_file());
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec(self)?;
+        fs::write(path, json)
+            .with_context(|| format!("Failed to write scan cache: {}", path.display()))
+    }
+
+    /// Size and modification time used to detect changed files
+    fn file_stamp(inf_path: &Path) -> Option<(u64, u64)> {
+        let metadata = fs::metadata(inf_path).ok()?;
+        let modified = metadata.modified().ok()?
+            .duration_since(std::time::UNIX_EPOCH).ok()?
+            .as_nanos() as u64;
+        Some((metadata.len(), modified))
+    }
+
+    /// Return the cached parse result for an unchanged file, or parse and remember it
+    fn parse(&mut self, inf_path: &Path, max_inf_size: u64) -> Result<ParsedInfFile> {
+        let key = std::path::absolute(inf_path)
+            .unwrap_or_else(|_| inf_path.to_path_buf())
+            .to_string_lossy()
+            .to_string();
+        let stamp = Self::file_stamp(inf_path);
+
+        if let (Some(entry), Some((size, modified))) = (self.entries.get(&key), stamp) {
+            if entry.size == size && entry.modified_nanos == modified && size <= max_inf_size {
+                self.hits += 1;
+                return Ok(entry.parsed.clone());
+            }
+        }
+
+        self.misses += 1;
+        let parsed = InfParser::parse_inf_file(inf_path, max_inf_size)?;
+        if let Some((size, modified_nanos)) = stamp {
+            self.entries.insert(key, ScanCacheEntry { size, modified_nanos, parsed: parsed.clone() });
+        }
+        Ok(parsed)
+    }
+}
+
+/// Bump whenever the index layout or `ParsedInfFile` changes; older indexes are rejected
+const SCAN_INDEX_VERSION: u32 = 1;
+
+/// Parsed metadata of a whole repository (`scan --write-index`), read back by `scan --index`
+/// so lookups don't have to parse every INF again
+#[derive(Serialize, Deserialize)]
+struct ScanIndex {
+    schema_version: u32,
+    /// Folder the entry paths are relative to
+    root: PathBuf,
+    created: String,
+    entries: Vec<ScanIndexEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScanIndexEntry {
+    /// `file_path` is relative to the index root
+    parsed: ParsedInfFile,
+    size: u64,
+    modified_nanos: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    folder_size: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    missing_files: Vec<String>,
+}
+
+impl ScanIndex {
+    /// Snapshot parsed INFs found under `root`; package status must already be filled in
+    fn build(root: &Path, parsed_files: &[ParsedInfFile], with_hashes: bool) -> Result<Self> {
+        let root = std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut entries = Vec::new();
+        for parsed in parsed_files {
+            let absolute = std::path::absolute(&parsed.file_path).unwrap_or_else(|_| parsed.file_path.clone());
+            let (size, modified_nanos) = ScanCache::file_stamp(&absolute).unwrap_or_default();
+            let sha256 = if with_hashes { Some(sha256_file(&absolute)?) } else { None };
+            let (folder_size, missing_files) = parsed.package.as_ref()
+                .map(|status| (status.folder_size, status.missing_files.clone()))
+                .unwrap_or_default();
+
+            let mut parsed = parsed.clone();
+            parsed.file_path = absolute.strip_prefix(&root).map(Path::to_path_buf).unwrap_or(absolute);
+            entries.push(ScanIndexEntry { parsed, size, modified_nanos, sha256, folder_size, missing_files });
+        }
+
+        Ok(ScanIndex {
+            schema_version: SCAN_INDEX_VERSION,
+            root,
+            created: Utc::now().to_rfc3339(),
+            entries,
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec(self)?;
+        create_parent_dir(path)?;
+        fs::write(path, json)
+            .with_context(|| format!("Failed to write index: {}", path.display()))
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("Failed to read index: {}", path.display()))?;
+        let index: ScanIndex = serde_json::from_slice(&bytes)
+            .with_context(|| format!("Invalid index file: {}", path.display()))?;
+        if index.schema_version != SCAN_INDEX_VERSION {
+            anyhow::bail!(
+                "Index {} has schema version {}, expected {}; re-create it with scan --write-index",
+                path.display(), index.schema_version, SCAN_INDEX_VERSION
+            );
+        }
+        Ok(index)
+    }
+
+    /// Turn the entries back into scan results, reporting INFs that were removed or
+    /// changed since the index was written (their indexed data is still used)
+    fn into_parsed_files(self, verbose: bool) -> Vec<ParsedInfFile> {
+        let source = self.root.display().to_string();
+        let (mut missing, mut changed) = (Vec::new(), Vec::new());
+
+        let parsed_files = self.entries.into_iter()
+            .map(|entry| {
+                let mut parsed = entry.parsed;
+                parsed.file_path = self.root.join(&parsed.file_path);
+                match ScanCache::file_stamp(&parsed.file_path) {
+                    None => missing.push(parsed.file_path.clone()),
+                    Some(stamp) if stamp != (entry.size, entry.modified_nanos) => changed.push(parsed.file_path.clone()),
+                    Some(_) => {}
+                }
+                parsed.source = source.clone();
+                parsed.package = Some(PackageStatus {
+                    folder_size: entry.folder_size,
+                    missing_files: entry.missing_files,
+                });
+                parsed
+            })
+            .collect();
+
+        if !missing.is_empty() || !changed.is_empty() {
+            eprintln!("Warning: index is stale: {} INF files missing, {} changed since {}",
+                missing.len(), changed.len(), self.created);
+            if verbose {
+                for path in &missing {
+                    eprintln!("  missing: {}", path.display());
+                }
+                for path in &changed {
+                    eprintln!("  changed: {}", path.display());
+                }
+            }
+        }
+        parsed_files
+    }
+}
+
+/// Settings for the inspect command
+struct InspectOptions {
+    output: Option<PathBuf>,
+    delimiter: char,
+    /// Selected `INSPECT_CSV_COLUMNS` indexes, `None` for all
+    csv_columns: Option<Vec<usize>>,
+    verbose: bool,
+    /// `Some(None)` keeps the temp dir, `Some(Some(dir))` extracts into `dir`
+    keep_extracted: Option<Option<PathBuf>>,
+    /// How many levels of nested archives to extract (0 = don't recurse)
+    archive_depth: usize,
+    /// Upper bound for everything extracted from one package, in bytes
+    max_extracted_bytes: u64,
+    /// INFs larger than this many bytes are rejected as parse errors
+    max_inf_size: u64,
+    /// Save a downloaded package in the current folder instead of a temp dir
+    keep_download: bool,
+    /// Accept plain `http://` package URLs
+    allow_http: bool,
+    /// Compare package versions against the drivers installed on this system
+    compare_system: bool,
+    /// `--force-arch`: compare as if this machine had that architecture
+    force_arch: Option<Architecture>,
+    /// Print each INF's sections and lines as the parser reads them (`--dump-sections`)
+    dump_sections: bool,
+    /// Keep the temp extraction folder when no INFs could be found or parsed (`--keep-temp-on-error`)
+    keep_temp_on_error: bool,
+    /// Only report installed devices the package would update (implies `compare_system`)
+    only_updatable: bool,
+    /// List every extracted file with its size
+    list_files: bool,
+    /// Print the file list flat instead of as a tree
+    flat: bool,
+    filter: InfFilter,
+    /// `--seven-zip`: tried before any detected 7-Zip
+    seven_zip: Option<PathBuf>,
+}
+
+/// A device line from an INF models section, resolved by `InfParser::resolve_device_sections`
+struct InfDeviceEntry {
+    /// `[Manufacturer]` name of the models section, `None` if no entry lists it
+    manufacturer: Option<String>,
+    /// Platform of the models section's decoration
+    architecture: Option<Architecture>,
+    description: String,
+    hardware_id: String,
+}
+
+/// A file found in an extracted package, for `inspect --list-files`
+struct PayloadFile {
+    /// The input the file came from
+    source: String,
+    /// Path relative to the extraction folder
+    path: PathBuf,
+    /// The extraction folder, for `--paths absolute`
+    work_dir: PathBuf,
+    size: u64,
+}
+
+// INF Parser for extracting driver information from INF files
+struct InfParser;
+
+impl InfParser {
+    /// Prefix shared by all temporary extraction directories
+    const TEMP_DIR_PREFIX: &'static str = "driver_inspect_";
+
+    /// Default 7-Zip install folders, used when the Program Files variables aren't set
+    const SEVEN_ZIP_FOLDERS: [&'static str; 2] = [
+        "C:\\Program Files\\7-Zip",
+        "C:\\Program Files (x86)\\7-Zip",
+    ];
+
+    /// 7-Zip executables to try, detected on first use unless `inspect --seven-zip` set them
+    fn seven_zip_paths() -> &'static [PathBuf] {
+        SEVEN_ZIP.get_or_init(|| Self::detect_seven_zip(None))
+    }
+
+    /// Look for `7z.exe`: `custom` first, then PATH, both Program Files folders and the
+    /// folder 7-Zip registers under `App Paths\7zFM.exe`. Only existing files are returned.
+    fn detect_seven_zip(custom: Option<&Path>) -> Vec<PathBuf> {
+        let mut candidates: Vec<PathBuf> = custom.map(Path::to_path_buf).into_iter().collect();
+        if let Some(path) = std::env::var_os("PATH") {
+            candidates.extend(std::env::split_paths(&path).map(|dir| dir.join("7z.exe")));
+        }
+        for variable in ["ProgramFiles", "ProgramW6432", "ProgramFiles(x86)"] {
+            if let Some(dir) = std::env::var_os(variable) {
+                candidates.push(PathBuf::from(dir).join("7-Zip\\7z.exe"));
+            }
+        }
+        candidates.extend(Self::SEVEN_ZIP_FOLDERS.iter().map(|dir| Path::new(dir).join("7z.exe")));
+        if let Some(dir) = Self::seven_zip_app_path() {
+            candidates.push(dir.join("7z.exe"));
+        }
+
+        let mut found: Vec<PathBuf> = Vec::new();
+        for candidate in candidates {
+            let known = found.iter().any(|path| path.to_string_lossy().eq_ignore_ascii_case(&candidate.to_string_lossy()));
+            if !known && candidate.is_file() {
+                found.push(candidate);
+            }
+        }
+        found
+    }
+
+    /// Install folder from `HKLM\...\App Paths\7zFM.exe`, set by the 7-Zip installer
+    fn seven_zip_app_path() -> Option<PathBuf> {
+        let output = Command::new("reg")
+            .args(["query", "HKLM\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\App Paths\\7zFM.exe", "/v", "Path"])
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| line.split_once("REG_SZ").map(|(_, value)| value.trim().to_string()))
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Extract driver package from installer (.exe, .zip) or use folder directly.
+    ///
+    /// Returns the directory to search for INF files and, when the package was
+    /// extracted to a temporary directory, a guard that removes it on drop.
+    /// If `keep_dir` is given, the package is extracted there and never removed.
+    fn extract_or_use_path(path: &Path, keep_dir: Option<&Path>, verbose: bool) -> Result<(PathBuf, Option<TempDir>)> {
+        if path.is_dir() {
+            return Ok((path.to_path_buf(), None));
+        }
+
+        let extension = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "exe" | "zip" | "7z" | "rar" | "cab" | "msi" => {
+                let (dest_dir, temp_guard) = match keep_dir {
+                    Some(dir) => {
+                        fs::create_dir_all(dir)
+                            .with_context(|| format!("Failed to create extraction directory: {}", dir.display()))?;
+                        (dir.to_path_buf(), None)
+                    }
+                    None => {
+                        let temp = tempfile::Builder::new()
+                            .prefix(Self::TEMP_DIR_PREFIX)
+                            .tempdir()
+                            .context("Failed to create temporary extraction directory")?;
+                        (temp.path().to_path_buf(), Some(temp))
+                    }
+                };
+
+                if verbose {
+                    println!("Extracting {} to {}...", path.display(), dest_dir.display());
+                }
+
+                Self::extract_archive(path, &dest_dir, verbose)?;
+                if interrupted() {
+                    anyhow::bail!("Interrupted by Ctrl-C");
+                }
+
+                if verbose {
+                    println!("Successfully extracted to {} ({:.1} MB)",
+                        dest_dir.display(), Self::dir_size(&dest_dir) as f64 / (1024.0 * 1024.0));
+                }
+                Ok((dest_dir, temp_guard))
+            }
+            "inf" => {
+                // Single INF file - use parent directory
+                Ok((path.parent().unwrap_or(Path::new(".")).to_path_buf(), None))
+            }
+            "" => {
+                // No extension to go by - look for INF files next to it
+                let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+                if !quiet() {
+                    println!("{} has no file extension; searching {} for INF files", path.display(), parent.display());
+                }
+                Ok((parent.to_path_buf(), None))
+            }
+            _ => anyhow::bail!("Unsupported file type: .{} (expected .exe, .zip, .7z, .rar, .cab, .msi, .inf or a folder)", extension)
+        }
+    }
+
+    /// Remove extraction directories left behind by crashed or killed runs.
+    /// Only directories older than a day are touched, so concurrent inspects are safe.
+    fn remove_stale_temp_dirs(verbose: bool) {
+        let max_age = std::time::Duration::from_secs(24 * 60 * 60);
+        let Ok(entries) = fs::read_dir(std::env::temp_dir()) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_ours = entry.file_name().to_string_lossy().starts_with(Self::TEMP_DIR_PREFIX);
+            if !is_ours || !path.is_dir() {
+                continue;
+            }
+
+            let is_stale = entry.metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .map(|age| age > max_age)
+                .unwrap_or(false);
+
+            if is_stale && fs::remove_dir_all(&path).is_ok() && verbose {
+                println!("Removed stale temporary directory: {}", path.display());
+            }
+        }
+    }
+
+    /// Check if a file has an archive/installer extension that inspect can extract
+    fn is_archive(path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| matches!(e.to_lowercase().as_str(), "exe" | "zip" | "7z" | "rar" | "cab" | "msi"))
+            .unwrap_or(false)
+    }
+
+    /// Extract archives found inside an extracted package, level by level up to `max_depth`.
+    /// Each archive is unpacked next to itself into `<name>_extracted`.
+    fn extract_nested_archives(root: &Path, max_depth: usize, max_bytes: u64, verbose: bool) -> Result<()> {
+        let mut search_dirs = vec![root.to_path_buf()];
+
+        for depth in 1..=max_depth {
+            let mut archives = Vec::new();
+            for dir in &search_dirs {
+                let mut warnings = Vec::new();
+                Self::find_files_recursive(dir, &WalkOptions::default(), &Self::is_archive, &mut archives, &mut warnings)?;
+            }
+            archives.sort();
+
+            let mut extracted_dirs = Vec::new();
+            for archive in archives {
+                let file_name = archive.file_name().unwrap_or_default().to_string_lossy().to_string();
+                let dest = archive.with_file_name(format!("{}_extracted", file_name));
+                if dest.exists() {
+                    continue;
+                }
+
+                fs::create_dir_all(&dest)
+                    .with_context(|| format!("Failed to create extraction directory: {}", dest.display()))?;
+
+                let result = Self::extract_archive(&archive, &dest, false);
+
+                match result {
+                    Ok(_) => {
+                        if verbose {
+                            println!("Extracted nested archive (level {}): {}", depth, archive.display());
+                        }
+                        extracted_dirs.push(dest);
+                    }
+                    Err(_) => {
+                        // Plain executables (setup tools etc.) aren't archives; that's expected
+                        let _ = fs::remove_dir_all(&dest);
+                    }
+                }
+
+                Self::check_extracted_size(root, max_bytes)?;
+                if interrupted() {
+                    anyhow::bail!("Interrupted by Ctrl-C");
+                }
+            }
+
+            if extracted_dirs.is_empty() {
+                break;
+            }
+            search_dirs = extracted_dirs;
+        }
+
+        Ok(())
+    }
+
+    /// Abort when extracted content grows past the limit (zip bomb protection)
+    fn check_extracted_size(dir: &Path, max_bytes: u64) -> Result<()> {
+        let size = Self::dir_size(dir);
+        if size > max_bytes {
+            anyhow::bail!(
+                "Extracted content is {} MB, exceeding the {} MB limit; use --max-extracted-mb to raise it",
+                size / (1024 * 1024),
+                max_bytes / (1024 * 1024)
+            );
+        }
+        Ok(())
+    }
+
+    /// Total size of all files below a directory, in bytes
+    fn dir_size(dir: &Path) -> u64 {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return 0;
+        };
+
+        entries.flatten()
+            .map(|entry| {
+                let path = entry.path();
+                if path.is_dir() {
+                    Self::dir_size(&path)
+                } else {
+                    entry.metadata().map(|m| m.len()).unwrap_or(0)
+                }
+            })
+            .sum()
+    }
+
+    fn extract_with_7z(archive: &Path, dest: &Path) -> Result<()> {
+        let seven_zips = Self::seven_zip_paths();
+        if seven_zips.is_empty() {
+            anyhow::bail!(
+                "7-Zip is needed to extract {} but wasn't found in PATH, Program Files or the registry; \
+                 install it from https://www.7-zip.org or pass --seven-zip <path to 7z.exe>",
+                archive.display()
+            );
+        }
+
+        let mut reason = String::new();
+        for seven_zip in seven_zips {
+            let output = Command::new(seven_zip)
+                .arg("x")
+                .arg("-y")
+                .arg(format!("-o{}", dest.display()))
+                .arg(archive)
+                .output();
+
+            match output {
+                Ok(result) if result.status.success() => return Ok(()),
+                Ok(result) => {
+                    // 7z puts the useful part ("Can not open the file as archive") last
+                    let stderr = String::from_utf8_lossy(&result.stderr);
+                    reason = stderr.lines().map(str::trim).rfind(|line| !line.is_empty())
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("exited with {}", result.status));
+                }
+                Err(e) => reason = e.to_string(),
+            }
+        }
+
+        anyhow::bail!(
+            "7-Zip could not extract {} ({}). Not every installer is an archive; to see 7-Zip's full output run: \"{}\" x -y \"-o{}\" \"{}\"",
+            archive.display(),
+            reason,
+            seven_zips[0].display(),
+            dest.display(),
+            archive.display()
+        )
+    }
+
+    /// Extract an archive: ZIPs and CABs are unpacked in-process, everything else goes through 7-Zip
+    fn extract_archive(archive: &Path, dest: &Path, verbose: bool) -> Result<()> {
+        let extension = archive.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "zip" => Self::extract_zip(archive, dest, verbose),
+            "cab" => Self::extract_cab(archive, dest, verbose)
+                .or_else(|e| {
+                    if verbose {
+                        println!("Built-in CAB extraction failed ({:#}), trying expand.exe...", e);
+                    }
+                    Self::extract_with_expand(archive, dest)
+                }),
+            // 7-Zip can read most MSIs too, but a failure there shouldn't hide msiexec's reason
+            "msi" => Self::extract_with_msiexec(archive, dest)
+                .or_else(|e| Self::extract_with_7z(archive, dest).map_err(|_| e)),
+            _ => Self::extract_with_7z(archive, dest),
+        }
+    }
+
+    /// Unpack an MSI as an administrative image (`msiexec /a`), which lays out
+    /// its files without installing anything
+    fn extract_with_msiexec(archive: &Path, dest: &Path) -> Result<()> {
+        let target = std::path::absolute(dest).unwrap_or_else(|_| dest.to_path_buf());
+
+        let mut command = Command::new("msiexec");
+        command.arg("/a").arg(archive).arg("/qn");
+        // msiexec needs the quotes around the value, not around the whole property
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            command.raw_arg(format!("TARGETDIR=\"{}\"", target.display()));
+        }
+        #[cfg(not(windows))]
+        command.arg(format!("TARGETDIR={}", target.display()));
+
+        let status = command.status().context("Failed to run msiexec")?;
+        match status.code() {
+            Some(0) => Ok(()),
+            // Policy and privilege failures that an elevated prompt resolves
+            Some(1625) | Some(1730) | Some(1925) => anyhow::bail!(
+                "{} needs administrator rights to create an administrative image; run from an elevated prompt",
+                archive.display()
+            ),
+            Some(code) => anyhow::bail!("msiexec failed to extract {} (exit code {})", archive.display(), code),
+            None => anyhow::bail!("msiexec was terminated while extracting {}", archive.display()),
+        }
+    }
+
+    /// Download a package into `dest_dir`, resuming with range requests if the
+    /// connection drops. The file is named from Content-Disposition or the URL
+    /// path so the extension-based extraction still applies.
+    fn download_package(url: &str, dest_dir: &Path, allow_http: bool) -> Result<PathBuf> {
+        const MAX_ATTEMPTS: usize = 4;
+
+        let parsed_url = reqwest::Url::parse(url).with_context(|| format!("Invalid URL: {}", url))?;
+        match parsed_url.scheme() {
+            "https" => {}
+            "http" if allow_http => {}
+            "http" => anyhow::bail!("Refusing to download over plain HTTP; use an https:// link or pass --allow-http"),
+            scheme => anyhow::bail!("Unsupported URL scheme: {}", scheme),
+        }
+
+        // No overall timeout: driver packages can be gigabytes on slow links
+        let client = reqwest::blocking::Client::builder()
+            .connect_timeout(Duration::from_secs(30))
+            .timeout(None)
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        let part_path = dest_dir.join("download.part");
+        let mut file_name: Option<String> = None;
+        let mut last_error = None;
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            if attempt > 1 {
+                eprintln!("\nDownload interrupted ({:#}), resuming (attempt {}/{})...",
+                    last_error.as_ref().expect("set on failure"), attempt, MAX_ATTEMPTS);
+            }
+
+            let resume_from = fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+            let mut request = client.get(parsed_url.clone());
+            if resume_from > 0 {
+                request = request.header(reqwest::header::RANGE, format!("bytes={}-", resume_from));
+            }
+
+            let response = match request.send().and_then(|r| r.error_for_status()) {
+                Ok(response) => response,
+                Err(e) if e.is_status() => return Err(e).with_context(|| format!("Download failed: {}", url)),
+                Err(e) => {
+                    last_error = Some(anyhow::Error::new(e));
+                    continue;
+                }
+            };
+
+            if file_name.is_none() {
+                file_name = Some(Self::download_file_name(&response));
+            }
+
+            // A server that ignores the range sends the whole file again
+            let resumed = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+            let already = if resumed { resume_from } else { 0 };
+            let total = response.content_length().map(|len| len + already);
+
+            match Self::write_download(response, &part_path, resumed, already, total) {
+                Ok(()) => {
+                    if !quiet() {
+                        println!();
+                    }
+                    let file_path = dest_dir.join(file_name.as_deref().unwrap_or("download.bin"));
+                    fs::rename(&part_path, &file_path)
+                        .with_context(|| format!("Failed to save download as {}", file_path.display()))?;
+                    return Ok(file_path);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        let _ = fs::remove_file(&part_path);
+        Err(last_error.expect("at least one attempt was made"))
+            .with_context(|| format!("Failed to download {} after {} attempts", url, MAX_ATTEMPTS))
+    }
+
+    /// Stream a response body to disk, printing progress as it goes
+    fn write_download(mut response: reqwest::blocking::Response, part_path: &Path, append: bool, already: u64, total: Option<u64>) -> Result<()> {
+        use std::io::{Read, Write};
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(part_path)
+            .with_context(|| format!("Failed to create {}", part_path.display()))?;
+
+        let mut downloaded = already;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            if interrupted() {
+                anyhow::bail!("Interrupted by Ctrl-C");
+            }
+            let read = response.read(&mut buffer).context("Connection lost")?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])
+                .with_context(|| format!("Failed to write {}", part_path.display()))?;
+            downloaded += read as u64;
+
+            if quiet() {
+                continue;
+            }
+            let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+            match total {
+                Some(total) if total > 0 => print!("\rDownloading... {:>3}% ({:.1} / {:.1} MB)",
+                    downloaded * 100 / total, mb(downloaded), mb(total)),
+                _ => print!("\rDownloading... {:.1} MB", mb(downloaded)),
+            }
+            let _ = std::io::stdout().flush();
+        }
+
+        if let Some(total) = total {
+            if downloaded < total {
+                anyhow::bail!("Connection closed after {} of {} bytes", downloaded, total);
+            }
+        }
+        Ok(())
+    }
+
+    /// File name for a download: Content-Disposition first, then the last URL segment
+    fn download_file_name(response: &reqwest::blocking::Response) -> String {
+        let from_header = response.headers()
+            .get(reqwest::header::CONTENT_DISPOSITION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                value.split(';')
+                    .map(str::trim)
+                    .find_map(|part| part.strip_prefix("filename=").or_else(|| part.strip_prefix("filename*=UTF-8''")))
+                    .map(|name| name.trim_matches('"').to_string())
+            });
+        let from_url = response.url()
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(|segment| segment.to_string());
+
+        // Never trust a server-supplied name with separators or reserved characters
+        from_header.into_iter().chain(from_url)
+            .map(|name| name.chars()
+                .map(|c| if c.is_control() || "\\/:*?\"<>|".contains(c) { '_' } else { c })
+                .collect::<String>())
+            .find(|name| !name.trim_matches(['.', '_', ' ']).is_empty())
+            .unwrap_or_else(|| "download.bin".to_string())
+    }
+
+    /// Unpack a CAB archive in-process. CAB entry names use backslashes and are
+    /// checked the same way as ZIP entries so nothing lands outside `dest`.
+    fn extract_cab(archive: &Path, dest: &Path, verbose: bool) -> Result<()> {
+        let file = fs::File::open(archive)
+            .with_context(|| format!("Failed to open archive: {}", archive.display()))?;
+        let mut cabinet = cab::Cabinet::new(file)
+            .with_context(|| format!("Not a valid CAB archive: {}", archive.display()))?;
+
+        let names: Vec<String> = cabinet.folder_entries()
+            .flat_map(|folder| folder.file_entries())
+            .map(|entry| entry.name().to_string())
+            .collect();
+
+        let total = names.len();
+        for (index, name) in names.iter().enumerate() {
+            let Some(relative) = Self::enclosed_cab_path(name) else {
+                anyhow::bail!("Refusing to extract '{}' from {}: path escapes the destination", name, archive.display());
+            };
+            let out_path = dest.join(relative);
+
+            if verbose {
+                println!("  [{}/{}] {}", index + 1, total, name);
+            }
+
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+            }
+            let mut reader = cabinet.read_file(name)
+                .with_context(|| format!("Corrupt entry '{}' in {}", name, archive.display()))?;
+            let mut out_file = fs::File::create(&out_path)
+                .with_context(|| format!("Failed to create file: {}", out_path.display()))?;
+            std::io::copy(&mut reader, &mut out_file)
+                .with_context(|| format!("Failed to extract '{}' from {} (archive may be corrupt)", name, archive.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Relative path for a CAB entry name, or `None` if it is absolute or climbs out with `..`
+    fn enclosed_cab_path(name: &str) -> Option<PathBuf> {
+        let mut path = PathBuf::new();
+        for part in name.split(['\\', '/']) {
+            match part {
+                "" | "." => continue,
+                ".." => return None,
+                _ if part.contains(':') => return None,
+                _ => path.push(part),
+            }
+        }
+        (!path.as_os_str().is_empty()).then_some(path)
+    }
+
+    /// Fallback CAB extraction with the `expand.exe` that ships with Windows
+    fn extract_with_expand(archive: &Path, dest: &Path) -> Result<()> {
+        let output = Command::new("expand.exe")
+            .arg("-F:*")
+            .arg(archive)
+            .arg(dest)
+            .output()
+            .context("Failed to run expand.exe")?;
+
+        if !output.status.success() {
+            anyhow::bail!("expand.exe failed to extract {}: {}",
+                archive.display(), String::from_utf8_lossy(&output.stdout).trim());
+        }
+        Ok(())
+    }
+
+    /// Expand CABs found directly inside an extracted package (one level), since
+    /// installers commonly wrap the actual driver files in one.
+    fn extract_nested_cabs(root: &Path, max_bytes: u64, verbose: bool) -> Result<()> {
+        let is_cab = |path: &Path| {
+            path.extension()
+                .map(|ext| ext.to_string_lossy().eq_ignore_ascii_case("cab"))
+                .unwrap_or(false)
+        };
+        let mut cabs = Vec::new();
+        let mut warnings = Vec::new();
+        Self::find_files_recursive(root, &WalkOptions::default(), &is_cab, &mut cabs, &mut warnings)?;
+        cabs.sort();
+
+        for cab_path in cabs {
+            let file_name = cab_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let dest = cab_path.with_file_name(format!("{}_extracted", file_name));
+            if dest.exists() {
+                continue;
+            }
+
+            fs::create_dir_all(&dest)
+                .with_context(|| format!("Failed to create extraction directory: {}", dest.display()))?;
+            match Self::extract_archive(&cab_path, &dest, false) {
+                Ok(_) => {
+                    if verbose {
+                        println!("Expanded nested CAB: {}", cab_path.display());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Warning: Could not expand {}: {:#}", cab_path.display(), e);
+                    let _ = fs::remove_dir_all(&dest);
+                }
+            }
+
+            Self::check_extracted_size(root, max_bytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Unpack a ZIP archive, streaming each entry to disk.
+    /// Entries whose path would escape `dest` (zip-slip) are rejected.
+    fn extract_zip(archive: &Path, dest: &Path, verbose: bool) -> Result<()> {
+        let file = fs::File::open(archive)
+            .with_context(|| format!("Failed to open archive: {}", archive.display()))?;
+        let mut zip = zip::ZipArchive::new(file)
+            .with_context(|| format!("Not a valid ZIP archive: {}", archive.display()))?;
+
+        let total = zip.len();
+        let show_progress = !verbose && !quiet() && total > 0;
+        let mut shown_percent = None;
+        for index in 0..total {
+            if show_progress && shown_percent != Some(index * 100 / total) {
+                shown_percent = Some(index * 100 / total);
+                print!("\rExtracting... {:>3}% ({} / {} files)", index * 100 / total, index, total);
+                let _ = std::io::Write::flush(&mut std::io::stdout());
+            }
+            let mut entry = zip.by_index(index)
+                .with_context(|| format!("Corrupt entry #{} in {}", index + 1, archive.display()))?;
+            let name = entry.name().to_string();
+            let Some(relative) = entry.enclosed_name() else {
+                anyhow::bail!("Refusing to extract '{}' from {}: path escapes the destination", name, archive.display());
+            };
+            let out_path = dest.join(relative);
+
+            if verbose {
+                println!("  [{}/{}] {}", index + 1, total, name);
+            }
+
+            if entry.is_dir() {
+                fs::create_dir_all(&out_path)
+                    .with_context(|| format!("Failed to create directory: {}", out_path.display()))?;
+                continue;
+            }
+
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+            }
+            let mut out_file = fs::File::create(&out_path)
+                .with_context(|| format!("Failed to create file: {}", out_path.display()))?;
+            std::io::copy(&mut entry, &mut out_file)
+                .with_context(|| format!("Failed to extract '{}' from {} (archive may be corrupt)", name, archive.display()))?;
+        }
+        if show_progress {
+            println!("\rExtracting... 100% ({} / {} files)", total, total);
+        }
+
+        Ok(())
+    }
+
+    /// Find all INF files in a directory recursively.
+    /// Folders that can't be read are returned as warnings instead of aborting the search.
+    fn find_inf_files(dir: &Path, walk: &WalkOptions) -> Result<(Vec<PathBuf>, PathErrors)> {
+        let mut inf_files = Vec::new();
+        let mut warnings = Vec::new();
+        Self::find_files_recursive(dir, walk, &Self::is_inf_file, &mut inf_files, &mut warnings)?;
+        inf_files.sort();
+        Ok((inf_files, warnings))
+    }
+
+    fn is_inf_file(path: &Path) -> bool {
+        path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase() == "inf")
+            .unwrap_or(false)
+    }
+
+    /// Find INF files in a single folder (non-recursive)
+    fn find_inf_files_in_folder(dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut inf_files = Vec::new();
+        
+        if !dir.is_dir() {
+            return Ok(inf_files);
+        }
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            
+            if path.is_file() {
+                if let Some(ext) = path.extension() {
+                    if ext.to_string_lossy().to_lowercase() == "inf" {
+                        inf_files.push(path);
+                    }
+                }
+            }
+        }
+
+        inf_files.sort();
+        Ok(inf_files)
+    }
+
+    /// Collect files below `dir` accepted by `matches`.
+    ///
+    /// Symlinks and junctions are skipped unless `walk.follow_links` is set, in which case
+    /// every folder is canonicalized and visited only once so junction loops terminate.
+    fn find_files_recursive(
+        dir: &Path,
+        walk: &WalkOptions,
+        matches: &dyn Fn(&Path) -> bool,
+        files: &mut Vec<PathBuf>,
+        warnings: &mut PathErrors,
+    ) -> Result<()> {
+        if !dir.is_dir() {
+            return Ok(());
+        }
+
+        let mut visited = std::collections::HashSet::new();
+        if let Ok(canonical) = fs::canonicalize(dir) {
+            visited.insert(canonical);
+        }
+
+        // The starting folder must be readable; problems further down are only warnings
+        let entries = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read folder: {}", dir.display()))?;
+        Self::walk_entries(entries, 1, walk, matches, files, warnings, &mut visited);
+        Ok(())
+    }
+
+    fn walk_entries(
+        entries: fs::ReadDir,
+        depth: usize,
+        walk: &WalkOptions,
+        matches: &dyn Fn(&Path) -> bool,
+        files: &mut Vec<PathBuf>,
+        warnings: &mut PathErrors,
+        visited: &mut std::collections::HashSet<PathBuf>,
+    ) {
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warnings.push((PathBuf::new(), e.to_string()));
+                    continue;
+                }
+            };
+            let path = entry.path();
+
+            // Symlinks and junctions both report as links here
+            let is_link = entry.file_type().map(|t| t.is_symlink()).unwrap_or(false);
+            if !path.is_dir() {
+                if (!is_link || walk.follow_links) && matches(&path) {
+                    files.push(path);
+                }
+                continue;
+            }
+
+            if is_link && !walk.follow_links {
+                continue;
+            }
+            if walk.is_excluded(&path) {
+                continue;
+            }
+            if walk.max_depth.map(|max| depth > max).unwrap_or(false) {
+                continue;
+            }
+            if walk.follow_links {
+                match fs::canonicalize(&path) {
+                    Ok(canonical) => {
+                        if !visited.insert(canonical) {
+                            continue;
+                        }
+                    }
+                    Err(e) => {
+                        warnings.push((path, e.to_string()));
+                        continue;
+                    }
+                }
+            }
+
+            match fs::read_dir(&path) {
+                Ok(sub_entries) => Self::walk_entries(sub_entries, depth + 1, walk, matches, files, warnings, visited),
+                Err(e) => warnings.push((path, e.to_string())),
+            }
+        }
+    }
+
+    /// Parse a single INF file, merging in the INFs it `Include=`s from the same folder
+    fn parse_inf_file(inf_path: &Path, max_inf_size: u64) -> Result<ParsedInfFile> {
+        Self::parse_inf_including(inf_path, max_inf_size, &mut std::collections::HashSet::new())
+    }
+
+    /// Layered and extension INFs take sections from the INFs they `Include=`. Included INFs
+    /// found next to `inf_path` are parsed too, and their devices (by hardware ID) and payload
+    /// files are added to the result. Others, usually inbox INFs, are only recorded.
+    /// `visited` holds the lowercase file names seen so far and breaks include cycles.
+    fn parse_inf_including(inf_path: &Path, max_inf_size: u64, visited: &mut std::collections::HashSet<String>) -> Result<ParsedInfFile> {
+        let mut parsed = Self::parse_single_inf(inf_path, max_inf_size)?;
+        visited.insert(parsed.file_name.to_lowercase());
+
+        let folder = inf_path.parent().unwrap_or(Path::new("."));
+        for include in parsed.includes.clone() {
+            let included_path = folder.join(&include);
+            if visited.contains(&include) || !included_path.is_file() {
+                continue;
+            }
+            let Ok(included) = Self::parse_inf_including(&included_path, max_inf_size, visited) else {
+                continue;
+            };
+
+            for driver in included.drivers {
+                let known = parsed.drivers.iter().any(|d| {
+                    d.hardware_id.as_deref().map(str::to_lowercase) == driver.hardware_id.as_deref().map(str::to_lowercase)
+                });
+                if !known {
+                    parsed.drivers.push(driver);
+                }
+            }
+            parsed.source_files.extend(included.source_files);
+            parsed.source_files.sort_by_key(|f| f.to_lowercase());
+            parsed.source_files.dedup_by_key(|f| f.to_lowercase());
+        }
+        Ok(parsed)
+    }
+
+    /// Parse one INF file on its own
+    fn parse_single_inf(inf_path: &Path, max_inf_size: u64) -> Result<ParsedInfFile> {
+        // Try different encodings (INF files can be UTF-8, UTF-16, or ANSI)
+        let content = Self::read_inf_content(inf_path, max_inf_size)?;
+        
+        let file_name = inf_path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown.inf")
+            .to_string();
+
+        let mut version_info = InfVersionInfo::default();
+        let mut manufacturers: BTreeMap<String, String> = BTreeMap::new();
+        let mut device_sections: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+        let mut string_tables: BTreeMap<String, HashMap<String, String>> = BTreeMap::new();
+        let mut excluded_ids: Vec<String> = Vec::new();
+        let mut source_disks: HashMap<String, String> = HashMap::new();
+        let mut source_disk_files: Vec<(String, String, String)> = Vec::new();
+        let mut includes: Vec<String> = Vec::new();
+        let mut needs: Vec<String> = Vec::new();
+        let mut current_section = String::new();
+
+        for line in content.lines() {
+            let line = line.trim();
+            
+            // Skip empty lines and comments
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            // Section header
+            if line.starts_with('[') && line.ends_with(']') {
+                current_section = line[1..line.len()-1].to_lowercase();
+                continue;
+            }
+
+            // Parse based on current section
+            match current_section.as_str() {
+                "version" => Self::parse_version_line(line, &mut version_info),
+                "manufacturer" => Self::parse_manufacturer_line(line, &mut manufacturers),
+                section if section == "strings" || section.starts_with("strings.") => {
+                    let locale = section["strings".len()..].trim_start_matches('.');
+                    let locale = normalize_locale(locale).unwrap_or_else(|| locale.to_string());
+                    Self::parse_strings_line(line, string_tables.entry(locale).or_default());
+                }
+                "controlflags" => Self::parse_control_flags_line(line, &mut excluded_ids),
+                section if section == "sourcedisksnames" || section.starts_with("sourcedisksnames.") => {
+                    Self::parse_source_disks_names_line(line, &mut source_disks);
+                }
+                section if section == "sourcedisksfiles" || section.starts_with("sourcedisksfiles.") => {
+                    Self::parse_source_disks_files_line(line, &mut source_disk_files);
+                }
+                section if Self::section_matches_manufacturer(section, &manufacturers) => {
+                    Self::parse_device_line(line, &current_section, &mut device_sections);
+                }
+                _ => Self::parse_include_line(line, &mut includes, &mut needs),
+            }
+        }
+
+        let string_table = Self::merge_string_tables(string_tables, inf_locale());
+
+        // Build driver info list
+        let resolved_provider = version_info.provider.as_ref()
+            .map(|p| Self::resolve_string(p, &string_table));
+        let drivers = Self::resolve_device_sections(&device_sections, &manufacturers, &string_table)
+            .into_iter()
+            .map(|device| InfDriverInfo {
+                device_name: Some(device.description.clone()),
+                description: Some(device.description),
+                device_class: version_info.class.clone(),
+                class_guid: version_info.class_guid.clone(),
+                driver_version: version_info.driver_version.clone(),
+                driver_date: version_info.driver_date.clone(),
+                driver_provider_name: resolved_provider.clone(),
+                excluded: if excluded_ids.iter().any(|id| id == "*") {
+                    Exclusion::All
+                } else if excluded_ids.iter().any(|id| id.eq_ignore_ascii_case(&device.hardware_id)) {
+                    Exclusion::Listed
+                } else {
+                    Exclusion::Selectable
+                },
+                architecture: device.architecture,
+                hardware_id: Some(device.hardware_id),
+                inf_name: Some(file_name.clone()),
+                catalog_file: version_info.catalog_file.clone(),
+                manufacturer: device.manufacturer,
+            })
+            .collect();
+
+        // Payload paths: the disk's folder, then the file's own subfolder, then its name
+        let mut source_files: Vec<String> = source_disk_files.iter()
+            .map(|(name, disk_id, subdir)| {
+                let disk_path = source_disks.get(disk_id).map(|p| p.as_str()).unwrap_or_default();
+                [disk_path, subdir.as_str(), name.as_str()]
+                    .iter()
+                    .map(|part| part.trim_matches('\\'))
+                    .filter(|part| !part.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("\\")
+            })
+            .collect();
+        source_files.sort_by_key(|f| f.to_lowercase());
+        source_files.dedup_by_key(|f| f.to_lowercase());
+
+        Ok(ParsedInfFile {
+            file_path: inf_path.to_path_buf(),
+            file_name,
+            drivers,
+            raw_version_info: version_info,
+            source_files,
+            includes,
+            needs,
+            source: String::new(),
+            package: None,
+            signed_date: None,
+        })
+    }
+
+    /// Provider from `[Version]`, resolved through the device entries when it's a `%token%`
+    fn provider_name(parsed: &ParsedInfFile) -> Option<&str> {
+        let provider = parsed.raw_version_info.provider.as_deref()?;
+        if provider.starts_with('%') && provider.ends_with('%') {
+            Some(parsed.drivers.first()
+                .and_then(|d| d.driver_provider_name.as_deref())
+                .unwrap_or(provider))
+        } else {
+            Some(provider)
+        }
+    }
+
+    /// Re-order INFs by a `--sort` key. The sort is stable, so ties keep the default order;
+    /// versions and dates compare numerically (v10 after v9), missing values sort first.
+    fn sort_parsed_files_by(parsed_files: &mut [ParsedInfFile], key: SortKey, descending: bool) {
+        parsed_files.sort_by(|a, b| {
+            let ordering = match key {
+                SortKey::Name => a.file_name.to_lowercase().cmp(&b.file_name.to_lowercase()),
+                SortKey::Class => {
+                    let class = |p: &ParsedInfFile| p.raw_version_info.class.as_deref().unwrap_or_default().to_lowercase();
+                    class(a).cmp(&class(b))
+                }
+                SortKey::Provider => {
+                    let provider = |p| Self::provider_name(p).unwrap_or_default().to_lowercase();
+                    provider(a).cmp(&provider(b))
+                }
+                SortKey::Version => compare_driver_versions(
+                    a.raw_version_info.driver_version.as_deref().unwrap_or_default(),
+                    b.raw_version_info.driver_version.as_deref().unwrap_or_default(),
+                ),
+                SortKey::Date => {
+                    let date = |p: &ParsedInfFile| p.raw_version_info.driver_date.as_deref().and_then(parse_driver_date);
+                    date(a).cmp(&date(b))
+                }
+                SortKey::Devices => a.drivers.len().cmp(&b.drivers.len()),
+            };
+            if descending { ordering.reverse() } else { ordering }
+        });
+    }
+
+    /// Order INFs by class, then file name, and their devices by description,
+    /// so repeated runs over the same tree produce identical output
+    fn sort_parsed_files(parsed_files: &mut [ParsedInfFile]) {
+        for parsed in parsed_files.iter_mut() {
+            parsed.drivers.sort_by(|a, b| {
+                (&a.description, &a.hardware_id).cmp(&(&b.description, &b.hardware_id))
+            });
+        }
+        parsed_files.sort_by(|a, b| {
+            (&a.raw_version_info.class, a.file_name.to_lowercase(), &a.file_path)
+                .cmp(&(&b.raw_version_info.class, b.file_name.to_lowercase(), &b.file_path))
+        });
+    }
+
+    fn read_inf_content(path: &Path, max_size: u64) -> Result<String> {
+        // Real INFs are a few hundred KB at most; anything huge is malformed or hostile
+        let size = fs::metadata(path)?.len();
+        if size > max_size {
+            anyhow::bail!(
+                "File is {} KB, larger than the {} KB INF size limit (see --max-inf-size)",
+                size / 1024,
+                max_size / 1024
+            );
+        }
+
+        // First try reading as bytes and detect encoding
+        let bytes = fs::read(path)?;
+        
+        // Check for UTF-16 LE BOM
+        if bytes.len() >= 2 && bytes[0] == 0xFF && bytes[1] == 0xFE {
+            let utf16_chars: Vec<u16> = bytes[2..]
+                .chunks(2)
+                .filter_map(|chunk| {
+                    if chunk.len() == 2 {
+                        Some(u16::from_le_bytes([chunk[0], chunk[1]]))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            return Ok(String::from_utf16_lossy(&utf16_chars));
+        }
+        
+        // Check for UTF-16 BE BOM
+        if bytes.len() >= 2 && bytes[0] == 0xFE && bytes[1] == 0xFF {
+            let utf16_chars: Vec<u16> = bytes[2..]
+                .chunks(2)
+                .filter_map(|chunk| {
+                    if chunk.len() == 2 {
+                        Some(u16::from_be_bytes([chunk[0], chunk[1]]))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            return Ok(String::from_utf16_lossy(&utf16_chars));
+        }
+
+        // Check for UTF-8 BOM
+        if bytes.len() >= 3 && bytes[0] == 0xEF && bytes[1] == 0xBB && bytes[2] == 0xBF {
+            return Ok(String::from_utf8_lossy(&bytes[3..]).to_string());
+        }
+
+        // Try UTF-8, fall back to Windows-1252/Latin-1
+        match String::from_utf8(bytes.clone()) {
+            Ok(s) => Ok(s),
+            Err(_) => Ok(bytes.iter().map(|&b| b as char).collect())
+        }
+    }
+
+    fn parse_version_line(line: &str, version_info: &mut InfVersionInfo) {
+        let parts: Vec<&str> = line.splitn(2, '=').collect();
+        if parts.len() != 2 {
+            return;
+        }
+
+        let key = parts[0].trim().to_lowercase();
+        let value = parts[1].trim().trim_matches('"').to_string();
+
+        match key.as_str() {
+            "driverver" => {
+                // Format: MM/DD/YYYY, version or YYYY/MM/DD, version
+                let dv_parts: Vec<&str> = value.splitn(2, ',').collect();
+                if !dv_parts.is_empty() {
+                    version_info.driver_date = Some(dv_parts[0].trim().to_string());
+                }
+                if dv_parts.len() > 1 {
+                    version_info.driver_version = Some(dv_parts[1].trim().to_string());
+                }
+            }
+            "class" => version_info.class = Some(value),
+            "classguid" => version_info.class_guid = Some(value),
+            "provider" => version_info.provider = Some(value),
+            "catalogfile" | "catalogfile.nt" | "catalogfile.ntamd64" | "catalogfile.ntx86" => {
+                version_info.catalog_file = Some(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn parse_manufacturer_line(line: &str, manufacturers: &mut BTreeMap<String, String>) {
+        let parts: Vec<&str> = line.splitn(2, '=').collect();
+        if parts.len() != 2 {
+            return;
+        }
+
+        let name = parts[0].trim().to_string();
+        let section = parts[1].trim().to_string();
+        manufacturers.insert(name, section);
+    }
+
+    /// Lowercase models section name from a `[Manufacturer]` value, without the
+    /// TargetOSVersion decorations: `Intel, NTamd64.10.0, NTx86` -> `intel`
+    fn models_section_base(value: &str) -> String {
+        value.split(',').next().unwrap_or(value).trim().to_lowercase()
+    }
+
+    /// Lowercase names of every models section a `[Manufacturer]` value declares: the base
+    /// plus one per listed decoration (`Intel, NTamd64.10.0` -> `intel`, `intel.ntamd64.10.0`)
+    fn models_section_names(value: &str) -> Vec<String> {
+        let base = Self::models_section_base(value);
+        if base.is_empty() {
+            return Vec::new();
+        }
+        let decorated = value.split(',').skip(1)
+            .map(|decoration| decoration.trim().to_lowercase())
+            .filter(|decoration| !decoration.is_empty())
+            .map(|decoration| format!("{}.{}", base, decoration));
+        std::iter::once(base.clone()).chain(decorated).collect()
+    }
+
+    /// `[Manufacturer]` entry (name, value) that declares the models section `section`, either
+    /// as its base (`[Intel]`) or with one of its listed decorations (`[Intel.NTamd64.10.0]`).
+    /// Names are built exactly, so `[IntelExt.NTamd64]` never counts as Intel's and a decoration
+    /// the entry doesn't list is not a models section. When two entries declare the same
+    /// section, the first by name gets it. `section` is the header without brackets, in any case.
+    fn manufacturer_for_section<'a>(section: &str, manufacturers: &'a BTreeMap<String, String>) -> Option<(&'a str, &'a str)> {
+        let section = section.trim().to_lowercase();
+        manufacturers.iter()
+            .find(|(_, value)| Self::models_section_names(value).contains(&section))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Whether `section` is a models section listed in `[Manufacturer]`
+    fn section_matches_manufacturer(section: &str, manufacturers: &BTreeMap<String, String>) -> bool {
+        Self::manufacturer_for_section(section, manufacturers).is_some()
+    }
+
+    /// Turn the device lines collected per models section into device entries, with the
+    /// description and manufacturer name resolved through `[Strings]` (`%IntelMfg%` -> `Intel`)
+    fn resolve_device_sections(
+        device_sections: &BTreeMap<String, Vec<(String, String)>>,
+        manufacturers: &BTreeMap<String, String>,
+        string_table: &HashMap<String, String>,
+    ) -> Vec<InfDeviceEntry> {
+        let mut devices = Vec::new();
+        for (section_name, entries) in device_sections {
+            let models = Self::manufacturer_for_section(section_name, manufacturers);
+            let manufacturer = models.map(|(name, _)| Self::resolve_string(name, string_table));
+            let architecture = models.and_then(|(_, value)| {
+                let base = Self::models_section_base(value);
+                let decoration = section_name.get(base.len()..).and_then(|rest| rest.strip_prefix('.'));
+                Architecture::from_decoration(decoration)
+            });
+            for (device_desc, hardware_id) in entries {
+                devices.push(InfDeviceEntry {
+                    manufacturer: manufacturer.clone(),
+                    architecture,
+                    description: Self::resolve_string(device_desc, string_table),
+                    hardware_id: hardware_id.clone(),
+                });
+            }
+        }
+        devices
+    }
+
+    /// `Include=` and `Needs=` from an install section; both take comma-separated lists
+    fn parse_include_line(line: &str, includes: &mut Vec<String>, needs: &mut Vec<String>) {
+        let Some((key, value)) = line.split_once('=') else {
+            return;
+        };
+        let (list, lowercase) = match key.trim().to_lowercase().as_str() {
+            "include" => (includes, true),
+            "needs" => (needs, false),
+            _ => return,
+        };
+        for item in value.split(',').map(|item| item.trim().trim_matches('"')).filter(|item| !item.is_empty()) {
+            let item = if lowercase { item.to_lowercase() } else { item.to_string() };
+            if !list.iter().any(|known| known.eq_ignore_ascii_case(&item)) {
+                list.push(item);
+            }
+        }
+    }
+
+    fn parse_device_line(line: &str, section: &str, device_sections: &mut BTreeMap<String, Vec<(String, String)>>) {
+        let parts: Vec<&str> = line.splitn(2, '=').collect();
+        if parts.len() != 2 {
+            return;
+        }
+
+        let device_desc = parts[0].trim().to_string();
+        let right_side = parts[1].trim();
+        
+        // Format: InstallSection, HardwareID [, CompatibleID, ...]
+        let hw_parts: Vec<&str> = right_side.split(',').collect();
+        if hw_parts.len() >= 2 {
+            let hardware_id = hw_parts[1].trim().to_string();
+            let hardware_id_upper = hardware_id.to_uppercase();
+            if !hardware_id.is_empty() && (
+                HARDWARE_ID_PREFIXES.iter().any(|prefix| hardware_id_upper.starts_with(prefix)) ||
+                hardware_id_upper.contains("VEN_") ||
+                hardware_id_upper.contains("DEV_")
+            ) {
+                device_sections
+                    .entry(section.to_string())
+                    .or_default()
+                    .push((device_desc, hardware_id));
+            }
+        }
+    }
+
+    /// Collect the device IDs listed by `ExcludeFromSelect[.Platform] = id, id, ...` (`*` means all)
+    fn parse_control_flags_line(line: &str, excluded_ids: &mut Vec<String>) {
+        let parts: Vec<&str> = line.splitn(2, '=').collect();
+        if parts.len() != 2 {
+            return;
+        }
+
+        let key = parts[0].trim().to_lowercase();
+        if key != "excludefromselect" && !key.starts_with("excludefromselect.") {
+            return;
+        }
+        excluded_ids.extend(parts[1].split(',')
+            .map(|id| id.trim().trim_matches('"').to_string())
+            .filter(|id| !id.is_empty()));
+    }
+
+    /// `diskid = description[, tagfile[, unused[, path]]]`: remember each disk's folder
+    fn parse_source_disks_names_line(line: &str, source_disks: &mut HashMap<String, String>) {
+        let parts: Vec<&str> = line.splitn(2, '=').collect();
+        if parts.len() != 2 {
+            return;
+        }
+
+        let disk_id = parts[0].trim().to_string();
+        let path = parts[1].split(',').nth(3).unwrap_or_default().trim().trim_matches('"').to_string();
+        source_disks.insert(disk_id, path);
+    }
+
+    /// `filename = diskid[, subdir[, size]]`
+    fn parse_source_disks_files_line(line: &str, source_disk_files: &mut Vec<(String, String, String)>) {
+        let parts: Vec<&str> = line.splitn(2, '=').collect();
+        if parts.len() != 2 {
+            return;
+        }
+
+        let name = parts[0].trim().trim_matches('"').to_string();
+        let mut fields = parts[1].split(',').map(|f| f.trim().trim_matches('"').to_string());
+        let disk_id = fields.next().unwrap_or_default();
+        let subdir = fields.next().unwrap_or_default();
+        if !name.is_empty() {
+            source_disk_files.push((name, disk_id, subdir));
+        }
+    }
+
+    /// Signing timestamp from a catalog's Authenticode signature, or `None` when the
+    /// catalog is missing, unsigned, or not timestamped. Handles RFC 3161 timestamps
+    /// (`genTime` in TSTInfo) and legacy countersignatures (their `signingTime`).
+    fn catalog_signed_date(catalog: &Path) -> Option<chrono::NaiveDateTime> {
+        // DER-encoded OIDs, tag and length included
+        const TST_INFO_OID: &[u8] = &[0x06, 0x0B, 0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x10, 0x01, 0x04];
+        const COUNTER_SIGNATURE_OID: &[u8] = &[0x06, 0x09, 0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x06];
+        const SIGNING_TIME_OID: &[u8] = &[0x06, 0x09, 0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x05];
+
+        let data = fs::read(catalog).ok()?;
+        let find = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).position(|w| w == needle);
+
+        // RFC 3161: genTime is the first GeneralizedTime inside TSTInfo
+        if let Some(start) = find(&data, TST_INFO_OID) {
+            let tst_info = &data[start + TST_INFO_OID.len()..];
+            let time = (0..tst_info.len())
+                .filter(|&i| tst_info[i] == 0x18)
+                .find_map(|i| Self::parse_der_time(&tst_info[i..]));
+            if time.is_some() {
+                return time;
+            }
+        }
+
+        // Legacy countersignature: signingTime attribute = SET { UTCTime | GeneralizedTime }
+        let counter_signature = &data[find(&data, COUNTER_SIGNATURE_OID)?..];
+        let attribute = &counter_signature[find(counter_signature, SIGNING_TIME_OID)? + SIGNING_TIME_OID.len()..];
+        match attribute {
+            [0x31, _, rest @ ..] => Self::parse_der_time(rest),
+            _ => None,
+        }
+    }
+
+    /// Decode a DER UTCTime (tag 0x17) or GeneralizedTime (tag 0x18) at the start of `bytes`
+    fn parse_der_time(bytes: &[u8]) -> Option<chrono::NaiveDateTime> {
+        let (&tag, &len) = (bytes.first()?, bytes.get(1)?);
+        let text = std::str::from_utf8(bytes.get(2..2 + len as usize)?).ok()?;
+        let text = text.strip_suffix('Z')?;
+        match tag {
+            0x17 => chrono::NaiveDateTime::parse_from_str(text, "%y%m%d%H%M%S").ok(),
+            // Fractional seconds are allowed after the first 14 digits
+            0x18 => chrono::NaiveDateTime::parse_from_str(text.get(..14)?, "%Y%m%d%H%M%S").ok(),
+            _ => None,
+        }
+    }
+
+    /// Measure an INF's folder and check that its catalog and source files are present.
+    /// `folder_sizes` caches sizes for folders holding several INFs.
+    fn check_package(parsed: &ParsedInfFile, folder_sizes: &mut HashMap<PathBuf, u64>) -> PackageStatus {
+        let folder = parsed.file_path.parent().unwrap_or(Path::new("."));
+        let folder_size = *folder_sizes.entry(folder.to_path_buf()).or_insert_with(|| Self::dir_size(folder));
+
+        let catalog = parsed.raw_version_info.catalog_file.as_deref()
+            .filter(|c| !c.starts_with('%'));
+        let missing_files = catalog.into_iter()
+            .chain(parsed.source_files.iter().map(|f| f.as_str()))
+            .filter(|file| {
+                let path = file.split(['\\', '/']).fold(folder.to_path_buf(), |path, part| path.join(part));
+                !path.exists()
+            })
+            .map(|file| file.to_string())
+            .collect();
+
+        PackageStatus { folder_size, missing_files }
+    }
+
+    /// Keys are case-insensitive and stored lowercase. When a table defines a key twice,
+    /// the first definition wins.
+    fn parse_strings_line(line: &str, string_table: &mut HashMap<String, String>) {
+        let parts: Vec<&str> = line.splitn(2, '=').collect();
+        if parts.len() != 2 {
+            return;
+        }
+
+        let key = parts[0].trim().to_lowercase();
+        let value = parts[1].trim().trim_matches('"').to_string();
+        string_table.entry(key).or_insert(value);
+    }
+
+    /// Combine the `[Strings]` tables, keyed by normalized language ID (`""` for the
+    /// undecorated table), into one. A key is looked up in `[Strings.<locale>]` first,
+    /// then in the undecorated `[Strings]`, then in the other localized tables in
+    /// ascending language ID order.
+    fn merge_string_tables(mut tables: BTreeMap<String, HashMap<String, String>>, locale: &str) -> HashMap<String, String> {
+        let mut merged = tables.remove(locale).unwrap_or_default();
+        let neutral = tables.remove("").unwrap_or_default();
+        for table in std::iter::once(neutral).chain(tables.into_values()) {
+            for (key, value) in table {
+                merged.entry(key).or_insert(value);
+            }
+        }
+        merged
+    }
+
+    fn resolve_string(s: &str, string_table: &HashMap<String, String>) -> String {
+        if s.starts_with('%') && s.ends_with('%') && s.len() > 2 {
+            let key = s[1..s.len()-1].to_lowercase();
+            string_table.get(&key).cloned().unwrap_or_else(|| s.to_string())
+        } else {
+            s.to_string()
+        }
+    }
+
+    /// Match the package's hardware IDs against the installed drivers and report, per
+    /// device, whether installing the package would upgrade, downgrade, or change nothing.
+    /// With `only_updatable`, only devices the package has a newer driver for are listed.
+    /// Entries for another architecture than `machine` are skipped and counted.
+    fn compare_with_system(parsed_files: &[ParsedInfFile], only_updatable: bool, machine: Architecture, verbose: bool) -> Result<()> {
+        let installed_drivers = DriverBackup::query_signed_drivers(None, None, verbose)?;
+        let entities = DriverBackup::query_pnp_entities(None, false, verbose).unwrap_or_else(|e| {
+            eprintln!("Warning: {:#}; matching on the primary hardware ID only", e);
+            HashMap::new()
+        });
+
+        let installed: Vec<(&PnPSignedDriver, DeviceIds)> = installed_drivers.iter()
+            .map(|driver| (driver, DeviceIds::of_driver(driver, &entities)))
+            .collect();
+
+        println!("----------------------------------------");
+        if only_updatable {
+            println!("Available driver updates:");
+        } else {
+            println!("Comparison with installed drivers:");
+        }
+        println!("----------------------------------------");
+
+        let (mut upgrades, mut downgrades, mut unchanged, mut not_present) = (0, 0, 0, 0);
+        let mut wrong_architecture = 0;
+        let mut uncomparable = 0;
+        let mut seen = std::collections::HashSet::new();
+
+        for parsed in parsed_files {
+            let package_version = parsed.raw_version_info.driver_version.as_deref().unwrap_or("?");
+            for driver in &parsed.drivers {
+                let Some(hwid) = driver.hardware_id.as_deref() else { continue };
+                if driver.architecture.is_some_and(|architecture| architecture != machine) {
+                    wrong_architecture += 1;
+                    continue;
+                }
+                // The same ID is usually listed once per architecture section
+                if !seen.insert((parsed.file_name.to_lowercase(), hwid.to_lowercase())) {
+                    continue;
+                }
+
+                // The highest ranked device wins, so a device's own ID beats another device's
+                // compatible ID; class-level IDs like USB\Class_03 would match too much to count
+                let device = installed.iter()
+                    .filter_map(|(device, ids)| ids.best_match(hwid).map(|found| (found, device)))
+                    .filter(|(found, _)| found.specificity >= HwidSpecificity::Compatible)
+                    .max_by_key(|(found, _)| found.score)
+                    .map(|(_, device)| device);
+                let Some(device) = device else {
+                    not_present += 1;
+                    if verbose && !only_updatable {
+                        println!("  {} [{}]: device not present", driver.device_name.as_deref().unwrap_or("Unknown"), hwid);
+                    }
+                    continue;
+                };
+
+                let installed_version = device.driver_version.as_deref().unwrap_or("?");
+                let (package, current) = (DriverVersion::parse(package_version), DriverVersion::parse(installed_version));
+                let verdict = match package.cmp(&current) {
+                    _ if !package.is_comparable() || !current.is_comparable() => { uncomparable += 1; "uncomparable" }
+                    std::cmp::Ordering::Greater => { upgrades += 1; "newer" }
+                    std::cmp::Ordering::Less => { downgrades += 1; "older" }
+                    std::cmp::Ordering::Equal => { unchanged += 1; "equal" }
+                };
+                let device_name = device.device_name.as_deref().or(driver.device_name.as_deref()).unwrap_or("Unknown");
+                if only_updatable {
+                    if verdict == "newer" {
+                        println!("  {}: installed {} -> available {} ({})", device_name, installed_version, package_version, parsed.file_name);
+                    }
+                    continue;
+                }
+                println!("  {} [{}]: package {} vs installed {} -> {}",
+                    device_name, hwid, package_version, installed_version, verdict);
+            }
+        }
+
+        println!();
+        if wrong_architecture > 0 {
+            println!("{} entries skipped: wrong architecture (this machine is {})", wrong_architecture, machine.name());
+        }
+        if only_updatable {
+            println!("{} devices have a newer driver available", upgrades);
+            println!();
+            return Ok(());
+        }
+        println!("{} devices would be upgraded, {} unchanged, {} would be downgraded, {} entries don't apply to this system",
+            upgrades, unchanged, downgrades, not_present);
+        if uncomparable > 0 {
+            println!("{} devices have a version that can't be compared", uncomparable);
+        }
+        println!();
+        Ok(())
+    }
+
+    /// Display parsed driver information
+    fn display_results(parsed_files: &[ParsedInfFile], show_source: bool, verbose: bool) {
+        println!("\n========================================");
+        println!("       Driver Package Inspection");
+        println!("========================================\n");
+
+        let total_drivers: usize = parsed_files.iter().map(|f| f.drivers.len()).sum();
+        println!("Found {} INF files with {} device entries\n", parsed_files.len(), total_drivers);
+
+        for parsed in parsed_files {
+            println!("----------------------------------------");
+            println!("INF File: {}", parsed.file_name);
+            if show_source {
+                println!("Source: {}", parsed.source);
+            }
+            println!("Path: {}", parsed.file_path.display());
+            
+            if let Some(ref class) = parsed.raw_version_info.class {
+                println!("Device Class: {}", class);
+            }
+            if let Some(ref guid) = parsed.raw_version_info.class_guid {
+                println!("Class GUID: {}", guid);
+            }
+            if let Some(ref version) = parsed.raw_version_info.driver_version {
+                println!("Driver Version: {}", version);
+            }
+            if let Some(ref date) = parsed.raw_version_info.driver_date {
+                println!("Driver Date: {}", date);
+            }
+            if let Some(ref provider) = parsed.raw_version_info.provider {
+                println!("Provider: {}", provider);
+            }
+            if let Some(ref catalog) = parsed.raw_version_info.catalog_file {
+                println!("Catalog File: {}", catalog);
+            }
+            if !parsed.includes.is_empty() {
+                println!("Includes: {}", parsed.includes.join(", "));
+            }
+            if verbose && !parsed.needs.is_empty() {
+                println!("Needs: {}", parsed.needs.join(", "));
+            }
+            if let Some(signed) = parsed.signed_date {
+                println!("Signed Date: {}", signed.format("%Y-%m-%d %H:%M:%S UTC"));
+            }
+
+            if !parsed.drivers.is_empty() {
+                println!("\nSupported Devices ({}):", parsed.drivers.len());
+                for (idx, driver) in parsed.drivers.iter().enumerate() {
+                    println!("\n  {}. {}", idx + 1, driver.device_name.as_deref().unwrap_or("Unknown"));
+                    println!("     Hardware ID: {}", driver.hardware_id.as_deref().unwrap_or("Unknown"));
+                    if driver.excluded.is_excluded() {
+                        println!("     Excluded from selection (ControlFlags)");
+                    }
+                    if verbose {
+                        if let Some(ref mfg) = driver.manufacturer {
+                            println!("     Manufacturer: {}", mfg);
+                        }
+                        if let Some(architecture) = driver.architecture {
+                            println!("     Architecture: {}", architecture.name());
+                        }
+                        if let Some(ref desc) = driver.description {
+                            if desc != driver.device_name.as_deref().unwrap_or("") {
+                                println!("     Description: {}", desc);
+                            }
+                        }
+                    }
+                }
+            } else {
+                println!("\nNo device entries found in this INF file.");
+            }
+            println!();
+        }
+    }
+
+    /// Export results to CSV
+    fn export_to_csv(parsed_files: &[ParsedInfFile], output_path: &Path, delimiter: char, columns: Option<&[usize]>) -> Result<()> {
+        let mut csv = CsvWriter::new(delimiter).with_columns(columns);
+        
+        // CSV Header matching PnPSignedDriver structure
+        csv.write_row(&INSPECT_CSV_COLUMNS.map(|(_, header)| header));
+
+        for parsed in parsed_files {
+            let signed_date = parsed.signed_date
+                .map(|signed| signed.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            for driver in &parsed.drivers {
+                csv.write_row(&[
+                    driver.device_name.as_deref().unwrap_or("Unknown"),
+                    driver.driver_version.as_deref().unwrap_or("Unknown"),
+                    driver.driver_date.as_deref().unwrap_or("Unknown"),
+                    driver.hardware_id.as_deref().unwrap_or("Unknown"),
+                    driver.inf_name.as_deref().unwrap_or("Unknown"),
+                    driver.description.as_deref().unwrap_or("Unknown"),
+                    driver.driver_provider_name.as_deref().unwrap_or("Unknown"),
+                    driver.device_class.as_deref().unwrap_or("Unknown"),
+                    driver.class_guid.as_deref().unwrap_or("Unknown"),
+                    driver.catalog_file.as_deref().unwrap_or("Unknown"),
+                    driver.manufacturer.as_deref().unwrap_or("Unknown"),
+                    signed_date.as_str(),
+                    if driver.excluded.is_excluded() { "Yes" } else { "No" },
+                    parsed.source.as_str(),
+                ]);
+            }
+        }
+
+        csv.save(output_path)?;
+
+        if !quiet() {
+            println!("Exported to: {}", output_path.display());
+        }
+        Ok(())
+    }
+
+    /// Main inspect function: inspect every input, then report them together.
+    /// A failing input is reported and skipped; the others still complete.
+    fn inspect(paths: &[PathBuf], options: &InspectOptions) -> Result<()> {
+        let verbose = options.verbose;
+        Self::remove_stale_temp_dirs(verbose);
+
+        if let Some(custom) = &options.seven_zip {
+            if !custom.is_file() {
+                anyhow::bail!("--seven-zip: {} does not exist", custom.display());
+            }
+        }
+        let seven_zips = SEVEN_ZIP.get_or_init(|| Self::detect_seven_zip(options.seven_zip.as_deref()));
+        if verbose {
+            println!("Extractors: built-in ZIP and CAB, expand.exe, msiexec");
+            match seven_zips.first() {
+                Some(path) => println!("  7-Zip: {}", path.display()),
+                None => println!("  7-Zip: not found (needed for .exe, .7z and .rar)"),
+            }
+            for other in seven_zips.iter().skip(1) {
+                println!("  7-Zip (fallback): {}", other.display());
+            }
+            println!();
+        }
+
+        // Catch typos before anything is downloaded or extracted
+        for path in paths {
+            let is_url = path.to_str().is_some_and(|p| p.starts_with("https://") || p.starts_with("http://"));
+            if !is_url && !path.exists() {
+                anyhow::bail!("Path does not exist: {}", path.display());
+            }
+        }
+
+        let mut parsed_files = Vec::new();
+        let mut payload_files = Vec::new();
+        let mut failed_count = 0;
+        for path in paths {
+            match Self::inspect_package(path, paths.len() > 1, options) {
+                Ok((parsed, payload)) => {
+                    parsed_files.extend(parsed);
+                    payload_files.extend(payload);
+                }
+                Err(e) if paths.len() == 1 => return Err(e),
+                Err(e) => {
+                    eprintln!("Error: {}: {:#}", path.display(), e);
+                    failed_count += 1;
+                }
+            }
+        }
+
+        Self::sort_parsed_files(&mut parsed_files);
+
+        let filtered_count = options.filter.apply(&mut parsed_files);
+        if filtered_count > 0 && !quiet() {
+            println!("Filtered out {} INF files", filtered_count);
+        }
+
+        // Display results; an update check only needs the comparison
+        if !quiet() && !options.only_updatable {
+            Self::display_results(&parsed_files, paths.len() > 1, verbose);
+        }
+
+        if options.list_files && !quiet() {
+            Self::display_payload_files(&payload_files, options.flat, paths.len() > 1);
+        }
+
+        if (options.compare_system || options.only_updatable) && !quiet() {
+            let machine = options.force_arch.unwrap_or_else(Architecture::native);
+            Self::compare_with_system(&parsed_files, options.only_updatable, machine, verbose)?;
+        }
+
+        // Export to CSV if requested
+        if let Some(csv_path) = &options.output {
+            Self::export_to_csv(&parsed_files, csv_path, options.delimiter, options.csv_columns.as_deref())?;
+
+            if options.list_files && !is_stdout_path(csv_path) {
+                Self::export_payload_csv(&payload_files, &Self::payload_csv_path(csv_path), options.delimiter)?;
+            }
+        }
+
+        if failed_count > 0 {
+            anyhow::bail!("{} of {} inputs could not be inspected", failed_count, paths.len());
+        }
+        Ok(())
+    }
+
+    /// Extract (or download) one input and parse its INF files, tagging each with the input.
+    /// With several inputs, `--keep-extracted DIR` gets one subfolder per input.
+    /// The extracted file list is only collected with `--list-files`.
+    fn inspect_package(path: &Path, multiple_inputs: bool, options: &InspectOptions) -> Result<(Vec<ParsedInfFile>, Vec<PayloadFile>)> {
+        let verbose = options.verbose;
+        let source = path.display().to_string();
+        let keep_dir = options.keep_extracted.as_ref().and_then(|d| d.as_deref()).map(|dir| {
+            match path.file_stem() {
+                Some(stem) if multiple_inputs => dir.join(stem),
+                _ => dir.to_path_buf(),
+            }
+        });
+        let keep_dir = keep_dir.as_deref();
+        if !quiet() {
+            println!("Inspecting driver package: {}", path.display());
+        }
+
+        // URLs are downloaded first; the guard removes the download unless --keep-download
+        let mut _download_guard = None;
+        let downloaded;
+        let path = match path.to_str().filter(|p| p.starts_with("https://") || p.starts_with("http://")) {
+            Some(url) => {
+                let dest_dir = if options.keep_download {
+                    std::env::current_dir().context("Failed to get current directory")?
+                } else {
+                    let temp = tempfile::Builder::new()
+                        .prefix(Self::TEMP_DIR_PREFIX)
+                        .tempdir()
+                        .context("Failed to create temporary download directory")?;
+                    let dir = temp.path().to_path_buf();
+                    _download_guard = Some(temp);
+                    dir
+                };
+                downloaded = Self::download_package(url, &dest_dir, options.allow_http)?;
+                if options.keep_download && !quiet() {
+                    println!("Download saved to: {}", downloaded.display());
+                }
+                downloaded.as_path()
+            }
+            None => path,
+        };
+
+        // Extract or use path directly; the temp dir guard cleans up on every return path
+        let (work_dir, temp_guard) = Self::extract_or_use_path(path, keep_dir, verbose)?;
+
+        let extracted = temp_guard.is_some() || keep_dir.is_some();
+        let result = Self::parse_extracted(&work_dir, extracted, &source, options);
+
+        // Keep or clean up the extracted payload
+        match temp_guard {
+            Some(temp) if options.keep_temp_on_error && !matches!(&result, Ok((parsed, _)) if !parsed.is_empty()) => {
+                eprintln!("Extracted files kept for debugging at: {}", temp.keep().display());
+            }
+            Some(temp) if options.keep_extracted.is_some() => {
+                let kept = temp.keep();
+                if !quiet() {
+                    println!("Extracted files kept at: {}", kept.display());
+                }
+            }
+            Some(temp) => {
+                if verbose {
+                    println!("Cleaning up temporary files...");
+                }
+                drop(temp);
+            }
+            None if keep_dir.is_some() && !quiet() => {
+                println!("Extracted files kept at: {}", work_dir.display());
+            }
+            None => {}
+        }
+
+        result
+    }
+
+    /// Expand nested archives in an extracted package, then find and parse its INF files
+    fn parse_extracted(work_dir: &Path, extracted: bool, source: &str, options: &InspectOptions) -> Result<(Vec<ParsedInfFile>, Vec<PayloadFile>)> {
+        let verbose = options.verbose;
+        if extracted {
+            Self::check_extracted_size(work_dir, options.max_extracted_bytes)?;
+
+            if options.archive_depth > 0 {
+                Self::extract_nested_archives(work_dir, options.archive_depth, options.max_extracted_bytes, verbose)?;
+            } else {
+                Self::extract_nested_cabs(work_dir, options.max_extracted_bytes, verbose)?;
+            }
+        }
+
+        let payload_files = if options.list_files {
+            Self::collect_payload_files(work_dir, source)?
+        } else {
+            Vec::new()
+        };
+
+        // Find all INF files
+        let (inf_files, walk_warnings) = Self::find_inf_files(work_dir, &WalkOptions::default())?;
+
+        if verbose {
+            for (dir, error) in &walk_warnings {
+                eprintln!("Warning: Skipped {}: {}", dir.display(), error);
+            }
+        }
+
+        if inf_files.is_empty() {
+            anyhow::bail!("No INF files found in the specified path");
+        }
+
+        if verbose {
+            println!("Found {} INF files", inf_files.len());
+        }
+
+        // Parse all INF files
+        let mut parsed_files = Vec::new();
+        for inf_path in &inf_files {
+            if options.dump_sections && !quiet() {
+                if let Err(e) = Self::dump_sections(inf_path, options.max_inf_size) {
+                    eprintln!("Warning: Failed to read {}: {:#}", inf_path.display(), e);
+                }
+            }
+            match Self::parse_inf_file(inf_path, options.max_inf_size) {
+                Ok(mut parsed) => {
+                    parsed.source = source.to_string();
+                    // Read while the extracted files still exist
+                    parsed.signed_date = parsed.raw_version_info.catalog_file.as_deref()
+                        .and_then(|catalog| Self::catalog_signed_date(&inf_path.with_file_name(catalog)));
+                    parsed_files.push(parsed);
+                }
+                Err(e) => {
+                    if verbose {
+                        eprintln!("Warning: Failed to parse {}: {}", inf_path.display(), e);
+                    }
+                }
+            }
+        }
+
+        Ok((parsed_files, payload_files))
+    }
+
+    /// `--dump-sections`: print the INF the way `parse_single_inf` walks it, one pass with
+    /// comments and blank lines dropped. Each section is labelled with what the parser takes
+    /// it for, and models lines it ignores for lack of a recognized hardware ID are marked.
+    fn dump_sections(inf_path: &Path, max_inf_size: u64) -> Result<()> {
+        let content = Self::read_inf_content(inf_path, max_inf_size)?;
+        let mut manufacturers: BTreeMap<String, String> = BTreeMap::new();
+        let mut current_section = String::new();
+
+        println!("\n--- Sections of {} ---", inf_path.display());
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                current_section = line[1..line.len()-1].to_lowercase();
+                let role = match current_section.as_str() {
+                    "version" => "version".to_string(),
+                    "manufacturer" => "manufacturer list".to_string(),
+                    "controlflags" => "control flags".to_string(),
+                    section if section == "strings" || section.starts_with("strings.") => "strings".to_string(),
+                    section if section.starts_with("sourcedisksnames") || section.starts_with("sourcedisksfiles") => "source files".to_string(),
+                    section => match Self::manufacturer_for_section(section, &manufacturers) {
+                        Some((name, _)) => format!("models of {}", name),
+                        None => "other".to_string(),
+                    },
+                };
+                println!("{} ({})", line, role);
+                continue;
+            }
+
+            let tokenized = match line.split_once('=') {
+                Some((key, value)) => format!("{} = {}", key.trim(), value.trim()),
+                None => line.to_string(),
+            };
+            let mut note = "";
+            if current_section == "manufacturer" {
+                Self::parse_manufacturer_line(line, &mut manufacturers);
+            } else if Self::section_matches_manufacturer(&current_section, &manufacturers) {
+                let mut probe = BTreeMap::new();
+                Self::parse_device_line(line, &current_section, &mut probe);
+                if probe.is_empty() {
+                    note = "    <- ignored: no recognized hardware ID";
+                }
+            }
+            println!("  {}{}", tokenized, note);
+        }
+        println!("--- End of {} ---", inf_path.file_name().unwrap_or_default().to_string_lossy());
+        Ok(())
+    }
+
+    /// List every file below the extraction folder with its size, sorted by path
+    fn collect_payload_files(work_dir: &Path, source: &str) -> Result<Vec<PayloadFile>> {
+        let mut paths = Vec::new();
+        let mut warnings = Vec::new();
+        Self::find_files_recursive(work_dir, &WalkOptions::default(), &|_| true, &mut paths, &mut warnings)?;
+        paths.sort();
+
+        Ok(paths.into_iter()
+            .map(|path| PayloadFile {
+                source: source.to_string(),
+                size: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+                path: path.strip_prefix(work_dir).map(Path::to_path_buf).unwrap_or(path),
+                work_dir: work_dir.to_path_buf(),
+            })
+            .collect())
+    }
+
+    /// Print the extracted files as a tree (or a flat list), then a per-extension summary
+    fn display_payload_files(payload_files: &[PayloadFile], flat: bool, show_source: bool) {
+        println!("----------------------------------------");
+        println!("Package Files:");
+        println!("----------------------------------------");
+
+        let mut current_source = None;
+        let mut previous_dirs: Vec<std::ffi::OsString> = Vec::new();
+        for file in payload_files {
+            if show_source && current_source != Some(file.source.as_str()) {
+                println!("\n[{}]", file.source);
+                current_source = Some(file.source.as_str());
+                previous_dirs.clear();
+            }
+
+            if flat {
+                println!("  {}  ({})", file.path.display(), format_size(file.size));
+                continue;
+            }
+
+            // Print only the folders that differ from the previous file's
+            let dirs: Vec<std::ffi::OsString> = file.path.parent()
+                .map(|parent| parent.iter().map(|c| c.to_os_string()).collect())
+                .unwrap_or_default();
+            let shared = dirs.iter().zip(&previous_dirs).take_while(|(a, b)| a == b).count();
+            for (depth, dir) in dirs.iter().enumerate().skip(shared) {
+                println!("  {}{}/", "  ".repeat(depth), dir.to_string_lossy());
+            }
+            let name = file.path.file_name().unwrap_or_default().to_string_lossy();
+            println!("  {}{}  ({})", "  ".repeat(dirs.len()), name, format_size(file.size));
+            previous_dirs = dirs;
+        }
+
+        // Extension summary, most common first
+        let mut by_extension: HashMap<String, usize> = HashMap::new();
+        for file in payload_files {
+            let extension = file.path.extension()
+                .map(|e| format!(".{}", e.to_string_lossy().to_lowercase()))
+                .unwrap_or_else(|| "(no extension)".to_string());
+            *by_extension.entry(extension).or_default() += 1;
+        }
+        let mut extensions: Vec<_> = by_extension.into_iter().collect();
+        extensions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let total_size: u64 = payload_files.iter().map(|f| f.size).sum();
+        let counts: Vec<String> = extensions.iter().map(|(ext, count)| format!("{} {}", count, ext)).collect();
+        println!();
+        println!("{} files: {}, total {}", payload_files.len(), counts.join(", "), format_size(total_size));
+        println!();
+    }
+
+    /// Export the extracted file list for `--list-files`
+    /// The `--list-files` CSV goes next to the driver CSV as `<name>_files.csv`
+    fn payload_csv_path(csv_path: &Path) -> PathBuf {
+        let stem = csv_path.file_stem().unwrap_or_default().to_string_lossy();
+        csv_path.with_file_name(format!("{}_files.csv", stem))
+    }
+
+    fn export_payload_csv(payload_files: &[PayloadFile], output_path: &Path, delimiter: char) -> Result<()> {
+        let mut csv = CsvWriter::new(delimiter);
+        csv.write_row(&["Path", "Size (bytes)", "Source"]);
+
+        for file in payload_files {
+            csv.write_row(&[
+                csv_path(&file.work_dir.join(&file.path), &file.work_dir, PathStyle::Relative),
+                file.size.to_string(),
+                file.source.clone(),
+            ]);
+        }
+
+        csv.save(output_path)?;
+
+        if !quiet() {
+            println!("Exported file list to: {}", output_path.display());
+        }
+        Ok(())
+    }
+
+    /// `scan --watch`: scan once, then scan again after every burst of file changes below
+    /// `paths` (see `WATCH_DEBOUNCE`), printing one line per update. Runs until Ctrl-C.
+    fn watch_folder(paths: &[PathBuf], options: &ScanOptions) -> Result<()> {
+        use notify::Watcher;
+
+        let output = match &options.output {
+            Some(output) if !is_stdout_path(output) => std::path::absolute(output).unwrap_or_else(|_| output.clone()),
+            _ => anyhow::bail!("--watch needs a CSV file for --output"),
+        };
+        Self::scan_folder(paths, options)?;
+
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).context("Failed to start watching for changes")?;
+        let mode = if options.recursive { notify::RecursiveMode::Recursive } else { notify::RecursiveMode::NonRecursive };
+        for path in paths {
+            watcher.watch(path, mode).with_context(|| format!("Failed to watch {}", path.display()))?;
+        }
+        println!("\nWatching for changes (Ctrl-C to stop)...");
+
+        // The CSV may be inside a watched folder; writing it mustn't trigger another scan
+        let is_relevant = |event: &notify::Event| {
+            !event.kind.is_access() && event.paths.iter().any(|path| {
+                std::path::absolute(path).map(|path| path != output).unwrap_or(true)
+            })
+        };
+
+        loop {
+            let mut changes = 0;
+            let mut last_change = Instant::now();
+            while !interrupted() {
+                match receiver.recv_timeout(Duration::from_millis(250)) {
+                    Ok(Ok(event)) if is_relevant(&event) => {
+                        changes += 1;
+                        last_change = Instant::now();
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => eprintln!("Warning: {}", e),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if changes > 0 && last_change.elapsed() >= WATCH_DEBOUNCE {
+                            break;
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("Stopped receiving change notifications"),
+                }
+            }
+            if interrupted() {
+                println!("Stopped watching.");
+                return Ok(());
+            }
+
+            // Only the status line is printed for a rescan
+            QUIET.store(true, Ordering::SeqCst);
+            let result = Self::scan_folder(paths, options);
+            QUIET.store(false, Ordering::SeqCst);
+            let time = chrono::Local::now().format("%H:%M:%S");
+            match result {
+                Ok(_) => println!("[{}] {} change(s), updated {}", time, changes, output.display()),
+                Err(e) => eprintln!("[{}] Rescan failed: {:#}", time, e),
+            }
+        }
+    }
+
+    /// Scan folder and display INF summary
+    /// Returns whether `--diff` found differences (always `false` without it)
+    fn scan_folder(paths: &[PathBuf], options: &ScanOptions) -> Result<bool> {
+        let verbose = options.verbose;
+        let recursive = options.recursive;
+
+        if options.write_index.is_some() && paths.len() != 1 {
+            anyhow::bail!("--write-index needs exactly one --path (entries are stored relative to it)");
+        }
+
+        if !quiet() {
+            for path in paths {
+                println!("Scanning folder: {}", path.display());
+            }
+            if let Some(index_path) = &options.index {
+                println!("Reading index: {}", index_path.display());
+            }
+            if recursive {
+                println!("Mode: Recursive (including subfolders)");
+            }
+            println!();
+        }
+
+        // Find all INF files, remembering which input each came from.
+        // A failing input is reported and skipped; the others still complete.
+        let mut inf_files: Vec<PathBuf> = Vec::new();
+        let mut sources: HashMap<PathBuf, String> = HashMap::new();
+        let mut walk_warnings: PathErrors = Vec::new();
+        let mut failed_count = 0;
+
+        for path in paths {
+            let found = if !path.is_dir() {
+                Err(anyhow::anyhow!("Path must be a directory: {}", path.display()))
+            } else if recursive {
+                Self::find_inf_files(path, &options.walk)
+            } else {
+                Self::find_inf_files_in_folder(path).map(|files| (files, Vec::new()))
+            };
+
+            match found {
+                Ok((files, warnings)) => {
+                    for file in files {
+                        sources.entry(file.clone()).or_insert_with(|| path.display().to_string());
+                        inf_files.push(file);
+                    }
+                    walk_warnings.extend(warnings);
+                }
+                Err(e) if paths.len() == 1 => return Err(e),
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    failed_count += 1;
+                }
+            }
+        }
+        inf_files.sort();
+        inf_files.dedup();
+
+        if inf_files.is_empty() && options.index.is_none() {
+            if !quiet() {
+                println!("No INF files found.");
+            }
+            if failed_count > 0 {
+                anyhow::bail!("{} of {} inputs could not be scanned", failed_count, paths.len());
+            }
+            return Ok(false);
+        }
+
+        // Parse all INF files, reusing cached results for unchanged files
+        let mut parsed_files: Vec<ParsedInfFile> = Vec::new();
+        let mut parse_errors: Vec<(PathBuf, String)> = Vec::new();
+        let mut cache = options.cache_path.as_deref().map(ScanCache::load);
+
+        for inf_path in &inf_files {
+            let result = match cache.as_mut() {
+                Some(cache) => cache.parse(inf_path, options.max_inf_size),
+                None => Self::parse_inf_file(inf_path, options.max_inf_size),
+            };
+            match result {
+                Ok(mut parsed) => {
+                    parsed.source = sources.get(inf_path).cloned().unwrap_or_default();
+                    parsed_files.push(parsed);
+                }
+                Err(e) => parse_errors.push((inf_path.clone(), e.to_string())),
+            }
+        }
+
+        if let Some(index_path) = &options.index {
+            parsed_files = ScanIndex::load(index_path)?.into_parsed_files(verbose);
+        }
+        let inf_count = inf_files.len().max(parsed_files.len());
+
+        // The --diff folder is always scanned recursively, through the same cache
+        let mut other_files: Vec<ParsedInfFile> = Vec::new();
+        if let Some(other) = &options.diff {
+            if !other.is_dir() {
+                anyhow::bail!("--diff path must be a directory: {}", other.display());
+            }
+            let (other_infs, _) = Self::find_inf_files(other, &options.walk)?;
+            for inf_path in &other_infs {
+                let result = match cache.as_mut() {
+                    Some(cache) => cache.parse(inf_path, options.max_inf_size),
+                    None => Self::parse_inf_file(inf_path, options.max_inf_size),
+                };
+                match result {
+                    Ok(parsed) => other_files.push(parsed),
+                    Err(e) if verbose => eprintln!("Warning: Failed to parse {}: {}", inf_path.display(), e),
+                    Err(_) => {}
+                }
+            }
+        }
+
+        if let (Some(cache), Some(cache_path)) = (cache.as_mut(), options.cache_path.as_deref()) {
+            if verbose {
+                println!("Scan cache: {} hits, {} parsed ({})", cache.hits, cache.misses, cache_path.display());
+            }
+            if let Err(e) = cache.save(cache_path) {
+                eprintln!("Warning: {:#}", e);
+            }
+        }
+
+        Self::sort_parsed_files(&mut parsed_files);
+
+        // Entries read from an index already carry their package status
+        let mut folder_sizes = HashMap::new();
+        for parsed in parsed_files.iter_mut().filter(|parsed| parsed.package.is_none()) {
+            parsed.package = Some(Self::check_package(parsed, &mut folder_sizes));
+        }
+
+        if let Some(index_path) = &options.write_index {
+            let index = ScanIndex::build(&paths[0], &parsed_files, options.index_hashes)?;
+            index.save(index_path)?;
+            if !quiet() {
+                println!("Index written: {} ({} INF files)", index_path.display(), index.entries.len());
+            }
+        }
+
+        // Keep only device entries on the requested bus, dropping INFs left without any
+        if let Some(bus) = options.bus {
+            for files in [&mut parsed_files, &mut other_files] {
+                for parsed in files.iter_mut() {
+                    parsed.drivers.retain(|d| d.hardware_id.as_deref().map(|id| bus.matches(id)).unwrap_or(false));
+                }
+                files.retain(|parsed| !parsed.drivers.is_empty());
+            }
+        }
+
+        let filtered_count = options.filter.apply(&mut parsed_files);
+        options.filter.apply(&mut other_files);
+
+        if let Some(key) = options.sort {
+            Self::sort_parsed_files_by(&mut parsed_files, key, options.descending);
+        }
+
+        // Display summary
+        if !quiet() {
+            println!("========================================");
+            println!("         INF Folder Scan Results");
+            println!("========================================");
+            println!();
+            for path in paths {
+                println!("Folder: {}", path.display());
+            }
+            if let Some(index_path) = &options.index {
+                println!("Index: {}", index_path.display());
+            }
+            println!("Total INF files found: {}", inf_count);
+            println!("Successfully parsed: {}", parsed_files.len() + filtered_count);
+            if !parse_errors.is_empty() {
+                println!("Failed to parse: {}", parse_errors.len());
+            }
+            if !walk_warnings.is_empty() {
+                println!("Unreadable folders skipped: {}", walk_warnings.len());
+            }
+            if filtered_count > 0 {
+                println!("Filtered out: {}", filtered_count);
+            }
+        
+            let total_devices: usize = parsed_files.iter().map(|f| f.drivers.len()).sum();
+            println!("Total device entries: {}", total_devices);
+
+            // Count each folder once, even when it holds several complete INFs
+            let complete_folders: BTreeMap<&Path, u64> = parsed_files.iter()
+                .filter_map(|parsed| parsed.package.as_ref()
+                    .filter(|status| status.missing_files.is_empty())
+                    .map(|status| (parsed.file_path.parent().unwrap_or(Path::new(".")), status.folder_size)))
+                .collect();
+            let complete_count = parsed_files.iter()
+                .filter(|parsed| parsed.package.as_ref().map(|s| s.missing_files.is_empty()).unwrap_or(false))
+                .count();
+            println!("Complete packages: {} of {} ({:.1} MB)",
+                complete_count, parsed_files.len(),
+                complete_folders.values().sum::<u64>() as f64 / (1024.0 * 1024.0));
+            println!();
+
+            Self::display_class_histogram(&parsed_files, &options.class_map);
+
+            match options.group_by {
+                Some(group_by) => Self::display_scan_grouped(&parsed_files, group_by, options.group_threshold, &options.class_map, verbose),
+                None => Self::display_scan_list(&parsed_files, verbose),
+            }
+
+            // Show parse errors if verbose
+            if verbose && !parse_errors.is_empty() {
+                println!("\n----------------------------------------");
+                println!("Parse Errors:");
+                for (path, error) in &parse_errors {
+                    println!("  - {}: {}", path.file_name().unwrap_or_default().to_string_lossy(), error);
+                }
+            }
+
+            if verbose && !walk_warnings.is_empty() {
+                println!("\n----------------------------------------");
+                println!("Skipped Folders:");
+                for (dir, error) in &walk_warnings {
+                    println!("  - {}: {}", dir.display(), error);
+                }
+            }
+        }
+
+        let duplicates = if options.duplicates || options.duplicates_csv.is_some() {
+            Self::find_duplicates(&parsed_files)
+        } else {
+            Vec::new()
+        };
+        if options.duplicates && !quiet() {
+            Self::display_duplicates(&parsed_files, &duplicates);
+        }
+        if let Some(dupes_path) = &options.duplicates_csv {
+            Self::export_duplicates_csv(&parsed_files, &duplicates, dupes_path, options.delimiter)?;
+        }
+
+        let mut differences_found = false;
+        if let Some(other) = &options.diff {
+            let differences = Self::diff_packages(&parsed_files, &other_files);
+            if !quiet() {
+                Self::display_diff(&differences, other);
+            }
+            if let Some(diff_path) = &options.diff_csv {
+                Self::export_diff_csv(&differences, diff_path, options.delimiter)?;
+            }
+            differences_found = !differences.is_empty();
+        }
+
+        // Export to CSV if requested
+        if let Some(csv_path) = &options.output {
+            Self::export_scan_csv(&parsed_files, csv_path, options.delimiter, options.csv_columns.as_deref())?;
+        }
+
+        if let Some(ndjson_path) = &options.ndjson {
+            let lines = parsed_files.iter().flat_map(|parsed| {
+                let line = move |device| ScanJsonLine {
+                    inf_path: &parsed.file_path,
+                    class: parsed.raw_version_info.class.as_deref(),
+                    complete: parsed.package.as_ref().map(|status| status.missing_files.is_empty()),
+                    device,
+                };
+                let devices: Vec<Option<&InfDriverInfo>> = match parsed.drivers.is_empty() {
+                    true => vec![None],
+                    false => parsed.drivers.iter().map(Some).collect(),
+                };
+                devices.into_iter().map(line)
+            });
+            let count = write_ndjson(lines, ndjson_path)?;
+            if !quiet() {
+                println!("JSON Lines written: {} ({} lines)", ndjson_path.display(), count);
+            }
+        }
+
+        if failed_count > 0 {
+            anyhow::bail!("{} of {} inputs could not be scanned", failed_count, paths.len());
+        }
+        Ok(differences_found)
+    }
+
+    /// Compare two sets of packages keyed by INF file name (the newest copy stands in
+    /// for duplicates). Packages with equal versions on both sides are left out.
+    fn diff_packages(a: &[ParsedInfFile], b: &[ParsedInfFile]) -> Vec<PackageDiff> {
+        fn newest_by_name(files: &[ParsedInfFile]) -> BTreeMap<String, &ParsedInfFile> {
+            let mut by_name: BTreeMap<String, &ParsedInfFile> = BTreeMap::new();
+            for parsed in files {
+                by_name.entry(parsed.file_name.to_lowercase())
+                    .and_modify(|current| {
+                        if InfParser::compare_package_age(parsed, current).is_gt() {
+                            *current = parsed;
                         }
+                    })
+                    .or_insert(parsed);
+            }
+            by_name
+        }
+
+        let (a, b) = (newest_by_name(a), newest_by_name(b));
+        let version = |p: Option<&&ParsedInfFile>| p.map(|p| p.raw_version_info.driver_version.clone().unwrap_or_default());
+
+        let names: std::collections::BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+        names.into_iter()
+            .filter_map(|name| {
+                let (in_a, in_b) = (a.get(name), b.get(name));
+                let (a_version, b_version) = (version(in_a), version(in_b));
+                if let (Some(va), Some(vb)) = (&a_version, &b_version) {
+                    if compare_driver_versions(va, vb).is_eq() {
+                        return None;
+                    }
+                }
+                let file_name = in_a.or(in_b).map(|p| p.file_name.clone()).unwrap_or_default();
+                Some(PackageDiff { file_name, a_version, b_version })
+            })
+            .collect()
+    }
+
+    /// Print `--diff` results: packages only on one side, then version changes
+    fn display_diff(differences: &[PackageDiff], other: &Path) {
+        println!("\n----------------------------------------");
+        println!("Differences against {}:", other.display());
+        println!("----------------------------------------");
+
+        if differences.is_empty() {
+            println!("\nNo differences found.");
+            return;
+        }
+
+        let only_a: Vec<_> = differences.iter().filter(|d| d.b_version.is_none()).collect();
+        let only_b: Vec<_> = differences.iter().filter(|d| d.a_version.is_none()).collect();
+        let changed: Vec<_> = differences.iter().filter(|d| d.a_version.is_some() && d.b_version.is_some()).collect();
+
+        if !only_a.is_empty() {
+            println!("\nOnly in this scan ({}):", only_a.len());
+            for diff in &only_a {
+                println!("  - {} v{}", diff.file_name, diff.a_version.as_deref().unwrap_or("?"));
+            }
+        }
+        if !only_b.is_empty() {
+            println!("\nOnly in {} ({}):", other.display(), only_b.len());
+            for diff in &only_b {
+                println!("  + {} v{}", diff.file_name, diff.b_version.as_deref().unwrap_or("?"));
+            }
+        }
+        if !changed.is_empty() {
+            println!("\nVersion differences ({}):", changed.len());
+            for diff in &changed {
+                let a_version = diff.a_version.as_deref().unwrap_or_default();
+                let b_version = diff.b_version.as_deref().unwrap_or_default();
+                let (a_parsed, b_parsed) = (DriverVersion::parse(a_version), DriverVersion::parse(b_version));
+                let direction = match b_parsed.cmp(&a_parsed) {
+                    _ if !a_parsed.is_comparable() || !b_parsed.is_comparable() => "uncomparable",
+                    std::cmp::Ordering::Greater => "newer",
+                    _ => "older",
+                };
+                println!("  * {}: {} -> {} ({} in {})", diff.file_name, a_version, b_version, direction, other.display());
+            }
+        }
+    }
+
+    /// Export `--diff` results, one row per differing package
+    fn export_diff_csv(differences: &[PackageDiff], output_path: &Path, delimiter: char) -> Result<()> {
+        let mut csv = CsvWriter::new(delimiter);
+        csv.write_row(&["INF File", "Status", "Version (scan)", "Version (diff)"]);
+
+        for diff in differences {
+            let status = match (&diff.a_version, &diff.b_version) {
+                (Some(_), None) => "only_in_scan",
+                (None, Some(_)) => "only_in_diff",
+                _ => "version_changed",
+            };
+            csv.write_row(&[
+                diff.file_name.as_str(),
+                status,
+                diff.a_version.as_deref().unwrap_or_default(),
+                diff.b_version.as_deref().unwrap_or_default(),
+            ]);
+        }
+
+        csv.save(output_path)?;
+
+        if !quiet() {
+            println!("Exported differences to: {}", output_path.display());
+        }
+        Ok(())
+    }
+
+    /// Group INFs that are copies of the same package: same file name, or at least one
+    /// shared hardware ID. Each cluster lists indexes into `parsed_files`, newest first.
+    fn find_duplicates(parsed_files: &[ParsedInfFile]) -> Vec<Vec<usize>> {
+        // Union-find over INF indexes
+        fn root(parents: &mut [usize], mut i: usize) -> usize {
+            while parents[i] != i {
+                parents[i] = parents[parents[i]];
+                i = parents[i];
+            }
+            i
+        }
+
+        let mut parents: Vec<usize> = (0..parsed_files.len()).collect();
+        let mut first_with_key: HashMap<String, usize> = HashMap::new();
+        for (index, parsed) in parsed_files.iter().enumerate() {
+            let keys = std::iter::once(format!("name:{}", parsed.file_name.to_lowercase()))
+                .chain(parsed.drivers.iter()
+                    .filter_map(|d| d.hardware_id.as_deref())
+                    .map(|id| format!("hwid:{}", id.to_lowercase())));
+            for key in keys {
+                let other = *first_with_key.entry(key).or_insert(index);
+                let (a, b) = (root(&mut parents, index), root(&mut parents, other));
+                parents[a] = b;
+            }
+        }
+
+        let mut clusters: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for index in 0..parsed_files.len() {
+            clusters.entry(root(&mut parents, index)).or_default().push(index);
+        }
+
+        let mut clusters: Vec<Vec<usize>> = clusters.into_values().filter(|c| c.len() > 1).collect();
+        for cluster in &mut clusters {
+            cluster.sort_by(|&a, &b| Self::compare_package_age(&parsed_files[b], &parsed_files[a]));
+        }
+        clusters.sort_by_key(|cluster| parsed_files[cluster[0]].file_name.to_lowercase());
+        clusters
+    }
+
+    /// Order two INFs by driver version, then by driver date (older first)
+    fn compare_package_age(a: &ParsedInfFile, b: &ParsedInfFile) -> std::cmp::Ordering {
+        let version = |p: &ParsedInfFile| p.raw_version_info.driver_version.clone().unwrap_or_default();
+        let date = |p: &ParsedInfFile| p.raw_version_info.driver_date.as_deref().and_then(parse_driver_date);
+        compare_driver_versions(&version(a), &version(b))
+            .then_with(|| date(a).cmp(&date(b)))
+    }
+
+    /// Print duplicate clusters, marking the newest copy of each
+    fn display_duplicates(parsed_files: &[ParsedInfFile], clusters: &[Vec<usize>]) {
+        println!("\n----------------------------------------");
+        println!("Duplicate Packages:");
+        println!("----------------------------------------");
+
+        if clusters.is_empty() {
+            println!("\nNo duplicates found.");
+            return;
+        }
+
+        for (number, cluster) in clusters.iter().enumerate() {
+            let newest = &parsed_files[cluster[0]];
+            println!("\n{}. {} ({} copies)", number + 1, newest.file_name, cluster.len());
+            for (position, &index) in cluster.iter().enumerate() {
+                let parsed = &parsed_files[index];
+                let marker = if position == 0 { "* " } else { "  " };
+                println!("   {}v{}  {}  {}",
+                    marker,
+                    parsed.raw_version_info.driver_version.as_deref().unwrap_or("?"),
+                    parsed.raw_version_info.driver_date.as_deref().unwrap_or("?"),
+                    parsed.file_path.display());
+            }
+        }
+
+        let redundant: usize = clusters.iter().map(|c| c.len() - 1).sum();
+        println!("\n{} duplicate groups, {} older or redundant copies (* = newest)", clusters.len(), redundant);
+    }
+
+    /// Export duplicate clusters, one row per INF copy
+    fn export_duplicates_csv(parsed_files: &[ParsedInfFile], clusters: &[Vec<usize>], output_path: &Path, delimiter: char) -> Result<()> {
+        let mut csv = CsvWriter::new(delimiter);
+        csv.write_row(&["Group", "INF File", "Driver Version", "Driver Date", "Newest", "Path"]);
+
+        for (number, cluster) in clusters.iter().enumerate() {
+            for (position, &index) in cluster.iter().enumerate() {
+                let parsed = &parsed_files[index];
+                csv.write_row(&[
+                    (number + 1).to_string().as_str(),
+                    parsed.file_name.as_str(),
+                    parsed.raw_version_info.driver_version.as_deref().unwrap_or("Unknown"),
+                    parsed.raw_version_info.driver_date.as_deref().unwrap_or("Unknown"),
+                    if position == 0 { "yes" } else { "no" },
+                    &csv_path(&parsed.file_path, Path::new(&parsed.source), PathStyle::Absolute),
+                ]);
+            }
+        }
+
+        csv.save(output_path)?;
+
+        if !quiet() {
+            println!("Exported duplicates to: {}", output_path.display());
+        }
+        Ok(())
+    }
+
+    /// Display scan results as a simple list
+    fn display_scan_list(parsed_files: &[ParsedInfFile], verbose: bool) {
+        println!("----------------------------------------");
+        println!("INF Files Summary:");
+        println!("----------------------------------------");
+        
+        for (idx, parsed) in parsed_files.iter().enumerate() {
+            println!("\n{}. {}", idx + 1, parsed.file_name);
+            
+            if let Some(ref class) = parsed.raw_version_info.class {
+                println!("   Class: {}", class);
+            }
+            if let Some(ref version) = parsed.raw_version_info.driver_version {
+                println!("   Version: {}", version);
+            }
+            if let Some(ref date) = parsed.raw_version_info.driver_date {
+                println!("   Date: {}", date);
+            }
+            if let Some(provider) = Self::provider_name(parsed) {
+                println!("   Provider: {}", provider);
+            }
+            println!("   Devices: {} entries", parsed.drivers.len());
+            if let Some(status) = &parsed.package {
+                println!("   Folder Size: {:.1} MB", status.folder_size as f64 / (1024.0 * 1024.0));
+                if status.missing_files.is_empty() {
+                    println!("   Complete: Yes");
+                } else {
+                    println!("   Complete: No (missing {})", status.missing_files.join(", "));
+                }
+            }
+
+            if verbose && !parsed.drivers.is_empty() {
+                println!("   Hardware IDs:");
+                for driver in &parsed.drivers {
+                    if let Some(ref hwid) = driver.hardware_id {
+                        let device_name = driver.device_name.as_deref().unwrap_or("Unknown");
+                        let excluded = if driver.excluded.is_excluded() { " [excluded]" } else { "" };
+                        println!("     - {} ({}){}", hwid, device_name, excluded);
                     }
                 }
             }
         }
+    }
 
-        // Build driver info list
-        let mut drivers = Vec::new();
-        
-        for (section_name, devices) in &device_sections {
-            for (device_desc, hardware_id) in devices {
-                // Resolve string references
-                let resolved_desc = Self::resolve_string(device_desc, &string_table);
-                let resolved_provider = version_info.provider.as_ref()
-                    .map(|p| Self::resolve_string(p, &string_table));
-
-                // Find manufacturer for this section
-                let manufacturer = manufacturers.iter()
-                    .find(|(_, sec)| {
-                        let base = sec.split(',').next().unwrap_or(sec);
-                        section_name.to_lowercase().starts_with(&base.to_lowercase())
-                    })
-                    .map(|(name, _)| Self::resolve_string(name, &string_table));
-
-                let driver_info = InfDriverInfo {
-                    device_name: Some(resolved_desc.clone()),
-                    description: Some(resolved_desc),
-                    device_class: version_info.class.clone(),
-                    class_guid: version_info.class_guid.clone(),
-                    driver_version: version_info.driver_version.clone(),
-                    driver_date: version_info.driver_date.clone(),
-                    driver_provider_name: resolved_provider,
-                    hardware_id: Some(hardware_id.clone()),
-                    inf_name: Some(file_name.clone()),
-                    catalog_file: version_info.catalog_file.clone(),
-                    manufacturer,
-                };
+    /// Compact per-class INF and device counts, largest class first
+    fn display_class_histogram(parsed_files: &[ParsedInfFile], class_map: &HashMap<String, String>) {
+        if parsed_files.is_empty() {
+            return;
+        }
+        let mut classes: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for parsed in parsed_files {
+            let class = map_device_class(class_map, parsed.raw_version_info.class.as_deref().unwrap_or("Unknown"));
+            let counts = classes.entry(class).or_default();
+            counts.0 += 1;
+            counts.1 += parsed.drivers.len();
+        }
+        let mut classes: Vec<_> = classes.into_iter().collect();
+        // Stable sort keeps equal counts in alphabetical order
+        classes.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        let width = classes.iter().map(|(class, _)| class.len()).max().unwrap_or(0);
+        println!("Classes:");
+        for (class, (infs, devices)) in &classes {
+            println!("  {:<width$}  {:>4} INFs, {:>5} devices", format!("{}:", class), infs, devices, width = width + 1);
+        }
+        println!();
+    }
+
+    /// Display scan results grouped by device class or provider
+    fn display_scan_grouped(parsed_files: &[ParsedInfFile], group_by: GroupBy, threshold: usize, class_map: &HashMap<String, String>, verbose: bool) {
+        let mut groups: BTreeMap<String, Vec<&ParsedInfFile>> = BTreeMap::new();
+
+        for parsed in parsed_files {
+            let group = match group_by {
+                GroupBy::Class => map_device_class(class_map, parsed.raw_version_info.class.as_deref().unwrap_or("Unknown")),
+                GroupBy::Provider => Self::provider_name(parsed).unwrap_or("Unknown").to_string(),
+            };
+            groups.entry(group).or_default().push(parsed);
+        }
+
+        println!("----------------------------------------");
+        match group_by {
+            GroupBy::Class => println!("INF Files by Device Class:"),
+            GroupBy::Provider => println!("INF Files by Provider:"),
+        }
+        println!("----------------------------------------");
 
-                drivers.push(driver_info);
+        // Small groups are listed together at the bottom, each INF tagged with its group
+        let (groups, small): (Vec<_>, Vec<_>) = groups.into_iter()
+            .partition(|(_, files)| files.len() >= threshold);
+        let other: Vec<(&ParsedInfFile, Option<&str>)> = small.iter()
+            .flat_map(|(group, files)| files.iter().map(move |parsed| (*parsed, Some(group.as_str()))))
+            .collect();
+
+        for (group, files) in &groups {
+            println!("\n[{}] ({} INF files)", group, files.len());
+            for parsed in files {
+                Self::display_grouped_inf(parsed, None, verbose);
             }
         }
 
-        Ok(ParsedInfFile {
-            file_path: inf_path.to_path_buf(),
-            file_name,
-            drivers,
-            raw_version_info: version_info,
-        })
+        if !other.is_empty() {
+            println!("\n[Other] ({} INF files)", other.len());
+            for (parsed, group) in other {
+                Self::display_grouped_inf(parsed, group, verbose);
+            }
+        }
     }
 
-    fn read_inf_content(path: &Path) -> Result<String> {
-        // First try reading as bytes and detect encoding
-        let bytes = fs::read(path)?;
+    /// One INF line of the grouped scan view, prefixed with its group when folded into "Other"
+    fn display_grouped_inf(parsed: &ParsedInfFile, group: Option<&str>, verbose: bool) {
+        let version = parsed.raw_version_info.driver_version
+            .as_deref()
+            .unwrap_or("?");
+        let devices = parsed.drivers.len();
+
+        let incomplete = match &parsed.package {
+            Some(status) if !status.missing_files.is_empty() => " [incomplete]",
+            _ => "",
+        };
+        let group = group.map(|group| format!("[{}] ", group)).unwrap_or_default();
+        println!("  - {}{} (v{}, {} devices){}", group, parsed.file_name, version, devices, incomplete);
+
+        if verbose {
+            for driver in &parsed.drivers {
+                if let Some(ref hwid) = driver.hardware_id {
+                    let excluded = if driver.excluded.is_excluded() { " [excluded]" } else { "" };
+                    println!("      HWID: {}{}", hwid, excluded);
+                }
+            }
+        }
+    }
+
+    /// Export scan results to CSV
+    fn export_scan_csv(parsed_files: &[ParsedInfFile], output_path: &Path, delimiter: char, columns: Option<&[usize]>) -> Result<()> {
+        let mut csv = CsvWriter::new(delimiter).with_columns(columns);
         
-        // Check for UTF-16 LE BOM
-        if bytes.len() >= 2 && bytes[0] == 0xFF && bytes[1] == 0xFE {
-            let utf16_chars: Vec<u16> = bytes[2..]
-                .chunks(2)
-                .filter_map(|chunk| {
-                    if chunk.len() == 2 {
-                        Some(u16::from_le_bytes([chunk[0], chunk[1]]))
-                    } else {
-                        None
-                    }
-                })
+        // CSV Header - summary format with device names
+        csv.write_row(&SCAN_CSV_COLUMNS.map(|(_, header)| header));
+
+        for parsed in parsed_files {
+            // Collect device names
+            let device_names: Vec<String> = parsed.drivers
+                .iter()
+                .filter_map(|d| d.device_name.clone())
                 .collect();
-            return Ok(String::from_utf16_lossy(&utf16_chars));
+            let device_names_str = device_names.join("; ");
+
+            // Collect hardware IDs
+            let hwids: Vec<String> = parsed.drivers
+                .iter()
+                .filter_map(|d| d.hardware_id.clone())
+                .collect();
+            let hwids_str = hwids.join("; ");
+
+            let resolved_provider = Self::provider_name(parsed).unwrap_or("Unknown");
+            let (folder_size, complete) = match &parsed.package {
+                Some(status) => (
+                    format!("{:.1}", status.folder_size as f64 / (1024.0 * 1024.0)),
+                    if status.missing_files.is_empty() { "Yes" } else { "No" },
+                ),
+                None => (String::new(), ""),
+            };
+
+            csv.write_row(&[
+                parsed.file_name.as_str(),
+                parsed.raw_version_info.class.as_deref().unwrap_or("Unknown"),
+                resolved_provider,
+                parsed.raw_version_info.driver_version.as_deref().unwrap_or("Unknown"),
+                parsed.raw_version_info.driver_date.as_deref().unwrap_or("Unknown"),
+                &parsed.drivers.len().to_string(),
+                &device_names_str,
+                &hwids_str,
+                &folder_size,
+                complete,
+                parsed.source.as_str(),
+            ]);
         }
-        
-        // Check for UTF-16 BE BOM
-        if bytes.len() >= 2 && bytes[0] == 0xFE && bytes[1] == 0xFF {
-            let utf16_chars: Vec<u16> = bytes[2..]
-                .chunks(2)
-                .filter_map(|chunk| {
-                    if chunk.len() == 2 {
-                        Some(u16::from_be_bytes([chunk[0], chunk[1]]))
-                    } else {
-                        None
+
+        csv.save(output_path)?;
+
+        if !quiet() {
+            println!("\nExported to: {}", output_path.display());
+        }
+        Ok(())
+    }
+
+    /// Parse every INF in a backup folder, recursively, for the summary CSV and class READMEs
+    fn scan_backup(backup_dir: &Path, verbose: bool) -> Result<Vec<ParsedInfFile>> {
+        // Find all INF files recursively in the backup folder
+        let (inf_files, _) = Self::find_inf_files(backup_dir, &WalkOptions::default())?;
+
+        if inf_files.is_empty() {
+            println!("No INF files found in backup folder.");
+            return Ok(Vec::new());
+        }
+
+        if verbose {
+            println!("Found {} INF files in backup", inf_files.len());
+        }
+
+        // Parse all INF files
+        let mut parsed_files: Vec<ParsedInfFile> = Vec::new();
+        for inf_path in &inf_files {
+            match Self::parse_inf_file(inf_path, DEFAULT_MAX_INF_SIZE) {
+                Ok(parsed) => parsed_files.push(parsed),
+                Err(e) => {
+                    if verbose {
+                        eprintln!("Warning: Failed to parse {}: {}", inf_path.display(), e);
                     }
-                })
-                .collect();
-            return Ok(String::from_utf16_lossy(&utf16_chars));
+                }
+            }
         }
 
-        // Check for UTF-8 BOM
-        if bytes.len() >= 3 && bytes[0] == 0xEF && bytes[1] == 0xBB && bytes[2] == 0xBF {
-            return Ok(String::from_utf8_lossy(&bytes[3..]).to_string());
+        if parsed_files.is_empty() {
+            println!("No valid INF files parsed.");
         }
+        Ok(parsed_files)
+    }
 
-        // Try UTF-8, fall back to Windows-1252/Latin-1
-        match String::from_utf8(bytes.clone()) {
-            Ok(s) => Ok(s),
-            Err(_) => Ok(bytes.iter().map(|&b| b as char).collect())
+    /// Export the summary CSV of a scanned backup folder (used by backup command)
+    fn export_backup_summary(parsed_files: &[ParsedInfFile], backup_dir: &Path, output_csv: &Path, preamble: &[String]) -> Result<()> {
+        if parsed_files.is_empty() {
+            return Ok(());
         }
+
+        // Export to CSV with folder name
+        Self::export_backup_summary_csv(parsed_files, backup_dir, output_csv, preamble)?;
+
+        println!("Summary CSV created: {}", output_csv.display());
+        println!("Total INF files: {}", parsed_files.len());
+        
+        let total_devices: usize = parsed_files.iter().map(|f| f.drivers.len()).sum();
+        println!("Total device entries: {}", total_devices);
+
+        Ok(())
     }
 
-    fn parse_version_line(line: &str, version_info: &mut InfVersionInfo) {
-        let parts: Vec<&str> = line.splitn(2, '=').collect();
-        if parts.len() != 2 {
-            return;
+    /// Export backup summary to CSV with relative folder paths, after `# `-prefixed `preamble` lines
+    fn export_backup_summary_csv(parsed_files: &[ParsedInfFile], backup_dir: &Path, output_path: &Path, preamble: &[String]) -> Result<()> {
+        let mut csv = CsvWriter::new(',');
+        for line in preamble {
+            csv.write_comment(line);
         }
+        
+        // CSV Header - includes Folder Name for backup
+        csv.write_row(&["INF File", "Device Class", "Provider", "Driver Version", "Driver Date", "Device Count", "Folder Name", "Device Names", "Hardware IDs"]);
 
-        let key = parts[0].trim().to_lowercase();
-        let value = parts[1].trim().trim_matches('"').to_string();
+        for parsed in parsed_files {
+            // Collect device names
+            let device_names: Vec<String> = parsed.drivers
+                .iter()
+                .filter_map(|d| d.device_name.clone())
+                .collect();
+            let device_names_str = device_names.join("; ");
+
+            // Collect hardware IDs
+            let hwids: Vec<String> = parsed.drivers
+                .iter()
+                .filter_map(|d| d.hardware_id.clone())
+                .collect();
+            let hwids_str = hwids.join("; ");
+
+            let resolved_provider = Self::summary_provider(parsed);
+
+            // Folder relative to backup_dir unless --paths absolute
+            let folder_name = parsed.file_path.parent()
+                .map(|folder| csv_path(folder, backup_dir, PathStyle::Relative))
+                .unwrap_or_else(|| "Unknown".to_string());
+
+            csv.write_row(&[
+                parsed.file_name.as_str(),
+                parsed.raw_version_info.class.as_deref().unwrap_or("Unknown"),
+                resolved_provider,
+                parsed.raw_version_info.driver_version.as_deref().unwrap_or("Unknown"),
+                parsed.raw_version_info.driver_date.as_deref().unwrap_or("Unknown"),
+                &parsed.drivers.len().to_string(),
+                &folder_name,
+                &device_names_str,
+                &hwids_str,
+            ]);
+        }
+
+        csv.save(output_path)?;
+
+        Ok(())
+    }
+
+    /// Provider for the backup summaries, with a `%token%` resolved through the device entries
+    fn summary_provider(parsed: &ParsedInfFile) -> &str {
+        let provider = parsed.raw_version_info.provider.as_deref().unwrap_or("Unknown");
+        if provider.starts_with('%') && provider.ends_with('%') {
+            parsed.drivers.first()
+                .and_then(|d| d.driver_provider_name.as_deref())
+                .unwrap_or(provider)
+        } else {
+            provider
+        }
+    }
+
+    /// Write a `README.txt` into each class folder of a backup, listing its packages in
+    /// fixed-width columns so the backup can be browsed without this tool.
+    /// Returns how many were written; INFs outside a class folder are left out.
+    fn write_class_readmes(parsed_files: &[ParsedInfFile], backup_dir: &Path, generated: &str) -> Result<usize> {
+        let mut classes: BTreeMap<String, Vec<[String; 6]>> = BTreeMap::new();
+        for parsed in parsed_files {
+            let Some(relative) = parsed.file_path.parent().and_then(|p| p.strip_prefix(backup_dir).ok()) else {
+                continue;
+            };
+            let mut components = relative.components().map(|c| c.as_os_str().to_string_lossy().to_string());
+            let Some(class_folder) = components.next() else {
+                continue;
+            };
+            let folder = components.collect::<Vec<_>>().join("\\");
+            let devices: Vec<&str> = parsed.drivers.iter().filter_map(|d| d.device_name.as_deref()).collect();
+            classes.entry(class_folder).or_default().push([
+                if folder.is_empty() { ".".to_string() } else { folder },
+                parsed.file_name.clone(),
+                Self::summary_provider(parsed).to_string(),
+                parsed.raw_version_info.driver_version.clone().unwrap_or_else(|| "Unknown".to_string()),
+                parsed.raw_version_info.driver_date.clone().unwrap_or_else(|| "Unknown".to_string()),
+                format!("{} ({})", devices.len(), devices.join("; ")),
+            ]);
+        }
 
-        match key.as_str() {
-            "driverver" => {
-                // Format: MM/DD/YYYY, version or YYYY/MM/DD, version
-                let dv_parts: Vec<&str> = value.splitn(2, ',').collect();
-                if !dv_parts.is_empty() {
-                    version_info.driver_date = Some(dv_parts[0].trim().to_string());
-                }
-                if dv_parts.len() > 1 {
-                    version_info.driver_version = Some(dv_parts[1].trim().to_string());
+        let header = ["Folder", "INF", "Provider", "Version", "Date", "Devices"];
+        for (class_folder, mut rows) in classes.iter_mut().map(|(name, rows)| (name, std::mem::take(rows))) {
+            rows.sort();
+            let mut widths = header.map(|title| title.chars().count());
+            for row in &rows {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.chars().count());
                 }
             }
-            "class" => version_info.class = Some(value),
-            "classguid" => version_info.class_guid = Some(value),
-            "provider" => version_info.provider = Some(value),
-            "catalogfile" | "catalogfile.nt" | "catalogfile.ntamd64" | "catalogfile.ntx86" => {
-                version_info.catalog_file = Some(value);
+            // The last column is left unpadded so lines don't end in spaces
+            let line = |cells: [&str; 6]| -> String {
+                let mut line = String::new();
+                for (i, cell) in cells.iter().enumerate() {
+                    if i + 1 == cells.len() {
+                        line.push_str(cell);
+                    } else {
+                        line.push_str(&format!("{:<width$}  ", cell, width = widths[i]));
+                    }
+                }
+                line.push_str("\r\n");
+                line
+            };
+
+            let mut text = String::new();
+            text.push_str(&format!("Driver packages in {}\r\n", class_folder));
+            text.push_str(&format!("Generated: {} by driver-backup {}\r\n\r\n", generated, env!("CARGO_PKG_VERSION")));
+            text.push_str(&line(header));
+            text.push_str(&line(widths.map(|width| "-".repeat(width)).each_ref().map(String::as_str)));
+            for row in &rows {
+                text.push_str(&line(row.each_ref().map(String::as_str)));
             }
-            _ => {}
+
+            let readme_path = backup_dir.join(class_folder).join("README.txt");
+            fs::write(&readme_path, text)
+                .with_context(|| format!("Failed to write {}", readme_path.display()))?;
         }
+        Ok(classes.len())
     }
+}
 
-    fn parse_manufacturer_line(line: &str, manufacturers: &mut HashMap<String, String>) {
-        let parts: Vec<&str> = line.splitn(2, '=').collect();
-        if parts.len() != 2 {
-            return;
-        }
+/// One package folder of a backup, as listed by `browse`
+struct BrowsePackage {
+    class: String,
+    folder: PathBuf,
+    /// From manifest.json, when the backup has one
+    oem_inf: Option<String>,
+    /// The export command as recorded in manifest.json
+    export: Option<ExportRecord>,
+    /// Listed in the manifest's `failures`: the export failed and the folder may be empty
+    failed: bool,
+    parsed: Vec<ParsedInfFile>,
+    /// Files of the package relative to its folder, with sizes
+    files: Vec<(PathBuf, u64)>,
+}
 
-        let name = parts[0].trim().to_string();
-        let section = parts[1].trim().to_string();
-        manufacturers.insert(name, section);
+impl BrowsePackage {
+    /// First device name of the package, or its folder name
+    fn name(&self) -> String {
+        self.parsed.iter()
+            .flat_map(|parsed| &parsed.drivers)
+            .find_map(|driver| driver.device_name.clone())
+            .unwrap_or_else(|| self.folder.file_name().unwrap_or_default().to_string_lossy().into_owned())
     }
 
-    fn parse_device_line(line: &str, section: &str, device_sections: &mut HashMap<String, Vec<(String, String)>>) {
-        let parts: Vec<&str> = line.splitn(2, '=').collect();
-        if parts.len() != 2 {
-            return;
+    /// A `[Version]` field of the first INF that has it
+    fn version_field(&self, get: fn(&InfVersionInfo) -> Option<&String>) -> &str {
+        self.parsed.iter().find_map(|parsed| get(&parsed.raw_version_info)).map(String::as_str).unwrap_or("")
+    }
+
+    /// The `[Version]` provider is often a %token%; device entries carry the resolved name
+    fn provider(&self) -> &str {
+        self.parsed.iter()
+            .flat_map(|parsed| &parsed.drivers)
+            .find_map(|driver| driver.driver_provider_name.as_deref())
+            .unwrap_or_else(|| self.version_field(|v| v.provider.as_ref()))
+    }
+
+    /// Whether a device name or hardware ID contains `needle` (lowercase)
+    fn matches(&self, needle: &str) -> bool {
+        self.parsed.iter().flat_map(|parsed| &parsed.drivers).any(|driver| {
+            [&driver.device_name, &driver.hardware_id].into_iter().flatten().any(|text| text.to_lowercase().contains(needle))
+        })
+    }
+}
+
+/// Which list of `browse` the arrow keys move in
+#[derive(Clone, Copy, PartialEq)]
+enum BrowseFocus {
+    Classes,
+    Packages,
+}
+
+/// State of the `browse` terminal UI
+struct Browser {
+    backup_dir: PathBuf,
+    packages: Vec<BrowsePackage>,
+    /// "All" followed by the classes of the backup
+    classes: Vec<String>,
+    class_state: ratatui::widgets::ListState,
+    package_state: ratatui::widgets::TableState,
+    focus: BrowseFocus,
+    search: String,
+    /// Keys go into the search box
+    searching: bool,
+    /// Destination being typed after `x`
+    extract_to: Option<String>,
+    /// Indexes into `packages` marked with Space
+    marked: std::collections::BTreeSet<usize>,
+    status: String,
+}
+
+impl Browser {
+    /// Parse every package folder of the backup; classes come from manifest.json when present
+    fn load(backup_dir: &Path) -> Result<Self> {
+        if !backup_dir.is_dir() {
+            anyhow::bail!("Backup folder not found: {}", backup_dir.display());
+        }
+        // Any manifest layout will do here; only the class, OEM INF and export record of each folder are used
+        let manifest: Option<BackupManifest> = fs::read_to_string(backup_dir.join("manifest.json")).ok()
+            .and_then(|json| serde_json::from_str(&json).ok());
+
+        let (inf_files, _) = InfParser::find_inf_files(backup_dir, &WalkOptions::default())?;
+        let mut by_folder: BTreeMap<PathBuf, Vec<ParsedInfFile>> = BTreeMap::new();
+        for inf_file in &inf_files {
+            match InfParser::parse_inf_file(inf_file, DEFAULT_MAX_INF_SIZE) {
+                Ok(parsed) => by_folder.entry(inf_file.parent().unwrap_or(backup_dir).to_path_buf()).or_default().push(parsed),
+                Err(e) => eprintln!("Warning: Skipping {}: {:#}", inf_file.display(), e),
+            }
+        }
+        if by_folder.is_empty() {
+            anyhow::bail!("No driver packages found in {}", backup_dir.display());
         }
 
-        let device_desc = parts[0].trim().to_string();
-        let right_side = parts[1].trim();
-        
-        // Format: InstallSection, HardwareID [, CompatibleID, ...]
-        let hw_parts: Vec<&str> = right_side.split(',').collect();
-        if hw_parts.len() >= 2 {
-            let hardware_id = hw_parts[1].trim().to_string();
-            if !hardware_id.is_empty() && (
-                hardware_id.to_uppercase().starts_with("PCI\\") ||
-                hardware_id.to_uppercase().starts_with("USB\\") ||
-                hardware_id.to_uppercase().starts_with("HDAUDIO\\") ||
-                hardware_id.to_uppercase().starts_with("ACPI\\") ||
-                hardware_id.to_uppercase().starts_with("HID\\") ||
-                hardware_id.to_uppercase().starts_with("SWD\\") ||
-                hardware_id.to_uppercase().starts_with("ROOT\\") ||
-                hardware_id.to_uppercase().contains("VEN_") ||
-                hardware_id.to_uppercase().contains("DEV_")
-            ) {
-                device_sections
-                    .entry(section.to_string())
-                    .or_default()
-                    .push((device_desc, hardware_id));
+        let mut packages = Vec::new();
+        for (folder, parsed) in by_folder {
+            let relative = folder.strip_prefix(backup_dir).unwrap_or(&folder).to_string_lossy().replace('/', "\\");
+            let recorded = manifest.as_ref()
+                .and_then(|manifest| manifest.packages.iter().find(|package| package.folder.eq_ignore_ascii_case(&relative)));
+            let class = recorded.map(|package| package.device_class.clone())
+                .or_else(|| parsed.iter().find_map(|p| p.raw_version_info.class.clone()))
+                .unwrap_or_else(|| "Unknown".to_string());
+
+            let mut files = Vec::new();
+            let mut warnings = Vec::new();
+            InfParser::find_files_recursive(&folder, &WalkOptions::default(), &|_| true, &mut files, &mut warnings)?;
+            files.sort();
+            let files = files.into_iter()
+                .map(|file| {
+                    let size = fs::metadata(&file).map(|m| m.len()).unwrap_or(0);
+                    (file.strip_prefix(&folder).unwrap_or(&file).to_path_buf(), size)
+                })
+                .collect();
+
+            packages.push(BrowsePackage {
+                class,
+                oem_inf: recorded.map(|package| package.oem_inf.clone()),
+                export: recorded.and_then(|package| package.export.clone()),
+                failed: false,
+                folder,
+                parsed,
+                files,
+            });
+        }
+        // Failed exports are listed too, for their export record
+        for failure in manifest.iter().flat_map(|manifest| &manifest.failures) {
+            let folder = backup_dir.join(&failure.folder);
+            if let Some(package) = packages.iter_mut().find(|package| package.folder == folder) {
+                package.failed = true;
+                package.export = failure.export.clone();
+                continue;
             }
+            packages.push(BrowsePackage {
+                class: failure.device_class.clone(),
+                oem_inf: Some(failure.oem_inf.clone()),
+                export: failure.export.clone(),
+                failed: true,
+                folder,
+                parsed: Vec::new(),
+                files: Vec::new(),
+            });
         }
+        packages.sort_by_cached_key(|package| (package.class.to_lowercase(), package.name().to_lowercase()));
+
+        let mut classes: Vec<String> = packages.iter().map(|package| package.class.clone()).collect();
+        classes.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+        classes.insert(0, "All".to_string());
+
+        let mut browser = Browser {
+            backup_dir: backup_dir.to_path_buf(),
+            packages,
+            classes,
+            class_state: Default::default(),
+            package_state: Default::default(),
+            focus: BrowseFocus::Packages,
+            search: String::new(),
+            searching: false,
+            extract_to: None,
+            marked: Default::default(),
+            status: String::new(),
+        };
+        browser.class_state.select(Some(0));
+        browser.package_state.select(Some(0));
+        Ok(browser)
     }
 
-    fn parse_strings_line(line: &str, string_table: &mut HashMap<String, String>) {
-        let parts: Vec<&str> = line.splitn(2, '=').collect();
-        if parts.len() != 2 {
-            return;
-        }
+    /// Indexes of the packages in the selected class that match the search
+    fn visible(&self) -> Vec<usize> {
+        let class = self.class_state.selected().filter(|&i| i > 0).and_then(|i| self.classes.get(i));
+        let needle = self.search.to_lowercase();
+        (0..self.packages.len())
+            .filter(|&i| class.is_none_or(|class| self.packages[i].class.eq_ignore_ascii_case(class)))
+            .filter(|&i| needle.is_empty() || self.packages[i].matches(&needle))
+            .collect()
+    }
 
-        let key = parts[0].trim().to_string();
-        let value = parts[1].trim().trim_matches('"').to_string();
-        string_table.insert(key, value);
+    fn selected(&self) -> Option<usize> {
+        self.package_state.selected().and_then(|row| self.visible().get(row).copied())
     }
 
-    fn resolve_string(s: &str, string_table: &HashMap<String, String>) -> String {
-        if s.starts_with('%') && s.ends_with('%') && s.len() > 2 {
-            let key = &s[1..s.len()-1];
-            string_table.get(key).cloned().unwrap_or_else(|| s.to_string())
-        } else {
-            s.to_string()
-        }
+    /// Take over the terminal until `q`; the terminal is restored even when drawing fails
+    fn run(mut self) -> Result<()> {
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal);
+        ratatui::restore();
+        result
     }
 
-    /// Display parsed driver information
-    fn display_results(parsed_files: &[ParsedInfFile], verbose: bool) {
-        println!("\n========================================");
-        println!("       Driver Package Inspection");
-        println!("========================================\n");
+    fn event_loop(&mut self, terminal: &mut ratatui::DefaultTerminal) -> Result<()> {
+        use ratatui::crossterm::event::{self, Event, KeyEventKind};
 
-        let total_drivers: usize = parsed_files.iter().map(|f| f.drivers.len()).sum();
-        println!("Found {} INF files with {} device entries\n", parsed_files.len(), total_drivers);
+        loop {
+            terminal.draw(|frame| self.draw(frame)).context("Failed to draw the terminal")?;
+            if let Event::Key(key) = event::read().context("Failed to read terminal input")? {
+                if key.kind == KeyEventKind::Press && self.handle_key(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
 
-        for parsed in parsed_files {
-            println!("----------------------------------------");
-            println!("INF File: {}", parsed.file_name);
-            println!("Path: {}", parsed.file_path.display());
-            
-            if let Some(ref class) = parsed.raw_version_info.class {
-                println!("Device Class: {}", class);
+    /// Apply a key press; returns true to quit
+    fn handle_key(&mut self, key: ratatui::crossterm::event::KeyEvent) -> bool {
+        use ratatui::crossterm::event::{KeyCode, KeyModifiers};
+
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return true;
+        }
+        if let Some(dest) = &mut self.extract_to {
+            match key.code {
+                KeyCode::Enter => {
+                    let dest = PathBuf::from(dest.trim().trim_matches('"'));
+                    self.extract_to = None;
+                    self.status = self.extract(&dest);
+                }
+                KeyCode::Esc => {
+                    self.extract_to = None;
+                    self.status = "Extract cancelled".to_string();
+                }
+                KeyCode::Backspace => {
+                    dest.pop();
+                }
+                KeyCode::Char(c) => dest.push(c),
+                _ => {}
             }
-            if let Some(ref guid) = parsed.raw_version_info.class_guid {
-                println!("Class GUID: {}", guid);
+            return false;
+        }
+        if self.searching {
+            match key.code {
+                KeyCode::Enter => self.searching = false,
+                KeyCode::Esc => {
+                    self.searching = false;
+                    self.search.clear();
+                }
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                KeyCode::Char(c) => self.search.push(c),
+                _ => {}
             }
-            if let Some(ref version) = parsed.raw_version_info.driver_version {
-                println!("Driver Version: {}", version);
+            self.package_state.select(Some(0));
+            return false;
+        }
+
+        self.status.clear();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    BrowseFocus::Classes => BrowseFocus::Packages,
+                    BrowseFocus::Packages => BrowseFocus::Classes,
+                };
             }
-            if let Some(ref date) = parsed.raw_version_info.driver_date {
-                println!("Driver Date: {}", date);
+            KeyCode::Left | KeyCode::Char('h') => self.focus = BrowseFocus::Classes,
+            KeyCode::Right | KeyCode::Char('l') => self.focus = BrowseFocus::Packages,
+            KeyCode::Up | KeyCode::Char('k') => self.move_cursor(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_cursor(1),
+            KeyCode::PageUp => self.move_cursor(-10),
+            KeyCode::PageDown => self.move_cursor(10),
+            KeyCode::Char('/') => {
+                self.searching = true;
+                self.focus = BrowseFocus::Packages;
             }
-            if let Some(ref provider) = parsed.raw_version_info.provider {
-                println!("Provider: {}", provider);
+            KeyCode::Char(' ') => {
+                if let Some(index) = self.selected() {
+                    if !self.marked.remove(&index) {
+                        self.marked.insert(index);
+                    }
+                    self.move_cursor(1);
+                }
             }
-            if let Some(ref catalog) = parsed.raw_version_info.catalog_file {
-                println!("Catalog File: {}", catalog);
+            KeyCode::Char('c') => {
+                if let Some(index) = self.selected() {
+                    let path = std::path::absolute(&self.packages[index].folder).unwrap_or_else(|_| self.packages[index].folder.clone());
+                    self.status = match Self::copy_to_clipboard(&path.to_string_lossy()) {
+                        Ok(()) => format!("Copied {}", path.display()),
+                        Err(e) => format!("Copy failed: {:#}", e),
+                    };
+                }
             }
-
-            if !parsed.drivers.is_empty() {
-                println!("\nSupported Devices ({}):", parsed.drivers.len());
-                for (idx, driver) in parsed.drivers.iter().enumerate() {
-                    println!("\n  {}. {}", idx + 1, driver.device_name.as_deref().unwrap_or("Unknown"));
-                    println!("     Hardware ID: {}", driver.hardware_id.as_deref().unwrap_or("Unknown"));
-                    if verbose {
-                        if let Some(ref mfg) = driver.manufacturer {
-                            println!("     Manufacturer: {}", mfg);
-                        }
-                        if let Some(ref desc) = driver.description {
-                            if desc != driver.device_name.as_deref().unwrap_or("") {
-                                println!("     Description: {}", desc);
-                            }
-                        }
-                    }
+            KeyCode::Char('x') => {
+                if self.marked.is_empty() && self.selected().is_none() {
+                    self.status = "Nothing to extract".to_string();
+                } else {
+                    self.extract_to = Some(String::new());
                 }
-            } else {
-                println!("\nNo device entries found in this INF file.");
             }
-            println!();
+            _ => {}
         }
+        false
     }
 
-    /// Export results to CSV
-    fn export_to_csv(parsed_files: &[ParsedInfFile], output_path: &Path) -> Result<()> {
-        let mut csv_content = String::new();
-        
-        // CSV Header matching PnPSignedDriver structure
-        csv_content.push_str("Device Name,Driver Version,Driver Date,Hardware ID,INF Name,Description,Provider,Device Class,Class GUID,Catalog File,Manufacturer\n");
-        
-        let escape_csv = |s: &str| -> String {
-            if s.contains(',') || s.contains('"') || s.contains('\n') {
-                format!("\"{}\"", s.replace("\"", "\"\""))
-            } else {
-                s.to_string()
-            }
+    fn move_cursor(&mut self, delta: isize) {
+        let (state_selected, len) = match self.focus {
+            BrowseFocus::Classes => (self.class_state.selected(), self.classes.len()),
+            BrowseFocus::Packages => (self.package_state.selected(), self.visible().len()),
         };
+        if len == 0 {
+            return;
+        }
+        let next = state_selected.unwrap_or(0).saturating_add_signed(delta).min(len - 1);
+        match self.focus {
+            BrowseFocus::Classes => {
+                self.class_state.select(Some(next));
+                self.package_state.select(Some(0));
+            }
+            BrowseFocus::Packages => self.package_state.select(Some(next)),
+        }
+    }
 
-        for parsed in parsed_files {
-            for driver in &parsed.drivers {
-                csv_content.push_str(&format!(
-                    "{},{},{},{},{},{},{},{},{},{},{}\n",
-                    escape_csv(driver.device_name.as_deref().unwrap_or("Unknown")),
-                    escape_csv(driver.driver_version.as_deref().unwrap_or("Unknown")),
-                    escape_csv(driver.driver_date.as_deref().unwrap_or("Unknown")),
-                    escape_csv(driver.hardware_id.as_deref().unwrap_or("Unknown")),
-                    escape_csv(driver.inf_name.as_deref().unwrap_or("Unknown")),
-                    escape_csv(driver.description.as_deref().unwrap_or("Unknown")),
-                    escape_csv(driver.driver_provider_name.as_deref().unwrap_or("Unknown")),
-                    escape_csv(driver.device_class.as_deref().unwrap_or("Unknown")),
-                    escape_csv(driver.class_guid.as_deref().unwrap_or("Unknown")),
-                    escape_csv(driver.catalog_file.as_deref().unwrap_or("Unknown")),
-                    escape_csv(driver.manufacturer.as_deref().unwrap_or("Unknown")),
-                ));
-            }
-        }
-
-        fs::write(output_path, csv_content)
-            .with_context(|| format!("Failed to write CSV file: {}", output_path.display()))?;
-
-        println!("Exported to: {}", output_path.display());
+    /// Put `text` on the Windows clipboard through PowerShell's Set-Clipboard
+    fn copy_to_clipboard(text: &str) -> Result<()> {
+        let output = Command::new("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", "Set-Clipboard -Value $env:DRIVER_BACKUP_CLIPBOARD"])
+            .env("DRIVER_BACKUP_CLIPBOARD", text)
+            .output()
+            .context("Failed to run PowerShell")?;
+        if !output.status.success() {
+            anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr).lines().next().unwrap_or("Set-Clipboard failed").trim());
+        }
         Ok(())
     }
 
-    /// Main inspect function
-    fn inspect(path: &Path, output: Option<&Path>, verbose: bool) -> Result<()> {
-        println!("Inspecting driver package: {}", path.display());
-
-        // Extract or use path directly
-        let (work_dir, needs_cleanup) = Self::extract_or_use_path(path, verbose)?;
-
-        // Find all INF files
-        let inf_files = Self::find_inf_files(&work_dir)?;
+    /// Copy the marked packages (or the selected one) into `dest`, keeping their
+    /// class\package layout. Returns the line for the status bar.
+    fn extract(&mut self, dest: &Path) -> String {
+        if dest.as_os_str().is_empty() {
+            return "Extract cancelled: no folder given".to_string();
+        }
+        let indexes: Vec<usize> = if self.marked.is_empty() {
+            self.selected().into_iter().collect()
+        } else {
+            self.marked.iter().copied().collect()
+        };
 
-        if inf_files.is_empty() {
-            if needs_cleanup {
-                let _ = fs::remove_dir_all(&work_dir);
+        let mut copied = 0;
+        let mut failed = 0;
+        for index in indexes {
+            let folder = &self.packages[index].folder;
+            let target = dest.join(folder.strip_prefix(&self.backup_dir).unwrap_or(folder));
+            let output = Command::new("xcopy")
+                .arg(folder)
+                .arg(&target)
+                .args(["/E", "/I", "/Y", "/Q"])
+                .output();
+            match output {
+                Ok(output) if output.status.success() => copied += 1,
+                _ => failed += 1,
             }
-            anyhow::bail!("No INF files found in the specified path");
         }
-
-        if verbose {
-            println!("Found {} INF files", inf_files.len());
+        self.marked.clear();
+        if failed > 0 {
+            format!("Extracted {} packages to {}, {} failed", copied, dest.display(), failed)
+        } else {
+            format!("Extracted {} packages to {}", copied, dest.display())
         }
+    }
 
-        // Parse all INF files
-        let mut parsed_files = Vec::new();
-        for inf_path in &inf_files {
-            match Self::parse_inf_file(inf_path) {
-                Ok(parsed) => parsed_files.push(parsed),
-                Err(e) => {
-                    if verbose {
-                        eprintln!("Warning: Failed to parse {}: {}", inf_path.display(), e);
+    fn draw(&mut self, frame: &mut ratatui::Frame) {
+        use ratatui::layout::{Constraint, Layout};
+        use ratatui::style::{Color, Modifier, Style};
+        use ratatui::text::Line;
+        use ratatui::widgets::{Block, List, Paragraph, Row, Table, Wrap};
+
+        let [main, footer] = Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [classes_area, packages_area, details_area] = Layout::horizontal([
+            Constraint::Percentage(18),
+            Constraint::Percentage(47),
+            Constraint::Percentage(35),
+        ]).areas(main);
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        let border = |focus: BrowseFocus| if self.focus == focus { Style::default().fg(Color::Yellow) } else { Style::default() };
+
+        let class_items: Vec<String> = self.classes.iter().enumerate()
+            .map(|(i, class)| {
+                let count = self.packages.iter().filter(|p| i == 0 || p.class.eq_ignore_ascii_case(class)).count();
+                format!("{} ({})", class, count)
+            })
+            .collect();
+        let class_list = List::new(class_items)
+            .block(Block::bordered().title("Classes").border_style(border(BrowseFocus::Classes)))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(class_list, classes_area, &mut self.class_state);
+
+        let visible = self.visible();
+        let rows: Vec<Row> = visible.iter()
+            .map(|&i| {
+                let package = &self.packages[i];
+                Row::new(vec![
+                    if self.marked.contains(&i) { "*".to_string() } else { String::new() },
+                    if package.failed { format!("✗ {}", package.name()) } else { package.name() },
+                    package.provider().to_string(),
+                    package.version_field(|v| v.driver_version.as_ref()).to_string(),
+                    package.version_field(|v| v.driver_date.as_ref()).to_string(),
+                ])
+            })
+            .collect();
+        let mut title = format!("Packages ({})", visible.len());
+        if self.searching || !self.search.is_empty() {
+            title.push_str(&format!(" search: {}{}", self.search, if self.searching { "_" } else { "" }));
+        }
+        let widths = [Constraint::Length(1), Constraint::Fill(3), Constraint::Fill(2), Constraint::Length(16), Constraint::Length(10)];
+        let table = Table::new(rows, widths)
+            .header(Row::new(vec!["", "Device", "Provider", "Version", "Date"]).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(Block::bordered().title(title).border_style(border(BrowseFocus::Packages)))
+            .row_highlight_style(highlight);
+        frame.render_stateful_widget(table, packages_area, &mut self.package_state);
+
+        let mut details: Vec<Line> = Vec::new();
+        if let Some(package) = self.selected().map(|i| &self.packages[i]) {
+            let relative = package.folder.strip_prefix(&self.backup_dir).unwrap_or(&package.folder);
+            details.push(Line::from(format!("Folder: {}", relative.display())));
+            if let Some(oem_inf) = &package.oem_inf {
+                details.push(Line::from(format!("OEM INF: {}", oem_inf)));
+            }
+            if package.failed {
+                details.push(Line::from("✗ Export failed").style(Style::default().fg(Color::Red)));
+            }
+            for parsed in &package.parsed {
+                let version = &parsed.raw_version_info;
+                details.push(Line::from(""));
+                details.push(Line::from(parsed.file_name.clone()).style(Style::default().add_modifier(Modifier::BOLD)));
+                details.push(Line::from(format!("Class: {}", version.class.as_deref().unwrap_or("Unknown"))));
+                details.push(Line::from(format!("Provider: {}", package.provider())));
+                details.push(Line::from(format!("Version: {}  Date: {}",
+                    version.driver_version.as_deref().unwrap_or("?"), version.driver_date.as_deref().unwrap_or("?"))));
+                if let Some(catalog) = &version.catalog_file {
+                    details.push(Line::from(format!("Catalog: {}", catalog)));
+                }
+                for driver in &parsed.drivers {
+                    details.push(Line::from(format!("  {} - {}",
+                        driver.device_name.as_deref().unwrap_or("Unknown"), driver.hardware_id.as_deref().unwrap_or("?"))));
+                }
+            }
+            if let Some(export) = &package.export {
+                details.push(Line::from(""));
+                details.push(Line::from("Export").style(Style::default().add_modifier(Modifier::BOLD)));
+                details.push(Line::from(format!("> {}", export.command)));
+                details.push(Line::from(format!("Exit code: {}  Duration: {:.2} s",
+                    export.exit_code.map_or("none".to_string(), |code| code.to_string()), export.duration_ms as f64 / 1000.0)));
+                for (name, text) in [("stdout", &export.stdout), ("stderr", &export.stderr)] {
+                    if !text.is_empty() {
+                        details.push(Line::from(format!("{}:", name)));
+                        details.extend(text.lines().map(|line| Line::from(format!("  {}", line))));
                     }
                 }
             }
+            let total: u64 = package.files.iter().map(|(_, size)| size).sum();
+            details.push(Line::from(""));
+            details.push(Line::from(format!("Files ({}, {:.1} MB):", package.files.len(), total as f64 / (1024.0 * 1024.0)))
+                .style(Style::default().add_modifier(Modifier::BOLD)));
+            for (file, size) in &package.files {
+                details.push(Line::from(format!("  {} ({:.1} KB)", file.display(), *size as f64 / 1024.0)));
+            }
         }
+        let details = Paragraph::new(details)
+            .block(Block::bordered().title("Details"))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(details, details_area);
+
+        let footer_text = if let Some(dest) = &self.extract_to {
+            let count = if self.marked.is_empty() { 1 } else { self.marked.len() };
+            format!("Extract {} packages to folder: {}_  (Enter to copy, Esc to cancel)", count, dest)
+        } else if !self.status.is_empty() {
+            self.status.clone()
+        } else {
+            "↑↓ move  ←→/Tab pane  / search  Space mark  c copy path  x extract marked  q quit".to_string()
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+}
 
-        // Display results
-        Self::display_results(&parsed_files, verbose);
+// Add CLI arguments for backup functionality
+#[derive(Parser)]
+#[command(name = "driver-backup")]
+#[command(version = "2.3")]
+#[command(about = "A tool to backup, inspect, and manage non-Microsoft drivers")]
+#[command(long_about = "Driver Backup Tool v2.3\n\n\
+    Commands:\n  \
+    backup   - Export all non-Microsoft drivers from the system (requires Admin)\n  \
+    inspect  - Extract driver info from installer packages (.exe, .zip, .7z, folder)\n  \
+    scan     - Identify and list all INF files in a folder\n\n\
+    Examples:\n  \
+    driver-backup backup -o D:\\Backup -v\n  \
+    driver-backup inspect -p C:\\Downloads\\driver.exe -o info.csv\n  \
+    driver-backup scan -p C:\\Drivers -r -g -o inventory.csv")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
 
-        // Export to CSV if requested
-        if let Some(csv_path) = output {
-            Self::export_to_csv(&parsed_files, csv_path)?;
-        }
+    /// Relaunch elevated through a UAC prompt when not running as Administrator
+    /// (automatic when started without a command, e.g. by double-clicking)
+    #[arg(long, global = true)]
+    elevate: bool,
 
-        // Cleanup temp directory if needed
-        if needs_cleanup {
-            if verbose {
-                println!("Cleaning up temporary files...");
-            }
-            let _ = fs::remove_dir_all(&work_dir);
-        }
+    /// Set on the relaunched instance so it never prompts again
+    #[arg(long, global = true, hide = true)]
+    elevated: bool,
 
-        Ok(())
-    }
+    /// Write path columns of CSV reports relative to the scanned folder, backup or extraction folder, or absolute
+    /// (by default the backup summary and inspect file list are relative, scan reports absolute)
+    #[arg(long, global = true, value_enum, value_name = "STYLE")]
+    paths: Option<PathStyle>,
 
-    /// Scan folder and display INF summary
-    fn scan_folder(path: &Path, output: Option<&Path>, verbose: bool, group_by_class: bool, recursive: bool) -> Result<()> {
-        if !path.is_dir() {
-            anyhow::bail!("Path must be a directory: {}", path.display());
-        }
+    /// Rebuild the OEM INF name lookup from the driver store as the run starts, before its first use
+    #[arg(long, global = true)]
+    refresh_lookup: bool,
 
-        println!("Scanning folder: {}", path.display());
-        if recursive {
-            println!("Mode: Recursive (including subfolders)");
-        }
-        println!();
+    #[command(flatten)]
+    hooks: CompletionHooks,
+}
 
-        // Find all INF files
-        let inf_files = if recursive {
-            Self::find_inf_files(path)?
-        } else {
-            Self::find_inf_files_in_folder(path)?
-        };
+/// `--on-complete-*`, `--eventlog` and `--notify` notifications sent when backup, restore or export --files finishes
+#[derive(clap::Args, Clone, Default)]
+struct CompletionHooks {
+    /// POST the run summary as JSON to this https:// URL when backup, restore or export --files finishes
+    #[arg(long, global = true, value_name = "URL", value_parser = parse_hook_url)]
+    on_complete_url: Option<String>,
 
-        if inf_files.is_empty() {
-            println!("No INF files found.");
-            return Ok(());
-        }
+    /// Run PROGRAM with the run summary JSON on stdin and DRIVER_BACKUP_EXIT_STATUS set (0 = success)
+    #[arg(long, global = true, value_name = "PROGRAM")]
+    on_complete_cmd: Option<PathBuf>,
 
-        // Parse all INF files
-        let mut parsed_files: Vec<ParsedInfFile> = Vec::new();
-        let mut parse_errors: Vec<(PathBuf, String)> = Vec::new();
+    /// Seconds to wait for each --on-complete-url request
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 30)]
+    hook_timeout: u64,
 
-        for inf_path in &inf_files {
-            match Self::parse_inf_file(inf_path) {
-                Ok(parsed) => parsed_files.push(parsed),
-                Err(e) => parse_errors.push((inf_path.clone(), e.to_string())),
+    /// Record the outcome of backup, restore or export --files in the Application event log
+    #[arg(long, global = true)]
+    eventlog: bool,
+
+    /// Show a Windows notification when backup, restore or export --files finishes
+    #[arg(long, global = true)]
+    notify: bool,
+}
+
+/// Event source registered in the Application log by `--eventlog`
+const EVENT_SOURCE: &str = "DriverBackup";
+
+/// What the completion hooks receive about a finished run
+#[derive(Serialize)]
+struct RunSummary<'a> {
+    command: &'a str,
+    success: bool,
+    error: Option<String>,
+    started: String,
+    finished: String,
+    duration_seconds: f64,
+    computer: String,
+    #[serde(flatten)]
+    stats: Option<&'a RunStats>,
+}
+
+/// Outcome counts of a backup, restore or export --files run, for the completion hooks
+#[derive(Serialize, Default, Debug)]
+struct RunStats {
+    /// Backup folder written, or backup / snapshot folder restored from
+    location: Option<PathBuf>,
+    succeeded: usize,
+    failed: usize,
+    /// The run itself succeeded but a follow-up step (`--mirror`) didn't
+    partial_failure: bool,
+}
+
+/// Exit code when the run succeeded but a follow-up step such as `--mirror` or `--verify-after-export` failed
+const EXIT_PARTIAL_FAILURE: i32 = 3;
+
+impl CompletionHooks {
+    /// Report a finished run to the configured hooks. Hook failures are printed as
+    /// warnings and never change the run's own result or exit code.
+    fn notify(&self, command: &str, started: chrono::DateTime<Utc>, result: &Result<RunStats>) {
+        if self.on_complete_url.is_none() && self.on_complete_cmd.is_none() && !self.eventlog && !self.notify {
+            return;
+        }
+        let finished = Utc::now();
+        let summary = RunSummary {
+            command,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            started: started.to_rfc3339(),
+            finished: finished.to_rfc3339(),
+            duration_seconds: (finished - started).num_milliseconds() as f64 / 1000.0,
+            computer: std::env::var("COMPUTERNAME").unwrap_or_default(),
+            stats: result.as_ref().ok(),
+        };
+        if self.eventlog {
+            if let Err(e) = Self::write_event(&summary) {
+                eprintln!("Warning: --eventlog failed: {:#}", e);
             }
         }
+        if self.notify {
+            Self::show_notification(&summary);
+        }
 
-        // Display summary
-        println!("========================================");
-        println!("         INF Folder Scan Results");
-        println!("========================================");
-        println!();
-        println!("Folder: {}", path.display());
-        println!("Total INF files found: {}", inf_files.len());
-        println!("Successfully parsed: {}", parsed_files.len());
-        if !parse_errors.is_empty() {
-            println!("Failed to parse: {}", parse_errors.len());
+        let json = match serde_json::to_vec(&summary) {
+            Ok(json) => json,
+            Err(e) => {
+                eprintln!("Warning: Failed to build the completion summary: {}", e);
+                return;
+            }
+        };
+
+        if let Some(url) = &self.on_complete_url {
+            match self.post_summary(url, &json) {
+                Ok(()) => println!("Completion summary sent to {}", url),
+                Err(e) => eprintln!("Warning: --on-complete-url failed: {:#}", e),
+            }
         }
-        
-        let total_devices: usize = parsed_files.iter().map(|f| f.drivers.len()).sum();
-        println!("Total device entries: {}", total_devices);
-        println!();
+        if let Some(program) = &self.on_complete_cmd {
+            match Self::run_command(program, &json, result.is_ok()) {
+                Ok(()) => {}
+                Err(e) => eprintln!("Warning: --on-complete-cmd failed: {:#}", e),
+            }
+        }
+    }
 
-        if group_by_class {
-            Self::display_scan_grouped(&parsed_files, verbose);
-        } else {
-            Self::display_scan_list(&parsed_files, verbose);
+    /// Write an information (ID 1000) or error (ID 1001) event from the `DriverBackup` source
+    /// with `ReportEventW`, registering the source on first use
+    fn write_event(summary: &RunSummary) -> Result<()> {
+        use windows::core::{HSTRING, PCWSTR};
+        use windows::Win32::Security::PSID;
+        use windows::Win32::System::EventLog::{
+            DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE,
+        };
+
+        let mut message = format!("driver-backup {} {}", summary.command, if summary.success { "succeeded" } else { "failed" });
+        if let Some(stats) = summary.stats {
+            message.push_str(&format!("\r\nSucceeded: {}\r\nFailed: {}", stats.succeeded, stats.failed));
+            if let Some(location) = &stats.location {
+                message.push_str(&format!("\r\nPath: {}", location.display()));
+            }
+        }
+        if let Some(error) = &summary.error {
+            message.push_str(&format!("\r\nError: {}", error));
+        }
+        message.push_str(&format!("\r\nStarted: {}\r\nDuration: {:.1} s", summary.started, summary.duration_seconds));
+        let (event_type, event_id) = if summary.success { (EVENTLOG_INFORMATION_TYPE, 1000) } else { (EVENTLOG_ERROR_TYPE, 1001) };
+
+        Self::register_event_source()?;
+        let message = HSTRING::from(message);
+        // SAFETY: the handle is released right after the report, and `message` outlives both calls
+        unsafe {
+            let source = RegisterEventSourceW(PCWSTR::null(), &HSTRING::from(EVENT_SOURCE))
+                .context("Failed to open the event source")?;
+            let reported = ReportEventW(source, event_type, 0, event_id, PSID::default(), 0, Some(&[PCWSTR(message.as_ptr())]), None);
+            let _ = DeregisterEventSource(source);
+            reported.context("Failed to write the event")
         }
+    }
 
-        // Show parse errors if verbose
-        if verbose && !parse_errors.is_empty() {
-            println!("\n----------------------------------------");
-            println!("Parse Errors:");
-            for (path, error) in &parse_errors {
-                println!("  - {}: {}", path.file_name().unwrap_or_default().to_string_lossy(), error);
+    /// Register `EVENT_SOURCE` under the Application log the way .NET's `CreateEventSource`
+    /// does, with the framework's message file that shows each event's text as is. Creating
+    /// the key needs elevation; once it exists, any user can write events.
+    fn register_event_source() -> Result<()> {
+        use windows::core::{w, HSTRING, PCWSTR};
+        use windows::Win32::Foundation::ERROR_ACCESS_DENIED;
+        use windows::Win32::System::Registry::{
+            RegCloseKey, RegCreateKeyExW, RegOpenKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_READ, KEY_SET_VALUE,
+            REG_DWORD, REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE,
+        };
+        const MESSAGE_FILE: &str = "%SystemRoot%\\Microsoft.NET\\Framework64\\v4.0.30319\\EventLogMessages.dll";
+
+        let subkey = HSTRING::from(format!("SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application\\{}", EVENT_SOURCE));
+        let mut key = HKEY::default();
+        // SAFETY: `key` is only used after a successful open or create and is closed on every path
+        unsafe {
+            if RegOpenKeyExW(HKEY_LOCAL_MACHINE, &subkey, 0, KEY_READ, &mut key).is_ok() {
+                let _ = RegCloseKey(key);
+                return Ok(());
+            }
+            match RegCreateKeyExW(HKEY_LOCAL_MACHINE, &subkey, 0, PCWSTR::null(), REG_OPTION_NON_VOLATILE, KEY_SET_VALUE, None, &mut key, None) {
+                ERROR_ACCESS_DENIED => anyhow::bail!("the {} event source isn't registered yet; run once as Administrator with --eventlog to register it", EVENT_SOURCE),
+                error => error.ok().context("Failed to register the event source")?,
             }
+            let message_file: Vec<u8> = MESSAGE_FILE.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect();
+            let written = RegSetValueExW(key, w!("EventMessageFile"), 0, REG_EXPAND_SZ, Some(&message_file)).ok()
+                .and_then(|()| RegSetValueExW(key, w!("TypesSupported"), 0, REG_DWORD, Some(&7u32.to_le_bytes())).ok());
+            let _ = RegCloseKey(key);
+            written.context("Failed to register the event source")
         }
+    }
 
-        // Export to CSV if requested
-        if let Some(csv_path) = output {
-            Self::export_scan_csv(&parsed_files, csv_path)?;
+    /// Show a toast like "Driver backup complete: 54 exported, 2 failed" with the folder.
+    /// Where toasts aren't available (Server Core, old builds) or the notification platform
+    /// doesn't answer within a few seconds, the console bell and message stand in.
+    fn show_notification(summary: &RunSummary) {
+        const TOAST_TIMEOUT: Duration = Duration::from_secs(5);
+        let (action, done) = match summary.command {
+            "backup" => ("Driver backup", "exported"),
+            "restore" => ("Driver restore", "installed"),
+            _ => ("Driver export", "exported"),
+        };
+        let (title, text) = match (summary.stats, &summary.error) {
+            (Some(stats), _) => (
+                format!("{} complete: {} {}, {} failed", action, stats.succeeded, done, stats.failed),
+                stats.location.as_ref().map(|location| location.display().to_string()).unwrap_or_default(),
+            ),
+            (None, error) => (format!("{} failed", action), error.clone().unwrap_or_default()),
+        };
+
+        // The toast runs on its own thread, which is left behind if the notification platform hangs
+        let (sender, receiver) = mpsc::channel();
+        let toast = (title.clone(), text.clone());
+        std::thread::spawn(move || {
+            let _ = sender.send(Self::show_toast(&toast.0, &toast.1));
+        });
+        let shown = matches!(receiver.recv_timeout(TOAST_TIMEOUT), Ok(Ok(())));
+        if !shown {
+            println!("\x07{}", title);
+            if !text.is_empty() {
+                println!("  {}", text);
+            }
         }
+    }
 
-        Ok(())
+    /// Show a two-line toast through the WinRT `ToastNotificationManager`
+    fn show_toast(title: &str, text: &str) -> windows::core::Result<()> {
+        use windows::core::HSTRING;
+        use windows::UI::Notifications::{ToastNotification, ToastNotificationManager, ToastTemplateType};
+        // PowerShell's own app ID, since this tool has no Start menu shortcut to register one
+        const APP_ID: &str = "{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\\WindowsPowerShell\\v1.0\\powershell.exe";
+
+        let xml = ToastNotificationManager::GetTemplateContent(ToastTemplateType::ToastText02)?;
+        let lines = xml.GetElementsByTagName(&HSTRING::from("text"))?;
+        lines.Item(0)?.AppendChild(&xml.CreateTextNode(&HSTRING::from(title))?)?;
+        lines.Item(1)?.AppendChild(&xml.CreateTextNode(&HSTRING::from(text))?)?;
+        let toast = ToastNotification::CreateToastNotification(&xml)?;
+        ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(APP_ID))?.Show(&toast)
     }
 
-    /// Display scan results as a simple list
-    fn display_scan_list(parsed_files: &[ParsedInfFile], verbose: bool) {
-        println!("----------------------------------------");
-        println!("INF Files Summary:");
-        println!("----------------------------------------");
-        
-        for (idx, parsed) in parsed_files.iter().enumerate() {
-            println!("\n{}. {}", idx + 1, parsed.file_name);
-            
-            if let Some(ref class) = parsed.raw_version_info.class {
-                println!("   Class: {}", class);
+    /// POST with `--hook-timeout`, retrying once after a network error or a non-2xx answer
+    fn post_summary(&self, url: &str, json: &[u8]) -> Result<()> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(self.hook_timeout))
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        let mut last_error = None;
+        for attempt in 1..=2 {
+            if attempt > 1 {
+                std::thread::sleep(Duration::from_secs(2));
             }
-            if let Some(ref version) = parsed.raw_version_info.driver_version {
-                println!("   Version: {}", version);
-            }
-            if let Some(ref date) = parsed.raw_version_info.driver_date {
-                println!("   Date: {}", date);
-            }
-            if let Some(ref provider) = parsed.raw_version_info.provider {
-                // Resolve provider string if it's a reference
-                let provider_display = if provider.starts_with('%') && provider.ends_with('%') {
-                    // Try to find in first driver's manufacturer or use as-is
-                    parsed.drivers.first()
-                        .and_then(|d| d.driver_provider_name.as_ref())
-                        .map(|s| s.as_str())
-                        .unwrap_or(provider)
-                } else {
-                    provider
-                };
-                println!("   Provider: {}", provider_display);
+            let response = client.post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(json.to_vec())
+                .send();
+            match response {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => last_error = Some(anyhow::anyhow!("server answered {}", response.status())),
+                Err(e) => last_error = Some(anyhow::Error::new(e)),
             }
-            println!("   Devices: {} entries", parsed.drivers.len());
+        }
+        Err(last_error.expect("set on failure")).context("gave up after 2 attempts")
+    }
 
-            if verbose && !parsed.drivers.is_empty() {
-                println!("   Hardware IDs:");
-                for driver in &parsed.drivers {
-                    if let Some(ref hwid) = driver.hardware_id {
-                        let device_name = driver.device_name.as_deref().unwrap_or("Unknown");
-                        println!("     - {} ({})", hwid, device_name);
-                    }
+    fn run_command(program: &Path, json: &[u8], success: bool) -> Result<()> {
+        use std::io::Write;
+        let mut child = Command::new(program)
+            .env("DRIVER_BACKUP_EXIT_STATUS", if success { "0" } else { "1" })
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start {}", program.display()))?;
+        // A hook that doesn't read stdin closes the pipe early; that's not its failure
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Err(e) = stdin.write_all(json) {
+                if e.kind() != std::io::ErrorKind::BrokenPipe {
+                    return Err(e).context("Failed to write the summary to the hook's stdin");
                 }
             }
         }
+        let status = child.wait().with_context(|| format!("Failed to wait for {}", program.display()))?;
+        if !status.success() {
+            anyhow::bail!("{} exited with {}", program.display(), status);
+        }
+        Ok(())
     }
+}
 
-    /// Display scan results grouped by device class
-    fn display_scan_grouped(parsed_files: &[ParsedInfFile], verbose: bool) {
-        // Group by device class
-        let mut by_class: HashMap<String, Vec<&ParsedInfFile>> = HashMap::new();
-        
-        for parsed in parsed_files {
-            let class = parsed.raw_version_info.class
-                .as_deref()
-                .unwrap_or("Unknown")
-                .to_string();
-            by_class.entry(class).or_default().push(parsed);
-        }
+/// `--on-complete-url` must be https://, since the summary names the machine
+fn parse_hook_url(value: &str) -> std::result::Result<String, String> {
+    let url = reqwest::Url::parse(value).map_err(|e| format!("invalid URL: {}", e))?;
+    if url.scheme() != "https" {
+        return Err("only https:// URLs are accepted".to_string());
+    }
+    Ok(value.to_string())
+}
 
-        // Sort classes
-        let mut classes: Vec<_> = by_class.keys().cloned().collect();
-        classes.sort();
+#[derive(Subcommand, Clone)]
+enum Commands {
+    /// Export all non-Microsoft drivers from the system (requires Administrator)
+    Backup {
+        /// Output directory for backup; {hostname}, {year}, {month} and {day} are filled in
+        #[arg(short, long, default_value = "driver_backup")]
+        output: PathBuf,
 
-        println!("----------------------------------------");
-        println!("INF Files by Device Class:");
-        println!("----------------------------------------");
+        /// Enable verbose output (-vv also logs stage timings; see RUST_LOG)
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
 
-        for class in classes {
-            if let Some(files) = by_class.get(&class) {
-                println!("\n[{}] ({} INF files)", class, files.len());
-                
-                for parsed in files {
-                    let version = parsed.raw_version_info.driver_version
-                        .as_deref()
-                        .unwrap_or("?");
-                    let devices = parsed.drivers.len();
-                    
-                    println!("  - {} (v{}, {} devices)", parsed.file_name, version, devices);
-                    
-                    if verbose {
-                        for driver in &parsed.drivers {
-                            if let Some(ref hwid) = driver.hardware_id {
-                                println!("      HWID: {}", hwid);
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
+        /// Preview operations without actually exporting drivers
+        #[arg(short, long)]
+        dry_run: bool,
 
-    /// Export scan results to CSV
-    fn export_scan_csv(parsed_files: &[ParsedInfFile], output_path: &Path) -> Result<()> {
-        let mut csv_content = String::new();
-        
-        // CSV Header - summary format with device names
-        csv_content.push_str("INF File,Device Class,Provider,Driver Version,Driver Date,Device Count,Device Names,Hardware IDs\n");
-        
-        let escape_csv = |s: &str| -> String {
-            if s.contains(',') || s.contains('"') || s.contains('\n') {
-                format!("\"{}\"", s.replace('"', "\"\""))
-            } else {
-                s.to_string()
-            }
-        };
+        /// Only back up drivers for devices on this bus
+        #[arg(long, value_enum)]
+        bus: Option<Bus>,
 
-        for parsed in parsed_files {
-            // Collect device names
-            let device_names: Vec<String> = parsed.drivers
-                .iter()
-                .filter_map(|d| d.device_name.clone())
-                .collect();
-            let device_names_str = device_names.join("; ");
+        /// Only back up drivers of devices whose name contains TEXT (case-insensitive, tolerates typos)
+        #[arg(long, value_name = "TEXT")]
+        match_device: Option<String>,
 
-            // Collect hardware IDs
-            let hwids: Vec<String> = parsed.drivers
-                .iter()
-                .filter_map(|d| d.hardware_id.clone())
-                .collect();
-            let hwids_str = hwids.join("; ");
+        /// Give up if WMI doesn't answer the driver query within this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        wmi_timeout: Option<u64>,
 
-            // Resolve provider - try to get from parsed drivers first
-            let provider = parsed.raw_version_info.provider.as_deref().unwrap_or("Unknown");
-            let resolved_provider = if provider.starts_with('%') && provider.ends_with('%') {
-                // Get resolved provider from first driver
-                parsed.drivers.first()
-                    .and_then(|d| d.driver_provider_name.as_deref())
-                    .unwrap_or(provider)
-            } else {
-                provider
-            };
+        /// WQL condition appended as `WHERE <EXPR>` to the driver query, passed to WMI verbatim
+        #[arg(long, value_name = "EXPR", value_parser = parse_wql_where)]
+        wql_where: Option<String>,
 
-            csv_content.push_str(&format!(
-                "{},{},{},{},{},{},{},{}\n",
-                escape_csv(&parsed.file_name),
-                escape_csv(parsed.raw_version_info.class.as_deref().unwrap_or("Unknown")),
-                escape_csv(resolved_provider),
-                escape_csv(parsed.raw_version_info.driver_version.as_deref().unwrap_or("Unknown")),
-                escape_csv(parsed.raw_version_info.driver_date.as_deref().unwrap_or("Unknown")),
-                parsed.drivers.len(),
-                escape_csv(&device_names_str),
-                escape_csv(&hwids_str),
-            ));
-        }
+        /// Skip third-party USB/System/HIDClass/HDC drivers identical to an inbox driver
+        #[arg(long)]
+        exclude_generic: bool,
 
-        fs::write(output_path, csv_content)
-            .with_context(|| format!("Failed to write CSV file: {}", output_path.display()))?;
+        /// Delete the partial backup folder if the run is interrupted with Ctrl-C
+        #[arg(long)]
+        clean_on_abort: bool,
 
-        println!("\nExported to: {}", output_path.display());
-        Ok(())
-    }
+        /// Replace identical files across packages with hardlinks to save space
+        #[arg(long)]
+        dedup_files: bool,
 
-    /// Scan backup folder recursively and export summary CSV (used by backup command)
-    fn scan_and_export(backup_dir: &Path, output_csv: &Path, verbose: bool) -> Result<()> {
-        // Find all INF files recursively in the backup folder
-        let inf_files = Self::find_inf_files(backup_dir)?;
+        /// Hardlink files identical to the newest earlier backup in the output folder to that copy
+        #[arg(long)]
+        dedupe_against_previous: bool,
 
-        if inf_files.is_empty() {
-            println!("No INF files found in backup folder.");
-            return Ok(());
-        }
+        /// Only export packages that are new or changed since BASE_DIR, an earlier full backup
+        /// (restore the result with restore --base BASE_DIR --diff <this backup>)
+        #[arg(long, value_name = "BASE_DIR")]
+        diff_base: Option<PathBuf>,
 
-        if verbose {
-            println!("Found {} INF files in backup", inf_files.len());
-        }
+        /// Use local time instead of UTC for the folder name and the recorded creation time
+        #[arg(long)]
+        local_time: bool,
 
-        // Parse all INF files
-        let mut parsed_files: Vec<ParsedInfFile> = Vec::new();
-        for inf_path in &inf_files {
-            match Self::parse_inf_file(inf_path) {
-                Ok(parsed) => parsed_files.push(parsed),
-                Err(e) => {
-                    if verbose {
-                        eprintln!("Warning: Failed to parse {}: {}", inf_path.display(), e);
-                    }
-                }
-            }
-        }
+        /// Leave the BIOS serial number out of the backup records
+        #[arg(long)]
+        no_serial: bool,
 
-        if parsed_files.is_empty() {
-            println!("No valid INF files parsed.");
-            return Ok(());
-        }
+        /// Also copy printer drivers from the spooler into PrinterDrivers\ with an install script
+        #[arg(long)]
+        printers: bool,
 
-        // Export to CSV with folder name
-        Self::export_backup_summary_csv(&parsed_files, backup_dir, output_csv)?;
+        /// Keep only the newest package per class, provider and base hardware ID
+        #[arg(long)]
+        latest_only: bool,
 
-        println!("Summary CSV created: {}", output_csv.display());
-        println!("Total INF files: {}", parsed_files.len());
-        
-        let total_devices: usize = parsed_files.iter().map(|f| f.drivers.len()).sum();
-        println!("Total device entries: {}", total_devices);
+        /// Also compress each package into its own <package>.zip (with a one-row CSV) in the class folder
+        #[arg(long)]
+        zip_per_package: bool,
 
-        Ok(())
-    }
+        /// Parse each exported INF and check its version, provider and class against WMI
+        #[arg(long)]
+        verify_after_export: bool,
 
-    /// Export backup summary to CSV with relative folder paths
-    fn export_backup_summary_csv(parsed_files: &[ParsedInfFile], backup_dir: &Path, output_path: &Path) -> Result<()> {
-        let mut csv_content = String::new();
-        
-        // CSV Header - includes Folder Name for backup
-        csv_content.push_str("INF File,Device Class,Provider,Driver Version,Driver Date,Device Count,Folder Name,Device Names,Hardware IDs\n");
-        
-        let escape_csv = |s: &str| -> String {
-            if s.contains(',') || s.contains('"') || s.contains('\n') {
-                format!("\"{}\"", s.replace('"', "\"\""))
-            } else {
-                s.to_string()
-            }
-        };
+        /// Also `reg export` the Class and Enum registry keys of the backed-up drivers into registry\\
+        #[arg(long)]
+        export_registry: bool,
 
-        for parsed in parsed_files {
-            // Collect device names
-            let device_names: Vec<String> = parsed.drivers
-                .iter()
-                .filter_map(|d| d.device_name.clone())
-                .collect();
-            let device_names_str = device_names.join("; ");
+        /// Don't write all_drivers.csv; with --no-readme the exported INFs aren't re-parsed at all
+        #[arg(long)]
+        no_summary_csv: bool,
 
-            // Collect hardware IDs
-            let hwids: Vec<String> = parsed.drivers
-                .iter()
-                .filter_map(|d| d.hardware_id.clone())
-                .collect();
-            let hwids_str = hwids.join("; ");
+        /// Don't write a README.txt listing the packages into each class folder
+        #[arg(long)]
+        no_readme: bool,
 
-            // Resolve provider
-            let provider = parsed.raw_version_info.provider.as_deref().unwrap_or("Unknown");
-            let resolved_provider = if provider.starts_with('%') && provider.ends_with('%') {
-                parsed.drivers.first()
-                    .and_then(|d| d.driver_provider_name.as_deref())
-                    .unwrap_or(provider)
-            } else {
-                provider
-            };
+        /// Only rebuild all_drivers.csv and the class READMEs of the existing backup BACKUP_DIR; nothing is exported
+        #[arg(long, value_name = "BACKUP_DIR", conflicts_with_all = ["no_summary_csv", "dry_run"])]
+        summary_csv_only: Option<PathBuf>,
 
-            // Get relative folder path from backup_dir
-            let folder_name = parsed.file_path.parent()
-                .and_then(|p| p.strip_prefix(backup_dir).ok())
-                .map(|p| p.to_string_lossy().to_string())
-                .unwrap_or_else(|| "Unknown".to_string());
+        /// Also write a standalone install script (install_drivers.cmd/.ps1) into the backup
+        #[arg(long, value_enum, value_name = "KIND")]
+        gen_install_script: Option<ScriptKind>,
 
-            csv_content.push_str(&format!(
-                "{},{},{},{},{},{},{},{},{}\n",
-                escape_csv(&parsed.file_name),
-                escape_csv(parsed.raw_version_info.class.as_deref().unwrap_or("Unknown")),
-                escape_csv(resolved_provider),
-                escape_csv(parsed.raw_version_info.driver_version.as_deref().unwrap_or("Unknown")),
-                escape_csv(parsed.raw_version_info.driver_date.as_deref().unwrap_or("Unknown")),
-                parsed.drivers.len(),
-                escape_csv(&folder_name),
-                escape_csv(&device_names_str),
-                escape_csv(&hwids_str),
-            ));
-        }
+        /// File of `from=to` lines renaming device class folders (e.g. SCSIAdapter=Storage)
+        #[arg(long, value_name = "FILE")]
+        class_map: Option<PathBuf>,
 
-        fs::write(output_path, csv_content)
-            .with_context(|| format!("Failed to write CSV file: {}", output_path.display()))?;
+        /// Write the exported package folders to this file (.xml for an unattend DriverPaths block)
+        #[arg(long, value_name = "PATH")]
+        deploy_list: Option<PathBuf>,
 
-        Ok(())
-    }
-}
+        /// Write an autounattend.xml PnpCustomizationsWinPE component listing every package folder
+        #[arg(long, value_name = "FILE")]
+        unattend_snippet: Option<PathBuf>,
 
-// Add CLI arguments for backup functionality
-#[derive(Parser)]
-#[command(name = "driver-backup")]
-#[command(version = "2.3")]
-#[command(about = "A tool to backup, inspect, and manage non-Microsoft drivers")]
-#[command(long_about = "Driver Backup Tool v2.3\n\n\
-    Commands:\n  \
-    backup   - Export all non-Microsoft drivers from the system (requires Admin)\n  \
-    inspect  - Extract driver info from installer packages (.exe, .zip, .7z, folder)\n  \
-    scan     - Identify and list all INF files in a folder\n\n\
-    Examples:\n  \
-    driver-backup backup -o D:\\Backup -v\n  \
-    driver-backup inspect -p C:\\Downloads\\driver.exe -o info.csv\n  \
-    driver-backup scan -p C:\\Drivers -r -g -o inventory.csv")]
-struct Args {
-    #[command(subcommand)]
-    command: Option<Commands>,
-}
+        /// Driver paths in the unattend snippet start with PREFIX (e.g. D:\\Drivers) instead of this backup's folder
+        #[arg(long, value_name = "PREFIX", requires = "unattend_snippet")]
+        path_prefix: Option<String>,
 
-#[derive(Subcommand)]
-enum Commands {
-    /// Export all non-Microsoft drivers from the system (requires Administrator)
-    Backup {
-        /// Output directory for backup
-        #[arg(short, long, default_value = "driver_backup")]
-        output: PathBuf,
+        /// Also copy the packages into DIR as flat ASCII-named folders for `dism /Add-Driver /Recurse`
+        #[arg(long, value_name = "DIR")]
+        for_dism: Option<PathBuf>,
+
+        /// After the backup, copy it into DEST (folder or \\server\share) and verify every file by SHA-256
+        #[arg(long, value_name = "DEST")]
+        mirror: Option<PathBuf>,
+
+        /// Render a Handlebars report into the backup folder (built-in Markdown layout without FILE)
+        #[arg(long, value_name = "FILE", num_args = 0..=1)]
+        report_template: Option<Option<PathBuf>>,
+
+        /// Export up to N driver packages of a device class at the same time
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..))]
+        jobs: u8,
+
+        /// What to do when two packages map to the same folder name (by-class layout; flat
+        /// packages with the same name share their folder)
+        #[arg(long, value_enum, value_name = "STRATEGY", default_value = "rename")]
+        on_collision: CollisionStrategy,
+
+        /// Folder layout of the exported packages
+        #[arg(long, value_enum, default_value = "by-class")]
+        layout: PackageLayout,
+
+        /// Make every Nth pnputil export report a synthetic failure (test builds only)
+        #[cfg(feature = "testhooks")]
+        #[arg(long, value_name = "N")]
+        simulate_failure: Option<usize>,
+    },
+    /// Check the environment: elevation, pnputil, 7-Zip, PowerShell, WMI and the temp folder
+    Doctor,
+    /// Install the drivers of a backup, saving the drivers they replace for --rollback
+    Restore {
+        /// Backup folder to install (a drivers_<timestamp> folder)
+        #[arg(short, long, required_unless_present_any = ["rollback", "diff"], conflicts_with = "rollback")]
+        path: Option<PathBuf>,
+
+        /// Full backup a --diff backup was made against
+        #[arg(long, value_name = "DIR", requires = "diff", conflicts_with_all = ["path", "rollback"])]
+        base: Option<PathBuf>,
+
+        /// Backup made with backup --diff-base, installed together with the --base packages it still needs
+        #[arg(long, value_name = "DIR", requires = "base", conflicts_with_all = ["path", "rollback"])]
+        diff: Option<PathBuf>,
+
+        /// Undo an earlier restore from its pre_restore_snapshot_<timestamp> folder
+        #[arg(long, value_name = "SNAPSHOT_DIR")]
+        rollback: Option<PathBuf>,
+
+        /// Don't export the drivers being replaced before installing
+        #[arg(long, conflicts_with = "rollback")]
+        no_snapshot: bool,
+
+        /// Install packages for ARCH (x86, amd64, arm64, ...) instead of this machine's architecture
+        #[arg(long, value_enum, value_name = "ARCH", conflicts_with = "rollback")]
+        force_arch: Option<Architecture>,
+
+        /// Don't create a System Restore point before changing drivers
+        #[arg(long, conflicts_with = "require_restore_point")]
+        no_restore_point: bool,
+
+        /// Stop without changing anything if no System Restore point can be created
+        #[arg(long)]
+        require_restore_point: bool,
+
+        /// Show what would change without installing anything
+        #[arg(short, long)]
+        dry_run: bool,
 
-        /// Enable verbose output with detailed logging
+        /// List every snapshotted package
         #[arg(short, long)]
         verbose: bool,
+    },
+    /// Browse a backup in a terminal view: classes, packages, search, INF details, copy and extract
+    Browse {
+        /// Backup folder to browse (a drivers_<timestamp> folder)
+        #[arg(short, long)]
+        path: PathBuf,
+    },
+    /// Delete old `drivers_<timestamp>` backups, keeping the newest or the recent ones
+    Prune {
+        /// Folder containing the backups (the backup command's --output)
+        #[arg(long, default_value = "driver_backup")]
+        root: PathBuf,
 
-        /// Preview operations without actually exporting drivers
+        /// Keep this many of the newest backups
+        #[arg(long, value_name = "N")]
+        keep_last: Option<usize>,
+
+        /// Delete backups older than this many days
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u64>,
+
+        /// Also delete base backups that kept --diff-base backups still need
+        #[arg(long)]
+        force: bool,
+
+        /// List the backups that would be deleted without deleting them
         #[arg(short, long)]
         dry_run: bool,
     },
     /// Extract driver information from installer package (.exe, .zip, .7z) or folder
     Inspect {
-        /// Path to driver installer (.exe, .zip, .7z, .rar) or folder containing INF files
+        /// Paths or https:// URLs of driver installers (.exe, .zip, .cab, .msi, .7z, .rar), or folders containing INF files
+        #[arg(short, long, num_args = 1.., required = true)]
+        path: Vec<PathBuf>,
+
+        /// Save a package downloaded from a URL in the current folder instead of deleting it
+        #[arg(long)]
+        keep_download: bool,
+
+        /// Allow downloading packages over plain http://
+        #[arg(long)]
+        allow_http: bool,
+
+        /// Compare the package against installed drivers (newer / older / equal / not present)
+        #[arg(long)]
+        compare_system: bool,
+
+        /// Only list installed devices the package(s) have a newer driver for (an update check)
+        #[arg(long)]
+        only_updatable: bool,
+
+        /// Compare as if this machine had ARCH instead of detecting it
+        #[arg(long, value_enum, value_name = "ARCH")]
+        force_arch: Option<Architecture>,
+
+        /// Print every INF's sections and lines as the parser reads them (to debug INFs with no devices)
+        #[arg(long)]
+        dump_sections: bool,
+
+        /// Keep the temporary extraction folder when no INF files are found or parsed
+        #[arg(long)]
+        keep_temp_on_error: bool,
+
+        /// List every extracted file with its size; with --output also writes <name>_files.csv
+        #[arg(long)]
+        list_files: bool,
+
+        /// Print the --list-files output as a flat list instead of a tree
+        #[arg(long, requires = "list_files")]
+        flat: bool,
+
+        #[command(flatten)]
+        filter: InfFilter,
+
+        /// Export results to CSV file
         #[arg(short, long)]
-        path: PathBuf,
+        output: Option<PathBuf>,
+
+        /// Show detailed output including all device entries
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Keep the extracted payload (optionally in DIR) instead of deleting it
+        #[arg(long, value_name = "DIR", num_args = 0..=1)]
+        keep_extracted: Option<Option<PathBuf>>,
+
+        /// Also extract archives/installers nested inside the package
+        #[arg(long)]
+        recurse_archives: bool,
+
+        /// Maximum nesting depth for --recurse-archives
+        #[arg(long, value_name = "N", default_value_t = 3)]
+        archive_depth: usize,
+
+        /// Abort if the extracted package grows beyond this many megabytes
+        #[arg(long, value_name = "MB", default_value_t = 4096)]
+        max_extracted_mb: u64,
+
+        /// Skip INF files larger than this many megabytes as malformed
+        #[arg(long, value_name = "MB", default_value_t = 8)]
+        max_inf_size: u64,
+
+        /// Language ID whose [Strings.LANGID] table names devices, before the neutral [Strings]
+        #[arg(long, value_name = "LANGID", default_value = "0409", value_parser = parse_locale)]
+        locale: String,
+
+        /// CSV field separator (a single character, or `tab`)
+        #[arg(long, default_value = ",", value_parser = parse_delimiter)]
+        delimiter: char,
+
+        /// Comma-separated CSV columns to write, in order (e.g. device_name,hardware_id,driver_version)
+        #[arg(long, value_name = "COLUMNS", value_delimiter = ',')]
+        csv_columns: Option<Vec<String>>,
+
+        /// Path to 7z.exe, tried before the 7-Zip found in PATH, Program Files or the registry
+        #[arg(long, value_name = "PATH")]
+        seven_zip: Option<PathBuf>,
+    },
+    /// Scan a folder to identify and list all INF files with summary
+    Scan {
+        /// Folders containing INF files
+        #[arg(short, long, num_args = 1.., required_unless_present = "index", conflicts_with = "index")]
+        path: Vec<PathBuf>,
+
+        /// Read results from an index written by --write-index instead of scanning folders
+        #[arg(long, value_name = "FILE")]
+        index: Option<PathBuf>,
+
+        /// Save all parsed results to an index file for fast lookups later
+        #[arg(long, value_name = "FILE")]
+        write_index: Option<PathBuf>,
+
+        /// Store a SHA-256 hash of each INF in the --write-index file
+        #[arg(long, requires = "write_index")]
+        index_hashes: bool,
 
         /// Export results to CSV file
         #[arg(short, long)]
         output: Option<PathBuf>,
 
-        /// Show detailed output including all device entries
-        #[arg(short, long)]
-        verbose: bool,
-    },
-    /// Scan a folder to identify and list all INF files with summary
-    Scan {
-        /// Path to folder containing INF files
-        #[arg(short, long)]
-        path: PathBuf,
+        /// Show detailed information including all Hardware IDs
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Group results by device class (Display, Net, Media, etc.) or by provider
+        #[arg(short, long, value_enum, value_name = "BY", num_args = 0..=1, default_missing_value = "class")]
+        group: Option<GroupBy>,
+
+        /// Fold groups with fewer than N INF files into a single "Other" group
+        #[arg(long, value_name = "N", default_value_t = 1, requires = "group")]
+        group_threshold: usize,
+
+        /// Order results (list, groups and CSV) by this field
+        #[arg(long, value_enum, value_name = "KEY")]
+        sort: Option<SortKey>,
+
+        /// Reverse the --sort order
+        #[arg(long, requires = "sort")]
+        desc: bool,
+
+        /// Include all subfolders in scan (recursive)
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Only list device entries on this bus
+        #[arg(long, value_enum)]
+        bus: Option<Bus>,
+
+        #[command(flatten)]
+        filter: InfFilter,
+
+        /// Report INFs present more than once (same file name or shared hardware IDs)
+        #[arg(long)]
+        duplicates: bool,
+
+        /// Write the duplicate groups to a CSV file
+        #[arg(long, value_name = "CSV")]
+        duplicates_csv: Option<PathBuf>,
+
+        /// Compare with another folder by INF name; exits with code 2 when they differ
+        #[arg(long, value_name = "FOLDER")]
+        diff: Option<PathBuf>,
+
+        /// Write the --diff results to a CSV file
+        #[arg(long, value_name = "CSV", requires = "diff")]
+        diff_csv: Option<PathBuf>,
+
+        /// Follow symlinks and junctions while scanning recursively
+        #[arg(long)]
+        follow_links: bool,
+
+        /// Maximum folder depth for recursive scans
+        #[arg(long, value_name = "N")]
+        max_depth: Option<usize>,
+
+        /// Skip folders matching this glob (name or full path); can be repeated
+        #[arg(long, value_name = "GLOB")]
+        exclude_dir: Vec<String>,
+
+        /// Parse cache file (default: %LOCALAPPDATA%\driver-backup\scan-cache.json)
+        #[arg(long, value_name = "FILE", conflicts_with = "no_cache")]
+        cache: Option<PathBuf>,
+
+        /// Re-parse every INF without reading or writing the parse cache
+        #[arg(long)]
+        no_cache: bool,
+
+        /// File of `from=to` lines renaming device classes when grouping (-g)
+        #[arg(long, value_name = "FILE")]
+        class_map: Option<PathBuf>,
+
+        /// Skip INF files larger than this many megabytes as malformed
+        #[arg(long, value_name = "MB", default_value_t = 8)]
+        max_inf_size: u64,
+
+        /// Language ID whose [Strings.LANGID] table names devices, before the neutral [Strings]
+        #[arg(long, value_name = "LANGID", default_value = "0409", value_parser = parse_locale)]
+        locale: String,
 
-        /// Export results to CSV file
-        #[arg(short, long)]
-        output: Option<PathBuf>,
+        /// CSV field separator (a single character, or `tab`)
+        #[arg(long, default_value = ",", value_parser = parse_delimiter)]
+        delimiter: char,
 
-        /// Show detailed information including all Hardware IDs
-        #[arg(short, long)]
-        verbose: bool,
+        /// Comma-separated CSV columns to write, in order (e.g. device_name,hardware_id,driver_version)
+        #[arg(long, value_name = "COLUMNS", value_delimiter = ',')]
+        csv_columns: Option<Vec<String>>,
 
-        /// Group results by device class (Display, Net, Media, etc.)
-        #[arg(short, long)]
-        group: bool,
+        /// Keep running and rewrite the --output CSV whenever the folders change (Ctrl-C to stop)
+        #[arg(long, requires = "output", conflicts_with_all = ["index", "diff"])]
+        watch: bool,
 
-        /// Include all subfolders in scan (recursive)
-        #[arg(short, long)]
-        recursive: bool,
+        /// Also write one JSON object per device entry to PATH as JSON Lines (`-` for stdout)
+        #[arg(long, value_name = "PATH", conflicts_with = "watch")]
+        ndjson: Option<PathBuf>,
     },
     /// Export connected device hardware IDs to CSV (no driver backup, just inventory)
     Export {
@@ -1536,26 +9567,155 @@ enum Commands {
         #[arg(short, long)]
         all: bool,
 
-        /// Show detailed output
-        #[arg(short, long)]
-        verbose: bool,
+        /// Only list devices whose name contains TEXT (case-insensitive, tolerates typos)
+        #[arg(long, value_name = "TEXT")]
+        match_device: Option<String>,
+
+        /// Show detailed output (-vv also logs stage timings; see RUST_LOG)
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
 
         /// Also export driver files (like backup command)
         #[arg(short, long)]
         files: bool,
+
+        /// Folder layout of the --files export
+        #[arg(long, value_enum, default_value = "flat")]
+        layout: PackageLayout,
+
+        /// Give up if WMI doesn't answer the driver query within this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        wmi_timeout: Option<u64>,
+
+        /// WQL condition appended as `WHERE <EXPR>` to the driver query, passed to WMI verbatim
+        #[arg(long, value_name = "EXPR", value_parser = parse_wql_where)]
+        wql_where: Option<String>,
+
+        /// CSV field separator (a single character, or `tab`)
+        #[arg(long, default_value = ",", value_parser = parse_delimiter)]
+        delimiter: char,
+
+        /// Add Present, Status and Problem Code columns from Win32_PnPEntity
+        #[arg(long)]
+        status: bool,
+
+        /// Add File Version and Version Mismatch columns read from the DriverStore binaries (slow)
+        #[arg(long)]
+        file_versions: bool,
+
+        /// Instead of the inventory, report drivers older than AGE (e.g. 90d, 6m, 3y)
+        #[arg(long, value_name = "AGE", value_parser = parse_driver_age, conflicts_with = "files")]
+        stale: Option<DriverAge>,
+
+        /// Write the --stale report to this CSV
+        #[arg(long, value_name = "PATH", requires = "stale")]
+        stale_csv: Option<PathBuf>,
+
+        /// Also list printer drivers (Win32_PrinterDriver) in a separate section of the CSV
+        #[arg(long)]
+        printers: bool,
+
+        /// Flag collections owning kernel services (Win32_SystemDriver) and list them after the inventory
+        #[arg(long)]
+        services: bool,
+
+        /// Group collections on the numeric version only (31.0.101.2111_whql -> 31.0.101.2111)
+        #[arg(long)]
+        strip_version_suffix: bool,
+
+        /// Search Windows Update (read-only, slow, needs network) and flag devices it has a newer driver for
+        #[arg(long, conflicts_with = "stale")]
+        compare_to_online: bool,
+
+        /// Inventory this remote host over WMI instead of the local machine (repeatable)
+        #[arg(long, value_name = "HOST", conflicts_with_all = ["status", "file_versions", "stale", "printers", "services", "compare_to_online"])]
+        computer: Vec<String>,
+
+        /// Connect to the --computer hosts as this user (DOMAIN\user)
+        #[arg(long, value_name = "USER", requires = "computer", requires = "password_prompt")]
+        username: Option<String>,
+
+        /// Ask for the --username password on the console
+        #[arg(long, requires = "username")]
+        password_prompt: bool,
+
+        /// Write one CSV per --computer host (<output>_<host>.csv) instead of one combined file
+        #[arg(long, requires = "computer")]
+        per_host: bool,
+
+        /// Also write one JSON object per driver to PATH as JSON Lines (`-` for stdout)
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["computer", "stale"])]
+        ndjson: Option<PathBuf>,
     },
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    let elevate = (args.elevate || args.command.is_none()) && !args.elevated;
+    let hooks = args.hooks.clone();
+    if let Some(style) = args.paths {
+        PATH_STYLE.set(style).expect("set once per run");
+    }
 
-    match args.command.unwrap_or(Commands::Backup {
-        output: PathBuf::from("driver_backup"),
-        verbose: false,
-        dry_run: false,
-    }) {
-        Commands::Backup { output, verbose, dry_run } => {
-            if verbose {
+    // Running without a subcommand behaves like a plain `backup` with its defaults
+    let command = args.command.unwrap_or_else(|| {
+        Args::parse_from(["driver-backup", "backup"])
+            .command
+            .expect("backup is a valid subcommand")
+    });
+
+    // First Ctrl-C lets the current step finish and cleans up; a second one quits immediately
+    if checks_interrupts(&command) {
+        ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+            eprintln!("\nInterrupted, stopping after the current step (press Ctrl-C again to quit now)...");
+        })
+        .context("Failed to install Ctrl-C handler")?;
+    }
+
+    // Stage timings are logged at debug level: shown with -vv, or controlled by RUST_LOG
+    let mut logger = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("off"));
+    if let Commands::Backup { verbose: 2.., .. } | Commands::Export { verbose: 2.., .. } = &command {
+        logger.filter_module(env!("CARGO_CRATE_NAME"), log::LevelFilter::Debug);
+    }
+    logger.init();
+
+    // `--output -` writes the CSV to stdout, so nothing else may be printed there
+    if let Commands::Inspect { output: Some(output), .. } | Commands::Scan { output: Some(output), .. } | Commands::Export { output, .. } = &command {
+        QUIET.store(is_stdout_path(output), Ordering::SeqCst);
+    }
+    if let Commands::Scan { ndjson: Some(ndjson), .. } | Commands::Export { ndjson: Some(ndjson), .. } = &command {
+        if is_stdout_path(ndjson) {
+            if quiet() {
+                anyhow::bail!("--output - and --ndjson - can't both write to stdout");
+            }
+            QUIET.store(true, Ordering::SeqCst);
+        }
+    }
+
+    if let Commands::Inspect { locale, .. } | Commands::Scan { locale, .. } = &command {
+        INF_LOCALE.set(locale.clone()).expect("set once per run");
+    }
+
+    DriverBackup::validate_output_paths(&command)?;
+    if args.refresh_lookup {
+        DriverBackup::refresh_inf_lookup();
+    }
+
+    // Non-zero codes that aren't errors, e.g. scan --diff finding differences
+    let mut exit_code = 0;
+
+    // export --files hands its own command to DriverBackup, which the match below takes apart
+    let export_files_command = matches!(command, Commands::Export { files: true, .. }).then(|| command.clone());
+
+    match command {
+        Commands::Backup { summary_csv_only: Some(backup_dir), no_readme, verbose, .. } => {
+            DriverBackup::regenerate_summary_csv(&backup_dir, no_readme, verbose > 0)?;
+        }
+        backup_command @ Commands::Backup { .. } => {
+            if let Commands::Backup { output, verbose: 1.., dry_run, .. } = &backup_command {
                 println!("Driver Export Tool");
                 println!("==================");
                 println!("Output directory: {}", output.display());
@@ -1563,159 +9723,997 @@ fn main() -> Result<()> {
                 println!();
             }
 
+            // Hand over to an elevated copy instead of failing the admin check below
+            if elevate && DriverBackup::check_admin_privileges().is_err() {
+                DriverBackup::relaunch_elevated()?;
+                return Ok(());
+            }
+
             // Create args for DriverBackup
             let backup_args = Args {
-                command: Some(Commands::Backup {
-                    output: output.clone(),
-                    verbose,
-                    dry_run,
-                })
+                command: Some(backup_command),
+                elevate: false,
+                elevated: false,
+                paths: args.paths,
+                refresh_lookup: args.refresh_lookup,
+                hooks: hooks.clone(),
             };
 
             // Initialize backup functionality
-            let backup = DriverBackup::new(backup_args)?;
-
-            // Run the backup process
-            tokio::runtime::Runtime::new()?.block_on(backup.run())?;
+            let started = Utc::now();
+            let result = DriverBackup::new(backup_args)
+                .and_then(|backup| tokio::runtime::Runtime::new()?.block_on(backup.run()));
+            hooks.notify("backup", started, &result);
+            if result?.partial_failure {
+                exit_code = EXIT_PARTIAL_FAILURE;
+            }
         }
-        Commands::Inspect { path, output, verbose } => {
+        Commands::Doctor => {
+            DriverBackup::doctor()?;
+        }
+        Commands::Restore { path, base, diff, rollback, no_snapshot, force_arch, no_restore_point, require_restore_point, dry_run, verbose } => {
+            if elevate && !dry_run && DriverBackup::check_admin_privileges().is_err() {
+                DriverBackup::relaunch_elevated()?;
+                return Ok(());
+            }
+            let options = RestoreOptions {
+                no_snapshot,
+                dry_run,
+                verbose,
+                restore_point: !no_restore_point,
+                require_restore_point,
+                force_arch,
+            };
+            let started = Utc::now();
+            let result = match (rollback, path, base, diff) {
+                (Some(snapshot_dir), ..) => DriverBackup::rollback_restore(&snapshot_dir, &options),
+                (None, Some(backup_dir), ..) => DriverBackup::restore_backup(&backup_dir, &[], &options),
+                (None, None, Some(base_dir), Some(diff_dir)) => DriverBackup::resolve_diff_chain(&base_dir, &diff_dir)
+                    .and_then(|base_packages| DriverBackup::restore_backup(&diff_dir, &base_packages, &options)),
+                _ => unreachable!("clap requires --path, --rollback or --base with --diff"),
+            };
+            hooks.notify("restore", started, &result);
+            result?;
+        }
+        Commands::Browse { path } => {
+            println!("Reading {}...", path.display());
+            Browser::load(&path)?.run()?;
+            // Back from the full-screen view; no "Press Enter" pause
+            return Ok(());
+        }
+        Commands::Prune { root, keep_last, older_than, force, dry_run } => {
+            DriverBackup::prune_backups(&root, keep_last, older_than, force, dry_run)?;
+        }
+        Commands::Inspect { path, output, verbose, keep_download, allow_http, compare_system, only_updatable, force_arch, keep_temp_on_error, list_files, flat, filter, keep_extracted, recurse_archives, archive_depth, max_extracted_mb, max_inf_size, delimiter, csv_columns, seven_zip, dump_sections, .. } => {
+            let verbose = verbose && !quiet();
             if verbose {
                 println!("Driver Package Inspector");
                 println!("========================");
-                println!("Input path: {}", path.display());
+                for input in &path {
+                    println!("Input path: {}", input.display());
+                }
                 if let Some(ref out) = output {
                     println!("Output CSV: {}", out.display());
                 }
                 println!();
             }
 
+            let options = InspectOptions {
+                output,
+                delimiter,
+                csv_columns: match csv_columns {
+                    Some(names) => Some(resolve_csv_columns(&names, &INSPECT_CSV_COLUMNS)?),
+                    None => default_csv_columns(&INSPECT_CSV_COLUMNS, path.len() > 1),
+                },
+                verbose,
+                keep_extracted,
+                archive_depth: if recurse_archives { archive_depth } else { 0 },
+                max_extracted_bytes: max_extracted_mb.saturating_mul(1024 * 1024),
+                max_inf_size: max_inf_size.saturating_mul(1024 * 1024),
+                keep_download,
+                allow_http,
+                compare_system,
+                force_arch,
+                only_updatable,
+                keep_temp_on_error,
+                list_files,
+                flat,
+                filter,
+                seven_zip,
+                dump_sections,
+            };
+
             // Run the inspect process
-            InfParser::inspect(&path, output.as_deref(), verbose)?;
+            InfParser::inspect(&path, &options)?;
         }
-        Commands::Scan { path, output, verbose, group, recursive } => {
+        Commands::Scan { path, index, write_index, index_hashes, output, verbose, group, group_threshold, sort, desc, recursive, bus, filter, duplicates, duplicates_csv, diff, diff_csv, follow_links, max_depth, exclude_dir, cache, no_cache, max_inf_size, class_map, delimiter, csv_columns, watch, ndjson, .. } => {
+            let verbose = verbose && !quiet();
             if verbose {
                 println!("INF Folder Scanner");
                 println!("==================");
-                println!("Folder: {}", path.display());
+                for folder in &path {
+                    println!("Folder: {}", folder.display());
+                }
                 if let Some(ref out) = output {
                     println!("Output CSV: {}", out.display());
                 }
-                println!("Group by class: {}", group);
+                match group {
+                    Some(group_by) => println!("Group by: {:?}", group_by),
+                    None => println!("Group by: none"),
+                }
                 println!("Recursive: {}", recursive);
                 println!();
             }
 
+            let options = ScanOptions {
+                output,
+                delimiter,
+                csv_columns: match csv_columns {
+                    Some(names) => Some(resolve_csv_columns(&names, &SCAN_CSV_COLUMNS)?),
+                    None => default_csv_columns(&SCAN_CSV_COLUMNS, path.len() > 1),
+                },
+                verbose,
+                group_by: group,
+                group_threshold,
+                sort,
+                descending: desc,
+                recursive,
+                walk: WalkOptions {
+                    follow_links,
+                    max_depth,
+                    exclude_dirs: exclude_dir.iter()
+                        .map(|pattern| glob::Pattern::new(pattern)
+                            .with_context(|| format!("Invalid --exclude-dir pattern: {}", pattern)))
+                        .collect::<Result<_>>()?,
+                },
+                bus,
+                cache_path: if no_cache { None } else { cache.or_else(ScanCache::default_path) },
+                max_inf_size: max_inf_size.saturating_mul(1024 * 1024),
+                class_map: class_map.as_deref().map(load_class_map).transpose()?.unwrap_or_default(),
+                filter,
+                duplicates,
+                duplicates_csv,
+                diff,
+                diff_csv,
+                index,
+                write_index,
+                index_hashes,
+                ndjson,
+            };
+
             // Run the scan process
-            InfParser::scan_folder(&path, output.as_deref(), verbose, group, recursive)?;
+            if watch {
+                InfParser::watch_folder(&path, &options)?;
+            } else if InfParser::scan_folder(&path, &options)? {
+                exit_code = 2;
+            }
+        }
+        Commands::Export { output, all, verbose, files, wmi_timeout, wql_where, delimiter, computer, username, per_host, strip_version_suffix, match_device, .. } if !computer.is_empty() => {
+            if files {
+                anyhow::bail!("--files can't be used with --computer: pnputil can only export drivers on the machine it runs on");
+            }
+            if per_host && quiet() {
+                anyhow::bail!("--output - cannot be combined with --per-host");
+            }
+            let verbose = verbose > 0 && !quiet();
+            if !quiet() {
+                println!("Remote Hardware Inventory Export");
+                println!("================================");
+            }
+            let credential = username.map(RemoteCredential::prompt).transpose()?;
+
+            // One failing host is reported and skipped; the others still get exported
+            let mut combined = CsvWriter::new(delimiter);
+            let mut results: Vec<(String, Result<(usize, usize)>)> = Vec::new();
+            for host in &computer {
+                if interrupted() {
+                    break;
+                }
+                if !quiet() {
+                    println!("\nQuerying {}...", host);
+                }
+                let result = DriverBackup::query_remote_signed_drivers(host, credential.as_ref(), wmi_timeout.map(Duration::from_secs), wql_where.as_deref())
+                    .and_then(|drivers| {
+                        let drivers = if all { drivers } else { DriverBackup::third_party_drivers(drivers) };
+                        let drivers = match &match_device {
+                            Some(query) => DriverBackup::filter_by_device_name(drivers, query),
+                            None => drivers,
+                        };
+                        // The local INF lookup and DriverStore don't describe another machine
+                        let enrichment = InventoryEnrichment {
+                            entities: HashMap::new(),
+                            status: false,
+                            file_versions: None,
+                            computer: Some(host.clone()),
+                            printers: None,
+                            services: None,
+                            online_drivers: None,
+                            strip_version_suffix,
+                        };
+                        let rows = DriverBackup::group_by_version(&drivers, strip_version_suffix).len();
+                        if per_host {
+                            let stem = output.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+                            let host_name = host.chars()
+                                .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '_' })
+                                .collect::<String>();
+                            let file_name = format!("{}_{}.csv", stem, host_name);
+                            let path = output.with_file_name(file_name);
+                            DriverBackup::export_wmi_drivers_csv_static(&drivers, &enrichment, &HashMap::new(), &path, delimiter, verbose)?;
+                        } else {
+                            if combined.content.is_empty() {
+                                combined.write_row(&DriverBackup::inventory_header(&enrichment));
+                            }
+                            DriverBackup::write_inventory_rows(&mut combined, &DriverBackup::group_by_version(&drivers, strip_version_suffix), &enrichment, &HashMap::new(), verbose);
+                        }
+                        Ok((drivers.len(), rows))
+                    });
+                if let Err(e) = &result {
+                    eprintln!("✗ {}: {:#}", host, e);
+                }
+                results.push((host.clone(), result));
+            }
+
+            if !per_host && !combined.content.is_empty() {
+                combined.save(&output)?;
+                if !quiet() {
+                    println!("\nExported to: {}", output.display());
+                }
+            }
+
+            let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+            if !quiet() {
+                println!("\nPer-host summary:");
+                for (host, result) in &results {
+                    match result {
+                        Ok((devices, rows)) => println!("  ✓ {}: {} devices, {} rows", host, devices, rows),
+                        Err(_) => println!("  ✗ {}: failed", host),
+                    }
+                }
+            }
+            if failed > 0 && failed == results.len() {
+                anyhow::bail!("Inventory failed on all {} hosts", failed);
+            }
         }
-        Commands::Export { output, all, verbose, files } => {
-            println!("Hardware Inventory Export");
-            println!("=========================");
+        Commands::Export { output, all, verbose, files, wmi_timeout, wql_where, delimiter, status, file_versions, stale, stale_csv, printers, services, strip_version_suffix, compare_to_online, ndjson, match_device, .. } => {
+            if files && quiet() {
+                anyhow::bail!("--output - and --ndjson - cannot be combined with --files");
+            }
+            let verbose = verbose > 0 && !quiet();
+            if !quiet() {
+                println!("Hardware Inventory Export");
+                println!("=========================");
+            }
             
             // Query WMI for connected devices
-            let com_con = COMLibrary::new().context("Failed to initialize COM library")?;
-            let wmi_con = WMIConnection::new(com_con.into()).context("Failed to create WMI connection")?;
-            
-            let drivers: Vec<PnPSignedDriver> = wmi_con.query()
-                .context("Failed to query WMI for PnP signed drivers")?;
+            let drivers = DriverBackup::query_signed_drivers(wmi_timeout.map(Duration::from_secs), wql_where.as_deref(), verbose)?;
             
             // Filter Microsoft drivers unless --all is specified
             let filtered_drivers: Vec<PnPSignedDriver> = if all {
                 drivers
             } else {
-                drivers.into_iter()
-                    .filter(|d| {
-                        d.driver_provider_name.as_ref()
-                            .map(|p| !p.to_lowercase().contains("microsoft"))
-                            .unwrap_or(true)
-                    })
-                    .collect()
+                DriverBackup::third_party_drivers(drivers)
+            };
+            let filtered_drivers = match &match_device {
+                Some(query) => DriverBackup::filter_by_device_name(filtered_drivers, query),
+                None => filtered_drivers,
             };
             
-            println!("Found {} connected devices", filtered_drivers.len());
-
-            // Export driver files if --files flag is set
-            if files {
-                let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
-                let backup_dir = if output.extension().map(|e| e == "csv").unwrap_or(false) {
-                    output.parent().unwrap_or(Path::new(".")).join(format!("drivers_{}", timestamp))
-                } else {
-                    output.join(format!("drivers_{}", timestamp))
-                };
-                
-                fs::create_dir_all(&backup_dir)
-                    .with_context(|| format!("Failed to create backup directory: {}", backup_dir.display()))?;
-
-                println!("\nExporting driver files to: {}", backup_dir.display());
-
-                // Group drivers by INF and export
-                let mut exported_infs: std::collections::HashSet<String> = std::collections::HashSet::new();
-                let mut success_count = 0;
-                let mut fail_count = 0;
-
-                for driver in &filtered_drivers {
-                    if let Some(inf_name) = &driver.inf_name {
-                        let inf_lower = inf_name.to_lowercase();
-                        if inf_lower.starts_with("oem") && !exported_infs.contains(&inf_lower) {
-                            exported_infs.insert(inf_lower.clone());
-
-                            // Create folder for this driver
-                            let device_class = driver.device_class.as_deref().unwrap_or("Unknown");
-                            let version = driver.driver_version.as_deref().unwrap_or("Unknown");
-                            let provider = driver.driver_provider_name.as_deref().unwrap_or("Unknown");
-                            
-                            let folder_name = format!("{}_{}_{}",device_class, provider, version)
-                                .chars()
-                                .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '_' })
-                                .collect::<String>();
+            if !quiet() {
+                println!("Found {} connected devices", filtered_drivers.len());
+            }
 
-                            let driver_dir = backup_dir.join(&folder_name);
-                            fs::create_dir_all(&driver_dir).ok();
+            if let Some(age) = stale {
+                DriverBackup::stale_report(&filtered_drivers, age, stale_csv.as_deref(), delimiter)?;
+            } else {
 
-                            if verbose {
-                                println!("  Exporting {} -> {}", inf_name, folder_name);
+                // The INF lookup is built once and shared with --files
+                let inf_lookup = DriverBackup::inf_lookup();
+                // The entity join only adds columns, so without --status a failed query is just a warning
+                let entities = match DriverBackup::query_pnp_entities(wmi_timeout.map(Duration::from_secs), status, verbose) {
+                    Ok(entities) => entities,
+                    Err(e) if !status => {
+                        eprintln!("Warning: {:#}", e);
+                        HashMap::new()
+                    }
+                    Err(e) => return Err(e),
+                };
+                let enrichment = InventoryEnrichment {
+                    entities,
+                    status,
+                    file_versions: file_versions.then(|| DriverBackup::collect_file_versions(&filtered_drivers, inf_lookup, verbose)),
+                    computer: None,
+                    printers: if printers {
+                        Some(DriverBackup::query_printer_drivers(wmi_timeout.map(Duration::from_secs))?)
+                    } else {
+                        None
+                    },
+                    services: if services {
+                        let services = DriverBackup::query_system_drivers(wmi_timeout.map(Duration::from_secs), inf_lookup, all)?;
+                        for (oem, summaries) in DriverBackup::boot_critical_services(&services) {
+                            eprintln!("Warning: {} ({}) provides boot-critical service {}",
+                                oem,
+                                inf_lookup.get(&oem).map(String::as_str).unwrap_or("unknown"),
+                                summaries.join(", "));
+                        }
+                        Some(services)
+                    } else {
+                        None
+                    },
+                    online_drivers: if compare_to_online {
+                        if !quiet() {
+                            println!("Searching Windows Update for newer drivers (this can take a few minutes)...");
+                        }
+                        // Only adds a column, so an unavailable Windows Update doesn't stop the export
+                        match DriverBackup::query_online_drivers() {
+                            Ok(online) => Some(online),
+                            Err(e) => {
+                                eprintln!("Warning: --compare-to-online skipped: {:#}", e);
+                                None
                             }
+                        }
+                    } else {
+                        None
+                    },
+                    strip_version_suffix,
+                };
 
-                            let status = Command::new("pnputil")
-                                .arg("/export-driver")
-                                .arg(inf_name)
-                                .arg(&driver_dir)
-                                .output();
+                if let Some(online) = enrichment.online_drivers.as_deref().filter(|_| !quiet()) {
+                    let newer: Vec<(&PnPSignedDriver, &OnlineDriver)> = filtered_drivers.iter()
+                        .filter_map(|d| Some((d, DriverBackup::newer_online_driver(d, &enrichment.entities, online)?)))
+                        .collect();
+                    println!("Devices with a newer driver on Windows Update: {}", newer.len());
+                    for (driver, offer) in newer {
+                        println!("  {} ({}): {}",
+                            driver.device_name.as_deref().unwrap_or("Unknown"),
+                            format_driver_date(driver.driver_date.as_deref(), false),
+                            offer.summary());
+                    }
+                }
 
-                            match status {
-                                Ok(result) if result.status.success() => {
-                                    success_count += 1;
-                                }
-                                _ => {
-                                    fail_count += 1;
-                                    if verbose {
-                                        eprintln!("    Failed to export {}", inf_name);
-                                    }
-                                }
-                            }
+                // Export driver files if --files flag is set
+                if files {
+                    let started = Utc::now();
+                    let export_command = export_files_command.expect("cloned for export --files");
+                    let options = PackageExportOptions::from_command(&export_command).expect("export --files has package options");
+                    let backup_args = Args {
+                        command: Some(export_command),
+                        elevate: false,
+                        elevated: false,
+                        paths: args.paths,
+                        refresh_lookup: args.refresh_lookup,
+                        hooks: hooks.clone(),
+                    };
+
+                    // Same pipeline as backup; all_drivers.csv is the inventory below instead of the INF summary
+                    let result = DriverBackup::new(backup_args).and_then(|backup| {
+                        let system = backup.get_system_context(true);
+                        tokio::runtime::Runtime::new()?.block_on(backup.backup_drivers(filtered_drivers.clone(), system, &options))
+                    });
+                    let result = result.and_then(|stats| {
+                        println!("Driver files exported: {} success, {} failed", stats.succeeded, stats.failed);
+                        if let Some(backup_dir) = &stats.location {
+                            let csv_path = backup_dir.join("all_drivers.csv");
+                            DriverBackup::export_wmi_drivers_csv_static(&filtered_drivers, &enrichment, inf_lookup, &csv_path, delimiter, verbose)?;
+                        }
+                        if let Some(ndjson_path) = &ndjson {
+                            let count = write_ndjson(&filtered_drivers, ndjson_path)?;
+                            println!("JSON Lines written: {} ({} drivers)", ndjson_path.display(), count);
+                        }
+                        Ok(stats)
+                    });
+                    hooks.notify("export", started, &result);
+                    if result?.partial_failure {
+                        exit_code = EXIT_PARTIAL_FAILURE;
+                    }
+                } else {
+                    // Just export CSV
+                    DriverBackup::export_wmi_drivers_csv_static(&filtered_drivers, &enrichment, inf_lookup, &output, delimiter, verbose)?;
+                    if !quiet() {
+                        println!("\nExported to: {}", output.display());
+                    }
+                    if let Some(ndjson_path) = &ndjson {
+                        let count = write_ndjson(&filtered_drivers, ndjson_path)?;
+                        if !quiet() {
+                            println!("JSON Lines written: {} ({} drivers)", ndjson_path.display(), count);
                         }
                     }
                 }
+            }
+        }
+    }
 
-                println!("Driver files exported: {} success, {} failed", success_count, fail_count);
+    // Add pause before closing; skipped when piping CSV so the consumer isn't left waiting.
+    // Nothing is left to stop, so a Ctrl-C at the prompt quits right away.
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    if !quiet() {
+        println!("\nPress Enter to close...");
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).expect("Failed to read line");
+    }
 
-                // Create CSV in backup directory
-                let csv_path = backup_dir.join("all_drivers.csv");
-                DriverBackup::export_wmi_drivers_csv_static(&filtered_drivers, &csv_path, verbose)?;
-                
-                println!("\nBackup location: {}", backup_dir.display());
-            } else {
-                // Just export CSV
-                DriverBackup::export_wmi_drivers_csv_static(&filtered_drivers, &output, verbose)?;
-                println!("\nExported to: {}", output.display());
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn driver_version_ordering_table() {
+        use std::cmp::Ordering::{Equal, Greater, Less};
+        let cases = [
+            ("30.0.101.1340", "30.0.101.1340", Equal),
+            // Segment-wise numeric, not text: 999 < 1340 although "9" > "1"
+            ("30.0.101.1340", "30.0.101.999", Greater),
+            ("10.1.18836.8283", "10.1.9999.9999", Greater),
+            ("31.0.101.2111", "30.0.101.1340", Greater),
+            ("010.0", "10.0", Equal),
+            // Missing segments count as zero
+            ("6.1", "6.1.0.0", Equal),
+            ("6.1", "6.1.0.1", Less),
+            ("6.2", "6.1.9.9", Greater),
+            ("6", "6.0", Equal),
+            // A pre-release suffix sorts before the release, suffixes compare as text
+            ("1.0.0.0-beta", "1.0.0.0", Less),
+            ("1.0.0.0-beta", "1.0.0.0-rc", Less),
+            ("1.0.0.0-BETA", "1.0.0.0-beta", Equal),
+            ("1.0.0.1-beta", "1.0.0.0", Greater),
+            ("1.0-beta", "1.0.0.0", Less),
+            (" 2.0 ", "2.0", Equal),
+            ("18446744073709551615.0", "1.0", Greater),
+            // Unparseable versions fall back to case-insensitive text order
+            ("", "", Equal),
+            ("unknown", "UNKNOWN", Equal),
+            ("v1.2", "v1.10", Greater),
+            ("%DriverVer%", "1.0", Less),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(compare_driver_versions(a, b), expected, "{} vs {}", a, b);
+            assert_eq!(compare_driver_versions(b, a), expected.reverse(), "{} vs {}", b, a);
+            assert_eq!(DriverVersion::parse(a) == DriverVersion::parse(b), expected == Equal, "{} == {}", a, b);
+        }
+    }
+
+    #[test]
+    fn driver_version_comparability_table() {
+        let cases = [
+            ("30.0.101.1340", true),
+            ("6.1", true),
+            ("6", true),
+            ("1.0.0.0-beta", true),
+            ("1.0 beta", true),
+            ("", false),
+            ("   ", false),
+            ("%DriverVer%", false),
+            ("v1.2", false),
+            ("1..2", false),
+            ("1.x.3", false),
+            ("1.0-beta.2", false),
+        ];
+        for (text, comparable) in cases {
+            assert_eq!(DriverVersion::parse(text).is_comparable(), comparable, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn driver_version_sort_order() {
+        let mut versions = vec!["10.0.1", "1.0.0.0", "9.9", "1.0.0.0-beta", "30.0.101.999", "30.0.101.1340", "6.1", "6.1.0.1"];
+        versions.sort_by(|a, b| compare_driver_versions(a, b));
+        assert_eq!(versions, vec!["1.0.0.0-beta", "1.0.0.0", "6.1", "6.1.0.1", "9.9", "10.0.1", "30.0.101.999", "30.0.101.1340"]);
+    }
+
+    /// One test per reserved device name: the bare name, any case, and with an extension
+    macro_rules! reserved_folder_name_tests {
+        ($($test:ident => $reserved:literal),* $(,)?) => {
+            $(
+                #[test]
+                fn $test() {
+                    assert_eq!(safe_folder_name($reserved.to_string()), format!("{}_dev", $reserved));
+                    assert_eq!(safe_folder_name($reserved.to_lowercase()), format!("{}_dev", $reserved.to_lowercase()));
+                    assert_eq!(safe_folder_name(format!("{}.inf", $reserved)), format!("{}_dev.inf", $reserved));
+                    assert_eq!(safe_folder_name(format!("{}.", $reserved)), format!("{}_dev._dev", $reserved));
+                    // Longer names that only start with one are fine
+                    assert_eq!(safe_folder_name(format!("{}_Audio", $reserved)), format!("{}_Audio", $reserved));
+                }
+            )*
+        };
+    }
+
+    reserved_folder_name_tests! {
+        con => "CON",
+        prn => "PRN",
+        aux => "AUX",
+        nul => "NUL",
+        com1 => "COM1",
+        com2 => "COM2",
+        com3 => "COM3",
+        com4 => "COM4",
+        com5 => "COM5",
+        com6 => "COM6",
+        com7 => "COM7",
+        com8 => "COM8",
+        com9 => "COM9",
+        lpt1 => "LPT1",
+        lpt2 => "LPT2",
+        lpt3 => "LPT3",
+        lpt4 => "LPT4",
+        lpt5 => "LPT5",
+        lpt6 => "LPT6",
+        lpt7 => "LPT7",
+        lpt8 => "LPT8",
+        lpt9 => "LPT9",
+    }
+
+    #[test]
+    fn safe_folder_name_trailing_dots_and_spaces() {
+        let cases = [
+            ("Realtek_1.0.", "Realtek_1.0._dev"),
+            ("Realtek_1.0 ", "Realtek_1.0 _dev"),
+            ("Intel...", "Intel..._dev"),
+            ("", "_dev"),
+            ("Intel_30.0.101.1340", "Intel_30.0.101.1340"),
+            ("CONSOLE", "CONSOLE"),
+            ("COM10", "COM10"),
+            ("LPT0", "LPT0"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(safe_folder_name(name.to_string()), expected, "{:?}", name);
+        }
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name)
+    }
+
+    fn manufacturer_table(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn section_matches_manufacturer_table() {
+        let manufacturers = manufacturer_table(&[("%IntelMfg%", "Intel, NTamd64.10.0, NTx86"), ("Realtek", "RTL")]);
+        let cases = [
+            ("Intel", true),
+            ("intel", true),
+            ("Intel.NTamd64.10.0", true),
+            ("INTEL.ntx86", true),
+            (" Intel.NTx86 ", true),
+            ("RTL", true),
+            ("Realtek", false),
+            ("Version", false),
+            ("Manufacturer", false),
+            ("Strings", false),
+            ("SourceDisksFiles", false),
+        ];
+        for (section, expected) in cases {
+            assert_eq!(InfParser::section_matches_manufacturer(section, &manufacturers), expected, "[{}]", section);
+        }
+        assert!(!InfParser::section_matches_manufacturer("Intel", &BTreeMap::new()));
+    }
+
+    #[test]
+    fn models_sections_are_matched_by_exact_decorated_name() {
+        let manufacturers = manufacturer_table(&[("%Intel%", "Intel, NTamd64"), ("%IntelExt%", "IntelExt, NTamd64"), ("%IntelDot%", "Intel.Ext, NTx86")]);
+        let cases = [
+            ("Intel", Some("%Intel%")),
+            ("Intel.NTamd64", Some("%Intel%")),
+            ("IntelExt.NTamd64", Some("%IntelExt%")),
+            ("Intel.Ext", Some("%IntelDot%")),
+            ("Intel.Ext.NTx86", Some("%IntelDot%")),
+            // Decorations the entry doesn't list, and prefixes of declared names
+            ("Intel.NTarm64", None),
+            ("IntelExt.NTx86", None),
+            ("Int", None),
+            ("IntelAudio", None),
+        ];
+        for (section, expected) in cases {
+            let found = InfParser::manufacturer_for_section(section, &manufacturers).map(|(name, _)| name);
+            assert_eq!(found, expected, "[{}]", section);
+            assert_eq!(InfParser::section_matches_manufacturer(section, &manufacturers), expected.is_some(), "[{}]", section);
+        }
+    }
+
+    #[test]
+    fn overlapping_manufacturer_names_fixture() {
+        let parsed = InfParser::parse_inf_file(&fixture("overlapping_manufacturers.inf"), DEFAULT_MAX_INF_SIZE).unwrap();
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for driver in &parsed.drivers {
+            *counts.entry(driver.manufacturer.as_deref().unwrap_or("(none)")).or_default() += 1;
+        }
+        assert_eq!(counts, BTreeMap::from([
+            ("Intel Corporation", 1),
+            ("Intel Dotted Models", 1),
+            ("Intel Extension Co", 1),
+            ("Realtek", 1),
+            ("Realtek HD Audio", 2),
+        ]));
+
+        let attributed: Vec<(&str, &str)> = parsed.drivers.iter()
+            .map(|driver| (driver.hardware_id.as_deref().unwrap(), driver.manufacturer.as_deref().unwrap()))
+            .collect();
+        for (hardware_id, manufacturer) in [
+            ("PCI\\VEN_8086&DEV_0001", "Intel Corporation"),
+            ("PCI\\VEN_8086&DEV_0002", "Intel Extension Co"),
+            ("PCI\\VEN_8086&DEV_0003", "Intel Dotted Models"),
+            ("HDAUDIO\\FUNC_01&VEN_10EC&DEV_0001", "Realtek"),
+            ("HDAUDIO\\FUNC_01&VEN_10EC&DEV_0256", "Realtek HD Audio"),
+            ("HDAUDIO\\FUNC_01&VEN_10EC&DEV_0295", "Realtek HD Audio"),
+        ] {
+            assert_eq!(attributed.iter().filter(|(id, _)| *id == hardware_id).collect::<Vec<_>>(), vec![&(hardware_id, manufacturer)]);
+        }
+        // [Intel.NTarm64] isn't declared by any entry, so its device isn't listed
+        assert!(attributed.iter().all(|(id, _)| *id != "PCI\\VEN_8086&DEV_0004"));
+    }
+
+    #[test]
+    fn oversized_inf_is_a_parse_error() {
+        let dir = TempDir::new().unwrap();
+        // Sparse, so the fixture costs no disk space; the guard only looks at the size
+        let huge = dir.path().join("huge.inf");
+        fs::File::create(&huge).unwrap().set_len(DEFAULT_MAX_INF_SIZE + 1).unwrap();
+        let error = InfParser::parse_inf_file(&huge, DEFAULT_MAX_INF_SIZE).unwrap_err();
+        assert!(error.to_string().contains("larger than the 8192 KB INF size limit"), "{}", error);
+
+        // A valid INF padded past a lower --max-inf-size is rejected too, and still parses under the default
+        let padded = dir.path().join("padded.inf");
+        let mut content = fs::read_to_string(fixture("overlapping_manufacturers.inf")).unwrap();
+        while content.len() <= 64 * 1024 {
+            content.push_str("; padding\r\n");
+        }
+        fs::write(&padded, &content).unwrap();
+        assert!(InfParser::parse_inf_file(&padded, 64 * 1024).is_err());
+        assert_eq!(InfParser::parse_inf_file(&padded, DEFAULT_MAX_INF_SIZE).unwrap().drivers.len(), 6);
+
+        // A cached result isn't handed out once the limit is lowered below the file's size
+        let mut cache = ScanCache::load(&dir.path().join("scan-cache.json"));
+        assert!(cache.parse(&padded, DEFAULT_MAX_INF_SIZE).is_ok());
+        assert!(cache.parse(&padded, 64 * 1024).is_err());
+    }
+
+    fn scan_options(output: PathBuf) -> ScanOptions {
+        ScanOptions {
+            output: Some(output),
+            delimiter: ',',
+            csv_columns: None,
+            verbose: false,
+            group_by: None,
+            group_threshold: 0,
+            sort: None,
+            descending: false,
+            recursive: true,
+            walk: WalkOptions::default(),
+            bus: None,
+            cache_path: None,
+            max_inf_size: DEFAULT_MAX_INF_SIZE,
+            class_map: HashMap::new(),
+            filter: InfFilter::default(),
+            duplicates: false,
+            duplicates_csv: None,
+            diff: None,
+            diff_csv: None,
+            index: None,
+            write_index: None,
+            index_hashes: false,
+            ndjson: None,
+        }
+    }
+
+    #[test]
+    fn scan_csv_is_byte_identical_across_runs() {
+        let tree = fixture("tree");
+        let dir = TempDir::new().unwrap();
+        let first = dir.path().join("first.csv");
+        let second = dir.path().join("second.csv");
+        InfParser::scan_folder(std::slice::from_ref(&tree), &scan_options(first.clone())).unwrap();
+        InfParser::scan_folder(std::slice::from_ref(&tree), &scan_options(second.clone())).unwrap();
+        let first = fs::read(first).unwrap();
+        assert_eq!(first, fs::read(second).unwrap());
+
+        // Classes, then INF names, then paths; devices by description
+        let csv = String::from_utf8(first).unwrap();
+        let rows: Vec<&str> = csv.lines().skip(1).map(|row| row.split(',').next().unwrap()).collect();
+        assert_eq!(rows, ["adispl.inf", "audfab.inf", "audfab.inf", "netzeta.inf"]);
+        assert!(csv.contains("Alpha Network Adapter; Mid Network Adapter; Zeta Network Adapter"), "{}", csv);
+    }
+
+    #[test]
+    fn parse_order_does_not_change_csv_output() {
+        let (mut inf_files, _) = InfParser::find_inf_files(&fixture("tree"), &WalkOptions::default()).unwrap();
+        let dir = TempDir::new().unwrap();
+        let mut outputs = Vec::new();
+        for run in 0..2 {
+            if run == 1 {
+                inf_files.reverse();
             }
+            let mut parsed: Vec<ParsedInfFile> = inf_files.iter()
+                .map(|inf| InfParser::parse_inf_file(inf, DEFAULT_MAX_INF_SIZE).unwrap())
+                .collect();
+            InfParser::sort_parsed_files(&mut parsed);
+            let scan_csv = dir.path().join(format!("scan{}.csv", run));
+            let inspect_csv = dir.path().join(format!("inspect{}.csv", run));
+            InfParser::export_scan_csv(&parsed, &scan_csv, ',', None).unwrap();
+            InfParser::export_to_csv(&parsed, &inspect_csv, ',', None).unwrap();
+            outputs.push((fs::read(scan_csv).unwrap(), fs::read(inspect_csv).unwrap()));
         }
+        assert!(outputs[0] == outputs[1], "CSV output depends on the order INFs were parsed in");
     }
 
-    // Add pause before closing
-    println!("\nPress Enter to close...");
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).expect("Failed to read line");
+    fn expected_lookup(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries.iter().map(|(oem, original)| (oem.to_string(), original.to_string())).collect()
+    }
 
-    Ok(())
+    #[test]
+    fn pnputil_enum_drivers_in_each_language() {
+        let expected = expected_lookup(&[("oem0.inf", "prnms003.inf"), ("oem12.inf", "nvlddmkm.inf"), ("oem7.inf", "RtkHDAud.inf")]);
+        for language in ["en", "de", "fr", "ja"] {
+            let output = fs::read_to_string(fixture(&format!("pnputil/enum_drivers_{}.txt", language))).unwrap();
+            assert_eq!(DriverBackup::parse_enum_drivers(&output), expected, "{} output", language);
+        }
+    }
+
+    #[test]
+    fn pnputil_enum_drivers_skips_incomplete_records() {
+        let output = "Published Name: oem1.inf\nProvider Name: Contoso\n\nPublished Name: oem2.inf\nOriginal Name: contoso.inf\nDriver Version: 01/01/2024 1.0.0.0\n";
+        assert_eq!(DriverBackup::parse_enum_drivers(output), expected_lookup(&[("oem2.inf", "contoso.inf")]));
+        assert!(DriverBackup::parse_enum_drivers("").is_empty());
+        assert!(DriverBackup::parse_enum_drivers("Microsoft PnP Utility\n\nNo driver packages found.").is_empty());
+    }
+
+    #[test]
+    fn driver_database_registry_output() {
+        let output = fs::read_to_string(fixture("reg_driverinffiles_de.txt")).unwrap();
+        assert_eq!(
+            DriverBackup::parse_driver_inf_files(&output),
+            expected_lookup(&[("oem0.inf", "prnms003.inf"), ("oem12.inf", "nvlddmkm.inf"), ("oem7.inf", "rtkhdaud.inf")])
+        );
+    }
+
+    #[test]
+    fn extracts_the_fixture_cab() {
+        let dest = TempDir::new().unwrap();
+        InfParser::extract_cab(&fixture("cab/package.cab"), dest.path(), false).unwrap();
+        assert!(dest.path().join("Readme.txt").is_file());
+        assert!(dest.path().join("Driver").join("cabdrv.cat").is_file());
+
+        let (inf_files, _) = InfParser::find_inf_files(dest.path(), &WalkOptions::default()).unwrap();
+        assert_eq!(inf_files, vec![dest.path().join("Driver").join("cabdrv.inf")]);
+        let parsed = InfParser::parse_inf_file(&inf_files[0], DEFAULT_MAX_INF_SIZE).unwrap();
+        assert_eq!(parsed.drivers.len(), 1);
+        assert_eq!(parsed.drivers[0].device_name.as_deref(), Some("Contoso USB Dock"));
+    }
+
+    #[test]
+    fn nested_cab_contents_become_visible() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir(root.path().join("setup")).unwrap();
+        fs::copy(fixture("cab/package.cab"), root.path().join("setup").join("package.cab")).unwrap();
+        InfParser::extract_nested_cabs(root.path(), u64::MAX, false).unwrap();
+        let (inf_files, _) = InfParser::find_inf_files(root.path(), &WalkOptions::default()).unwrap();
+        assert_eq!(inf_files.len(), 1, "{:?}", inf_files);
+        assert!(inf_files[0].ends_with(Path::new("Driver").join("cabdrv.inf")), "{}", inf_files[0].display());
+    }
+
+    #[test]
+    fn cab_entries_cannot_escape_the_destination() {
+        let dir = TempDir::new().unwrap();
+        let dest = dir.path().join("out");
+        fs::create_dir(&dest).unwrap();
+        let error = InfParser::extract_cab(&fixture("cab/escape.cab"), &dest, false).unwrap_err();
+        assert!(error.to_string().contains("path escapes the destination"), "{}", error);
+        assert!(!dir.path().join("escape.inf").exists());
+
+        let cases = [
+            ("Driver\\cabdrv.inf", Some(Path::new("Driver").join("cabdrv.inf"))),
+            ("./Driver//cabdrv.inf", Some(Path::new("Driver").join("cabdrv.inf"))),
+            ("..\\escape.inf", None),
+            ("Driver\\..\\..\\escape.inf", None),
+            ("C:\\Windows\\escape.inf", None),
+            ("\\", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(InfParser::enclosed_cab_path(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn resolve_device_sections_resolves_names_through_strings() {
+        let manufacturers = manufacturer_table(&[("%IntelMfg%", "Intel, NTamd64.10.0"), ("Contoso", "Contoso")]);
+        let strings: HashMap<String, String> = [("intelmfg", "Intel Corporation"), ("nicdesc", "Intel(R) Ethernet Connection")]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let mut device_sections: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+        device_sections.insert("Intel.NTamd64.10.0".to_string(), vec![
+            ("%NicDesc%".to_string(), "PCI\\VEN_8086&DEV_15B8".to_string()),
+        ]);
+        device_sections.insert("Contoso".to_string(), vec![
+            ("Contoso Widget".to_string(), "USB\\VID_1234&PID_0001".to_string()),
+            ("%NoSuchString%".to_string(), "USB\\VID_1234&PID_0002".to_string()),
+        ]);
+        device_sections.insert("Orphan".to_string(), vec![
+            ("Orphan Device".to_string(), "ACPI\\ORPH0001".to_string()),
+        ]);
+
+        let devices = InfParser::resolve_device_sections(&device_sections, &manufacturers, &strings);
+        let resolved: Vec<(Option<&str>, &str, &str)> = devices.iter()
+            .map(|device| (device.manufacturer.as_deref(), device.description.as_str(), device.hardware_id.as_str()))
+            .collect();
+        assert_eq!(resolved, vec![
+            (Some("Contoso"), "Contoso Widget", "USB\\VID_1234&PID_0001"),
+            (Some("Contoso"), "%NoSuchString%", "USB\\VID_1234&PID_0002"),
+            (Some("Intel Corporation"), "Intel(R) Ethernet Connection", "PCI\\VEN_8086&DEV_15B8"),
+            (None, "Orphan Device", "ACPI\\ORPH0001"),
+        ]);
+    }
+
+    #[test]
+    fn ctrl_c_handler_only_for_commands_that_check_it() {
+        let cases: [(&[&str], bool); 9] = [
+            (&["backup"], true),
+            (&["backup", "--summary-csv-only", "old"], false),
+            (&["inspect", "-p", "driver.exe"], true),
+            (&["scan", "-p", "drivers"], false),
+            (&["scan", "-p", "drivers", "--watch", "-o", "drivers.csv"], true),
+            (&["export"], false),
+            (&["export", "--files"], true),
+            (&["export", "--computer", "pc01"], true),
+            (&["prune", "--root", "backups", "--keep-last", "3"], false),
+        ];
+        for (command_line, expected) in cases {
+            let args = Args::try_parse_from(std::iter::once("driver-backup").chain(command_line.iter().copied())).expect("valid command line");
+            assert_eq!(checks_interrupts(&args.command.unwrap()), expected, "{:?}", command_line);
+        }
+    }
+
+    fn exclusion_fixtures() -> Vec<ParsedInfFile> {
+        ["exclude_all.inf", "exclude_listed.inf"].into_iter()
+            .map(|name| InfParser::parse_inf_file(&fixture(name), DEFAULT_MAX_INF_SIZE).unwrap())
+            .collect()
+    }
+
+    fn device_exclusions(parsed_files: &[ParsedInfFile]) -> Vec<(&str, Exclusion)> {
+        parsed_files.iter()
+            .flat_map(|parsed| &parsed.drivers)
+            .map(|driver| (driver.hardware_id.as_deref().unwrap(), driver.excluded))
+            .collect()
+    }
+
+    #[test]
+    fn backup_timestamp_prefers_the_manifest_offset() {
+        let utc = |text: &str| chrono::NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap().and_utc();
+        // A --local-time backup made at 12:00 in UTC+2
+        let local = DriverBackup::backup_timestamp(&fixture("backups").join("drivers_20240101_120000"));
+        assert_eq!(local, Some(utc("2024-01-01 10:00")));
+        // No manifest: the folder name is taken as UTC
+        let named = DriverBackup::backup_timestamp(&fixture("backups").join("drivers_20240102_080000"));
+        assert_eq!(named, Some(utc("2024-01-02 08:00")));
+        assert_eq!(DriverBackup::backup_timestamp(&fixture("backups")), None);
+    }
+
+    #[test]
+    fn manifest_system_reads_the_written_field_names() {
+        let system = DriverBackup::manifest_system(&fixture("backups").join("drivers_20240101_120000")).unwrap();
+        assert_eq!(system.os.as_ref().and_then(|os| os.architecture.as_deref()), Some("64-bit"));
+        assert_eq!(system.computer.as_ref().and_then(|computer| computer.model.as_deref()), Some("20XW0026GE"));
+        assert!(system.bios.is_none());
+        assert!(DriverBackup::manifest_system(&fixture("backups").join("drivers_20240102_080000")).is_none());
+    }
+
+    #[test]
+    fn exclude_from_select_marks_device_entries() {
+        let parsed_files = exclusion_fixtures();
+        assert_eq!(device_exclusions(&parsed_files), vec![
+            ("PCI\\VEN_10DE&DEV_2484", Exclusion::All),
+            ("PCI\\VEN_10DE&DEV_2488", Exclusion::All),
+            ("PCI\\VEN_8086&DEV_15BB", Exclusion::Selectable),
+            ("PCI\\VEN_8086&DEV_15BC", Exclusion::Listed),
+        ]);
+    }
+
+    #[test]
+    fn filter_keeps_wildcard_excluded_entries_and_hides_listed_ones() {
+        let mut parsed_files = exclusion_fixtures();
+        assert_eq!(InfFilter::default().apply(&mut parsed_files), 0);
+        assert_eq!(device_exclusions(&parsed_files), vec![
+            ("PCI\\VEN_10DE&DEV_2484", Exclusion::All),
+            ("PCI\\VEN_10DE&DEV_2488", Exclusion::All),
+            ("PCI\\VEN_8086&DEV_15BB", Exclusion::Selectable),
+        ]);
+
+        let mut parsed_files = exclusion_fixtures();
+        InfFilter { show_excluded: true, ..InfFilter::default() }.apply(&mut parsed_files);
+        assert_eq!(device_exclusions(&parsed_files).len(), 4);
+
+        // --hwid still finds the package through its hidden entry
+        let mut parsed_files = exclusion_fixtures();
+        assert_eq!(InfFilter { hwid: Some("DEV_15BC".to_string()), ..InfFilter::default() }.apply(&mut parsed_files), 1);
+        assert_eq!(parsed_files.iter().map(|parsed| parsed.file_name.as_str()).collect::<Vec<_>>(), vec!["exclude_listed.inf"]);
+    }
+
+    #[test]
+    fn show_excluded_does_not_need_verbose() {
+        assert!(Args::try_parse_from(["driver-backup", "scan", "-p", "drivers", "--show-excluded"]).is_ok());
+        assert!(Args::try_parse_from(["driver-backup", "inspect", "-p", "driver.inf", "--show-excluded"]).is_ok());
+    }
+
+    fn signed_driver(device_class: &str, device_name: &str, provider: Option<&str>, version: &str) -> PnPSignedDriver {
+        PnPSignedDriver {
+            class_guid: None,
+            description: None,
+            device_class: Some(device_class.to_string()),
+            device_name: Some(device_name.to_string()),
+            driver_date: None,
+            driver_provider_name: provider.map(str::to_string),
+            driver_version: Some(version.to_string()),
+            inf_name: Some("oem12.inf".to_string()),
+            hardware_id: None,
+            device_id: None,
+            location: None,
+        }
+    }
+
+    fn package_options(command_line: &[&str]) -> Option<PackageExportOptions> {
+        let args = Args::try_parse_from(command_line).expect("valid command line");
+        PackageExportOptions::from_command(&args.command.expect("a command"))
+    }
+
+    #[test]
+    fn flat_layout_keeps_the_export_files_folder_names() {
+        // The names export --files gave its folders before it went through backup_drivers
+        let cases = [
+            ("Net", Some("Intel Corporation"), "22.40.0.7", "Net_Intel_Corporation_22.40.0.7"),
+            ("Display", Some("Advanced Micro Devices, Inc."), "31.0.21001.45002", "Display_Advanced_Micro_Devices__Inc._31.0.21001.45002"),
+            ("MEDIA", Some("Realtek Semiconductor Corp."), "6.0.9088.1", "MEDIA_Realtek_Semiconductor_Corp._6.0.9088.1"),
+            ("USB", None, "1.0.0.0", "USB_Unknown_1.0.0.0"),
+        ];
+        for (device_class, provider, version, expected) in cases {
+            let driver = signed_driver(device_class, "Some Device", provider, version);
+            assert_eq!(PackageLayout::Flat.folder_name(device_class, &driver), expected, "{:?}", (device_class, provider));
+        }
+    }
+
+    #[test]
+    fn by_class_layout_keeps_the_backup_folder_names() {
+        let cases = [
+            ("Intel(R) Ethernet Connection (7) I219-V", "12.19.1.37", "Intel(R) Ethernet Connection (7) I219-V_12.19.1.37 Package"),
+            ("Realtek High Definition Audio", "6.0.9088.1", "Realtek High Definition Audio_6.0.9088.1 Package"),
+            ("AMD Radeon RX 6600/6700", "31.0.21001.45002", "AMD Radeon RX 6600_6700_31.0.21001.45002 Package"),
+        ];
+        for (device_name, version, expected) in cases {
+            let driver = signed_driver("Display", device_name, Some("Contoso"), version);
+            assert_eq!(PackageLayout::ByClass.folder_name("Display", &driver), expected, "{}", device_name);
+        }
+    }
+
+    #[test]
+    fn flat_packages_of_one_class_provider_and_version_get_one_folder() {
+        // Flat packages with the same name share their folder instead of going through --on-collision
+        let first = signed_driver("Net", "Intel(R) Wi-Fi 6 AX201", Some("Intel"), "22.40.0.7");
+        let second = signed_driver("Net", "Intel(R) Ethernet I219-V", Some("Intel"), "22.40.0.7");
+        assert_eq!(PackageLayout::Flat.folder_name("Net", &first), PackageLayout::Flat.folder_name("Net", &second));
+        assert_ne!(PackageLayout::ByClass.folder_name("Net", &first), PackageLayout::ByClass.folder_name("Net", &second));
+    }
+
+    #[test]
+    fn backup_and_export_files_share_the_package_options() {
+        let backup = package_options(&["driver-backup", "backup", "-o", "out", "--layout", "flat", "-v"]).expect("backup options");
+        let export = package_options(&["driver-backup", "export", "--files", "-o", "out", "-v"]).expect("export --files options");
+        assert_eq!((&backup.output, backup.layout, backup.verbose), (&export.output, export.layout, export.verbose));
+        // export writes its inventory as all_drivers.csv instead of the INF summary
+        assert!(backup.summary_csv && backup.readmes);
+        assert!(!export.summary_csv && !export.readmes);
+
+        let backup = package_options(&["driver-backup", "backup"]).expect("backup options");
+        assert_eq!((backup.output, backup.layout), (PathBuf::from("driver_backup"), PackageLayout::ByClass));
+        let export = package_options(&["driver-backup", "export", "--files"]).expect("export --files options");
+        assert_eq!((export.output, export.layout), (PathBuf::from("."), PackageLayout::Flat));
+        let export = package_options(&["driver-backup", "export", "--files", "-o", "out/inventory.csv", "--layout", "by-class"]).expect("export --files options");
+        assert_eq!((export.output, export.layout), (PathBuf::from("out"), PackageLayout::ByClass));
+
+        assert_eq!(package_options(&["driver-backup", "export"]), None);
+    }
 }